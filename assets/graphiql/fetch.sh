@@ -0,0 +1,10 @@
+#!/bin/sh
+# Downloads the GraphiQL assets bundled into the gateway binary, pinned to the
+# versions the CDN mode loads. Run from anywhere, then rebuild.
+set -e
+cd "$(dirname "$0")"
+
+curl -fsSL -o react.production.min.js https://unpkg.com/react@17.0.2/umd/react.production.min.js
+curl -fsSL -o react-dom.production.min.js https://unpkg.com/react-dom@17.0.2/umd/react-dom.production.min.js
+curl -fsSL -o graphiql.min.js https://unpkg.com/graphiql@1.5.0/graphiql.min.js
+curl -fsSL -o graphiql.min.css https://unpkg.com/graphiql@1.5.0/graphiql.min.css
@@ -1,7 +1,24 @@
-use std::{env, fs, path::PathBuf};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Files of the GraphiQL IDE served from the binary, fetched into
+// `assets/graphiql` by `assets/graphiql/fetch.sh`
+const GRAPHIQL_ASSETS: [&str; 4] = [
+    "react.production.min.js",
+    "react-dom.production.min.js",
+    "graphiql.min.js",
+    "graphiql.min.css",
+];
 
 fn main() {
     println!("cargo:rerun-if-changed=schemas");
+    println!("cargo:rerun-if-changed=assets/graphiql");
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let out_schemas = out_dir.join("schemas");
@@ -14,4 +31,103 @@ fn main() {
         let file_name = path.file_name().unwrap();
         fs::copy(&path, out_schemas.join(file_name)).unwrap();
     }
+
+    // Assets that weren't fetched are left out, the gateway then refuses to
+    // start in bundled mode. Each one is bundled with its gzip and brotli
+    // encodings and the ETag of its content.
+    let assets_dir = PathBuf::from("assets/graphiql");
+    let out_assets = out_dir.join("graphiql");
+    fs::create_dir_all(&out_assets).unwrap();
+    let mut assets = String::from("&[\n");
+    for name in GRAPHIQL_ASSETS {
+        let path = assets_dir.join(name);
+        if path.is_file() {
+            let content = fs::read(&path).unwrap();
+            let gzip_path = out_assets.join(format!("{}.gz", name));
+            fs::write(&gzip_path, gzip(&content)).unwrap();
+            let brotli_path = out_assets.join(format!("{}.br", name));
+            fs::write(&brotli_path, brotli(&content)).unwrap();
+            let etag = format!("\"{}\"", &hex::encode(Sha256::digest(&content))[..16]);
+
+            let path = fs::canonicalize(&path).unwrap();
+            assets.push_str(&format!(
+                "    ({:?}, {:?}, include_bytes!({:?}) as &[u8], include_bytes!({:?}) as &[u8], include_bytes!({:?}) as &[u8]),\n",
+                name, etag, path, gzip_path, brotli_path
+            ));
+        }
+    }
+    assets.push(']');
+    fs::write(out_dir.join("graphiql_assets.rs"), assets).unwrap();
+
+    build_info();
+}
+
+fn gzip(content: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(content).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn brotli(content: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 11, 22);
+    encoder.write_all(content).unwrap();
+    drop(encoder);
+    encoded
+}
+
+// What `/admin/version` and the startup banner report about the build
+fn build_info() {
+    // The commit changes with HEAD, or with the branch HEAD points to
+    let head = Path::new(".git/HEAD");
+    if head.is_file() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = fs::read_to_string(head)
+            .ok()
+            .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+            && Path::new(".git").join(&branch).is_file()
+        {
+            println!("cargo:rerun-if-changed=.git/{}", branch);
+        }
+    }
+    let git_sha = command_output("git", &["rev-parse", "HEAD"]);
+    println!("cargo:rustc-env=PORTKEY_GIT_SHA={}", git_sha);
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+    println!("cargo:rustc-env=PORTKEY_RUSTC_VERSION={}", rustc_version);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=PORTKEY_FEATURES={}", features.join(","));
+
+    // Reproducible builds pin the timestamp
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let built_at = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=PORTKEY_BUILT_AT={}", built_at);
+}
+
+// First line the command prints, `unknown` when it can't be run
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|stdout| stdout.lines().next().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string())
 }
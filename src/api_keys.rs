@@ -0,0 +1,236 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiKeyConfig {
+    // Requires every request to carry a minted, unrevoked `x-api-key`
+    pub enabled: bool,
+    // Keeps the keys in this JSON file so they survive restarts, otherwise
+    // they only live in memory
+    pub file: Option<PathBuf>,
+}
+
+// Limits of a key, taking precedence over the gateway-wide ones
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiKeyLimits {
+    // Cost the key may spend per quota window
+    pub quota: Option<u64>,
+    // Highest estimated cost of a single operation
+    pub max_cost: Option<u64>,
+}
+
+// A key as listed by the admin API. The secret clients send is only
+// returned once, when the key is minted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub limits: ApiKeyLimits,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// Body of `POST /admin/api-keys`
+#[derive(Debug, Deserialize)]
+pub struct MintApiKey {
+    pub name: String,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub limits: ApiKeyLimits,
+}
+
+// A freshly minted key together with its secret
+#[derive(Debug, Serialize)]
+pub struct MintedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+// Keys by the hex SHA-256 of their secret, secrets themselves are never
+// stored. Shared stores let keys minted through one gateway instance be
+// accepted by the others.
+#[async_trait]
+pub trait ApiKeyStore: Send + Sync {
+    async fn insert(&self, secret_hash: String, key: ApiKey) -> Result<(), String>;
+    async fn get(&self, secret_hash: &str) -> Result<Option<ApiKey>, String>;
+    async fn list(&self) -> Result<Vec<ApiKey>, String>;
+    // Marks the key revoked, `None` when there is no key with that id
+    async fn revoke(&self, id: &str, revoked_at: DateTime<Utc>) -> Result<Option<ApiKey>, String>;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredApiKey {
+    secret_hash: String,
+    #[serde(flatten)]
+    key: ApiKey,
+}
+
+fn revoke_stored(keys: &mut [StoredApiKey], id: &str, revoked_at: DateTime<Utc>) -> Option<ApiKey> {
+    let stored = keys.iter_mut().find(|stored| stored.key.id == id)?;
+    stored.key.revoked_at.get_or_insert(revoked_at);
+    Some(stored.key.clone())
+}
+
+#[derive(Default)]
+pub struct InMemoryApiKeyStore {
+    keys: Mutex<Vec<StoredApiKey>>,
+}
+
+impl InMemoryApiKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for InMemoryApiKeyStore {
+    async fn insert(&self, secret_hash: String, key: ApiKey) -> Result<(), String> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.push(StoredApiKey { secret_hash, key });
+        Ok(())
+    }
+
+    async fn get(&self, secret_hash: &str) -> Result<Option<ApiKey>, String> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .iter()
+            .find(|stored| stored.secret_hash == secret_hash)
+            .map(|stored| stored.key.clone()))
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, String> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys.iter().map(|stored| stored.key.clone()).collect())
+    }
+
+    async fn revoke(&self, id: &str, revoked_at: DateTime<Utc>) -> Result<Option<ApiKey>, String> {
+        let mut keys = self.keys.lock().unwrap();
+        Ok(revoke_stored(&mut keys, id, revoked_at))
+    }
+}
+
+// Keeps the keys in memory and rewrites the whole file on every change
+pub struct FileApiKeyStore {
+    path: PathBuf,
+    keys: Mutex<Vec<StoredApiKey>>,
+}
+
+impl FileApiKeyStore {
+    // Loads the keys of an existing file, a missing file starts out empty
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let keys = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("Failed to parse API keys {:?}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read API keys {:?}: {}", path, e)),
+        };
+        Ok(FileApiKeyStore {
+            path: path.to_path_buf(),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    fn save(&self, keys: &[StoredApiKey]) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(keys).map_err(|e| e.to_string())?;
+        fs::write(&self.path, contents)
+            .map_err(|e| format!("Failed to write API keys {:?}: {}", self.path, e))
+    }
+}
+
+#[async_trait]
+impl ApiKeyStore for FileApiKeyStore {
+    async fn insert(&self, secret_hash: String, key: ApiKey) -> Result<(), String> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.push(StoredApiKey { secret_hash, key });
+        self.save(&keys)
+    }
+
+    async fn get(&self, secret_hash: &str) -> Result<Option<ApiKey>, String> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .iter()
+            .find(|stored| stored.secret_hash == secret_hash)
+            .map(|stored| stored.key.clone()))
+    }
+
+    async fn list(&self) -> Result<Vec<ApiKey>, String> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys.iter().map(|stored| stored.key.clone()).collect())
+    }
+
+    async fn revoke(&self, id: &str, revoked_at: DateTime<Utc>) -> Result<Option<ApiKey>, String> {
+        let mut keys = self.keys.lock().unwrap();
+        let revoked = revoke_stored(&mut keys, id, revoked_at);
+        if revoked.is_some() {
+            self.save(&keys)?;
+        }
+        Ok(revoked)
+    }
+}
+
+// Mints, revokes and checks the keys of a store
+pub struct ApiKeys {
+    store: Box<dyn ApiKeyStore>,
+}
+
+impl ApiKeys {
+    pub fn new(store: Box<dyn ApiKeyStore>) -> Self {
+        ApiKeys { store }
+    }
+
+    pub async fn mint(&self, mint: MintApiKey) -> Result<MintedApiKey, String> {
+        let secret = format!("pk_{}", random_hex(32));
+        let key = ApiKey {
+            id: format!("key_{}", random_hex(8)),
+            name: mint.name,
+            tier: mint.tier,
+            limits: mint.limits,
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        self.store.insert(secret_hash(&secret), key.clone()).await?;
+        Ok(MintedApiKey { key, secret })
+    }
+
+    pub async fn list(&self) -> Result<Vec<ApiKey>, String> {
+        self.store.list().await
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<Option<ApiKey>, String> {
+        self.store.revoke(id, Utc::now()).await
+    }
+
+    // The key a request's `x-api-key` secret belongs to
+    pub async fn authenticate(&self, secret: Option<&str>) -> Result<ApiKey, String> {
+        let key = match secret {
+            Some(secret) => self.store.get(&secret_hash(secret)).await?,
+            None => None,
+        };
+        key.filter(|key| key.revoked_at.is_none())
+            .ok_or_else(|| "Missing or invalid API key".to_string())
+    }
+}
+
+fn secret_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+// Hex of `bytes` bytes from the operating system's CSPRNG
+fn random_hex(bytes: usize) -> String {
+    let mut random = vec![0; bytes];
+    getrandom::fill(&mut random).expect("operating system randomness is unavailable");
+    hex::encode(random)
+}
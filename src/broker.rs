@@ -0,0 +1,136 @@
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Broker {
+    Nats,
+    // Needs the gateway built with the `kafka` feature
+    Kafka,
+}
+
+// Where a service publishes the events of a subscription field, for services
+// that don't stream subscriptions themselves. Every message is the JSON value
+// of the field for one event: the rest of the client's selection is resolved
+// from it like from an event the service streamed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TopicConfig {
+    pub broker: Broker,
+    // `nats://host:4222` for NATS, the bootstrap servers for Kafka
+    pub url: String,
+    // The NATS subject or the Kafka topic
+    pub topic: String,
+}
+
+// Consumes the messages published to `topic` from now on, each parsed as
+// JSON. Messages over `max_message_bytes` or that aren't JSON are reported
+// as errors without ending the stream. Every subscription consumes on its
+// own, so each of them sees every message. Dropping the stream unsubscribes.
+pub async fn subscribe(
+    topic: &TopicConfig,
+    max_message_bytes: usize,
+) -> Result<BoxStream<'static, Result<Value, String>>, String> {
+    let messages = match topic.broker {
+        Broker::Nats => subscribe_nats(topic).await?,
+        Broker::Kafka => subscribe_kafka(topic).await?,
+    };
+    let name = topic.topic.clone();
+    Ok(messages
+        .map(move |message| {
+            let message = message?;
+            if message.len() > max_message_bytes {
+                return Err(format!(
+                    "Message on {} exceeds the limit of {} bytes",
+                    name, max_message_bytes
+                ));
+            }
+            serde_json::from_slice(&message)
+                .map_err(|e| format!("Failed to parse message on {}: {}", name, e))
+        })
+        .boxed())
+}
+
+async fn subscribe_nats(
+    topic: &TopicConfig,
+) -> Result<BoxStream<'static, Result<Vec<u8>, String>>, String> {
+    let client = async_nats::connect(topic.url.as_str())
+        .await
+        .map_err(|e| format!("Failed to connect to NATS at {}: {}", topic.url, e))?;
+    let subscriber = client
+        .subscribe(topic.topic.clone())
+        .await
+        .map_err(|e| format!("Failed to subscribe to {}: {}", topic.topic, e))?;
+
+    // The client is held for as long as the stream, which owns the connection
+    Ok(stream::unfold(
+        (client, subscriber),
+        |(client, mut subscriber)| async move {
+            let message = subscriber.next().await?;
+            Some((Ok(message.payload.to_vec()), (client, subscriber)))
+        },
+    )
+    .boxed())
+}
+
+#[cfg(feature = "kafka")]
+async fn subscribe_kafka(
+    topic: &TopicConfig,
+) -> Result<BoxStream<'static, Result<Vec<u8>, String>>, String> {
+    use rdkafka::{
+        ClientConfig, Message,
+        consumer::{Consumer, StreamConsumer},
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // A consumer group of its own, so the subscription isn't handed a share
+    // of the partitions only
+    static CONSUMERS: AtomicU64 = AtomicU64::new(0);
+    let group_id = format!(
+        "portkey-{}-{}",
+        std::process::id(),
+        CONSUMERS.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &topic.url)
+        .set("group.id", &group_id)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "latest")
+        .create()
+        .map_err(|e| format!("Failed to connect to Kafka at {}: {}", topic.url, e))?;
+    consumer
+        .subscribe(&[topic.topic.as_str()])
+        .map_err(|e| format!("Failed to subscribe to {}: {}", topic.topic, e))?;
+
+    let name = topic.topic.clone();
+    Ok(stream::unfold(Some(consumer), move |consumer| {
+        let name = name.clone();
+        async move {
+            let consumer = consumer?;
+            match consumer.recv().await {
+                Ok(message) => {
+                    let payload = message.payload().unwrap_or_default().to_vec();
+                    drop(message);
+                    Some((Ok(payload), Some(consumer)))
+                }
+                // Reported once, ending the stream
+                Err(e) => Some((Err(format!("Failed to consume {}: {}", name, e)), None)),
+            }
+        }
+    })
+    .boxed())
+}
+
+#[cfg(not(feature = "kafka"))]
+async fn subscribe_kafka(
+    topic: &TopicConfig,
+) -> Result<BoxStream<'static, Result<Vec<u8>, String>>, String> {
+    Err(format!(
+        "Kafka topic {} needs the gateway built with the kafka feature",
+        topic.topic
+    ))
+}
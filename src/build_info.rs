@@ -0,0 +1,56 @@
+use chrono::DateTime;
+use serde_json::{Value, json};
+
+// Embedded by build.rs
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_SHA: &str = env!("PORTKEY_GIT_SHA");
+pub const RUSTC_VERSION: &str = env!("PORTKEY_RUSTC_VERSION");
+// Seconds since the epoch
+const BUILT_AT: &str = env!("PORTKEY_BUILT_AT");
+// Comma separated
+const FEATURES: &str = env!("PORTKEY_FEATURES");
+
+pub fn features() -> Vec<&'static str> {
+    FEATURES
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+// RFC 3339 timestamp of the build
+pub fn built_at() -> String {
+    BUILT_AT
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map(|built_at| built_at.to_rfc3339())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// As served by `/admin/version`
+pub fn build_info() -> Value {
+    json!({
+        "version": VERSION,
+        "gitSha": GIT_SHA,
+        "rustc": RUSTC_VERSION,
+        "features": features(),
+        "builtAt": built_at(),
+    })
+}
+
+// Printed when the gateway starts
+pub fn banner() -> String {
+    let features = features();
+    format!(
+        "portkey {} ({}, built {} with {}, features: {})",
+        VERSION,
+        GIT_SHA,
+        built_at(),
+        RUSTC_VERSION,
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    )
+}
@@ -0,0 +1,225 @@
+use graphql_parser::query::{
+    self, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::introspection::{IntrospectionSchema, is_introspection_field};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ComplexityConfig {
+    pub enabled: bool,
+    // Operations estimated above this cost are rejected, unset only reports it
+    pub max_cost: Option<u64>,
+    // Cost of a field without a weight of its own
+    pub default_field_cost: u64,
+    // "Type.field" or "Type" -> cost of resolving the field, or any field
+    // returning the type. Field weights take precedence.
+    pub weights: HashMap<String, u64>,
+    // Arguments giving the number of items a list field returns
+    pub list_size_arguments: Vec<String>,
+    // Assumed number of items for list fields without a size argument
+    pub default_list_size: u64,
+}
+
+impl Default for ComplexityConfig {
+    fn default() -> Self {
+        ComplexityConfig {
+            enabled: false,
+            max_cost: None,
+            default_field_cost: 1,
+            weights: HashMap::new(),
+            list_size_arguments: vec!["first".to_string(), "last".to_string(), "limit".to_string()],
+            default_list_size: 10,
+        }
+    }
+}
+
+impl ComplexityConfig {
+    // Upper bound on the cost of the selected operation: every field costs its
+    // weight, plus the cost of its selections times the number of items it
+    // returns. Fragments on different types are all counted and @skip/@include
+    // are ignored, so the estimate never undercounts.
+    pub fn estimate(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Option<Value>,
+        schema: &IntrospectionSchema,
+    ) -> u64 {
+        let Ok(doc) = query::parse_query::<String>(query) else {
+            return 0;
+        };
+
+        let fragments: HashMap<&str, &FragmentDefinition<String>> = doc
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
+
+        let operation = doc.definitions.iter().find_map(|def| match def {
+            Definition::Operation(OperationDefinition::SelectionSet(s))
+                if operation_name.is_none() =>
+            {
+                Some(("Query", s))
+            }
+            Definition::Operation(OperationDefinition::Query(q))
+                if operation_name.is_none() || q.name.as_deref() == operation_name =>
+            {
+                Some(("Query", &q.selection_set))
+            }
+            Definition::Operation(OperationDefinition::Mutation(m))
+                if operation_name.is_none() || m.name.as_deref() == operation_name =>
+            {
+                Some(("Mutation", &m.selection_set))
+            }
+            Definition::Operation(OperationDefinition::Subscription(s))
+                if operation_name.is_none() || s.name.as_deref() == operation_name =>
+            {
+                Some(("Subscription", &s.selection_set))
+            }
+            _ => None,
+        });
+
+        let Some((root_type, selection_set)) = operation else {
+            return 0;
+        };
+
+        let estimator = Estimator {
+            config: self,
+            schema,
+            fragments: &fragments,
+            variables,
+        };
+        estimator.selection_set_cost(selection_set, root_type, &mut Vec::new())
+    }
+}
+
+struct Estimator<'e, 'q> {
+    config: &'e ComplexityConfig,
+    schema: &'e IntrospectionSchema,
+    fragments: &'e HashMap<&'q str, &'e FragmentDefinition<'q, String>>,
+    variables: &'e Option<Value>,
+}
+
+impl<'e, 'q> Estimator<'e, 'q> {
+    fn selection_set_cost(
+        &self,
+        selection_set: &'e SelectionSet<'q, String>,
+        parent_type: &str,
+        visited_fragments: &mut Vec<&'e str>,
+    ) -> u64 {
+        let mut cost: u64 = 0;
+
+        for selection in &selection_set.items {
+            let selection_cost = match selection {
+                Selection::Field(field) => self.field_cost(field, parent_type, visited_fragments),
+                Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                        None => parent_type,
+                    };
+                    self.selection_set_cost(&fragment.selection_set, type_name, visited_fragments)
+                }
+                Selection::FragmentSpread(spread) => {
+                    match self.fragments.get(spread.fragment_name.as_str()) {
+                        Some(fragment) if !visited_fragments.contains(&fragment.name.as_str()) => {
+                            let TypeCondition::On(type_name) = &fragment.type_condition;
+                            visited_fragments.push(&fragment.name);
+                            let cost = self.selection_set_cost(
+                                &fragment.selection_set,
+                                type_name,
+                                visited_fragments,
+                            );
+                            visited_fragments.pop();
+                            cost
+                        }
+                        _ => 0,
+                    }
+                }
+            };
+            cost = cost.saturating_add(selection_cost);
+        }
+
+        cost
+    }
+
+    fn field_cost(
+        &self,
+        field: &'e query::Field<'q, String>,
+        parent_type: &str,
+        visited_fragments: &mut Vec<&'e str>,
+    ) -> u64 {
+        // Answered by the gateway without touching a subgraph
+        if field.name == "__typename"
+            || (parent_type == "Query" && is_introspection_field(&field.name))
+        {
+            return 0;
+        }
+
+        let field_type = self
+            .schema
+            .get_type(parent_type)
+            .and_then(|ty| ty["fields"].as_array())
+            .and_then(|fields| fields.iter().find(|f| f["name"] == field.name.as_str()))
+            .map(|definition| definition["type"].clone())
+            .unwrap_or(Value::Null);
+        let type_name = named_type(&field_type);
+
+        let weight = self
+            .config
+            .weights
+            .get(&format!("{}.{}", parent_type, field.name))
+            .or_else(|| self.config.weights.get(type_name))
+            .copied()
+            .unwrap_or(self.config.default_field_cost);
+
+        let items = match self.list_size(field) {
+            Some(size) => size,
+            None if is_list(&field_type) => self.config.default_list_size,
+            None => 1,
+        };
+
+        let selections =
+            self.selection_set_cost(&field.selection_set, type_name, visited_fragments);
+        weight.saturating_add(items.saturating_mul(selections))
+    }
+
+    fn list_size(&self, field: &query::Field<String>) -> Option<u64> {
+        field.arguments.iter().find_map(|(name, value)| {
+            if !self.config.list_size_arguments.contains(name) {
+                return None;
+            }
+            match value {
+                query::Value::Int(n) => n.as_i64().map(|n| n.max(0) as u64),
+                query::Value::Variable(var_name) => self
+                    .variables
+                    .as_ref()
+                    .and_then(|vars| vars.get(var_name))
+                    .and_then(Value::as_u64),
+                _ => None,
+            }
+        })
+    }
+}
+
+pub(crate) fn named_type(type_ref: &Value) -> &str {
+    match type_ref["kind"].as_str() {
+        Some("NON_NULL" | "LIST") => named_type(&type_ref["ofType"]),
+        _ => type_ref["name"].as_str().unwrap_or_default(),
+    }
+}
+
+fn is_list(type_ref: &Value) -> bool {
+    match type_ref["kind"].as_str() {
+        Some("LIST") => true,
+        Some("NON_NULL") => is_list(&type_ref["ofType"]),
+        _ => false,
+    }
+}
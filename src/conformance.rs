@@ -0,0 +1,393 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+use crate::query_executor::QueryExecutor;
+use crate::query_planner::QueryPlanner;
+use crate::schema_registry::SchemaRegistry;
+use crate::{FederatedSchema, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner};
+
+// Canonical cases a `SchemaRegistry`, `QueryPlanner` or `QueryExecutor` has
+// to pass to stand in for the built-in ones. Every check runs its cases
+// against two services, `products` owning the `Product` entity and `reviews`
+// extending it, and fails with the name of the first case that didn't pass.
+
+pub const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+pub const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews(first: Int): [Review]
+}
+"#;
+
+const MULTI_SERVICE_QUERY: &str = "{ products { name reviews { body } } }";
+
+const FRAGMENT_QUERY: &str = r#"
+query {
+    products { ...Details }
+}
+
+fragment Details on Product {
+    name
+    reviews { ...Review }
+}
+
+fragment Review on Review { body }
+"#;
+
+const VARIABLES_QUERY: &str =
+    "query($n: Int, $unused: String) { products { name reviews(first: $n) { body } } }";
+
+// `registry` is expected to start out empty
+pub async fn check_schema_registry(
+    registry: &mut (dyn SchemaRegistry + Send + Sync),
+) -> Result<(), String> {
+    for (name, schema) in [("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)] {
+        registry
+            .register_service(service(name, &format!("http://{}/graphql", name), schema))
+            .await
+            .map_err(|e| format!("registers the {} service: {}", name, e))?;
+    }
+    let schema = registry.get_schema().await?;
+
+    check(
+        "serves every registered service",
+        schema.services.contains_key("products") && schema.services.contains_key("reviews"),
+        schema.services.keys().collect::<Vec<_>>(),
+    )?;
+    let owners = schema.type_to_service_map.get("Product");
+    check(
+        "maps an entity to every service resolving it",
+        owners.is_some_and(|owners| {
+            owners.iter().any(|owner| owner == "products")
+                && owners.iter().any(|owner| owner == "reviews")
+        }),
+        owners,
+    )?;
+    check(
+        "records the key of an entity",
+        schema.entity_keys.get("Product").map(String::as_str) == Some("id"),
+        schema.entity_keys.get("Product"),
+    )?;
+    check(
+        "records the type of every field",
+        schema.field_types.get("Query.products").map(String::as_str) == Some("Product")
+            && schema
+                .field_types
+                .get("Product.reviews")
+                .map(String::as_str)
+                == Some("Review"),
+        &schema.field_types,
+    )?;
+
+    // A schema that doesn't parse is refused when registered, or fails to
+    // compose. Registries still serving a schema serve the last good one.
+    let refused = registry
+        .register_service(service("broken", "http://broken/graphql", "type Query {"))
+        .await
+        .is_err();
+    let schema = registry.get_schema().await;
+    let reported = registry.composition_error().await.is_some();
+    check(
+        "refuses services with invalid schemas",
+        refused || reported || schema.is_err(),
+        "the broken service was accepted",
+    )?;
+    if let Ok(schema) = schema {
+        check(
+            "keeps serving the last valid schema",
+            schema.services.contains_key("products") && !schema.services.contains_key("broken"),
+            schema.services.keys().collect::<Vec<_>>(),
+        )?;
+    }
+
+    Ok(())
+}
+
+pub async fn check_query_planner(planner: &dyn QueryPlanner) -> Result<(), String> {
+    let schema = compose("http://products/graphql", "http://reviews/graphql").await?;
+    let plan = |query: &'static str, variables: Option<Value>, operation: Option<&'static str>| {
+        planner.plan_query(query, &schema, variables, operation)
+    };
+
+    let single = plan("{ products { id name } }", None, None).await?;
+    let fetches = single.fetches();
+    check(
+        "sends a query resolved by one service in a single fetch",
+        fetches.len() == 1 && fetches[0].fetch.service_name == "products",
+        fetches.iter().map(|f| &f.fetch).collect::<Vec<_>>(),
+    )?;
+
+    for (case, query) in [
+        (
+            "fetches the fields of other services as entities",
+            MULTI_SERVICE_QUERY,
+        ),
+        ("resolves fragments across services", FRAGMENT_QUERY),
+    ] {
+        let multi = plan(query, None, None).await?;
+        let fetches = multi.fetches();
+        let root = fetches
+            .iter()
+            .find(|f| f.fetch.service_name == "products" && f.path.is_empty());
+        let entities = fetches.iter().find(|f| {
+            f.fetch.service_name == "reviews"
+                && f.path == ["products".to_string()]
+                && f.fetch
+                    .entity
+                    .as_ref()
+                    .is_some_and(|entity| entity.type_name == "Product")
+        });
+        check(
+            case,
+            root.is_some_and(|root| {
+                let query = root.fetch.query.as_str();
+                query.contains("name") && query.contains("id") && !query.contains("reviews")
+            }) && entities.is_some_and(|entities| entities.fetch.query.contains("body"))
+                && fetches.iter().all(|f| f.fetch.operation().is_ok()),
+            fetches.iter().map(|f| &f.fetch).collect::<Vec<_>>(),
+        )?;
+    }
+
+    let with_variables = plan(
+        VARIABLES_QUERY,
+        Some(json!({ "n": 1, "unused": "x" })),
+        None,
+    )
+    .await?;
+    let variables = with_variables
+        .fetches()
+        .iter()
+        .map(|f| (f.fetch.service_name.clone(), f.fetch.variables.clone()))
+        .collect::<Vec<_>>();
+    check(
+        "passes every fetch the variables it uses",
+        variables.contains(&("products".to_string(), json!({})))
+            && variables.contains(&("reviews".to_string(), json!({ "n": 1 }))),
+        &variables,
+    )?;
+
+    let named = plan(
+        "query A { products { id } } query B { products { name } }",
+        None,
+        Some("B"),
+    )
+    .await?;
+    let fetches = named.fetches();
+    check(
+        "plans the operation named by the request",
+        fetches.len() == 1 && fetches[0].fetch.query.contains("name"),
+        fetches.iter().map(|f| &f.fetch).collect::<Vec<_>>(),
+    )?;
+
+    for (case, query, operation) in [
+        ("rejects queries that don't parse", "{ products {", None),
+        (
+            "rejects unknown fragments",
+            "{ products { ...Missing } }",
+            None,
+        ),
+        (
+            "rejects unknown operation names",
+            "query A { products { id } }",
+            Some("B"),
+        ),
+    ] {
+        let result = plan(query, None, operation).await;
+        check(case, result.is_err(), result.map(|plan| plan.node))?;
+    }
+
+    Ok(())
+}
+
+pub async fn check_query_executor(executor: &dyn QueryExecutor) -> Result<(), String> {
+    let products = CannedService::start(|_| {
+        json!({ "data": { "products": [
+            { "__typename": "Product", "id": "1", "name": "Lamp" },
+            { "__typename": "Product", "id": "2", "name": "Desk" },
+        ] } })
+    })
+    .await;
+    let reviews = CannedService::start(|request| {
+        let entities = request["variables"]["representations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|representation| {
+                json!({
+                    "__typename": "Product",
+                    "reviews": [{ "body": format!("Review of {}", representation["id"]) }],
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+    let failing = CannedService::start(
+        |_| json!({ "data": null, "errors": [{ "message": "Reviews are unavailable" }] }),
+    )
+    .await;
+
+    let schema = compose(&products.url, &reviews.url).await?;
+    let execute = |schema: FederatedSchema, query: &'static str, variables: Option<Value>| async move {
+        let plan = SimpleQueryPlanner::new()
+            .plan_query(query, &schema, variables, None)
+            .await?;
+        executor.execute_plan(plan, &schema, None).await
+    };
+
+    let expected = json!({ "products": [
+        { "name": "Lamp", "reviews": [{ "body": "Review of \"1\"" }] },
+        { "name": "Desk", "reviews": [{ "body": "Review of \"2\"" }] },
+    ] });
+    for (case, query) in [
+        ("merges the entities of other services", MULTI_SERVICE_QUERY),
+        ("answers the selection of fragments", FRAGMENT_QUERY),
+    ] {
+        let response = execute(schema.clone(), query, None).await?;
+        check(case, response.get("data") == Some(&expected), &response)?;
+    }
+
+    execute(schema.clone(), VARIABLES_QUERY, Some(json!({ "n": 1 }))).await?;
+    let sent = reviews.requests();
+    check(
+        "sends services the variables of their fetches",
+        sent.last()
+            .is_some_and(|request| request["variables"]["n"] == 1),
+        &sent,
+    )?;
+
+    let schema = compose(&products.url, &failing.url).await?;
+    let response = execute(schema, MULTI_SERVICE_QUERY, None).await?;
+    check(
+        "reports the errors of services along with the data resolved",
+        response["data"]["products"][0]["name"] == "Lamp"
+            && response["errors"].as_array().is_some_and(|errors| {
+                errors
+                    .iter()
+                    .any(|error| error["message"] == "Reviews are unavailable")
+            }),
+        &response,
+    )?;
+
+    // Nothing listens on port 9 of the loopback interface
+    let schema = compose("http://127.0.0.1:9/graphql", &reviews.url).await?;
+    let response = execute(schema, MULTI_SERVICE_QUERY, None).await;
+    check(
+        "fails or reports an error for unreachable services",
+        response
+            .as_ref()
+            .map_or(true, |response| response.get("errors").is_some()),
+        &response,
+    )?;
+
+    Ok(())
+}
+
+fn check(case: &str, passed: bool, found: impl Debug) -> Result<(), String> {
+    if passed {
+        Ok(())
+    } else {
+        Err(format!("{}: got {:?}", case, found))
+    }
+}
+
+fn service(name: &str, url: &str, schema: &str) -> ServiceConfig {
+    ServiceConfig {
+        name: name.to_string(),
+        url: url.to_string(),
+        schema: schema.to_string(),
+        ..Default::default()
+    }
+}
+
+async fn compose(products_url: &str, reviews_url: &str) -> Result<FederatedSchema, String> {
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(service("products", products_url, PRODUCTS_SCHEMA))
+        .await?;
+    registry
+        .register_service(service("reviews", reviews_url, REVIEWS_SCHEMA))
+        .await?;
+    registry.get_schema().await
+}
+
+type Handler = dyn Fn(&Value) -> Value + Send + Sync;
+
+// A GraphQL service on a local port answering every request with `handler`,
+// recording the request bodies it received
+struct CannedService {
+    url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl CannedService {
+    async fn start(handler: impl Fn(&Value) -> Value + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .expect("Failed to bind a local port");
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let handler = Arc::clone(&handler);
+                        let recorded = Arc::clone(&recorded);
+                        async move {
+                            let body = req.collect().await.map(|b| b.to_bytes());
+                            let body: Value = body
+                                .ok()
+                                .and_then(|body| serde_json::from_slice(&body).ok())
+                                .unwrap_or(Value::Null);
+                            let response = handler(&body);
+                            recorded.lock().unwrap().push(body);
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .header("Content-Type", "application/json")
+                                    .body(Full::new(Bytes::from(response.to_string())))
+                                    .unwrap(),
+                            )
+                        }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        CannedService { url, requests }
+    }
+
+    fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,604 @@
+use graphql_parser::query::{
+    self, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition,
+};
+use graphql_parser::schema::{self, Type};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::complexity::named_type;
+use crate::introspection::IntrospectionSchema;
+
+const PAGE_INFO_SDL: &str = r#"
+type PageInfo {
+    hasNextPage: Boolean!
+    hasPreviousPage: Boolean!
+    startCursor: String
+    endCursor: String
+}
+"#;
+
+// List fields the gateway exposes as Relay connections for subgraphs that
+// return plain lists. Clients page with `first` and `after`; the gateway
+// fetches the whole list and slices it, its cursors being offsets into it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    // "Type.field" of the list fields, e.g. "Query.products"
+    pub fields: Vec<String>,
+    // Items of a page when the client doesn't pass `first`
+    pub default_page_size: usize,
+    // `first` is capped to this many items
+    pub max_page_size: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            fields: Vec::new(),
+            default_page_size: 20,
+            max_page_size: 100,
+        }
+    }
+}
+
+// A connection the operation selects, sliced out of the list the subgraph
+// returned once the response is in
+#[derive(Debug)]
+pub struct ConnectionSlice {
+    // Response keys leading to the list, lists along the way are traversed
+    path: Vec<String>,
+    node_type: String,
+    first: usize,
+    // Offset of the item the page starts after
+    after: Option<usize>,
+    selections: Vec<Projection>,
+}
+
+// The client's selection on a connection, edge or page info object. Nodes
+// are taken as the executor shaped them.
+#[derive(Debug)]
+struct Projection {
+    response_key: String,
+    field_name: String,
+    selections: Vec<Projection>,
+}
+
+impl ConnectionConfig {
+    fn is_connection(&self, type_name: &str, field_name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|field| field.split_once('.') == Some((type_name, field_name)))
+    }
+
+    // Turns the configured list fields of the subgraph documents into
+    // connections taking `first` and `after`, and adds the connection, edge
+    // and page info types, for the schema clients see. The subgraphs keep
+    // being queried for plain lists.
+    pub fn add_connection_types(&self, documents: &mut [schema::Document<'static, String>]) {
+        use graphql_parser::schema::{Definition, InputValue, TypeDefinition, TypeExtension};
+
+        let mut node_types = BTreeSet::new();
+        for document in documents.iter_mut() {
+            for definition in &mut document.definitions {
+                let (type_name, fields) = match definition {
+                    Definition::TypeDefinition(TypeDefinition::Object(obj)) => {
+                        (&obj.name, &mut obj.fields)
+                    }
+                    Definition::TypeExtension(TypeExtension::Object(ext)) => {
+                        (&ext.name, &mut ext.fields)
+                    }
+                    _ => continue,
+                };
+                for field in fields {
+                    if !self.is_connection(type_name, &field.name) {
+                        continue;
+                    }
+                    let Some(node_type) = list_item_type(&field.field_type) else {
+                        continue;
+                    };
+
+                    field.field_type = Type::NonNullType(Box::new(Type::NamedType(format!(
+                        "{}Connection",
+                        node_type
+                    ))));
+                    for (name, value_type) in [("first", "Int"), ("after", "String")] {
+                        if !field.arguments.iter().any(|argument| argument.name == name) {
+                            field.arguments.push(InputValue {
+                                position: field.position,
+                                description: None,
+                                name: name.to_string(),
+                                value_type: Type::NamedType(value_type.to_string()),
+                                default_value: None,
+                                directives: Vec::new(),
+                            });
+                        }
+                    }
+                    node_types.insert(node_type);
+                }
+            }
+        }
+        if node_types.is_empty() {
+            return;
+        }
+
+        let mut sdl = PAGE_INFO_SDL.to_string();
+        for node_type in node_types {
+            sdl.push_str(&format!(
+                "type {0}Connection {{ edges: [{0}Edge!]! pageInfo: PageInfo! }}\n\
+                 type {0}Edge {{ cursor: String! node: {0} }}\n",
+                node_type
+            ));
+        }
+        if let (Ok(document), Some(first)) =
+            (schema::parse_schema::<String>(&sdl), documents.first_mut())
+        {
+            first.definitions.extend(document.into_static().definitions);
+        }
+    }
+
+    // The query to plan, selecting plain lists in place of the connections
+    // the operation selects, together with the slices to cut from them.
+    // `None` when the operation selects no connection.
+    pub fn rewrite(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Option<Value>,
+        schema: &IntrospectionSchema,
+    ) -> Result<Option<(String, Vec<ConnectionSlice>)>, String> {
+        if self.fields.is_empty() {
+            return Ok(None);
+        }
+        let Ok(document) = query::parse_query::<String>(query) else {
+            return Ok(None);
+        };
+        let mut document = document.into_static();
+
+        let fragments: HashMap<&str, &FragmentDefinition<String>> = document
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
+        let Some((root_type, selection_set)) =
+            document.definitions.iter().find_map(|def| match def {
+                Definition::Operation(operation) => operation_root(operation, operation_name),
+                _ => None,
+            })
+        else {
+            return Ok(None);
+        };
+
+        let mut slices = Vec::new();
+        let collector = SliceCollector {
+            config: self,
+            schema,
+            fragments: &fragments,
+            variables,
+        };
+        collector.collect(
+            selection_set,
+            root_type,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut slices,
+        )?;
+        if slices.is_empty() {
+            return Ok(None);
+        }
+
+        for definition in &mut document.definitions {
+            let (type_name, selection_set) = match definition {
+                Definition::Operation(operation) => match operation {
+                    OperationDefinition::SelectionSet(s) => ("Query".to_string(), s),
+                    OperationDefinition::Query(q) => ("Query".to_string(), &mut q.selection_set),
+                    OperationDefinition::Mutation(m) => {
+                        ("Mutation".to_string(), &mut m.selection_set)
+                    }
+                    OperationDefinition::Subscription(s) => {
+                        ("Subscription".to_string(), &mut s.selection_set)
+                    }
+                },
+                Definition::Fragment(fragment) => {
+                    let TypeCondition::On(type_name) = &fragment.type_condition;
+                    (type_name.clone(), &mut fragment.selection_set)
+                }
+            };
+            self.rewrite_selection_set(selection_set, &type_name, schema)?;
+        }
+
+        // Inner connections are cut first, before the lists holding them
+        // are moved into edges
+        slices.sort_by_key(|slice| std::cmp::Reverse(slice.path.len()));
+        Ok(Some((document.to_string(), slices)))
+    }
+
+    fn rewrite_selection_set(
+        &self,
+        selection_set: &mut SelectionSet<'static, String>,
+        parent_type: &str,
+        schema: &IntrospectionSchema,
+    ) -> Result<(), String> {
+        for selection in &mut selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.is_connection(parent_type, &field.name) {
+                        let field_type = field_type(schema, parent_type, &field.name);
+                        self.rewrite_selection_set(&mut field.selection_set, &field_type, schema)?;
+                        continue;
+                    }
+
+                    let node_type = node_type(&field_type(schema, parent_type, &field.name));
+                    let mut nodes = SelectionSet {
+                        span: field.selection_set.span,
+                        items: node_selections(&field.selection_set, parent_type, &field.name)?
+                            .into_iter()
+                            .flat_map(|nodes| nodes.items.iter().cloned())
+                            .collect(),
+                    };
+                    self.rewrite_selection_set(&mut nodes, &node_type, schema)?;
+                    if nodes.items.is_empty() {
+                        nodes.items.push(Selection::Field(query::Field {
+                            position: field.position,
+                            alias: None,
+                            name: "__typename".to_string(),
+                            arguments: Vec::new(),
+                            directives: Vec::new(),
+                            selection_set: SelectionSet {
+                                span: field.selection_set.span,
+                                items: Vec::new(),
+                            },
+                        }));
+                    }
+                    field
+                        .arguments
+                        .retain(|(name, _)| name != "first" && name != "after");
+                    field.selection_set = nodes;
+                }
+                Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.clone(),
+                        None => parent_type.to_string(),
+                    };
+                    self.rewrite_selection_set(&mut fragment.selection_set, &type_name, schema)?;
+                }
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+struct SliceCollector<'c, 'q> {
+    config: &'c ConnectionConfig,
+    schema: &'c IntrospectionSchema,
+    fragments: &'c HashMap<&'q str, &'q FragmentDefinition<'static, String>>,
+    variables: &'c Option<Value>,
+}
+
+impl<'c, 'q> SliceCollector<'c, 'q> {
+    fn collect(
+        &self,
+        selection_set: &'q SelectionSet<'static, String>,
+        parent_type: &str,
+        path: &mut Vec<String>,
+        visited_fragments: &mut Vec<&'q str>,
+        slices: &mut Vec<ConnectionSlice>,
+    ) -> Result<(), String> {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    let response_key = field.alias.as_ref().unwrap_or(&field.name);
+                    let field_type = field_type(self.schema, parent_type, &field.name);
+                    path.push(response_key.clone());
+
+                    if self.config.is_connection(parent_type, &field.name) {
+                        let node_type = node_type(&field_type);
+                        slices.push(ConnectionSlice {
+                            path: path.clone(),
+                            node_type: node_type.clone(),
+                            first: self.first(field)?,
+                            after: self.after(field)?,
+                            selections: projections(&field.selection_set),
+                        });
+                        for nodes in
+                            node_selections(&field.selection_set, parent_type, &field.name)?
+                        {
+                            self.collect(nodes, &node_type, path, visited_fragments, slices)?;
+                        }
+                    } else {
+                        self.collect(
+                            &field.selection_set,
+                            &field_type,
+                            path,
+                            visited_fragments,
+                            slices,
+                        )?;
+                    }
+                    path.pop();
+                }
+                Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                        None => parent_type,
+                    };
+                    self.collect(
+                        &fragment.selection_set,
+                        type_name,
+                        path,
+                        visited_fragments,
+                        slices,
+                    )?;
+                }
+                Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = self.fragments.get(spread.fragment_name.as_str())
+                        && !visited_fragments.contains(&fragment.name.as_str())
+                    {
+                        let TypeCondition::On(type_name) = &fragment.type_condition;
+                        visited_fragments.push(&fragment.name);
+                        self.collect(
+                            &fragment.selection_set,
+                            type_name,
+                            path,
+                            visited_fragments,
+                            slices,
+                        )?;
+                        visited_fragments.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn argument(&self, field: &query::Field<'static, String>, name: &str) -> Value {
+        match field
+            .arguments
+            .iter()
+            .find(|(argument, _)| argument == name)
+        {
+            Some((_, query::Value::Variable(variable))) => self
+                .variables
+                .as_ref()
+                .and_then(|variables| variables.get(variable))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Some((_, query::Value::Int(n))) => n.as_i64().map(Value::from).unwrap_or(Value::Null),
+            Some((_, query::Value::String(s))) => Value::String(s.clone()),
+            _ => Value::Null,
+        }
+    }
+
+    fn first(&self, field: &query::Field<'static, String>) -> Result<usize, String> {
+        let first = match self.argument(field, "first") {
+            Value::Null => self.config.default_page_size,
+            value => value
+                .as_u64()
+                .ok_or_else(|| format!("Argument first of {} must not be negative", field.name))?
+                as usize,
+        };
+        Ok(first.min(self.config.max_page_size))
+    }
+
+    fn after(&self, field: &query::Field<'static, String>) -> Result<Option<usize>, String> {
+        match self.argument(field, "after") {
+            Value::String(cursor) => decode_cursor(&cursor)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid cursor {}", cursor)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl ConnectionSlice {
+    // Replaces the lists in `data` by the connections cut from them
+    pub fn apply(&self, data: &mut Value) {
+        visit(data, &self.path, &mut |list| {
+            let connection = self.connection(list.take());
+            *list = connection;
+        });
+    }
+
+    fn connection(&self, list: Value) -> Value {
+        let Value::Array(items) = list else {
+            return Value::Null;
+        };
+
+        let total = items.len();
+        let start = self.after.map_or(0, |after| after + 1).min(total);
+        let end = (start + self.first).min(total);
+        let edges: Vec<Value> = items
+            .into_iter()
+            .enumerate()
+            .skip(start)
+            .take(end - start)
+            .map(|(offset, node)| {
+                json!({
+                    "__typename": format!("{}Edge", self.node_type),
+                    "cursor": encode_cursor(offset),
+                    "node": node,
+                })
+            })
+            .collect();
+        let connection = json!({
+            "__typename": format!("{}Connection", self.node_type),
+            "pageInfo": {
+                "__typename": "PageInfo",
+                "hasNextPage": end < total,
+                "hasPreviousPage": start > 0,
+                "startCursor": edges.first().map(|edge| edge["cursor"].clone()),
+                "endCursor": edges.last().map(|edge| edge["cursor"].clone()),
+            },
+            "edges": edges,
+        });
+
+        project(&connection, &self.selections)
+    }
+}
+
+fn visit(value: &mut Value, path: &[String], f: &mut impl FnMut(&mut Value)) {
+    let Some((key, rest)) = path.split_first() else {
+        f(value);
+        return;
+    };
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                visit(item, path, f);
+            }
+        }
+        Value::Object(obj) => {
+            if let Some(value) = obj.get_mut(key) {
+                visit(value, rest, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn project(value: &Value, selections: &[Projection]) -> Value {
+    match value {
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|item| project(item, selections)).collect())
+        }
+        Value::Object(obj) => {
+            let mut projected = Map::new();
+            for selection in selections {
+                let value = obj.get(&selection.field_name).unwrap_or(&Value::Null);
+                let value = if selection.field_name == "node" || selection.selections.is_empty() {
+                    value.clone()
+                } else {
+                    project(value, &selection.selections)
+                };
+                projected.insert(selection.response_key.clone(), value);
+            }
+            Value::Object(projected)
+        }
+        other => other.clone(),
+    }
+}
+
+fn projections(selection_set: &SelectionSet<'static, String>) -> Vec<Projection> {
+    selection_set
+        .items
+        .iter()
+        .filter_map(|selection| match selection {
+            Selection::Field(field) => Some(Projection {
+                response_key: field.alias.as_ref().unwrap_or(&field.name).clone(),
+                field_name: field.name.clone(),
+                selections: if field.name == "node" {
+                    Vec::new()
+                } else {
+                    projections(&field.selection_set)
+                },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// The selections of every `edges { node { ... } }` of a connection, which
+// become the selections of the list
+fn node_selections<'q>(
+    selection_set: &'q SelectionSet<'static, String>,
+    parent_type: &str,
+    field_name: &str,
+) -> Result<Vec<&'q SelectionSet<'static, String>>, String> {
+    let unsupported = || {
+        format!(
+            "Fragments on the connection {}.{} are not supported",
+            parent_type, field_name
+        )
+    };
+
+    let mut nodes = Vec::new();
+    for selection in &selection_set.items {
+        let Selection::Field(field) = selection else {
+            return Err(unsupported());
+        };
+        if field.name != "edges" {
+            continue;
+        }
+        for edge_selection in &field.selection_set.items {
+            let Selection::Field(edge_field) = edge_selection else {
+                return Err(unsupported());
+            };
+            if edge_field.name == "node" {
+                nodes.push(&edge_field.selection_set);
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+fn operation_root<'a>(
+    operation: &'a OperationDefinition<'static, String>,
+    operation_name: Option<&str>,
+) -> Option<(&'static str, &'a SelectionSet<'static, String>)> {
+    match operation {
+        OperationDefinition::SelectionSet(s) if operation_name.is_none() => Some(("Query", s)),
+        OperationDefinition::Query(q)
+            if operation_name.is_none() || q.name.as_deref() == operation_name =>
+        {
+            Some(("Query", &q.selection_set))
+        }
+        OperationDefinition::Mutation(m)
+            if operation_name.is_none() || m.name.as_deref() == operation_name =>
+        {
+            Some(("Mutation", &m.selection_set))
+        }
+        OperationDefinition::Subscription(s)
+            if operation_name.is_none() || s.name.as_deref() == operation_name =>
+        {
+            Some(("Subscription", &s.selection_set))
+        }
+        _ => None,
+    }
+}
+
+// Named type of a field in the schema clients see
+fn field_type(schema: &IntrospectionSchema, parent_type: &str, field_name: &str) -> String {
+    schema
+        .get_type(parent_type)
+        .and_then(|ty| ty["fields"].as_array())
+        .and_then(|fields| fields.iter().find(|f| f["name"] == field_name))
+        .map(|definition| named_type(&definition["type"]).to_string())
+        .unwrap_or_default()
+}
+
+fn node_type(connection_type: &str) -> String {
+    connection_type
+        .strip_suffix("Connection")
+        .unwrap_or(connection_type)
+        .to_string()
+}
+
+fn list_item_type(field_type: &Type<String>) -> Option<String> {
+    match field_type {
+        Type::NonNullType(inner) => list_item_type(inner),
+        Type::ListType(item) => {
+            let mut item = item.as_ref();
+            while let Type::NonNullType(inner) | Type::ListType(inner) = item {
+                item = inner;
+            }
+            match item {
+                Type::NamedType(name) => Some(name.clone()),
+                _ => None,
+            }
+        }
+        Type::NamedType(_) => None,
+    }
+}
+
+pub fn encode_cursor(offset: usize) -> String {
+    hex::encode(format!("cursor:{}", offset))
+}
+
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    let decoded = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+    decoded.strip_prefix("cursor:")?.parse().ok()
+}
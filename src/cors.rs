@@ -0,0 +1,166 @@
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    // Request path -> policy, paths without a policy reject every preflight
+    pub routes: HashMap<String, CorsPolicy>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsPolicy {
+    // Exact origins, or "*" to allow any
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    // Matched case-insensitively against Access-Control-Request-Headers
+    pub allowed_headers: Vec<String>,
+    // How long browsers may cache a successful preflight, in seconds
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        CorsPolicy {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: Vec::new(),
+            max_age: None,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let graphql = CorsPolicy {
+            allowed_methods: vec!["POST".to_string()],
+            allowed_headers: [
+                "Content-Type",
+                "Authorization",
+                "x-api-key",
+                "x-token",
+                "apollographql-client-name",
+                "x-client-name",
+                "x-portkey-explain",
+            ]
+            .iter()
+            .map(|header| header.to_string())
+            .collect(),
+            max_age: Some(600),
+            ..Default::default()
+        };
+
+        CorsConfig {
+            routes: HashMap::from([
+                ("/graphql".to_string(), graphql.clone()),
+                ("/plan".to_string(), graphql),
+                ("/graphiql".to_string(), CorsPolicy::default()),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Preflight {
+    // Headers to send back with a 204
+    Allowed(Vec<(&'static str, String)>),
+    // Answered with a 403
+    Rejected(String),
+}
+
+// Preflight responses depend on these request headers, so caches must key on them
+pub const PREFLIGHT_VARY: &str =
+    "Origin, Access-Control-Request-Method, Access-Control-Request-Headers";
+
+impl CorsConfig {
+    pub fn preflight(&self, path: &str, headers: &HeaderMap) -> Preflight {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let Some(policy) = self.routes.get(path) else {
+            return Preflight::Rejected(format!("CORS is not enabled for {}", path));
+        };
+        let Some(origin) = header("Origin") else {
+            return Preflight::Rejected("Missing Origin header".to_string());
+        };
+        let Some(allow_origin) = policy.allow_origin(origin) else {
+            return Preflight::Rejected(format!("Origin {} is not allowed", origin));
+        };
+
+        let Some(method) = header("Access-Control-Request-Method") else {
+            return Preflight::Rejected("Missing Access-Control-Request-Method header".to_string());
+        };
+        if !policy
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+        {
+            return Preflight::Rejected(format!("Method {} is not allowed", method));
+        }
+
+        let requested_headers = header("Access-Control-Request-Headers").unwrap_or_default();
+        for requested in requested_headers
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if !policy
+                .allowed_headers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(requested))
+            {
+                return Preflight::Rejected(format!("Header {} is not allowed", requested));
+            }
+        }
+
+        let mut response_headers = vec![
+            ("Access-Control-Allow-Origin", allow_origin),
+            (
+                "Access-Control-Allow-Methods",
+                policy.allowed_methods.join(", "),
+            ),
+            ("Vary", PREFLIGHT_VARY.to_string()),
+        ];
+        if !policy.allowed_headers.is_empty() {
+            response_headers.push((
+                "Access-Control-Allow-Headers",
+                policy.allowed_headers.join(", "),
+            ));
+        }
+        if let Some(max_age) = policy.max_age {
+            response_headers.push(("Access-Control-Max-Age", max_age.to_string()));
+        }
+
+        Preflight::Allowed(response_headers)
+    }
+
+    // CORS headers for a regular (non-preflight) response on `path`
+    pub fn response_headers(&self, path: &str, headers: &HeaderMap) -> Vec<(&'static str, String)> {
+        let allow_origin = headers
+            .get("Origin")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|origin| self.routes.get(path)?.allow_origin(origin));
+
+        match allow_origin {
+            Some(allow_origin) => vec![
+                ("Access-Control-Allow-Origin", allow_origin),
+                ("Vary", "Origin".to_string()),
+            ],
+            None => vec![("Vary", "Origin".to_string())],
+        }
+    }
+}
+
+impl CorsPolicy {
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            Some("*".to_string())
+        } else {
+            self.allowed_origins
+                .iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .map(|_| origin.to_string())
+        }
+    }
+}
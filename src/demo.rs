@@ -0,0 +1,287 @@
+use bytes::Bytes;
+use graphql_parser::query::{self, Definition, OperationDefinition, Selection, SelectionSet};
+use http_body_util::{BodyExt, Full};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{Map, Value, json};
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+use crate::ServiceConfig;
+
+const USERS_SCHEMA: &str = include_str!(concat!(env!("OUT_SCHEMAS"), "/service_1.graphql"));
+const PRODUCTS_SCHEMA: &str = include_str!(concat!(env!("OUT_SCHEMAS"), "/service_2.graphql"));
+
+// An in-memory collection exposed with the shape shared by the example
+// subgraphs: `<single>(id)`, `<list>`, `create<Type>(input)` and
+// `delete<Type>(id)`.
+struct Collection {
+    type_name: &'static str,
+    single_field: &'static str,
+    list_field: &'static str,
+    items: Mutex<Vec<Value>>,
+}
+
+impl Collection {
+    fn users() -> Self {
+        Collection {
+            type_name: "User",
+            single_field: "user",
+            list_field: "users",
+            items: Mutex::new(vec![
+                json!({ "id": "1", "name": "John Doe", "email": "john@example.com" }),
+                json!({ "id": "2", "name": "Jane Smith", "email": "jane@example.com" }),
+            ]),
+        }
+    }
+
+    fn products() -> Self {
+        Collection {
+            type_name: "Product",
+            single_field: "product",
+            list_field: "products",
+            items: Mutex::new(vec![
+                json!({ "id": "1", "name": "Laptop", "price": 999.99 }),
+                json!({ "id": "2", "name": "Smartphone", "price": 699.99 }),
+            ]),
+        }
+    }
+
+    fn execute(&self, body: &Value) -> Value {
+        match self.try_execute(body) {
+            Ok(data) => json!({ "data": data }),
+            Err(e) => json!({ "errors": [{ "message": e }] }),
+        }
+    }
+
+    fn try_execute(&self, body: &Value) -> Result<Value, String> {
+        let query = body["query"].as_str().ok_or("Missing query")?;
+        let variables = &body["variables"];
+        let doc = query::parse_query::<String>(query)
+            .map_err(|e| format!("Failed to parse query: {}", e))?;
+
+        let selection_set = doc
+            .definitions
+            .iter()
+            .find_map(|def| match def {
+                Definition::Operation(OperationDefinition::SelectionSet(s)) => Some(s),
+                Definition::Operation(OperationDefinition::Query(q)) => Some(&q.selection_set),
+                Definition::Operation(OperationDefinition::Mutation(m)) => Some(&m.selection_set),
+                _ => None,
+            })
+            .ok_or("No operation found")?;
+
+        let mut data = Map::new();
+        for field in fields(selection_set) {
+            let value = self.resolve_root_field(field, variables)?;
+            let type_name = if field.name.starts_with("delete") {
+                "DeleteResult"
+            } else {
+                self.type_name
+            };
+            data.insert(
+                response_key(field),
+                project(&value, &field.selection_set, type_name),
+            );
+        }
+
+        Ok(Value::Object(data))
+    }
+
+    fn resolve_root_field(
+        &self,
+        field: &query::Field<String>,
+        variables: &Value,
+    ) -> Result<Value, String> {
+        let argument = |name: &str| {
+            field
+                .arguments
+                .iter()
+                .find(|(arg, _)| arg == name)
+                .map(|(_, value)| to_json(value, variables))
+                .unwrap_or(Value::Null)
+        };
+        let mut items = self.items.lock().unwrap();
+
+        match field.name.as_str() {
+            "__typename" => Ok(json!("Query")),
+            name if name == self.list_field => Ok(Value::Array(items.clone())),
+            name if name == self.single_field => {
+                let id = argument("id");
+                Ok(items
+                    .iter()
+                    .find(|item| item["id"] == id)
+                    .cloned()
+                    .unwrap_or(Value::Null))
+            }
+            name if name == format!("create{}", self.type_name) => {
+                let mut item = argument("input");
+                let next_id = items
+                    .iter()
+                    .filter_map(|item| item["id"].as_str()?.parse::<usize>().ok())
+                    .max()
+                    .unwrap_or(0)
+                    + 1;
+                item["id"] = json!(next_id.to_string());
+                items.push(item.clone());
+                Ok(item)
+            }
+            name if name == format!("delete{}", self.type_name) => {
+                let id = argument("id");
+                let label = id.as_str().map_or_else(|| id.to_string(), str::to_string);
+                let before = items.len();
+                items.retain(|item| item["id"] != id);
+
+                Ok(if items.len() < before {
+                    json!({ "success": true, "message": format!("{} with ID {} successfully deleted", self.type_name, label) })
+                } else {
+                    json!({ "success": false, "message": format!("{} with ID {} not found", self.type_name, label) })
+                })
+            }
+            name => Err(format!("Cannot query field \"{}\"", name)),
+        }
+    }
+}
+
+fn fields<'a, 'b>(
+    selection_set: &'b SelectionSet<'a, String>,
+) -> Vec<&'b query::Field<'a, String>> {
+    let mut fields = Vec::new();
+    for selection in &selection_set.items {
+        match selection {
+            Selection::Field(field) => fields.push(field),
+            Selection::InlineFragment(fragment) => {
+                fields.extend(self::fields(&fragment.selection_set))
+            }
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+    fields
+}
+
+fn response_key(field: &query::Field<String>) -> String {
+    field.alias.clone().unwrap_or_else(|| field.name.clone())
+}
+
+fn project(value: &Value, selection_set: &SelectionSet<String>, type_name: &str) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| project(item, selection_set, type_name))
+                .collect(),
+        ),
+        Value::Object(obj) if !selection_set.items.is_empty() => {
+            let mut projected = Map::new();
+            for field in fields(selection_set) {
+                let value = match field.name.as_str() {
+                    "__typename" => json!(type_name),
+                    name => obj.get(name).cloned().unwrap_or(Value::Null),
+                };
+                projected.insert(response_key(field), value);
+            }
+            Value::Object(projected)
+        }
+        other => other.clone(),
+    }
+}
+
+fn to_json(value: &query::Value<String>, variables: &Value) -> Value {
+    match value {
+        query::Value::Variable(name) => variables.get(name).cloned().unwrap_or(Value::Null),
+        query::Value::Int(i) => json!(i.as_i64()),
+        query::Value::Float(f) => json!(f),
+        query::Value::String(s) => json!(s),
+        query::Value::Boolean(b) => json!(b),
+        query::Value::Null => Value::Null,
+        query::Value::Enum(e) => json!(e),
+        query::Value::List(items) => {
+            Value::Array(items.iter().map(|item| to_json(item, variables)).collect())
+        }
+        query::Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(key, value)| (key.clone(), to_json(value, variables)))
+                .collect(),
+        ),
+    }
+}
+
+async fn serve(collection: Collection) -> Result<String, String> {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .map_err(|e| format!("Failed to bind demo service: {}", e))?;
+    let url = format!(
+        "http://{}/graphql",
+        listener
+            .local_addr()
+            .map_err(|e| format!("Failed to bind demo service: {}", e))?
+    );
+    let collection = Arc::new(collection);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let collection = Arc::clone(&collection);
+
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                    let collection = Arc::clone(&collection);
+                    async move {
+                        let body = req
+                            .collect()
+                            .await
+                            .map(|b| b.to_bytes())
+                            .unwrap_or_default();
+                        let response = match serde_json::from_slice::<Value>(&body) {
+                            Ok(body) => collection.execute(&body),
+                            Err(e) => {
+                                json!({ "errors": [{ "message": format!("Invalid JSON request: {}", e) }] })
+                            }
+                        };
+
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .header("Content-Type", "application/json")
+                                .body(Full::new(Bytes::from(response.to_string())))
+                                .unwrap(),
+                        )
+                    }
+                });
+
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    warn!("Demo service connection error: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(url)
+}
+
+// Starts the example users and products subgraphs on local ephemeral ports
+// and returns their configuration, ready to be registered with the gateway.
+pub async fn start_demo_services() -> Result<Vec<ServiceConfig>, String> {
+    let users_url = serve(Collection::users()).await?;
+    let products_url = serve(Collection::products()).await?;
+
+    Ok(vec![
+        ServiceConfig {
+            name: "service_1".to_string(),
+            url: users_url,
+            schema: USERS_SCHEMA.to_string(),
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "service_2".to_string(),
+            url: products_url,
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        },
+    ])
+}
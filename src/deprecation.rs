@@ -0,0 +1,305 @@
+use chrono::{DateTime, Utc};
+use graphql_parser::query::{
+    self, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::GraphQLRequest;
+use crate::complexity::named_type;
+use crate::introspection::IntrospectionSchema;
+use crate::operation::operation_name;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeprecationConfig {
+    // Checked in order, the first entry matching a request applies
+    pub operations: Vec<OperationDeprecation>,
+}
+
+// Dates are RFC 3339 timestamps, e.g. `2026-12-31T00:00:00Z`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OperationDeprecation {
+    // Operation name, where `*` matches any run of characters, e.g. `Legacy*`
+    #[serde(default)]
+    pub name: Option<String>,
+    // Hex SHA-256 of the query text, as reported by the request mirror
+    #[serde(default)]
+    pub hash: Option<String>,
+    // Sent as the `Deprecation` header when set
+    #[serde(default)]
+    pub deprecated_at: Option<DateTime<Utc>>,
+    pub sunset: DateTime<Utc>,
+    // Rejects the operation once the sunset date has passed, otherwise it
+    // keeps being served with the warning
+    #[serde(default)]
+    pub block_after_sunset: bool,
+    // Appended to the warning, e.g. which operation replaces this one
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl DeprecationConfig {
+    pub fn find(&self, request: &GraphQLRequest) -> Option<&OperationDeprecation> {
+        if self.operations.is_empty() {
+            return None;
+        }
+
+        let hash = hex::encode(Sha256::digest(request.query.as_bytes()));
+        let name = operation_name(request);
+        self.operations.iter().find(|deprecation| {
+            deprecation.hash.as_deref() == Some(hash.as_str())
+                || deprecation
+                    .name
+                    .as_deref()
+                    .zip(name.as_deref())
+                    .is_some_and(|(pattern, name)| matches_pattern(pattern, name))
+        })
+    }
+}
+
+impl OperationDeprecation {
+    pub fn is_retired(&self, now: DateTime<Utc>) -> bool {
+        self.block_after_sunset && now >= self.sunset
+    }
+
+    // `Sunset` as an HTTP date (RFC 8594) and `Deprecation` as a structured
+    // date (RFC 9745)
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(
+            "Sunset",
+            self.sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        )];
+        if let Some(deprecated_at) = self.deprecated_at {
+            headers.push(("Deprecation", format!("@{}", deprecated_at.timestamp())));
+        }
+        headers
+    }
+
+    // The entry for `extensions.warnings` of responses to the operation
+    pub fn warning(&self) -> Value {
+        let mut message = format!(
+            "This operation is deprecated and will be retired on {}",
+            self.sunset_date()
+        );
+        if let Some(extra) = &self.message {
+            message.push_str(". ");
+            message.push_str(extra);
+        }
+        json!({
+            "code": "OPERATION_DEPRECATED",
+            "message": message,
+            "sunset": self.sunset.to_rfc3339(),
+        })
+    }
+
+    pub fn sunset_date(&self) -> String {
+        self.sunset.format("%Y-%m-%d").to_string()
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut remaining) = name.strip_prefix(prefix) else {
+        return false;
+    };
+
+    // Every literal between stars is matched as early as possible, the last
+    // one has to end the name
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let suffix = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(suffix)
+}
+
+// A field marked `@deprecated` in the composed schema that an operation
+// selects, reported in `extensions.deprecations`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeprecatedField {
+    // `Type.field`
+    pub field: String,
+    pub reason: String,
+}
+
+// The deprecated fields the selected operation selects, each once, in the
+// order they first appear. Fragments on every type are visited and
+// @skip/@include are ignored.
+pub fn deprecated_fields(
+    query: &str,
+    operation_name: Option<&str>,
+    schema: &IntrospectionSchema,
+) -> Vec<DeprecatedField> {
+    let Ok(doc) = query::parse_query::<String>(query) else {
+        return Vec::new();
+    };
+
+    let fragments: HashMap<&str, &FragmentDefinition<String>> = doc
+        .definitions
+        .iter()
+        .filter_map(|def| match def {
+            Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+            _ => None,
+        })
+        .collect();
+
+    let operation = doc.definitions.iter().find_map(|def| match def {
+        Definition::Operation(OperationDefinition::SelectionSet(s)) if operation_name.is_none() => {
+            Some(("Query", s))
+        }
+        Definition::Operation(OperationDefinition::Query(q))
+            if operation_name.is_none() || q.name.as_deref() == operation_name =>
+        {
+            Some(("Query", &q.selection_set))
+        }
+        Definition::Operation(OperationDefinition::Mutation(m))
+            if operation_name.is_none() || m.name.as_deref() == operation_name =>
+        {
+            Some(("Mutation", &m.selection_set))
+        }
+        Definition::Operation(OperationDefinition::Subscription(s))
+            if operation_name.is_none() || s.name.as_deref() == operation_name =>
+        {
+            Some(("Subscription", &s.selection_set))
+        }
+        _ => None,
+    });
+    let Some((root_type, selection_set)) = operation else {
+        return Vec::new();
+    };
+
+    let mut deprecated = Vec::new();
+    collect_deprecated(
+        selection_set,
+        root_type,
+        schema,
+        &fragments,
+        &mut Vec::new(),
+        &mut deprecated,
+    );
+    deprecated
+}
+
+fn collect_deprecated<'a>(
+    selection_set: &'a SelectionSet<'a, String>,
+    parent_type: &str,
+    schema: &IntrospectionSchema,
+    fragments: &HashMap<&str, &'a FragmentDefinition<'a, String>>,
+    visited_fragments: &mut Vec<&'a str>,
+    deprecated: &mut Vec<DeprecatedField>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            Selection::Field(field) => {
+                let Some(definition) = schema
+                    .get_type(parent_type)
+                    .and_then(|ty| ty["fields"].as_array())
+                    .and_then(|fields| fields.iter().find(|f| f["name"] == field.name.as_str()))
+                else {
+                    continue;
+                };
+
+                let coordinate = format!("{}.{}", parent_type, field.name);
+                if definition["isDeprecated"] == true
+                    && !deprecated.iter().any(|used| used.field == coordinate)
+                {
+                    deprecated.push(DeprecatedField {
+                        field: coordinate,
+                        reason: definition["deprecationReason"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string(),
+                    });
+                }
+
+                collect_deprecated(
+                    &field.selection_set,
+                    named_type(&definition["type"]),
+                    schema,
+                    fragments,
+                    visited_fragments,
+                    deprecated,
+                );
+            }
+            Selection::InlineFragment(fragment) => {
+                let type_name = match &fragment.type_condition {
+                    Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                    None => parent_type,
+                };
+                collect_deprecated(
+                    &fragment.selection_set,
+                    type_name,
+                    schema,
+                    fragments,
+                    visited_fragments,
+                    deprecated,
+                );
+            }
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = fragments.get(spread.fragment_name.as_str())
+                    && !visited_fragments.contains(&fragment.name.as_str())
+                {
+                    let TypeCondition::On(type_name) = &fragment.type_condition;
+                    visited_fragments.push(&fragment.name);
+                    collect_deprecated(
+                        &fragment.selection_set,
+                        type_name,
+                        schema,
+                        fragments,
+                        visited_fragments,
+                        deprecated,
+                    );
+                    visited_fragments.pop();
+                }
+            }
+        }
+    }
+}
+
+// How often each deprecated field was selected, for finding the clients
+// still relying on it
+#[derive(Debug, Default)]
+pub struct DeprecatedFieldUsage {
+    selections: Mutex<BTreeMap<String, u64>>,
+}
+
+impl DeprecatedFieldUsage {
+    pub fn record(&self, fields: &[DeprecatedField]) {
+        let mut selections = self.selections.lock().unwrap();
+        for field in fields {
+            *selections.entry(field.field.clone()).or_default() += 1;
+        }
+    }
+
+    // One series per field, in the Prometheus text format
+    pub fn render_metrics(&self) -> String {
+        let selections = self.selections.lock().unwrap();
+        if selections.is_empty() {
+            return String::new();
+        }
+
+        let name = "portkey_deprecated_field_selections_total";
+        let mut metrics = String::new();
+        let _ = writeln!(
+            metrics,
+            "# HELP {} Operations selecting a deprecated field.",
+            name
+        );
+        let _ = writeln!(metrics, "# TYPE {} counter", name);
+        for (field, count) in selections.iter() {
+            let _ = writeln!(metrics, "{}{{field=\"{}\"}} {}", name, field, count);
+        }
+        metrics
+    }
+}
@@ -0,0 +1,174 @@
+use lru::LruCache;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::FetchNode;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntityCacheKey {
+    pub type_name: String,
+    // The representation sent to `_entities`, i.e. `__typename` and key fields
+    pub representation: String,
+    // Hash of the forwarded auth headers, so entities resolved for one session
+    // are never served to another
+    session: String,
+    // Hash of the service, query and variables the entity was fetched with,
+    // since those decide which of its fields the cached value holds
+    fetch: String,
+}
+
+struct CachedEntity {
+    value: Value,
+    stored_at: Instant,
+}
+
+struct Entries {
+    generation: u64,
+    entities: LruCache<EntityCacheKey, CachedEntity>,
+}
+
+// Results of `_entities` fetches, consulted before the executor sends
+// representations to a service. Entries expire after `ttl`, are dropped when
+// the schema generation changes and can be invalidated by type or by key.
+pub struct EntityCache {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl EntityCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        EntityCache {
+            ttl,
+            entries: Mutex::new(Entries {
+                generation: 0,
+                entities: LruCache::new(capacity),
+            }),
+        }
+    }
+
+    pub fn key(
+        fetch: &FetchNode,
+        representation: &Value,
+        auth_headers: &Option<HashMap<String, String>>,
+    ) -> EntityCacheKey {
+        EntityCacheKey {
+            type_name: representation["__typename"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            representation: representation.to_string(),
+            session: session_hash(auth_headers),
+            fetch: hash(&[
+                &fetch.service_name,
+                &fetch.query,
+                &fetch.variables.to_string(),
+            ]),
+        }
+    }
+
+    pub fn get(&self, key: &EntityCacheKey, generation: u64) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            entries.entities.clear();
+            entries.generation = generation;
+            return None;
+        }
+
+        let expired = entries
+            .entities
+            .get(key)
+            .map(|entity| entity.stored_at.elapsed() >= self.ttl)?;
+        if expired {
+            entries.entities.pop(key);
+            return None;
+        }
+
+        entries.entities.get(key).map(|entity| entity.value.clone())
+    }
+
+    pub fn insert(&self, key: EntityCacheKey, value: Value, generation: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation == generation {
+            entries.entities.put(
+                key,
+                CachedEntity {
+                    value,
+                    stored_at: Instant::now(),
+                },
+            );
+        }
+    }
+
+    // Drops every cached copy of one entity, e.g. after a mutation changed it.
+    // `key_fields` holds the key field values, like `{"id": "1"}`.
+    pub fn invalidate(&self, type_name: &str, key_fields: &Value) -> usize {
+        let Some(key_fields) = key_fields.as_object() else {
+            return 0;
+        };
+
+        self.remove_where(|key| {
+            key.type_name == type_name
+                && serde_json::from_str::<Value>(&key.representation).is_ok_and(|repr| {
+                    key_fields
+                        .iter()
+                        .all(|(field, value)| repr.get(field) == Some(value))
+                })
+        })
+    }
+
+    pub fn invalidate_type(&self, type_name: &str) -> usize {
+        self.remove_where(|key| key.type_name == type_name)
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().entities.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn remove_where(&self, matches: impl Fn(&EntityCacheKey) -> bool) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<EntityCacheKey> = entries
+            .entities
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            entries.entities.pop(key);
+        }
+        keys.len()
+    }
+}
+
+pub(crate) fn session_hash(auth_headers: &Option<HashMap<String, String>>) -> String {
+    let Some(headers) = auth_headers else {
+        return String::new();
+    };
+
+    let mut headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}", name.to_lowercase(), value))
+        .collect();
+    headers.sort();
+    hash(&headers.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
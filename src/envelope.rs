@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+// The shape of the responses of a legacy endpoint, an extra path serving
+// GraphQL like `/graphql` for consumers expecting another envelope, e.g.
+// `{"success": true, "result": {...}, "errors": []}`. Batched responses are
+// wrapped one by one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvelopeConfig {
+    // Field holding whether the response has no errors, left out when unset
+    pub success_field: Option<String>,
+    // Field holding the data
+    pub result_field: String,
+    // Field holding the errors, an empty list when there are none
+    pub errors_field: String,
+    // Answers operations selecting a single root field with the field's value
+    // in place of the data, e.g. the product of `{ product(id: 1) { name } }`
+    pub unwrap_single_field: bool,
+    // Field holding the response's extensions, left out when unset
+    pub extensions_field: Option<String>,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        EnvelopeConfig {
+            success_field: None,
+            result_field: "data".to_string(),
+            errors_field: "errors".to_string(),
+            unwrap_single_field: false,
+            extensions_field: None,
+        }
+    }
+}
+
+impl EnvelopeConfig {
+    pub fn wrap(&self, response: Value) -> Value {
+        let Value::Object(mut response) = response else {
+            return match response {
+                Value::Array(responses) => {
+                    Value::Array(responses.into_iter().map(|r| self.wrap(r)).collect())
+                }
+                other => other,
+            };
+        };
+
+        let data = match response.remove("data") {
+            Some(Value::Object(fields)) if self.unwrap_single_field && fields.len() == 1 => {
+                fields.into_iter().map(|(_, value)| value).next()
+            }
+            data => data,
+        };
+        let errors = match response.remove("errors") {
+            Some(Value::Array(errors)) => errors,
+            _ => Vec::new(),
+        };
+
+        let mut envelope = Map::new();
+        if let Some(success_field) = &self.success_field {
+            envelope.insert(success_field.clone(), json!(errors.is_empty()));
+        }
+        envelope.insert(self.result_field.clone(), data.unwrap_or(Value::Null));
+        envelope.insert(self.errors_field.clone(), Value::Array(errors));
+        if let (Some(extensions_field), Some(extensions)) =
+            (&self.extensions_field, response.remove("extensions"))
+        {
+            envelope.insert(extensions_field.clone(), extensions);
+        }
+        Value::Object(envelope)
+    }
+}
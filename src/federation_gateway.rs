@@ -1,32 +1,94 @@
-use serde::Deserialize;
-use serde_json::Value;
-use std::{collections::HashMap, fs, io, path::Path, sync::Arc};
+use chrono::Utc;
+use futures::{
+    StreamExt,
+    future::join_all,
+    stream::{self, BoxStream},
+};
+use graphql_parser::query::{Definition, OperationDefinition};
+use serde_json::{Map, Value, json};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 use tokio::sync::RwLock;
+use tracing::{debug, info};
 
 use crate::{
-    GraphQLRequest, ServiceConfig, query_executor::QueryExecutor, query_planner::QueryPlanner,
+    CacheInvalidation, FederatedSchema, GraphQLRequest, ServiceConfig,
+    api_keys::{ApiKey, ApiKeys},
+    build_info,
+    complexity::ComplexityConfig,
+    config::CacheBypassConfig,
+    connections::ConnectionConfig,
+    deprecation::{self, DeprecatedFieldUsage, DeprecationConfig, OperationDeprecation},
+    logging::LogControl,
+    memory,
+    messages::MessageCatalog,
+    operation,
+    pagination::PaginationConfig,
+    persisted_queries::{self, PersistedQueryStore},
+    plan_cache,
+    quarantine::FieldQuarantine,
+    query_executor::QueryExecutor,
+    query_limits::QueryLimitsConfig,
+    query_planner::QueryPlanner,
+    quota::QuotaTracker,
+    request_context::RequestContext,
+    request_journal::{ReplayRequest, RequestJournal},
+    request_mirror::{MirrorRecord, RequestMirror},
     schema_registry::SchemaRegistry,
+    service_health::ServiceHealth,
+    subgraph_log::SubgraphLogger,
+    supergraph_source::{SupergraphSource, SupergraphSourceConfig, SupergraphSources},
+    upload::Uploads,
+    validation::{self, ValidationRule},
 };
 
-#[derive(Debug, Deserialize)]
-struct SupergraphConfig {
-    subgraphs: HashMap<String, SubgraphConfig>,
-}
-
-#[derive(Debug, Deserialize)]
-struct SubgraphConfig {
-    routing_url: String,
-    schema: SchemaConfig,
-}
-
-#[derive(Debug, Deserialize)]
-struct SchemaConfig {
-    file: String,
-}
 pub struct FederationGateway {
     schema_registry: Arc<RwLock<Box<dyn SchemaRegistry + Send + Sync>>>,
     query_planner: Arc<Box<dyn QueryPlanner + Send + Sync>>,
     query_executor: Arc<Box<dyn QueryExecutor + Send + Sync>>,
+    request_mirror: Option<RequestMirror>,
+    messages: MessageCatalog,
+    cache_bypass: CacheBypassConfig,
+    complexity: ComplexityConfig,
+    query_limits: QueryLimitsConfig,
+    deprecations: DeprecationConfig,
+    deprecated_field_usage: DeprecatedFieldUsage,
+    // Region hint for requests that don't carry one
+    default_region: Option<String>,
+    // Automatic persisted queries are refused without a store
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
+    request_journal: Option<RequestJournal>,
+    subgraph_logger: Option<Arc<SubgraphLogger>>,
+    quarantine: Option<Arc<FieldQuarantine>>,
+    // Introspection responses list the root fields of unavailable services
+    // when set
+    service_health: Option<Arc<ServiceHealth>>,
+    log_control: Option<Arc<LogControl>>,
+    // Custom rules run after the built-in validation
+    validation_rules: Vec<Arc<dyn ValidationRule>>,
+    // Answers `extensions.explain: true` with the plan next to the data
+    dev_mode: bool,
+    quota: Option<QuotaTracker>,
+    // Requests are refused without a valid `x-api-key` once keys are managed
+    api_keys: Option<ApiKeys>,
+    // Reports the time spent in every service in `extensions.timing`
+    report_timing: bool,
+    // Planning and executing a request past it fails the request
+    execution_deadline: Option<Duration>,
+    // Reports the gateway's version and build in `extensions.portkey.build`
+    report_build: bool,
+    // List fields served as connections, paged by the gateway
+    connections: ConnectionConfig,
+    pagination: PaginationConfig,
+    // Cleared while the schema is loaded in the background, GraphQL requests
+    // being refused until it's set
+    schema_ready: AtomicBool,
+    supergraph_sources: SupergraphSources,
 }
 
 impl FederationGateway {
@@ -39,27 +101,848 @@ impl FederationGateway {
             schema_registry: Arc::new(RwLock::new(schema_registry)),
             query_planner: Arc::new(query_planner),
             query_executor: Arc::new(query_executor),
+            request_mirror: None,
+            messages: MessageCatalog::new(),
+            cache_bypass: CacheBypassConfig::default(),
+            complexity: ComplexityConfig::default(),
+            query_limits: QueryLimitsConfig::default(),
+            deprecations: DeprecationConfig::default(),
+            deprecated_field_usage: DeprecatedFieldUsage::default(),
+            default_region: None,
+            persisted_queries: None,
+            request_journal: None,
+            subgraph_logger: None,
+            quarantine: None,
+            service_health: None,
+            log_control: None,
+            validation_rules: Vec::new(),
+            dev_mode: false,
+            quota: None,
+            api_keys: None,
+            report_timing: false,
+            execution_deadline: None,
+            report_build: false,
+            connections: ConnectionConfig::default(),
+            pagination: PaginationConfig::default(),
+            schema_ready: AtomicBool::new(true),
+            supergraph_sources: SupergraphSources::new(&SupergraphSourceConfig::default()),
         }
     }
 
+    // Starts out without a schema, answering GraphQL requests with
+    // SCHEMA_NOT_READY until `mark_schema_ready` once it's loaded
+    pub fn with_lazy_schema(self, lazy: bool) -> Self {
+        self.schema_ready.store(!lazy, Ordering::Release);
+        self
+    }
+
+    pub fn mark_schema_ready(&self) {
+        self.schema_ready.store(true, Ordering::Release);
+    }
+
+    pub fn is_schema_ready(&self) -> bool {
+        self.schema_ready.load(Ordering::Acquire)
+    }
+
+    pub fn with_api_keys(mut self, api_keys: ApiKeys) -> Self {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    pub fn api_keys(&self) -> Option<&ApiKeys> {
+        self.api_keys.as_ref()
+    }
+
+    pub fn with_quota(mut self, quota: QuotaTracker) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn with_subgraph_timing(mut self, report_timing: bool) -> Self {
+        self.report_timing = report_timing;
+        self
+    }
+
+    pub fn with_execution_deadline(mut self, execution_deadline: Option<Duration>) -> Self {
+        self.execution_deadline = execution_deadline;
+        self
+    }
+
+    pub fn with_build_extension(mut self, report_build: bool) -> Self {
+        self.report_build = report_build;
+        self
+    }
+
+    pub fn with_connections(mut self, connections: ConnectionConfig) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    pub fn with_pagination(mut self, pagination: PaginationConfig) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    pub fn with_dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    pub fn with_validation_rule(mut self, rule: Arc<dyn ValidationRule>) -> Self {
+        self.validation_rules.push(rule);
+        self
+    }
+
+    pub fn with_request_journal(mut self, request_journal: RequestJournal) -> Self {
+        self.request_journal = Some(request_journal);
+        self
+    }
+
+    pub fn request_journal(&self) -> Option<&RequestJournal> {
+        self.request_journal.as_ref()
+    }
+
+    // The logger the executor was given, for the admin API to adjust
+    pub fn with_subgraph_logger(mut self, logger: Arc<SubgraphLogger>) -> Self {
+        self.subgraph_logger = Some(logger);
+        self
+    }
+
+    pub fn subgraph_logger(&self) -> Option<&SubgraphLogger> {
+        self.subgraph_logger.as_deref()
+    }
+
+    // The control of the installed logger, for the admin API to adjust
+    pub fn with_log_control(mut self, log_control: Arc<LogControl>) -> Self {
+        self.log_control = Some(log_control);
+        self
+    }
+
+    pub fn log_control(&self) -> Option<&LogControl> {
+        self.log_control.as_deref()
+    }
+
+    // The quarantine the executor was given, for the admin API to list
+    pub fn with_quarantine(mut self, quarantine: Arc<FieldQuarantine>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    pub fn quarantine(&self) -> Option<&FieldQuarantine> {
+        self.quarantine.as_deref()
+    }
+
+    // The service health the executor was given
+    pub fn with_service_health(mut self, service_health: Arc<ServiceHealth>) -> Self {
+        self.service_health = Some(service_health);
+        self
+    }
+
+    pub fn with_persisted_query_store(mut self, store: Arc<dyn PersistedQueryStore>) -> Self {
+        self.persisted_queries = Some(store);
+        self
+    }
+
+    pub fn with_request_mirror(mut self, request_mirror: RequestMirror) -> Self {
+        self.request_mirror = Some(request_mirror);
+        self
+    }
+
+    pub fn with_message_catalog(mut self, messages: MessageCatalog) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn with_cache_bypass(mut self, cache_bypass: CacheBypassConfig) -> Self {
+        self.cache_bypass = cache_bypass;
+        self
+    }
+
+    pub fn with_complexity(mut self, complexity: ComplexityConfig) -> Self {
+        self.complexity = complexity;
+        self
+    }
+
+    pub fn with_query_limits(mut self, query_limits: QueryLimitsConfig) -> Self {
+        self.query_limits = query_limits;
+        self
+    }
+
+    pub fn with_deprecations(mut self, deprecations: DeprecationConfig) -> Self {
+        self.deprecations = deprecations;
+        self
+    }
+
+    pub fn with_default_region(mut self, region: String) -> Self {
+        self.default_region = Some(region);
+        self
+    }
+
+    pub fn with_supergraph_sources(mut self, config: &SupergraphSourceConfig) -> Self {
+        self.supergraph_sources = SupergraphSources::new(config);
+        self
+    }
+
+    pub fn messages(&self) -> &MessageCatalog {
+        &self.messages
+    }
+
     pub async fn process_request(&self, request: GraphQLRequest) -> Result<Value, String> {
-        println!("Processing request: {:?}", request);
+        self.process(request, false, Uploads::default())
+            .await
+            .map(|(response, _)| response)
+    }
 
-        let schema_registry = self.schema_registry.read().await;
-        let schema = schema_registry.get_schema().await?;
-        drop(schema_registry);
+    // Like `process_request`, for a multipart request with the files its
+    // variables hold placeholders for
+    pub async fn process_upload(
+        &self,
+        request: GraphQLRequest,
+        uploads: Uploads,
+    ) -> Result<Value, String> {
+        self.process(request, false, uploads)
+            .await
+            .map(|(response, _)| response)
+    }
+
+    // Like `process_request`, with the operation's deferred fragments left
+    // out of the response and delivered by the returned stream as they're
+    // resolved
+    pub async fn process_incremental(
+        &self,
+        request: GraphQLRequest,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        self.process(request, true, Uploads::default()).await
+    }
+
+    async fn process(
+        &self,
+        mut request: GraphQLRequest,
+        incremental: bool,
+        uploads: Uploads,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        self.resolve_persisted_query(&mut request).await?;
+        debug!("Processing request {}", operation::display_name(&request));
+
+        let mirror_record = self
+            .request_mirror
+            .as_ref()
+            .map(|_| MirrorRecord::from_request(&request));
+        let started = Instant::now();
+
+        let result = self.execute_request(request, incremental, uploads).await;
+
+        if let (Some(request_mirror), Some(mut record)) = (&self.request_mirror, mirror_record) {
+            record.duration_ms = started.elapsed().as_millis() as u64;
+            record.success = result.is_ok();
+            request_mirror.record(record);
+        }
+
+        result
+    }
+
+    // Executes each listed operation of the request's document concurrently,
+    // answering with their responses in the listed order. An operation that
+    // fails only fails its own response.
+    pub async fn process_batch(
+        &self,
+        request: GraphQLRequest,
+        operation_names: Vec<String>,
+    ) -> Result<Value, String> {
+        if operation_names.is_empty() {
+            return Err("Batched requests must name at least one operation".to_string());
+        }
+
+        let responses = join_all(operation_names.into_iter().map(|operation_name| {
+            let request = GraphQLRequest {
+                operation_name: Some(operation_name),
+                ..request.clone()
+            };
+            let accept_language = request.accept_language.clone();
+            async move {
+                self.process_request(request).await.unwrap_or_else(
+                    |e| json!({ "errors": [self.messages.error(&e, accept_language.as_deref())] }),
+                )
+            }
+        }))
+        .await;
+
+        Ok(Value::Array(responses))
+    }
+
+    // Runs a journaled request again with every subgraph it touches pointed at
+    // its staging URL, bypassing the caches. Nothing is sent when one of the
+    // subgraphs has no staging URL, so replays never reach production.
+    pub async fn replay(&self, replay: ReplayRequest) -> Result<Value, String> {
+        let Some(journal) = &self.request_journal else {
+            return Err("Request journal is not enabled".to_string());
+        };
+        let entry = journal
+            .get(replay.id)
+            .ok_or_else(|| format!("No journaled request with id {}", replay.id))?;
+        let request = entry.replay_request(replay.variables);
+
+        let mut schema = self.current_schema().await?;
+        for service in schema.services.values_mut() {
+            if let Some(url) = journal.staging().get(&service.name) {
+                service.url = url.clone();
+                service.regions.clear();
+                service.preferred_region = None;
+            }
+        }
+
+        let query_plan = self
+            .query_planner
+            .plan_query_uncached(
+                &request.query,
+                &schema,
+                request.variables,
+                request.operation_name.as_deref(),
+            )
+            .await?;
+        if let Some(missing) = query_plan
+            .fetches()
+            .iter()
+            .find(|planned| !journal.staging().contains_key(&planned.fetch.service_name))
+        {
+            return Err(format!(
+                "Service {} has no staging URL to replay against",
+                missing.fetch.service_name
+            ));
+        }
+
+        self.query_executor
+            .execute_plan_uncached(query_plan, &schema, request.auth_headers)
+            .await
+    }
+
+    // Looks up the query of a request sent as a persisted query hash, or
+    // registers it when the request carries the query too
+    pub async fn resolve_persisted_query(
+        &self,
+        request: &mut GraphQLRequest,
+    ) -> Result<(), String> {
+        persisted_queries::resolve(self.persisted_queries.as_deref(), request).await
+    }
+
+    // Plans the request without sending anything to the subgraphs
+    pub async fn explain_request(&self, mut request: GraphQLRequest) -> Result<Value, String> {
+        self.resolve_persisted_query(&mut request).await?;
+        let schema = self.current_schema().await?;
 
         let query_plan = self
             .query_planner
-            .plan_query(&request.query, &schema, request.variables)
+            .plan_query(
+                &request.query,
+                &schema,
+                request.variables,
+                request.operation_name.as_deref(),
+            )
             .await?;
 
-        let response = self
-            .query_executor
-            .execute_plan(query_plan, &schema, request.auth_headers)
+        Ok(json!({ "queryPlan": query_plan.explain() }))
+    }
+
+    // `Sunset` and `Deprecation` headers for responses to deprecated operations
+    pub fn deprecation_headers(&self, request: &GraphQLRequest) -> Vec<(&'static str, String)> {
+        self.deprecations
+            .find(request)
+            .map(|deprecation| deprecation.headers())
+            .unwrap_or_default()
+    }
+
+    // The response, and with `incremental` the payloads delivering the
+    // deferred fragments it leaves out
+    async fn execute_request(
+        &self,
+        request: GraphQLRequest,
+        incremental: bool,
+        uploads: Uploads,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        let api_key = match self.authenticate(&request).await {
+            Ok(api_key) => api_key,
+            Err(errors) => return Ok((errors, None)),
+        };
+
+        let deprecation = self.deprecations.find(&request).cloned();
+        if let Some(errors) = deprecation
+            .as_ref()
+            .and_then(|deprecation| self.retired_errors(deprecation, &request))
+        {
+            return Ok((errors, None));
+        }
+
+        let mut schema = self.current_schema().await?;
+        self.route_to_region(&mut schema, &request);
+
+        // Invalid operations are answered with spec errors and never planned
+        if let Some(errors) = self.validation_errors(&request, &schema) {
+            return Ok((errors, None));
+        }
+
+        let (cost, remaining_quota) = match self.charge(&request, api_key.as_ref(), &schema) {
+            Ok(charged) => charged,
+            Err(errors) => return Ok((errors, None)),
+        };
+
+        // Connections over plain lists are planned as the lists, and sliced
+        // into pages once the response is in
+        let (rewritten, connection_slices) = match self.connections.rewrite(
+            &request.query,
+            request.operation_name.as_deref(),
+            &request.variables,
+            &schema.introspection,
+        ) {
+            Ok(Some((query, slices))) => (Some(query), slices),
+            Ok(None) => (None, Vec::new()),
+            Err(message) => {
+                return Ok((
+                    json!({
+                        "errors": [self.messages.error(&message, request.accept_language.as_deref())],
+                    }),
+                    None,
+                ));
+            }
+        };
+        let query = rewritten.as_deref().unwrap_or(&request.query);
+
+        // Pagination rules bound the lists the subgraphs are asked for
+        let paged = self
+            .pagination
+            .rewrite(query, &request.variables, &schema.introspection);
+        let (query, variables) = match paged {
+            Some((ref query, variables)) => (query.as_str(), variables),
+            None => (query, request.variables.clone()),
+        };
+
+        let deprecated_fields = deprecation::deprecated_fields(
+            &request.query,
+            request.operation_name.as_deref(),
+            &schema.introspection,
+        );
+        self.deprecated_field_usage.record(&deprecated_fields);
+
+        // Introspection fields never reach a subgraph, they're answered from
+        // the composed schema and merged into the response below
+        let introspection =
+            if request.query.contains("__schema") || request.query.contains("__type") {
+                schema.introspection.resolve(
+                    &request.query,
+                    request.operation_name.as_deref(),
+                    &request.variables,
+                )?
+            } else {
+                Map::new()
+            };
+
+        // Bypassing skips cache lookups, the caches are still refreshed with
+        // the results of this request
+        let bypass_cache = (request.no_cache
+            || has_no_cache_directive(&request.query, request.operation_name.as_deref()))
+            && self.cache_bypass.allows(request.client_name.as_deref());
+        let mut context = RequestContext::new(bypass_cache);
+        context.cost = cost;
+        context.remaining_quota = remaining_quota;
+        context.report_timing = self.report_timing;
+        context.tenant = metered_client(&request, api_key.as_ref()).cloned();
+        context.tier = api_key.as_ref().and_then(|api_key| api_key.tier.clone());
+        context.uploads = uploads;
+        context.region = request.region.clone().or(self.default_region.clone());
+        context.experiments = request
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions["experiments"].as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|experiment| experiment.as_str().map(str::to_string))
+            .collect();
+        context.query_hash = Some(plan_cache::cache_key(
+            query,
+            request.operation_name.as_deref(),
+            &variables,
+        ));
+
+        // In dev mode, `extensions.explain: true` adds the plan, whether it
+        // came from the plan cache and the subgraphs it queries to the response
+        let explain = self.dev_mode
+            && request
+                .extensions
+                .as_ref()
+                .is_some_and(|extensions| extensions["explain"] == true);
+        let plan_cache = if bypass_cache {
+            "bypassed"
+        } else if explain
+            && self.query_planner.is_cached(
+                query,
+                &schema,
+                &variables,
+                request.operation_name.as_deref(),
+            )
+        {
+            "hit"
+        } else {
+            "miss"
+        };
+
+        let execution = async {
+            let query_plan = self
+                .query_planner
+                .plan_in_context(
+                    query,
+                    &schema,
+                    variables,
+                    request.operation_name.as_deref(),
+                    &context,
+                )
+                .await?;
+            let explained = explain.then(|| {
+                let mut subgraphs: Vec<&str> = query_plan
+                    .fetches()
+                    .iter()
+                    .map(|planned| planned.fetch.service_name.as_str())
+                    .collect();
+                subgraphs.sort();
+                subgraphs.dedup();
+                json!({
+                    "queryPlan": query_plan.explain(),
+                    "cache": { "plan": plan_cache },
+                    "subgraphs": subgraphs,
+                })
+            });
+
+            let (response, rest) = if incremental {
+                self.query_executor
+                    .execute_incremental(query_plan, &schema, request.auth_headers, &context)
+                    .await?
+            } else {
+                let response = self
+                    .query_executor
+                    .execute_in_context(query_plan, &schema, request.auth_headers, &context)
+                    .await?;
+                (response, None)
+            };
+            Ok::<_, String>((response, rest, explained))
+        };
+        // Past the deadline the execution is dropped, aborting the fetches
+        // still in flight. Deferred parts of the response aren't bounded.
+        let (mut response, rest, explained) = match self.execution_deadline {
+            Some(deadline) => tokio::time::timeout(deadline, execution)
+                .await
+                .map_err(|_| {
+                    format!(
+                        "Request exceeded the execution deadline of {} ms",
+                        deadline.as_millis()
+                    )
+                })??,
+            None => execution.await?,
+        };
+
+        for slice in &connection_slices {
+            slice.apply(&mut response["data"]);
+        }
+
+        if !introspection.is_empty() {
+            if response["data"].is_null() {
+                response["data"] = json!({});
+            }
+            if let Some(data) = response["data"].as_object_mut() {
+                data.extend(introspection);
+            }
+
+            let unavailable_fields = self
+                .service_health
+                .as_ref()
+                .map(|health| health.unavailable_fields(&schema))
+                .unwrap_or_default();
+            if !unavailable_fields.is_empty() {
+                response["extensions"]["unavailableFields"] = json!(unavailable_fields);
+            }
+        }
+
+        context.extend_response(&mut response);
+
+        if let Some(deprecation) = deprecation {
+            response["extensions"]["warnings"] = json!([deprecation.warning()]);
+        }
+
+        if !deprecated_fields.is_empty() {
+            response["extensions"]["deprecations"] = json!(deprecated_fields);
+        }
+
+        if let Some(explained) = explained {
+            response["extensions"]["portkey"]["explain"] = explained;
+        }
+
+        if self.report_build {
+            response["extensions"]["portkey"]["build"] = build_info::build_info();
+        }
+
+        Ok((response, rest))
+    }
+
+    // Opens the event stream of a subscription, every item being the response
+    // to one event. Other operations, and subscriptions failing validation,
+    // are answered with a stream of a single response.
+    pub async fn subscribe(
+        &self,
+        mut request: GraphQLRequest,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        self.resolve_persisted_query(&mut request).await?;
+        if !is_subscription(&request.query, request.operation_name.as_deref()) {
+            let response = self.process_request(request).await?;
+            return Ok(stream::once(async { response }).boxed());
+        }
+
+        let api_key = match self.authenticate(&request).await {
+            Ok(api_key) => api_key,
+            Err(errors) => return Ok(stream::once(async { errors }).boxed()),
+        };
+
+        if let Some(errors) = self
+            .deprecations
+            .find(&request)
+            .and_then(|deprecation| self.retired_errors(deprecation, &request))
+        {
+            return Ok(stream::once(async { errors }).boxed());
+        }
+
+        let mut schema = self.current_schema().await?;
+        self.route_to_region(&mut schema, &request);
+
+        if let Some(errors) = self.validation_errors(&request, &schema) {
+            return Ok(stream::once(async { errors }).boxed());
+        }
+
+        // Subscriptions are charged once, when they start
+        if let Err(errors) = self.charge(&request, api_key.as_ref(), &schema) {
+            return Ok(stream::once(async { errors }).boxed());
+        }
+
+        let query_plan = self
+            .query_planner
+            .plan_query(
+                &request.query,
+                &schema,
+                request.variables,
+                request.operation_name.as_deref(),
+            )
             .await?;
+        self.query_executor
+            .subscribe(query_plan, &schema, request.auth_headers)
+            .await
+    }
+
+    // Points the services of this request's copy of the schema at the hinted
+    // region's deployments
+    fn route_to_region(&self, schema: &mut FederatedSchema, request: &GraphQLRequest) {
+        let Some(region) = request.region.as_ref().or(self.default_region.as_ref()) else {
+            return;
+        };
+        for service in schema.services.values_mut() {
+            service.preferred_region = Some(region.clone());
+        }
+    }
+
+    // The API key the request's `x-api-key` belongs to, when keys are
+    // managed, or the errors refusing the request
+    async fn authenticate(&self, request: &GraphQLRequest) -> Result<Option<ApiKey>, Value> {
+        let Some(api_keys) = &self.api_keys else {
+            return Ok(None);
+        };
+        let secret = request
+            .auth_headers
+            .as_ref()
+            .and_then(|headers| headers.get("x-api-key"))
+            .map(String::as_str);
+        api_keys.authenticate(secret).await.map(Some).map_err(|e| {
+            json!({
+                "errors": [self.messages.error(&e, request.accept_language.as_deref())]
+            })
+        })
+    }
+
+    // The estimated cost of the request with the most it may cost, and the
+    // quota its client has left once charged, or the errors refusing it. The
+    // limits of its API key take precedence over the gateway-wide ones.
+    fn charge(
+        &self,
+        request: &GraphQLRequest,
+        api_key: Option<&ApiKey>,
+        schema: &FederatedSchema,
+    ) -> Result<(Option<Value>, Option<u64>), Value> {
+        let limits = api_key.map(|api_key| &api_key.limits);
+        let cost = self.complexity.enabled.then(|| {
+            json!({
+                "estimated": self.complexity.estimate(
+                    &request.query,
+                    request.operation_name.as_deref(),
+                    &request.variables,
+                    &schema.introspection,
+                ),
+                "max": limits
+                    .and_then(|limits| limits.max_cost)
+                    .or(self.complexity.max_cost),
+            })
+        });
+        if let Some(cost) = &cost
+            && let (Some(estimated), Some(max)) = (cost["estimated"].as_u64(), cost["max"].as_u64())
+            && estimated > max
+        {
+            let message = format!(
+                "Operation cost {} exceeds the maximum cost of {}",
+                estimated, max
+            );
+            return Err(json!({
+                "errors": [self.messages.error(&message, request.accept_language.as_deref())],
+                "extensions": { "cost": cost },
+            }));
+        }
+
+        let remaining_quota = match (&self.quota, metered_client(request, api_key)) {
+            (Some(quota), Some(client)) => {
+                let cost = cost
+                    .as_ref()
+                    .and_then(|cost| cost["estimated"].as_u64())
+                    .unwrap_or(1);
+                let limit = limits.and_then(|limits| limits.quota);
+                match quota.charge(client, cost, limit) {
+                    Ok(remaining) => Some(remaining),
+                    Err(message) => {
+                        return Err(json!({
+                            "errors": [self.messages.error(&message, request.accept_language.as_deref())],
+                            "extensions": { "remainingQuota": quota.remaining(client, limit) },
+                        }));
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        Ok((cost, remaining_quota))
+    }
+
+    // Operations past their sunset date are rejected when configured so
+    fn retired_errors(
+        &self,
+        deprecation: &OperationDeprecation,
+        request: &GraphQLRequest,
+    ) -> Option<Value> {
+        if !deprecation.is_retired(Utc::now()) {
+            return None;
+        }
+
+        let message = format!("Operation was retired on {}", deprecation.sunset_date());
+        Some(json!({
+            "errors": [self.messages.error(&message, request.accept_language.as_deref())]
+        }))
+    }
 
-        Ok(response)
+    fn validation_errors(
+        &self,
+        request: &GraphQLRequest,
+        schema: &FederatedSchema,
+    ) -> Option<Value> {
+        // Operations over the structural limits aren't worth validating
+        if let Err(message) = self
+            .query_limits
+            .check(&request.query, request.operation_name.as_deref())
+        {
+            return Some(json!({
+                "errors": [self.messages.error(&message, request.accept_language.as_deref())],
+            }));
+        }
+
+        let mut validation_errors = validation::validate(
+            &request.query,
+            request.operation_name.as_deref(),
+            &schema.introspection,
+        );
+        let custom = validation_errors.is_empty();
+        if custom {
+            validation_errors = validation::validate_rules(
+                &request.query,
+                request.operation_name.as_deref(),
+                schema,
+                &self.validation_rules,
+            );
+        }
+        if validation_errors.is_empty() {
+            return None;
+        }
+
+        let errors: Vec<Value> = validation_errors
+            .iter()
+            .map(|error| {
+                let mut rendered = self
+                    .messages
+                    .error(&error.message, request.accept_language.as_deref());
+                // Messages of custom rules aren't in the catalog
+                if custom {
+                    rendered["extensions"]["code"] = json!("GRAPHQL_VALIDATION_FAILED");
+                }
+                rendered["locations"] = json!([{ "line": error.line, "column": error.column }]);
+                rendered
+            })
+            .collect();
+        Some(json!({ "errors": errors }))
+    }
+
+    // Purges the plan and entity caches, returning how many entries each lost
+    pub fn invalidate_caches(&self, invalidation: &CacheInvalidation) -> Value {
+        let plans: usize = invalidation
+            .query_hash_prefixes
+            .iter()
+            .map(|prefix| self.query_planner.invalidate(prefix))
+            .sum();
+        let entities = self.query_executor.invalidate_entities(invalidation);
+
+        json!({ "invalidated": { "plans": plans, "entities": entities } })
+    }
+
+    // Process metrics followed by the executor's, in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let mut metrics = memory::render_process_metrics();
+        metrics.push_str(&self.deprecated_field_usage.render_metrics());
+        metrics.push_str(&self.query_planner.metrics());
+        metrics.push_str(&self.query_executor.metrics());
+        metrics
+    }
+
+    // The schema requests are served with, once it's loaded
+    async fn current_schema(&self) -> Result<FederatedSchema, String> {
+        if !self.is_schema_ready() {
+            return Err("Schema is not ready yet, it is still being loaded".to_string());
+        }
+        let schema_registry = self.schema_registry.read().await;
+        schema_registry.get_schema().await
+    }
+
+    // Whether a schema is being served, and why the latest registrations
+    // failed to compose when an older schema is served in their place
+    pub async fn schema_health(&self) -> Value {
+        if !self.is_schema_ready() {
+            return json!({
+                "status": "loading",
+                "generation": null,
+                "compositionError": null,
+                "source": null,
+            });
+        }
+        let schema_registry = self.schema_registry.read().await;
+        let schema = schema_registry.get_schema().await;
+        let composition_error = schema_registry.composition_error().await;
+
+        let status = match (&schema, &composition_error) {
+            (Err(_), _) => "unavailable",
+            (Ok(_), Some(_)) => "degraded",
+            (Ok(_), None) => "ok",
+        };
+        json!({
+            "status": status,
+            "generation": schema.as_ref().ok().map(|schema| schema.generation),
+            "compositionError": composition_error.or(schema.err()),
+            "source": self.supergraph_sources.active().map(SupergraphSource::describe),
+        })
     }
 
     pub async fn register_service(&self, service: ServiceConfig) -> Result<(), String> {
@@ -67,34 +950,96 @@ impl FederationGateway {
         schema_registry.register_service(service).await
     }
 
+    // Registers the subgraphs of the first supergraph source that loads
     pub async fn load_schemas(&self) -> Result<(), String> {
-        let config_path = Path::new("./schemas/supergraph.yaml");
-        let config_dir = config_path.parent().unwrap_or_else(|| Path::new(""));
-        println!("Config path: {:?}", config_path);
-
-        let config_contents = fs::read_to_string(config_path)
-            .map_err(|e| format!("Failed to read config file: {}", e))?;
-        let config: SupergraphConfig = serde_yaml::from_str(&config_contents)
-            .map_err(|e| format!("Failed to parse config file: {}", e))?;
-
-        for (name, subgraph_config) in config.subgraphs {
-            let schema_content = read_schema_file(config_dir, &subgraph_config.schema.file)
-                .map_err(|e| format!("Failed to read schema file: {}", e))?;
-
-            let service_config = ServiceConfig {
-                name,
-                url: subgraph_config.routing_url,
-                schema: schema_content,
-            };
+        self.load_supergraph(usize::MAX).await.map(|_| ())
+    }
 
-            self.register_service(service_config).await?;
+    // Switches back to a source listed before the active one once it loads
+    // again, returning the source switched to
+    pub async fn retry_preferred_sources(&self) -> Result<Option<String>, String> {
+        match self.supergraph_sources.active_index() {
+            Some(active) if active > 0 => self.load_supergraph(active).await.map(Some),
+            _ => Ok(None),
         }
-        Ok(())
     }
+
+    pub fn supergraph_sources(&self) -> &SupergraphSources {
+        &self.supergraph_sources
+    }
+
+    // Loads the first source before `until`, replacing the services of the
+    // previously active source
+    async fn load_supergraph(&self, until: usize) -> Result<String, String> {
+        let (index, services) = self.supergraph_sources.fetch(until).await?;
+        let mut schema_registry = self.schema_registry.write().await;
+        for service in &services {
+            schema_registry.register_service(service.clone()).await?;
+        }
+        for name in self.supergraph_sources.activate(index, &services) {
+            schema_registry.unregister_service(&name).await?;
+        }
+
+        let source = self
+            .supergraph_sources
+            .active()
+            .map(SupergraphSource::describe)
+            .unwrap_or_default();
+        info!("Loaded the supergraph from {}", source);
+        Ok(source)
+    }
+}
+
+// Whether the operation the request selects is a subscription
+pub fn is_subscription(query: &str, operation_name: Option<&str>) -> bool {
+    if !query.contains("subscription") {
+        return false;
+    }
+    let Ok(doc) = graphql_parser::query::parse_query::<String>(query) else {
+        return false;
+    };
+
+    doc.definitions.iter().any(|def| match def {
+        Definition::Operation(OperationDefinition::Subscription(s)) => {
+            operation_name.is_none() || s.name.as_deref() == operation_name
+        }
+        _ => false,
+    })
+}
+
+// Whether the selected operation carries `@noCache`, e.g. `query Feed @noCache { ... }`
+// Clients are metered by API key, or by name without one
+fn metered_client<'r>(
+    request: &'r GraphQLRequest,
+    api_key: Option<&'r ApiKey>,
+) -> Option<&'r String> {
+    api_key
+        .map(|api_key| &api_key.id)
+        .or_else(|| {
+            request
+                .auth_headers
+                .as_ref()
+                .and_then(|headers| headers.get("x-api-key"))
+        })
+        .or(request.client_name.as_ref())
 }
 
-fn read_schema_file(base_dir: &Path, file_path: &str) -> io::Result<String> {
-    let full_path = base_dir.join(file_path);
-    println!("Reading schema file: {:?}", full_path);
-    fs::read_to_string(full_path)
+fn has_no_cache_directive(query: &str, operation_name: Option<&str>) -> bool {
+    if !query.contains("@noCache") {
+        return false;
+    }
+    let Ok(doc) = graphql_parser::query::parse_query::<String>(query) else {
+        return false;
+    };
+
+    doc.definitions.iter().any(|def| {
+        let (name, directives) = match def {
+            Definition::Operation(OperationDefinition::Query(q)) => (&q.name, &q.directives),
+            Definition::Operation(OperationDefinition::Mutation(m)) => (&m.name, &m.directives),
+            Definition::Operation(OperationDefinition::Subscription(s)) => (&s.name, &s.directives),
+            _ => return false,
+        };
+        (operation_name.is_none() || name.as_deref() == operation_name)
+            && directives.iter().any(|d| d.name == "noCache")
+    })
 }
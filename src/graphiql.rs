@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Where the IDE page loads React and GraphiQL from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphiqlAssets {
+    #[default]
+    Cdn,
+    // Served by the gateway under `/graphiql/assets/`, for air-gapped
+    // deployments without access to unpkg
+    Bundled,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphiqlConfig {
+    pub assets: GraphiqlAssets,
+}
+
+pub const ASSETS_PATH: &str = "/graphiql/assets/";
+
+// Asset file name -> CDN URL, in the order the page loads them
+const CDN_URLS: [(&str, &str); 4] = [
+    (
+        "graphiql.min.css",
+        "https://unpkg.com/graphiql@1.5.0/graphiql.min.css",
+    ),
+    (
+        "react.production.min.js",
+        "https://unpkg.com/react@17.0.2/umd/react.production.min.js",
+    ),
+    (
+        "react-dom.production.min.js",
+        "https://unpkg.com/react-dom@17.0.2/umd/react-dom.production.min.js",
+    ),
+    (
+        "graphiql.min.js",
+        "https://unpkg.com/graphiql@1.5.0/graphiql.min.js",
+    ),
+];
+
+// The assets fetched into `assets/graphiql` at build time: name, ETag,
+// content, and the content gzipped and brotli compressed
+type BundledAsset = (
+    &'static str,
+    &'static str,
+    &'static [u8],
+    &'static [u8],
+    &'static [u8],
+);
+static BUNDLED: &[BundledAsset] = include!(concat!(env!("OUT_DIR"), "/graphiql_assets.rs"));
+
+pub struct Asset {
+    pub content_type: &'static str,
+    // Quoted, as sent in the `ETag` header
+    pub etag: &'static str,
+    content: &'static [u8],
+    gzip: &'static [u8],
+    brotli: &'static [u8],
+}
+
+const HTML: &str = r#"
+<!DOCTYPE html>
+<html>
+<head>
+  <title>GraphiQL - Portkey Federation Gateway</title>
+  <link href="{graphiql.min.css}" rel="stylesheet" />
+  <style>
+    body { margin: 0; padding: 0; height: 100vh; }
+    #graphiql { height: 100vh; }
+  </style>
+</head>
+<body>
+  <div id="graphiql"></div>
+
+  <script src="{react.production.min.js}"></script>
+  <script src="{react-dom.production.min.js}"></script>
+  <script src="{graphiql.min.js}"></script>
+  <script>
+
+    const token = localStorage.getItem('auth_token') || '';
+
+
+    function graphQLFetcher(graphQLParams) {
+      return fetch('/graphql', {
+        method: 'post',
+        headers: {
+          'Content-Type': 'application/json',
+
+          'Authorization': token ? `Bearer ${token}` : '',
+        },
+        body: JSON.stringify(graphQLParams),
+      }).then(response => response.json());
+    }
+
+
+    ReactDOM.render(
+      React.createElement(GraphiQL, { fetcher: graphQLFetcher }),
+      document.getElementById('graphiql')
+    );
+  </script>
+</body>
+</html>
+"#;
+
+impl GraphiqlConfig {
+    // The IDE page, loading its assets as configured
+    pub fn html(&self) -> String {
+        CDN_URLS
+            .iter()
+            .fold(HTML.to_string(), |html, (name, cdn_url)| {
+                let url = match self.assets {
+                    GraphiqlAssets::Cdn => cdn_url.to_string(),
+                    GraphiqlAssets::Bundled => format!("{}{}", ASSETS_PATH, name),
+                };
+                html.replace(&format!("{{{}}}", name), &url)
+            })
+    }
+
+    // ETag of the page, which only changes with the configuration
+    pub fn etag(&self) -> String {
+        format!(
+            "\"{}\"",
+            &hex::encode(Sha256::digest(self.html().as_bytes()))[..16]
+        )
+    }
+
+    // Fails in bundled mode when the binary was built without the assets
+    pub fn validate(&self) -> Result<(), String> {
+        let missing = missing_assets();
+        if self.assets == GraphiqlAssets::Bundled && !missing.is_empty() {
+            return Err(format!(
+                "GraphiQL assets {} were not bundled, run assets/graphiql/fetch.sh and rebuild",
+                missing.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+// A bundled asset by file name
+pub fn asset(name: &str) -> Option<Asset> {
+    let (_, etag, content, gzip, brotli) = BUNDLED.iter().find(|(asset, ..)| *asset == name)?;
+    let content_type = if name.ends_with(".css") {
+        "text/css"
+    } else {
+        "application/javascript"
+    };
+    Some(Asset {
+        content_type,
+        etag,
+        content,
+        gzip,
+        brotli,
+    })
+}
+
+impl Asset {
+    // The content in the best encoding the client accepts, brotli over gzip,
+    // along with the `Content-Encoding` it's sent with
+    pub fn encoded(&self, accept_encoding: Option<&str>) -> (Option<&'static str>, &'static [u8]) {
+        let accept_encoding = accept_encoding.unwrap_or_default();
+        if accepts(accept_encoding, "br") {
+            (Some("br"), self.brotli)
+        } else if accepts(accept_encoding, "gzip") {
+            (Some("gzip"), self.gzip)
+        } else {
+            (None, self.content)
+        }
+    }
+}
+
+// Whether an `If-None-Match` header lists `etag`, so the client's copy is
+// still current
+pub fn is_fresh(if_none_match: Option<&str>, etag: &str) -> bool {
+    if_none_match.is_some_and(|header| {
+        header
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*")
+    })
+}
+
+// Whether an `Accept-Encoding` header accepts `encoding` with a non-zero
+// quality, by name or else through `*`
+fn accepts(accept_encoding: &str, encoding: &str) -> bool {
+    let quality = |name: &str| {
+        accept_encoding.split(',').find_map(|item| {
+            let mut parts = item.split(';');
+            parts.next()?.trim().eq_ignore_ascii_case(name).then(|| {
+                parts
+                    .find_map(|param| param.trim().strip_prefix("q=")?.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0)
+            })
+        })
+    };
+    quality(encoding)
+        .or_else(|| quality("*"))
+        .is_some_and(|q| q > 0.0)
+}
+
+pub fn missing_assets() -> Vec<&'static str> {
+    CDN_URLS
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| asset(name).is_none())
+        .collect()
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+// Incremental delivery over multipart HTTP: every payload is a JSON part, the
+// ones after the first carry `incremental` results, and `hasNext` tells the
+// client whether more parts follow
+pub const MULTIPART_CONTENT_TYPE: &str = "multipart/mixed; boundary=\"-\"; deferSpec=20220824";
+
+// Closes the multipart body after the last part
+pub const MULTIPART_END: &str = "\r\n-----\r\n";
+
+// Whether the client's `Accept` header takes incremental delivery
+pub fn accepts_multipart(accept: &str) -> bool {
+    accept
+        .split(',')
+        .any(|media_type| media_type.trim().starts_with("multipart/mixed"))
+}
+
+pub fn multipart_part(payload: &Value) -> String {
+    format!(
+        "\r\n---\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{}",
+        payload
+    )
+}
+
+// Sends large top-level lists a chunk at a time to clients accepting
+// multipart responses, as if the client had asked for `@stream`, so the first
+// items arrive without waiting for the whole list to be written and parsed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListStreamingConfig {
+    pub enabled: bool,
+    // Lists with fewer items are sent whole
+    pub min_items: usize,
+    // Items in the initial payload and in every part after it
+    pub chunk_size: usize,
+}
+
+impl Default for ListStreamingConfig {
+    fn default() -> Self {
+        ListStreamingConfig {
+            enabled: false,
+            min_items: 1000,
+            chunk_size: 100,
+        }
+    }
+}
+
+impl ListStreamingConfig {
+    // The payloads to send for `response`: the response itself when no list
+    // is large enough, otherwise the response with the first chunk of every
+    // large list followed by one payload per remaining chunk
+    pub fn chunk_lists(&self, mut response: Value) -> Vec<Value> {
+        let chunk_size = self.chunk_size.max(1);
+        let Some(data) = response.get_mut("data").and_then(Value::as_object_mut) else {
+            return vec![response];
+        };
+
+        let mut streamed: Vec<(String, Vec<Value>)> = Vec::new();
+        for (key, value) in data.iter_mut() {
+            if let Value::Array(items) = value
+                && items.len() >= self.min_items
+                && items.len() > chunk_size
+            {
+                let rest = items.split_off(chunk_size);
+                streamed.push((key.clone(), rest));
+            }
+        }
+        if streamed.is_empty() {
+            return vec![response];
+        }
+
+        let mut payloads = Vec::new();
+        if let Value::Object(obj) = &mut response {
+            obj.insert("hasNext".to_string(), json!(true));
+        }
+        payloads.push(response);
+
+        for (key, rest) in streamed {
+            let mut index = chunk_size;
+            for chunk in rest.chunks(chunk_size) {
+                payloads.push(json!({
+                    "incremental": [{ "items": chunk, "path": [key, index] }],
+                    "hasNext": true,
+                }));
+                index += chunk.len();
+            }
+        }
+        if let Some(last) = payloads.last_mut() {
+            last["hasNext"] = json!(false);
+        }
+
+        payloads
+    }
+}
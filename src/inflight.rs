@@ -0,0 +1,105 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::oneshot;
+
+use crate::memory::write_metric;
+
+type Waiters = Vec<oneshot::Sender<Result<Value, String>>>;
+
+// Fetches in flight by key. A fetch made while an identical one is in
+// flight waits for that one's result instead of being sent, so concurrent
+// requests asking a service the same thing cost it a single request.
+#[derive(Default)]
+pub struct InflightFetches {
+    pending: Mutex<HashMap<String, Waiters>>,
+    deduplicated: AtomicU64,
+}
+
+// Clears the key of a fetch that ends without completing, e.g. when its
+// request is dropped, so the fetches waiting for it make their own
+struct Leader<'a> {
+    fetches: &'a InflightFetches,
+    key: Option<String>,
+}
+
+impl Leader<'_> {
+    fn complete(mut self, result: &Result<Value, String>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let waiters = self.fetches.pending.lock().unwrap().remove(&key);
+        for waiter in waiters.into_iter().flatten() {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = &self.key {
+            self.fetches.pending.lock().unwrap().remove(key);
+        }
+    }
+}
+
+impl InflightFetches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs `fetch`, or waits for the result of the fetch in flight under
+    // `key`. Whether the result was shared is returned with it.
+    pub async fn run<F>(&self, key: String, fetch: F) -> (Result<Value, String>, bool)
+    where
+        F: Future<Output = Result<Value, String>>,
+    {
+        let waiting = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.get_mut(&key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    pending.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = waiting {
+            return match receiver.await {
+                Ok(result) => {
+                    self.deduplicated.fetch_add(1, Ordering::Relaxed);
+                    (result, true)
+                }
+                // The fetch waited for was dropped before it completed
+                Err(_) => (fetch.await, false),
+            };
+        }
+
+        let leader = Leader {
+            fetches: self,
+            key: Some(key),
+        };
+        let result = fetch.await;
+        leader.complete(&result);
+        (result, false)
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let mut metrics = String::new();
+        write_metric(
+            &mut metrics,
+            "portkey_deduplicated_fetches_total",
+            "counter",
+            "Fetches answered with the result of an identical fetch in flight.",
+            self.deduplicated.load(Ordering::Relaxed),
+        );
+        metrics
+    }
+}
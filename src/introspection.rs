@@ -0,0 +1,549 @@
+use graphql_parser::{query, schema};
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, HashMap};
+
+const BUILTIN_SCALARS: [&str; 5] = ["Int", "Float", "String", "Boolean", "ID"];
+const DEFAULT_DEPRECATION_REASON: &str = "No longer supported";
+
+// Root fields answered from the composed schema instead of a subgraph
+pub fn is_introspection_field(field_name: &str) -> bool {
+    field_name == "__schema" || field_name == "__type"
+}
+
+// The composed schema in introspection form. Types are stored as complete
+// `__Type` objects; references to other types inside them only carry `kind`,
+// `name` and `ofType` and are expanded by name when a query selects more.
+#[derive(Debug, Default)]
+pub struct IntrospectionSchema {
+    types: BTreeMap<String, Value>,
+}
+
+// The parts of a type definition or extension the introspection cares about
+struct TypeParts<'d, 'a> {
+    kind: &'static str,
+    name: &'d str,
+    description: Option<&'d String>,
+    fields: &'d [schema::Field<'a, String>],
+    interfaces: &'d [String],
+    members: &'d [String],
+    enum_values: &'d [schema::EnumValue<'a, String>],
+    input_fields: &'d [schema::InputValue<'a, String>],
+}
+
+impl<'d> TypeParts<'d, '_> {
+    fn new(kind: &'static str, name: &'d str) -> Self {
+        TypeParts {
+            kind,
+            name,
+            description: None,
+            fields: &[],
+            interfaces: &[],
+            members: &[],
+            enum_values: &[],
+            input_fields: &[],
+        }
+    }
+}
+
+impl IntrospectionSchema {
+    // Merges the type definitions and extensions of every subgraph. Types
+    // declared by several subgraphs end up with the union of their fields;
+    // federation internals such as `_entities` and `_Any` are left out.
+    pub fn compose(documents: &[schema::Document<String>]) -> Self {
+        let parts: Vec<TypeParts> = documents
+            .iter()
+            .flat_map(|document| &document.definitions)
+            .filter_map(type_parts)
+            .filter(|parts| !is_federation_internal(parts.name))
+            .collect();
+
+        let mut kinds: HashMap<&str, &str> = BUILTIN_SCALARS
+            .iter()
+            .map(|scalar| (*scalar, "SCALAR"))
+            .collect();
+        for parts in &parts {
+            kinds.entry(parts.name).or_insert(parts.kind);
+        }
+
+        let mut types = BTreeMap::new();
+        for scalar in BUILTIN_SCALARS {
+            types.insert(scalar.to_string(), full_type("SCALAR", scalar, None));
+        }
+
+        for parts in &parts {
+            let ty = types
+                .entry(parts.name.to_string())
+                .or_insert_with(|| full_type(parts.kind, parts.name, parts.description));
+            if ty["description"].is_null()
+                && let Some(description) = parts.description
+            {
+                ty["description"] = json!(description);
+            }
+
+            for field in parts.fields {
+                if parts.name == "Query" && is_federation_internal(&field.name) {
+                    continue;
+                }
+                push_unique(&mut ty["fields"], field_value(field, &kinds));
+            }
+            for interface in parts.interfaces {
+                push_unique(&mut ty["interfaces"], type_ref(interface, &kinds));
+            }
+            for member in parts.members {
+                push_unique(&mut ty["possibleTypes"], type_ref(member, &kinds));
+            }
+            for value in parts.enum_values {
+                push_unique(&mut ty["enumValues"], enum_value(value));
+            }
+            for input_field in parts.input_fields {
+                push_unique(&mut ty["inputFields"], input_value(input_field, &kinds));
+            }
+        }
+
+        // Interfaces list the object types implementing them
+        let implementations: Vec<(String, Value)> = types
+            .values()
+            .filter(|ty| ty["kind"] == "OBJECT")
+            .flat_map(|ty| {
+                ty["interfaces"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|interface| interface["name"].as_str())
+                    .map(|interface| (interface.to_string(), type_ref_of(ty)))
+            })
+            .collect();
+        for (interface, implementation) in implementations {
+            if let Some(ty) = types.get_mut(&interface) {
+                push_unique(&mut ty["possibleTypes"], implementation);
+            }
+        }
+
+        IntrospectionSchema { types }
+    }
+
+    pub fn get_type(&self, name: &str) -> Option<&Value> {
+        self.types.get(name)
+    }
+
+    fn schema_value(&self) -> Value {
+        let root = |name: &str| self.types.get(name).map(type_ref_of).unwrap_or(Value::Null);
+
+        json!({
+            "description": null,
+            "queryType": root("Query"),
+            "mutationType": root("Mutation"),
+            "subscriptionType": root("Subscription"),
+            "types": self.types.values().cloned().collect::<Vec<_>>(),
+            "directives": builtin_directives(),
+        })
+    }
+
+    // Answers the `__schema` and `__type` root fields of the selected
+    // operation, keyed by response key. Other root fields are ignored.
+    pub fn resolve(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Option<Value>,
+    ) -> Result<Map<String, Value>, String> {
+        let doc = query::parse_query::<String>(query)
+            .map_err(|e| format!("Failed to parse query: {}", e))?;
+
+        let fragments: HashMap<&str, &query::FragmentDefinition<String>> = doc
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                query::Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
+
+        let selection_set = doc.definitions.iter().find_map(|def| match def {
+            query::Definition::Operation(query::OperationDefinition::SelectionSet(s))
+                if operation_name.is_none() =>
+            {
+                Some(s)
+            }
+            query::Definition::Operation(query::OperationDefinition::Query(q))
+                if operation_name.is_none() || q.name.as_deref() == operation_name =>
+            {
+                Some(&q.selection_set)
+            }
+            _ => None,
+        });
+
+        let resolver = Resolver {
+            schema: self,
+            fragments: &fragments,
+            variables,
+        };
+        let mut data = Map::new();
+
+        for field in selection_set
+            .map(|selection_set| resolver.collect_fields(selection_set))
+            .unwrap_or_default()
+        {
+            let value = match field.name.as_str() {
+                "__schema" => {
+                    resolver.project(&self.schema_value(), &field.selection_set, "__Schema")
+                }
+                "__type" => {
+                    let name = resolver.argument(field, "name");
+                    match name.as_str().and_then(|name| self.types.get(name)) {
+                        Some(ty) => resolver.project(ty, &field.selection_set, "__Type"),
+                        None => Value::Null,
+                    }
+                }
+                _ => continue,
+            };
+
+            data.insert(
+                field.alias.clone().unwrap_or_else(|| field.name.clone()),
+                value,
+            );
+        }
+
+        Ok(data)
+    }
+}
+
+struct Resolver<'r, 'q> {
+    schema: &'r IntrospectionSchema,
+    fragments: &'r HashMap<&'q str, &'r query::FragmentDefinition<'q, String>>,
+    variables: &'r Option<Value>,
+}
+
+impl<'r, 'q> Resolver<'r, 'q> {
+    // Introspection types are never abstract, so every fragment applies
+    fn collect_fields(
+        &self,
+        selection_set: &'r query::SelectionSet<'q, String>,
+    ) -> Vec<&'r query::Field<'q, String>> {
+        let mut fields = Vec::new();
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => fields.push(field),
+                query::Selection::InlineFragment(fragment) => {
+                    fields.extend(self.collect_fields(&fragment.selection_set))
+                }
+                query::Selection::FragmentSpread(spread) => {
+                    if let Some(fragment) = self.fragments.get(spread.fragment_name.as_str()) {
+                        fields.extend(self.collect_fields(&fragment.selection_set));
+                    }
+                }
+            }
+        }
+        fields
+    }
+
+    fn argument(&self, field: &query::Field<String>, name: &str) -> Value {
+        match field.arguments.iter().find(|(arg, _)| arg == name) {
+            Some((_, query::Value::Variable(var_name))) => self
+                .variables
+                .as_ref()
+                .and_then(|vars| vars.get(var_name))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Some((_, query::Value::String(s))) => json!(s),
+            Some((_, query::Value::Boolean(b))) => json!(b),
+            _ => Value::Null,
+        }
+    }
+
+    fn project(
+        &self,
+        value: &Value,
+        selection_set: &'r query::SelectionSet<'q, String>,
+        type_name: &str,
+    ) -> Value {
+        let obj = match value {
+            Value::Array(items) => {
+                return Value::Array(
+                    items
+                        .iter()
+                        .map(|item| self.project(item, selection_set, type_name))
+                        .collect(),
+                );
+            }
+            Value::Object(obj) => obj,
+            other => return other.clone(),
+        };
+
+        // Type references only hold kind, name and ofType
+        let obj = match obj.get("name").and_then(Value::as_str) {
+            Some(name) if type_name == "__Type" => self
+                .schema
+                .types
+                .get(name)
+                .and_then(Value::as_object)
+                .unwrap_or(obj),
+            _ => obj,
+        };
+
+        let mut projected = Map::new();
+        for field in self.collect_fields(selection_set) {
+            let response_key = field.alias.clone().unwrap_or_else(|| field.name.clone());
+            if field.name == "__typename" {
+                projected.insert(response_key, json!(type_name));
+                continue;
+            }
+
+            let mut value = obj.get(&field.name).cloned().unwrap_or(Value::Null);
+            if matches!(
+                field.name.as_str(),
+                "fields" | "enumValues" | "inputFields" | "args"
+            ) && self.argument(field, "includeDeprecated") != json!(true)
+                && let Value::Array(items) = &mut value
+            {
+                items.retain(|item| item["isDeprecated"] != json!(true));
+            }
+
+            let value = match child_type(type_name, &field.name) {
+                Some(child_type) => self.project(&value, &field.selection_set, child_type),
+                None => value,
+            };
+            projected.insert(response_key, value);
+        }
+
+        Value::Object(projected)
+    }
+}
+
+fn child_type(type_name: &str, field_name: &str) -> Option<&'static str> {
+    match (type_name, field_name) {
+        ("__Schema", "types" | "queryType" | "mutationType" | "subscriptionType") => Some("__Type"),
+        ("__Schema", "directives") => Some("__Directive"),
+        ("__Type", "fields") => Some("__Field"),
+        ("__Type", "interfaces" | "possibleTypes" | "ofType") => Some("__Type"),
+        ("__Type", "enumValues") => Some("__EnumValue"),
+        ("__Type", "inputFields") => Some("__InputValue"),
+        ("__Field" | "__Directive", "args") => Some("__InputValue"),
+        ("__Field" | "__InputValue", "type") => Some("__Type"),
+        _ => None,
+    }
+}
+
+fn type_parts<'d, 'a>(definition: &'d schema::Definition<'a, String>) -> Option<TypeParts<'d, 'a>> {
+    use schema::{Definition, TypeDefinition, TypeExtension};
+
+    let parts = match definition {
+        Definition::TypeDefinition(TypeDefinition::Scalar(scalar)) => TypeParts {
+            description: scalar.description.as_ref(),
+            ..TypeParts::new("SCALAR", &scalar.name)
+        },
+        Definition::TypeDefinition(TypeDefinition::Object(obj)) => TypeParts {
+            description: obj.description.as_ref(),
+            fields: &obj.fields,
+            interfaces: &obj.implements_interfaces,
+            ..TypeParts::new("OBJECT", &obj.name)
+        },
+        Definition::TypeDefinition(TypeDefinition::Interface(iface)) => TypeParts {
+            description: iface.description.as_ref(),
+            fields: &iface.fields,
+            interfaces: &iface.implements_interfaces,
+            ..TypeParts::new("INTERFACE", &iface.name)
+        },
+        Definition::TypeDefinition(TypeDefinition::Union(union_type)) => TypeParts {
+            description: union_type.description.as_ref(),
+            members: &union_type.types,
+            ..TypeParts::new("UNION", &union_type.name)
+        },
+        Definition::TypeDefinition(TypeDefinition::Enum(enum_type)) => TypeParts {
+            description: enum_type.description.as_ref(),
+            enum_values: &enum_type.values,
+            ..TypeParts::new("ENUM", &enum_type.name)
+        },
+        Definition::TypeDefinition(TypeDefinition::InputObject(input)) => TypeParts {
+            description: input.description.as_ref(),
+            input_fields: &input.fields,
+            ..TypeParts::new("INPUT_OBJECT", &input.name)
+        },
+        Definition::TypeExtension(TypeExtension::Object(ext)) => TypeParts {
+            fields: &ext.fields,
+            interfaces: &ext.implements_interfaces,
+            ..TypeParts::new("OBJECT", &ext.name)
+        },
+        Definition::TypeExtension(TypeExtension::Interface(ext)) => TypeParts {
+            fields: &ext.fields,
+            ..TypeParts::new("INTERFACE", &ext.name)
+        },
+        Definition::TypeExtension(TypeExtension::Union(ext)) => TypeParts {
+            members: &ext.types,
+            ..TypeParts::new("UNION", &ext.name)
+        },
+        Definition::TypeExtension(TypeExtension::Enum(ext)) => TypeParts {
+            enum_values: &ext.values,
+            ..TypeParts::new("ENUM", &ext.name)
+        },
+        Definition::TypeExtension(TypeExtension::InputObject(ext)) => TypeParts {
+            input_fields: &ext.fields,
+            ..TypeParts::new("INPUT_OBJECT", &ext.name)
+        },
+        _ => return None,
+    };
+
+    Some(parts)
+}
+
+// `_Any`, `_Entity`, `_Service`, `_entities`, ... but not `__typename` and friends
+fn is_federation_internal(name: &str) -> bool {
+    name.starts_with('_') && !name.starts_with("__")
+}
+
+fn push_unique(list: &mut Value, item: Value) {
+    if !list.is_array() {
+        *list = json!([]);
+    }
+    if let Value::Array(items) = list
+        && !items
+            .iter()
+            .any(|existing| existing["name"] == item["name"])
+    {
+        items.push(item);
+    }
+}
+
+fn full_type(kind: &str, name: &str, description: Option<&String>) -> Value {
+    let list_for = |kinds: &[&str]| {
+        if kinds.contains(&kind) {
+            json!([])
+        } else {
+            Value::Null
+        }
+    };
+
+    json!({
+        "kind": kind,
+        "name": name,
+        "description": description,
+        "specifiedByURL": null,
+        "fields": list_for(&["OBJECT", "INTERFACE"]),
+        "interfaces": list_for(&["OBJECT", "INTERFACE"]),
+        "possibleTypes": list_for(&["INTERFACE", "UNION"]),
+        "enumValues": list_for(&["ENUM"]),
+        "inputFields": list_for(&["INPUT_OBJECT"]),
+        "ofType": null,
+    })
+}
+
+fn type_ref(name: &str, kinds: &HashMap<&str, &str>) -> Value {
+    json!({
+        "kind": kinds.get(name).copied().unwrap_or("OBJECT"),
+        "name": name,
+        "ofType": null,
+    })
+}
+
+fn type_ref_of(ty: &Value) -> Value {
+    json!({ "kind": ty["kind"], "name": ty["name"], "ofType": null })
+}
+
+fn wrapped_type_ref(field_type: &schema::Type<String>, kinds: &HashMap<&str, &str>) -> Value {
+    match field_type {
+        schema::Type::NamedType(name) => type_ref(name, kinds),
+        schema::Type::ListType(inner) => json!({
+            "kind": "LIST",
+            "name": null,
+            "ofType": wrapped_type_ref(inner, kinds),
+        }),
+        schema::Type::NonNullType(inner) => json!({
+            "kind": "NON_NULL",
+            "name": null,
+            "ofType": wrapped_type_ref(inner, kinds),
+        }),
+    }
+}
+
+fn deprecation(directives: &[schema::Directive<String>]) -> (bool, Value) {
+    match directives.iter().find(|d| d.name == "deprecated") {
+        Some(directive) => {
+            let reason = directive
+                .arguments
+                .iter()
+                .find_map(|(name, value)| match value {
+                    schema::Value::String(reason) if name == "reason" => Some(reason.as_str()),
+                    _ => None,
+                })
+                .unwrap_or(DEFAULT_DEPRECATION_REASON);
+            (true, json!(reason))
+        }
+        None => (false, Value::Null),
+    }
+}
+
+fn field_value(field: &schema::Field<String>, kinds: &HashMap<&str, &str>) -> Value {
+    let (is_deprecated, deprecation_reason) = deprecation(&field.directives);
+    json!({
+        "name": field.name,
+        "description": field.description,
+        "args": field
+            .arguments
+            .iter()
+            .map(|arg| input_value(arg, kinds))
+            .collect::<Vec<_>>(),
+        "type": wrapped_type_ref(&field.field_type, kinds),
+        "isDeprecated": is_deprecated,
+        "deprecationReason": deprecation_reason,
+    })
+}
+
+fn input_value(input: &schema::InputValue<String>, kinds: &HashMap<&str, &str>) -> Value {
+    let (is_deprecated, deprecation_reason) = deprecation(&input.directives);
+    json!({
+        "name": input.name,
+        "description": input.description,
+        "type": wrapped_type_ref(&input.value_type, kinds),
+        "defaultValue": input.default_value.as_ref().map(|value| value.to_string()),
+        "isDeprecated": is_deprecated,
+        "deprecationReason": deprecation_reason,
+    })
+}
+
+fn enum_value(value: &schema::EnumValue<String>) -> Value {
+    let (is_deprecated, deprecation_reason) = deprecation(&value.directives);
+    json!({
+        "name": value.name,
+        "description": value.description,
+        "isDeprecated": is_deprecated,
+        "deprecationReason": deprecation_reason,
+    })
+}
+
+fn builtin_directives() -> Value {
+    let condition = |name: &str, description: &str| {
+        json!({
+            "name": name,
+            "description": description,
+            "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"],
+            "args": [{
+                "name": "if",
+                "description": null,
+                "type": { "kind": "NON_NULL", "name": null, "ofType": { "kind": "SCALAR", "name": "Boolean", "ofType": null } },
+                "defaultValue": null,
+                "isDeprecated": false,
+                "deprecationReason": null,
+            }],
+            "isRepeatable": false,
+        })
+    };
+
+    json!([
+        condition("include", "Directs the executor to include this field or fragment only when the `if` argument is true."),
+        condition("skip", "Directs the executor to skip this field or fragment when the `if` argument is true."),
+        {
+            "name": "deprecated",
+            "description": "Marks an element of a GraphQL schema as no longer supported.",
+            "locations": ["FIELD_DEFINITION", "ARGUMENT_DEFINITION", "INPUT_FIELD_DEFINITION", "ENUM_VALUE"],
+            "args": [{
+                "name": "reason",
+                "description": null,
+                "type": { "kind": "SCALAR", "name": "String", "ofType": null },
+                "defaultValue": format!("\"{}\"", DEFAULT_DEPRECATION_REASON),
+                "isDeprecated": false,
+                "deprecationReason": null,
+            }],
+            "isRepeatable": false,
+        },
+    ])
+}
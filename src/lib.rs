@@ -1,7 +1,52 @@
+pub mod api_keys;
+pub mod broker;
+pub mod build_info;
+pub mod complexity;
+pub mod config;
+#[cfg(feature = "test-suite")]
+pub mod conformance;
+pub mod connections;
+pub mod cors;
+pub mod demo;
+pub mod deprecation;
+pub mod entity_cache;
+pub mod envelope;
 pub mod federation_gateway;
+pub mod graphiql;
+pub mod incremental;
+pub mod inflight;
+pub mod intern;
+pub mod introspection;
+pub mod literal;
+pub mod logging;
+pub mod memory;
+pub mod messages;
+pub mod operation;
+pub mod pagination;
+pub mod persisted_queries;
+pub mod plan_cache;
+pub mod planner_experiment;
+pub mod quarantine;
 pub mod query_executor;
+pub mod query_limits;
 pub mod query_planner;
+pub mod quota;
+pub mod request_context;
+pub mod request_journal;
+pub mod request_mirror;
+pub mod response_cache;
+pub mod s3;
 pub mod schema_registry;
+pub mod schema_trim;
+pub mod service_health;
+pub mod service_selector;
+pub mod subgraph_hook;
+pub mod subgraph_log;
+pub mod subscription_limit;
+pub mod supergraph_source;
+pub mod upload;
+pub mod validation;
+pub mod websocket;
 
 pub use federation_gateway::FederationGateway;
 pub use query_executor::HttpQueryExecutor;
@@ -9,34 +54,832 @@ pub use query_planner::SimpleQueryPlanner;
 pub use schema_registry::InMemorySchemaRegistry;
 
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
 type ServiceMap = HashMap<String, ServiceConfig>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ServiceConfig {
     pub name: String,
     pub url: String,
     pub schema: String,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub capabilities: ServiceCapabilities,
+    // Region -> URL of the service's deployment in that region
+    #[serde(default)]
+    pub regions: BTreeMap<String, String>,
+    // Prefix the service's root fields are exposed under, e.g. `users_` turns
+    // its `search` into `users_search`, so services declaring the same root
+    // field can be told apart. Stripped again before forwarding.
+    #[serde(default)]
+    pub namespace: Option<String>,
+    // Root fields several services declare are routed to the service with
+    // the highest priority, ties go to the first service by name
+    #[serde(default)]
+    pub priority: i32,
+    // Fields this service resolves for types of other services without
+    // federation `@key`s
+    #[serde(default)]
+    pub joins: Vec<JoinConfig>,
+    // Sends the values of the variables as literals in the query instead,
+    // for services mishandling variables or caching on the query text alone
+    #[serde(default)]
+    pub inline_variables: bool,
+    // Sent with every request to the service whatever the client sent, e.g.
+    // an internal API key or a `Host` override. They replace forwarded client
+    // headers of the same name.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // "Subscription.field" -> topic the service publishes the field's events
+    // to, consumed by the gateway in place of subscribing at the service
+    #[serde(default)]
+    pub topics: HashMap<String, broker::TopicConfig>,
+    // Set by the gateway on its per-request copy of the schema from the
+    // request's region hint
+    #[serde(skip)]
+    pub preferred_region: Option<String>,
+}
+
+impl ServiceConfig {
+    // URLs to try in order: the preferred region's deployment, the default
+    // URL, then the other regions by name. Later ones are only used when the
+    // earlier ones can't be reached or fail with a server error.
+    pub fn endpoints(&self) -> Vec<&str> {
+        let preferred = self
+            .preferred_region
+            .as_ref()
+            .and_then(|region| self.regions.get(region));
+
+        let mut endpoints: Vec<&str> = preferred.into_iter().map(String::as_str).collect();
+        for url in std::iter::once(&self.url).chain(self.regions.values()) {
+            if !endpoints.contains(&url.as_str()) {
+                endpoints.push(url);
+            }
+        }
+        endpoints
+    }
+}
+
+// A field added to another service's type and resolved by a root query field
+// of this service, schema-stitching style, with arguments taken from the
+// parent object: `Product.reviews` resolved by
+// `reviewsByProductId(productId: $Product.id)`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JoinConfig {
+    pub field: String,
+    pub resolver: String,
+}
+
+impl JoinConfig {
+    // Parses a join as declared in `supergraph.yaml`, returning the service
+    // it belongs to, e.g.
+    // `Product.reviews -> reviews.reviewsByProductId(productId: $Product.id)`
+    pub fn parse(declaration: &str) -> Result<(String, JoinConfig), String> {
+        let invalid = || {
+            format!(
+                "Invalid join \"{}\", expected Type.field -> service.field(argument: $Type.field)",
+                declaration
+            )
+        };
+        let (field, target) = declaration.split_once("->").ok_or_else(invalid)?;
+        let (service, resolver) = target.trim().split_once('.').ok_or_else(invalid)?;
+        let field = field.trim();
+        if !field.contains('.') || service.is_empty() || resolver.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok((
+            service.to_string(),
+            JoinConfig {
+                field: field.to_string(),
+                resolver: resolver.to_string(),
+            },
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    // Advertise gzip, brotli and deflate support to the service
+    pub enabled: bool,
+    // Upper bound on a response body once decompressed, so a small compressed
+    // payload can't expand into an arbitrarily large one
+    pub max_decompressed_bytes: usize,
+    // Compresses request bodies for services accepting compressed requests.
+    // Unset sends them as they are.
+    pub request_encoding: Option<ContentEncoding>,
+    // Smaller request bodies are sent uncompressed, where compressing them
+    // costs more than it saves
+    pub min_request_bytes: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentEncoding {
+    Gzip,
+    Br,
+}
+
+impl ContentEncoding {
+    // The `Content-Encoding` header value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Br => "br",
+        }
+    }
+}
+
+// Protocol features the service supports beyond plain GraphQL over HTTP.
+// Everything is off by default and the gateway only relies on what is turned
+// on here; `@defer` and `@stream` are handled by the gateway and never sent
+// to a service.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceCapabilities {
+    // Streams subscription events over server-sent events, as in GraphQL
+    // over SSE
+    pub subscriptions: bool,
+    // Takes subscriptions over a WebSocket instead, speaking
+    // graphql-transport-ws at the service's URL
+    pub websocket_subscriptions: bool,
+    // Accepts automatic persisted queries, sent as a hash before the full
+    // query. A service answering `PersistedQueryNotSupported` gets full
+    // queries from then on.
+    pub persisted_queries: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            max_decompressed_bytes: 16 * 1024 * 1024,
+            request_encoding: None,
+            min_request_bytes: 1024,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
 pub struct GraphQLRequest {
+    // Empty when the client sends only the hash of a persisted query
+    #[serde(default)]
     pub query: String,
     pub variables: Option<Value>,
+    #[serde(rename = "operationName", alias = "operation_name", default)]
     pub operation_name: Option<String>,
     #[serde(skip)]
     pub auth_headers: Option<HashMap<String, String>>,
+    #[serde(skip)]
+    pub client_name: Option<String>,
+    // Used to localize the errors the gateway reports in a successful response
+    #[serde(skip)]
+    pub accept_language: Option<String>,
+    // Set by `Cache-Control: no-cache` or `max-age=0`
+    #[serde(skip)]
+    pub no_cache: bool,
+    // Region whose subgraph deployments should serve the request, taken from
+    // the configured hint header
+    #[serde(skip)]
+    pub region: Option<String>,
+    // Carries `persistedQuery` for automatic persisted queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+}
+
+// A document with several operations, executed once for each operation the
+// client lists in `operationName`
+#[derive(Deserialize, Debug)]
+pub struct BatchRequest {
+    // Empty when the client sends only the hash of a persisted query
+    #[serde(default)]
+    pub query: String,
+    pub variables: Option<Value>,
+    #[serde(rename = "operationName", alias = "operation_name")]
+    pub operation_names: Vec<String>,
+    #[serde(default)]
+    pub extensions: Option<Value>,
+}
+
+// Body of `POST /admin/cache/invalidate`. Every entry is applied, so one
+// request can purge several types, entities and query hashes at once.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CacheInvalidation {
+    // Entity types dropped from the entity cache
+    pub types: Vec<String>,
+    // Single entities dropped from the entity cache
+    pub keys: Vec<EntityInvalidation>,
+    // Prefixes of the query hashes dropped from the plan cache
+    pub query_hash_prefixes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EntityInvalidation {
+    #[serde(rename = "typename", alias = "__typename")]
+    pub type_name: String,
+    // Key field values, e.g. `{"id": "1"}`
+    pub key: Value,
 }
 
 #[derive(Clone)]
 pub struct FederatedSchema {
     pub services: ServiceMap,
-    pub type_to_service_map: HashMap<String, Vec<String>>,
+    // Type, "Type.field" or "Type.field.argument" -> services resolving it, in
+    // order of preference
+    pub type_to_service_map: HashMap<intern::Name, Vec<intern::Name>>,
+    // "Type.field" -> named return type of the field
+    pub field_types: HashMap<String, String>,
+    // Entity type -> `@key(fields: ...)` selection
+    pub entity_keys: HashMap<String, String>,
+    // "Type.field" -> `@requires(fields: ...)` selection the field depends on
+    pub requires: HashMap<String, String>,
+    // "Type.field" -> service -> `@provides(fields: ...)` selection the service
+    // resolves along with the field
+    pub provides: HashMap<String, HashMap<String, String>>,
+    // Interface or union -> object types belonging to it
+    pub possible_types: HashMap<String, Vec<String>>,
+    // Interface or union -> service -> object types belonging to it in that
+    // service's own schema, the only ones the service returns for it
+    pub service_possible_types: HashMap<String, HashMap<String, Vec<String>>>,
+    // Root fields ("Query.search") several services declare with nothing to
+    // choose between them, only recorded when ambiguity is rejected
+    pub ambiguous_root_fields: HashSet<String>,
+    // "Type.field" -> how the configured join resolving the field is fetched
+    pub joins: HashMap<String, JoinField>,
+    // "Type" or "Type.field" -> `@cacheControl(maxAge: ...)` seconds, the
+    // shortest of the services declaring it
+    pub cache_control: HashMap<String, u64>,
+    // The composed schema in introspection form, answers `__schema` and `__type`
+    pub introspection: Arc<introspection::IntrospectionSchema>,
+    pub composition: CompositionMetrics,
+    // Bumped every time the registry composes a new schema
+    pub generation: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinField {
+    pub service_name: String,
+    // Root query field of the service, as the service itself names it
+    pub resolver: String,
+    pub arguments: Vec<JoinArgument>,
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinArgument {
+    pub name: String,
+    // Field of the parent object the argument takes
+    pub field: String,
+    // Declared type of the argument, e.g. `ID!`
+    pub value_type: graphql_parser::schema::Type<'static, String>,
 }
 
+// How long the registry took to compose the schema
+#[derive(Clone, Debug, Default)]
+pub struct CompositionMetrics {
+    pub subgraphs: usize,
+    // Parsing and indexing the subgraphs, which runs in parallel
+    pub indexing: Duration,
+    // Everything, including merging the subgraphs and building the
+    // introspection schema
+    pub total: Duration,
+}
+
+// Serializes deterministically, see the `Serialize` impl below
+#[derive(Clone, Debug)]
 pub struct QueryPlan {
-    pub service_queries: HashMap<String, String>,
-    pub service_variables: HashMap<String, Value>,
+    pub node: PlanNode,
+    // `Query`, `Mutation` or `Subscription`
+    pub root_type: String,
+    // The client's selection, used to shape the merged response so it holds
+    // exactly the fields that were asked for, deferred ones included. Left
+    // empty, the merged data is returned as is.
+    pub response_shape: Vec<ResponseField>,
+    // `@defer`red fragments, resolved after `node`
+    pub deferred: Vec<DeferredPlan>,
+    // `@stream`ed list fields, fetched in full by `node`
+    pub streams: Vec<StreamField>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeferredPlan {
+    pub label: Option<String>,
+    // Response path of the object the fragment applies to, empty for
+    // fragments on the root type
+    pub path: Vec<String>,
+    // Runs on top of the data resolved by the initial plan
+    pub node: PlanNode,
+    // The fragment's selection, relative to the objects at `path`
+    pub response_shape: Vec<ResponseField>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamField {
+    pub label: Option<String>,
+    // Response path of the list field
+    pub path: Vec<String>,
+    // Items to deliver with the initial payload, the rest can follow later
+    pub initial_count: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseField {
+    pub response_key: String,
+    // Key the value is read from in the merged data, for a root field the
+    // plan only fetches once under the key of an identical sibling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_key: Option<String>,
+    pub field_name: String,
+    // Types the parent object must belong to, from the enclosing inline fragments
+    pub type_conditions: Vec<String>,
+    pub selections: Vec<ResponseField>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub enum PlanNode {
+    Fetch(FetchNode),
+    // Children are independent of each other and run concurrently
+    Parallel(Vec<PlanNode>),
+    // Children run in order, each one seeing the data produced by the previous ones
+    Sequence(Vec<PlanNode>),
+    // Runs an entity fetch for every object found at `path`
+    Flatten(FlattenNode),
+    // Runs the same root field against several services and merges the values
+    // they return for it
+    Merge(MergeNode),
+    // Opens an event stream for a subscription root field, only ever at the
+    // root of a plan
+    Subscription(SubscriptionNode),
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchNode {
+    pub service_name: String,
+    pub query: String,
+    pub variables: Value,
+    // Set for `_entities` fetches, describes how to build each representation
+    pub entity: Option<EntityKey>,
+    // Set for fetches resolving a join, run once for every parent object
+    pub join: Option<JoinKey>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinKey {
+    pub type_name: String,
+    // Variable of the fetch -> field of the parent object it takes
+    pub arguments: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityKey {
+    pub type_name: String,
+    pub key_fields: Vec<KeyField>,
+}
+
+// One field of an `@key(fields: ...)` selection. Composite keys have several,
+// nested keys such as `organization { id }` carry their own selections.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KeyField {
+    pub name: String,
+    pub selections: Vec<KeyField>,
+}
+
+impl FetchNode {
+    fn normalized(&self) -> FetchNode {
+        FetchNode {
+            query: collapse_whitespace(&self.query),
+            ..self.clone()
+        }
+    }
+
+    // The operation sent to the service, parsed
+    pub fn operation(&self) -> Result<graphql_parser::query::Document<'_, String>, String> {
+        graphql_parser::query::parse_query(&self.query)
+            .map_err(|e| format!("Failed to parse fetch query: {}", e))
+    }
+
+    // Variables the operation declares, apart from the representations the
+    // executor provides for entity fetches and the arguments of joins
+    pub fn required_variables(&self) -> Result<Vec<String>, String> {
+        use graphql_parser::query::{Definition, OperationDefinition};
+
+        let document = self.operation()?;
+        let variable_definitions = document.definitions.iter().flat_map(|def| match def {
+            Definition::Operation(OperationDefinition::Query(q)) => &q.variable_definitions[..],
+            Definition::Operation(OperationDefinition::Mutation(m)) => &m.variable_definitions[..],
+            Definition::Operation(OperationDefinition::Subscription(s)) => {
+                &s.variable_definitions[..]
+            }
+            _ => &[],
+        });
+
+        Ok(variable_definitions
+            .filter(|def| self.entity.is_none() || def.name != "representations")
+            .filter(|def| {
+                self.join
+                    .as_ref()
+                    .is_none_or(|join| !join.arguments.contains_key(&def.name))
+            })
+            .map(|def| def.name.clone())
+            .collect())
+    }
+}
+
+// Collapses every run of whitespace outside string literals to one space
+fn collapse_whitespace(query: &str) -> String {
+    let mut collapsed = String::with_capacity(query.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut pending_space = false;
+
+    for c in query.trim().chars() {
+        if in_string {
+            collapsed.push(c);
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => in_string = false,
+                _ => escaped = false,
+            }
+        } else if c.is_whitespace() {
+            pending_space = true;
+        } else {
+            if pending_space {
+                collapsed.push(' ');
+                pending_space = false;
+            }
+            in_string = c == '"';
+            collapsed.push(c);
+        }
+    }
+
+    collapsed
+}
+
+impl FederatedSchema {
+    // Whether objects `service` returns for `abstract_type` can match the
+    // type condition. Services that don't define the abstract type themselves
+    // may return any object.
+    pub fn service_matches_condition(
+        &self,
+        service: &str,
+        abstract_type: &str,
+        type_condition: &str,
+    ) -> bool {
+        let Some(members) = self
+            .service_possible_types
+            .get(abstract_type)
+            .and_then(|by_service| by_service.get(service))
+        else {
+            return true;
+        };
+
+        type_condition == abstract_type
+            || members.iter().any(|member| {
+                member == type_condition
+                    || self
+                        .possible_types
+                        .get(type_condition)
+                        .is_some_and(|types| types.contains(member))
+            })
+    }
+}
+
+impl KeyField {
+    // Parses the `fields` argument of `@key`, e.g. `id organization { id }`
+    pub fn parse(fields: &str) -> Result<Vec<KeyField>, String> {
+        let selection = format!("{{{}}}", fields);
+        let doc = graphql_parser::query::parse_query::<String>(&selection)
+            .map_err(|e| format!("Invalid @key fields \"{}\": {}", fields, e))?;
+
+        match doc.definitions.first() {
+            Some(graphql_parser::query::Definition::Operation(
+                graphql_parser::query::OperationDefinition::SelectionSet(selection_set),
+            )) => Ok(Self::from_selection_set(selection_set)),
+            _ => Err(format!("Invalid @key fields \"{}\"", fields)),
+        }
+    }
+
+    fn from_selection_set(
+        selection_set: &graphql_parser::query::SelectionSet<String>,
+    ) -> Vec<Self> {
+        selection_set
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                graphql_parser::query::Selection::Field(field) => Some(KeyField {
+                    name: field.name.clone(),
+                    selections: Self::from_selection_set(&field.selection_set),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Copies this key field out of an entity object, keeping only the nested
+    // key fields of object values
+    pub fn extract(&self, obj: &serde_json::Map<String, Value>) -> Value {
+        fn select(value: &Value, selections: &[KeyField]) -> Value {
+            match value {
+                Value::Array(items) => {
+                    Value::Array(items.iter().map(|item| select(item, selections)).collect())
+                }
+                Value::Object(obj) if !selections.is_empty() => Value::Object(
+                    selections
+                        .iter()
+                        .map(|field| (field.name.clone(), field.extract(obj)))
+                        .collect(),
+                ),
+                other => other.clone(),
+            }
+        }
+
+        select(
+            obj.get(&self.name).unwrap_or(&Value::Null),
+            &self.selections,
+        )
+    }
+}
+
+impl std::fmt::Display for KeyField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.selections.is_empty() {
+            f.write_str(" {")?;
+            for selection in &self.selections {
+                write!(f, " {}", selection)?;
+            }
+            f.write_str(" }")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlattenNode {
+    // Response path to the entity objects; lists along the path are flattened
+    pub path: Vec<String>,
+    pub node: Box<PlanNode>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeNode {
+    pub response_key: String,
+    // Lists are concatenated, otherwise the first non-null value wins
+    pub nodes: Vec<PlanNode>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionNode {
+    // Sent once to the service owning the root field, which answers with a
+    // stream of events
+    pub primary: FetchNode,
+    // Resolves the fields other services contribute, run for every event
+    pub rest: Option<Box<PlanNode>>,
+}
+
+// A fetch of the plan together with the response path its results are merged
+// at, empty for root fetches
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedFetch<'a> {
+    pub path: &'a [String],
+    pub fetch: &'a FetchNode,
+}
+
+// Plans serialize the same whatever order concurrent fetches were planned in
+// and however their queries are formatted, so they can be compared with
+// golden files: the children of parallel nodes are sorted and the whitespace
+// of fetch queries is collapsed to single spaces
+impl Serialize for QueryPlan {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct StablePlan<'a> {
+            node: PlanNode,
+            root_type: &'a str,
+            response_shape: &'a [ResponseField],
+            deferred: Vec<DeferredPlan>,
+            streams: &'a [StreamField],
+        }
+
+        StablePlan {
+            node: self.node.normalized(),
+            root_type: &self.root_type,
+            response_shape: &self.response_shape,
+            deferred: self
+                .deferred
+                .iter()
+                .map(|deferred| DeferredPlan {
+                    node: deferred.node.normalized(),
+                    ..deferred.clone()
+                })
+                .collect(),
+            streams: &self.streams,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl QueryPlan {
+    // Every fetch the plan runs, deferred ones included, in the order they
+    // appear in the plan
+    pub fn fetches(&self) -> Vec<PlannedFetch<'_>> {
+        let mut fetches = Vec::new();
+        self.node.collect_fetches(&[], &mut fetches);
+        for deferred in &self.deferred {
+            deferred.node.collect_fetches(&[], &mut fetches);
+        }
+        fetches
+    }
+
+    // JSON description of the plan: the per-service queries, their variables
+    // and the order they run in
+    pub fn explain(&self) -> Value {
+        let mut explained = self.node.explain();
+
+        if !self.deferred.is_empty() {
+            explained = json!({
+                "kind": "Defer",
+                "primary": explained,
+                "deferred": self
+                    .deferred
+                    .iter()
+                    .map(|deferred| json!({
+                        "label": deferred.label,
+                        "path": deferred.path,
+                        "node": deferred.node.explain(),
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+        }
+
+        if !self.streams.is_empty() {
+            explained["streams"] = self
+                .streams
+                .iter()
+                .map(|stream| {
+                    json!({
+                        "label": stream.label,
+                        "path": stream.path,
+                        "initialCount": stream.initial_count,
+                    })
+                })
+                .collect();
+        }
+
+        explained
+    }
+}
+
+impl PlanNode {
+    pub fn explain(&self) -> Value {
+        match self {
+            PlanNode::Fetch(fetch) => {
+                let mut explained = json!({
+                    "kind": "Fetch",
+                    "serviceName": fetch.service_name,
+                    "query": fetch.query,
+                    "variables": fetch.variables,
+                });
+                if let Some(entity) = &fetch.entity {
+                    explained["entity"] = json!({
+                        "typeName": entity.type_name,
+                        "keyFields": entity
+                            .key_fields
+                            .iter()
+                            .map(KeyField::to_string)
+                            .collect::<Vec<_>>(),
+                    });
+                }
+                if let Some(join) = &fetch.join {
+                    explained["join"] = json!({
+                        "typeName": join.type_name,
+                        "arguments": join.arguments,
+                    });
+                }
+                explained
+            }
+            PlanNode::Parallel(nodes) => json!({
+                "kind": "Parallel",
+                "nodes": nodes.iter().map(PlanNode::explain).collect::<Vec<_>>(),
+            }),
+            PlanNode::Sequence(nodes) => json!({
+                "kind": "Sequence",
+                "nodes": nodes.iter().map(PlanNode::explain).collect::<Vec<_>>(),
+            }),
+            PlanNode::Flatten(flatten) => json!({
+                "kind": "Flatten",
+                "path": flatten.path,
+                "node": flatten.node.explain(),
+            }),
+            PlanNode::Merge(merge) => json!({
+                "kind": "Merge",
+                "responseKey": merge.response_key,
+                "nodes": merge.nodes.iter().map(PlanNode::explain).collect::<Vec<_>>(),
+            }),
+            PlanNode::Subscription(subscription) => {
+                let mut explained = json!({
+                    "kind": "Subscription",
+                    "primary": PlanNode::Fetch(subscription.primary.clone()).explain(),
+                });
+                if let Some(rest) = &subscription.rest {
+                    explained["rest"] = rest.explain();
+                }
+                explained
+            }
+        }
+    }
+
+    fn collect_fetches<'a>(&'a self, path: &'a [String], fetches: &mut Vec<PlannedFetch<'a>>) {
+        match self {
+            PlanNode::Fetch(fetch) => fetches.push(PlannedFetch { path, fetch }),
+            PlanNode::Parallel(nodes) | PlanNode::Sequence(nodes) => {
+                for node in nodes {
+                    node.collect_fetches(path, fetches);
+                }
+            }
+            PlanNode::Flatten(flatten) => flatten.node.collect_fetches(&flatten.path, fetches),
+            PlanNode::Merge(merge) => {
+                for node in &merge.nodes {
+                    node.collect_fetches(path, fetches);
+                }
+            }
+            PlanNode::Subscription(subscription) => {
+                fetches.push(PlannedFetch {
+                    path,
+                    fetch: &subscription.primary,
+                });
+                if let Some(rest) = &subscription.rest {
+                    rest.collect_fetches(path, fetches);
+                }
+            }
+        }
+    }
+
+    // A copy with sorted parallel children and collapsed query whitespace.
+    // Merge nodes keep their order, it decides how lists are concatenated.
+    fn normalized(&self) -> PlanNode {
+        match self {
+            PlanNode::Fetch(fetch) => PlanNode::Fetch(fetch.normalized()),
+            PlanNode::Parallel(nodes) => {
+                let mut nodes: Vec<(String, PlanNode)> = nodes
+                    .iter()
+                    .map(|node| {
+                        let node = node.normalized();
+                        (serde_json::to_string(&node).unwrap_or_default(), node)
+                    })
+                    .collect();
+                nodes.sort_by(|a, b| a.0.cmp(&b.0));
+                PlanNode::Parallel(nodes.into_iter().map(|(_, node)| node).collect())
+            }
+            PlanNode::Sequence(nodes) => {
+                PlanNode::Sequence(nodes.iter().map(PlanNode::normalized).collect())
+            }
+            PlanNode::Flatten(flatten) => PlanNode::Flatten(FlattenNode {
+                path: flatten.path.clone(),
+                node: Box::new(flatten.node.normalized()),
+            }),
+            PlanNode::Merge(merge) => PlanNode::Merge(MergeNode {
+                response_key: merge.response_key.clone(),
+                nodes: merge.nodes.iter().map(PlanNode::normalized).collect(),
+            }),
+            PlanNode::Subscription(subscription) => PlanNode::Subscription(SubscriptionNode {
+                primary: subscription.primary.normalized(),
+                rest: subscription
+                    .rest
+                    .as_ref()
+                    .map(|rest| Box::new(rest.normalized())),
+            }),
+        }
+    }
+
+    pub fn parallel(mut nodes: Vec<PlanNode>) -> PlanNode {
+        if nodes.len() == 1 {
+            nodes.remove(0)
+        } else {
+            PlanNode::Parallel(nodes)
+        }
+    }
+
+    pub fn sequence(mut nodes: Vec<PlanNode>) -> PlanNode {
+        if nodes.len() == 1 {
+            nodes.remove(0)
+        } else {
+            PlanNode::Sequence(nodes)
+        }
+    }
 }
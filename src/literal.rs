@@ -0,0 +1,249 @@
+use graphql_parser::query::{self, Definition, OperationDefinition};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::complexity::named_type;
+use crate::introspection::IntrospectionSchema;
+
+// Replaces the variables of the operation in `query` by literals of their
+// values and drops its variable definitions, for services that can't be sent
+// variables. Variables without a value take their default, or null. Strings
+// are written as enum values where the schema expects an enum.
+pub fn inline_variables(
+    query: &str,
+    variables: &Value,
+    schema: &IntrospectionSchema,
+) -> Result<String, String> {
+    let document =
+        query::parse_query::<String>(query).map_err(|e| format!("Failed to parse query: {}", e))?;
+    let definitions = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            Definition::Operation(OperationDefinition::Query(q)) => Some(&q.variable_definitions),
+            Definition::Operation(OperationDefinition::Mutation(m)) => {
+                Some(&m.variable_definitions)
+            }
+            Definition::Operation(OperationDefinition::Subscription(s)) => {
+                Some(&s.variable_definitions)
+            }
+            _ => None,
+        });
+    let definitions: HashMap<&str, &query::VariableDefinition<String>> = definitions
+        .into_iter()
+        .flatten()
+        .map(|definition| (definition.name.as_str(), definition))
+        .collect();
+
+    let mut out = String::with_capacity(query.len());
+    let mut chars = query.char_indices().peekable();
+    let mut depth = 0;
+    let mut definitions_dropped = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let end = string_end(query, i);
+                out.push_str(&query[i..end]);
+                while chars.peek().is_some_and(|&(j, _)| j < end) {
+                    chars.next();
+                }
+            }
+            '#' => {
+                while chars.peek().is_some_and(|&(_, c)| c != '\n') {
+                    chars.next();
+                }
+            }
+            // The variable definitions are the first parentheses of the
+            // document, ahead of any directive or selection
+            '(' if depth == 0 && !definitions_dropped => {
+                definitions_dropped = true;
+                let mut nesting = 1;
+                while let Some((j, c)) = chars.next() {
+                    match c {
+                        '(' => nesting += 1,
+                        ')' => nesting -= 1,
+                        '"' => {
+                            let end = string_end(query, j);
+                            while chars.peek().is_some_and(|&(k, _)| k < end) {
+                                chars.next();
+                            }
+                        }
+                        _ => {}
+                    }
+                    if nesting == 0 {
+                        break;
+                    }
+                }
+            }
+            '$' => {
+                let mut name = String::new();
+                while let Some(&(_, c)) = chars.peek()
+                    && (c.is_ascii_alphanumeric() || c == '_')
+                {
+                    name.push(c);
+                    chars.next();
+                }
+                let definition = definitions.get(name.as_str());
+                let value = match (variables.get(&name), definition) {
+                    (Some(value), _) => value.clone(),
+                    (None, Some(definition)) => definition
+                        .default_value
+                        .as_ref()
+                        .map(const_value)
+                        .unwrap_or(Value::Null),
+                    (None, None) => Value::Null,
+                };
+                let type_name = definition.map(|definition| var_type_name(&definition.var_type));
+                write_literal(&mut out, &value, type_name, schema)
+                    .map_err(|e| format!("Variable ${} can't be inlined: {}", name, e))?;
+            }
+            _ => {
+                match c {
+                    '{' | '@' if depth == 0 => {
+                        definitions_dropped = true;
+                        depth += usize::from(c == '{');
+                    }
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                out.push(c);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+// Writes `value` as a GraphQL literal of the type named `type_name`
+pub fn write_literal(
+    out: &mut String,
+    value: &Value,
+    type_name: Option<&str>,
+    schema: &IntrospectionSchema,
+) -> Result<(), String> {
+    let schema_type = type_name.and_then(|type_name| schema.get_type(type_name));
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::String(s) => {
+            if schema_type.is_some_and(|t| t["kind"] == "ENUM") {
+                if !is_name(s) {
+                    return Err(format!("{} is not an enum value", s));
+                }
+                out.push_str(s);
+            } else {
+                write_string(out, s);
+            }
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_literal(out, item, type_name, schema)?;
+            }
+            out.push(']');
+        }
+        Value::Object(obj) => {
+            let input_fields = schema_type.and_then(|t| t["inputFields"].as_array());
+            out.push('{');
+            for (i, (key, value)) in obj.iter().enumerate() {
+                if !is_name(key) {
+                    return Err(format!("{} is not a field name", key));
+                }
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(key);
+                out.push_str(": ");
+                let field_type = input_fields
+                    .and_then(|fields| fields.iter().find(|f| f["name"] == key.as_str()))
+                    .map(|field| named_type(&field["type"]));
+                write_literal(out, value, field_type, schema)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+// Writes `s` as a quoted GraphQL string, escaping quotes, backslashes and
+// control characters so the value can't end the string early
+pub fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Byte offset just past the string starting at `start`, a block string when
+// it opens with three quotes
+pub(crate) fn string_end(query: &str, start: usize) -> usize {
+    let rest = &query[start..];
+    if let Some(block) = rest.strip_prefix("\"\"\"") {
+        let mut offset = 0;
+        while let Some(found) = block[offset..].find("\"\"\"") {
+            let at = offset + found;
+            if !block[..at].ends_with('\\') {
+                return start + 3 + at + 3;
+            }
+            offset = at + 3;
+        }
+        return query.len();
+    }
+
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return start + i + 1,
+            _ => escaped = false,
+        }
+    }
+    query.len()
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn var_type_name<'a>(var_type: &'a query::Type<String>) -> &'a str {
+    match var_type {
+        query::Type::NamedType(name) => name,
+        query::Type::ListType(inner) | query::Type::NonNullType(inner) => var_type_name(inner),
+    }
+}
+
+pub(crate) fn const_value(value: &query::Value<String>) -> Value {
+    match value {
+        query::Value::Int(i) => i.as_i64().into(),
+        query::Value::Float(f) => (*f).into(),
+        query::Value::String(s) | query::Value::Enum(s) => Value::String(s.clone()),
+        query::Value::Boolean(b) => Value::Bool(*b),
+        query::Value::List(items) => Value::Array(items.iter().map(const_value).collect()),
+        query::Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(key, value)| (key.clone(), const_value(value)))
+                .collect(),
+        ),
+        query::Value::Null | query::Value::Variable(_) => Value::Null,
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Registry, fmt, reload};
+
+// Modules whose debug logging can be turned on on their own, by the name
+// they're toggled with
+const MODULES: [(&str, &str); 3] = [
+    ("planner", "portkey::query_planner"),
+    ("executor", "portkey::query_executor"),
+    ("registry", "portkey::schema_registry"),
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    // "error", "warn", "info", "debug", "trace" or "off"
+    pub level: String,
+    // Modules logging at debug level whatever the level: "planner",
+    // "executor" or "registry"
+    pub debug: Vec<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            debug: Vec::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    fn filter(&self) -> Result<EnvFilter, String> {
+        let level = LevelFilter::from_str(&self.level)
+            .map_err(|_| format!("Unknown log level {}", self.level))?;
+        let mut filter = EnvFilter::default().add_directive(level.into());
+        for module in &self.debug {
+            let target = MODULES
+                .iter()
+                .find(|(name, _)| name == module)
+                .map(|(_, target)| target)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown module {}, expected planner, executor or registry",
+                        module
+                    )
+                })?;
+            let directive = format!("{}=debug", target)
+                .parse()
+                .map_err(|e| format!("Invalid log directive: {}", e))?;
+            filter = filter.add_directive(directive);
+        }
+        Ok(filter)
+    }
+}
+
+// A change to the logging, as sent to `PUT /admin/log-level`. Modules map to
+// whether they log at debug level.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LogLevelUpdate {
+    pub level: Option<String>,
+    pub debug: BTreeMap<String, bool>,
+}
+
+pub type FilterLayer = reload::Layer<EnvFilter, Registry>;
+
+// Swaps the filter of the installed subscriber, so verbosity can be ramped up
+// during an incident without a restart
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    settings: Mutex<LoggingConfig>,
+}
+
+impl LogControl {
+    // The control and the filter layer it reloads, to put under the layers
+    // writing the logs
+    pub fn new(config: &LoggingConfig) -> Result<(Self, FilterLayer), String> {
+        let (layer, handle) = reload::Layer::new(config.filter()?);
+        let control = LogControl {
+            handle,
+            settings: Mutex::new(config.clone()),
+        };
+        Ok((control, layer))
+    }
+
+    // Installs the global subscriber, writing logs to stdout
+    pub fn init(config: &LoggingConfig) -> Result<Self, String> {
+        let (control, layer) = Self::new(config)?;
+        tracing_subscriber::registry()
+            .with(layer)
+            .with(fmt::layer())
+            .try_init()
+            .map_err(|e| format!("Failed to install the logger: {}", e))?;
+        Ok(control)
+    }
+
+    pub fn settings(&self) -> LoggingConfig {
+        self.settings.lock().unwrap().clone()
+    }
+
+    // Applies the update, returning the settings now in effect. Nothing
+    // changes when the update names an unknown level or module.
+    pub fn update(&self, update: LogLevelUpdate) -> Result<LoggingConfig, String> {
+        let mut settings = self.settings.lock().unwrap();
+        let mut updated = settings.clone();
+        if let Some(level) = update.level {
+            updated.level = level;
+        }
+        for (module, enabled) in update.debug {
+            updated.debug.retain(|debug| debug != &module);
+            if enabled {
+                updated.debug.push(module);
+            }
+        }
+        updated.debug.sort();
+
+        let filter = updated.filter()?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| format!("Failed to change the log level: {}", e))?;
+        *settings = updated.clone();
+        Ok(updated)
+    }
+}
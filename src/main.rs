@@ -1,56 +1,121 @@
-mod federation_gateway;
-mod query_executor;
-mod query_planner;
-mod schema_registry;
-
-use federation_gateway::FederationGateway;
-use query_executor::HttpQueryExecutor;
-use query_planner::SimpleQueryPlanner;
-use schema_registry::InMemorySchemaRegistry;
-use serde::{Deserialize, Serialize};
+use clap::{Parser, Subcommand};
+use futures::{
+    StreamExt,
+    channel::mpsc,
+    stream::{self, BoxStream},
+};
+use portkey::{
+    BatchRequest, CacheInvalidation, FederationGateway, GraphQLRequest, HttpQueryExecutor,
+    InMemorySchemaRegistry, SimpleQueryPlanner,
+    api_keys::{ApiKeyStore, ApiKeys, FileApiKeyStore, InMemoryApiKeyStore, MintApiKey},
+    build_info,
+    config::{GatewayConfig, RuntimeFlavor},
+    cors::{PREFLIGHT_VARY, Preflight},
+    demo::start_demo_services,
+    entity_cache::EntityCache,
+    envelope::EnvelopeConfig,
+    federation_gateway::is_subscription,
+    graphiql,
+    incremental::{self, MULTIPART_CONTENT_TYPE, MULTIPART_END},
+    logging::{LogControl, LogLevelUpdate},
+    memory::MemoryLimiter,
+    messages::MessageCatalog,
+    persisted_queries::InMemoryPersistedQueryStore,
+    plan_cache::CachingQueryPlanner,
+    quarantine::FieldQuarantine,
+    query_executor::QueryExecutor,
+    query_planner::QueryPlanner,
+    quota::QuotaTracker,
+    request_journal::{ReplayRequest, RequestJournal},
+    request_mirror::{HttpMirrorSink, RequestMirror},
+    response_cache::CachingQueryExecutor,
+    service_health::ServiceHealth,
+    subgraph_log::{SubgraphLogUpdate, SubgraphLogger},
+    subscription_limit::SubscriptionLimiter,
+    upload,
+    websocket::{self, ConnectionContext, GRAPHQL_TRANSPORT_WS},
+};
 use serde_json::{Value, json};
 
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 use bytes::Bytes;
-use http_body_util::{BodyExt, Full, combinators::BoxBody};
-use hyper::body::Incoming;
+use http_body_util::{BodyExt, Full, StreamBody, combinators::BoxBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::HeaderValue;
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
-use tokio::net::TcpListener;
 
-// Define our core types
-type ServiceMap = HashMap<String, ServiceConfig>;
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to the gateway configuration file
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct ServiceConfig {
-    name: String,
-    url: String,
-    schema: String,
-}
+    /// Number of runtime worker threads
+    #[arg(long)]
+    worker_threads: Option<usize>,
+
+    /// Maximum number of threads used for blocking operations
+    #[arg(long)]
+    max_blocking_threads: Option<usize>,
+
+    /// Name given to the runtime threads
+    #[arg(long)]
+    thread_name: Option<String>,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct GraphQLRequest {
-    query: String,
-    variables: Option<Value>,
-    operation_name: Option<String>,
-    #[serde(skip)]
-    auth_headers: Option<HashMap<String, String>>,
+    /// Serve every connection from a single-threaded runtime
+    #[arg(long)]
+    current_thread: bool,
+
+    /// Enable developer tooling such as query plan explanations
+    #[arg(long)]
+    dev: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Clone)]
-struct FederatedSchema {
-    services: ServiceMap,
-    type_to_service_map: HashMap<String, Vec<String>>,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve the gateway over built-in example users and products subgraphs
+    Demo,
 }
 
-struct QueryPlan {
-    service_queries: HashMap<String, String>,
-    pub service_variables: HashMap<String, Value>,
+impl Cli {
+    fn load_config(&self) -> Result<GatewayConfig, String> {
+        let mut config = match &self.config {
+            Some(path) => GatewayConfig::from_file(path)?,
+            None => GatewayConfig::default(),
+        };
+
+        if let Some(worker_threads) = self.worker_threads {
+            config.runtime.worker_threads = Some(worker_threads);
+        }
+        if let Some(max_blocking_threads) = self.max_blocking_threads {
+            config.runtime.max_blocking_threads = Some(max_blocking_threads);
+        }
+        if let Some(thread_name) = &self.thread_name {
+            config.runtime.thread_name = thread_name.clone();
+        }
+        if self.current_thread {
+            config.runtime.flavor = RuntimeFlavor::CurrentThread;
+        }
+        if self.dev {
+            config.dev_mode = true;
+        }
+        config.graphiql.validate()?;
+
+        Ok(config)
+    }
 }
 
 // Create a response body from a string
@@ -60,60 +125,256 @@ fn full<T: Into<Bytes>>(value: T) -> BoxBody<Bytes, hyper::Error> {
         .boxed()
 }
 
-// GraphiQL HTML template remains the same
-const GRAPHIQL_HTML: &str = r#"
-<!DOCTYPE html>
-<html>
-<head>
-  <title>GraphiQL - Portkey Federation Gateway</title>
-  <link href="https://unpkg.com/graphiql@1.5.0/graphiql.min.css" rel="stylesheet" />
-  <style>
-    body { margin: 0; padding: 0; height: 100vh; }
-    #graphiql { height: 100vh; }
-  </style>
-</head>
-<body>
-  <div id="graphiql"></div>
-
-  <script src="https://unpkg.com/react@17.0.2/umd/react.production.min.js"></script>
-  <script src="https://unpkg.com/react-dom@17.0.2/umd/react-dom.production.min.js"></script>
-  <script src="https://unpkg.com/graphiql@1.5.0/graphiql.min.js"></script>
-  <script>
-
-    const token = localStorage.getItem('auth_token') || '';
-
-
-    function graphQLFetcher(graphQLParams) {
-      return fetch('/graphql', {
-        method: 'post',
-        headers: {
-          'Content-Type': 'application/json',
-
-          'Authorization': token ? `Bearer ${token}` : '',
-        },
-        body: JSON.stringify(graphQLParams),
-      }).then(response => response.json());
-    }
+// Sends every response as a `next` event and ends with `complete`, as in
+// GraphQL over server-sent events. The events are forwarded from a task so
+// the body is `Sync`, the task stops as soon as the client goes away.
+fn event_stream_body(events: BoxStream<'static, Value>) -> BoxBody<Bytes, hyper::Error> {
+    let (sender, receiver) = mpsc::channel(16);
+    let events = events
+        .map(|response| format!("event: next\ndata: {}\n\n", response))
+        .chain(stream::once(async {
+            "event: complete\ndata:\n\n".to_string()
+        }))
+        .map(|event| Ok(Ok::<_, hyper::Error>(Frame::data(Bytes::from(event)))));
+    tokio::spawn(events.forward(sender));
 
+    BodyExt::boxed(StreamBody::new(receiver))
+}
 
-    ReactDOM.render(
-      React.createElement(GraphiQL, { fetcher: graphQLFetcher }),
-      document.getElementById('graphiql')
-    );
-  </script>
-</body>
-</html>
-"#;
+// Sends every payload as a part of a multipart response, forwarded from a
+// task like event streams
+fn multipart_body(payloads: BoxStream<'static, Value>) -> BoxBody<Bytes, hyper::Error> {
+    let (sender, receiver) = mpsc::channel(16);
+    let parts = payloads
+        .map(|payload| incremental::multipart_part(&payload))
+        .chain(stream::once(async { MULTIPART_END.to_string() }))
+        .map(|part| Ok(Ok::<_, hyper::Error>(Frame::data(Bytes::from(part)))));
+    tokio::spawn(parts.forward(sender));
+
+    BodyExt::boxed(StreamBody::new(receiver))
+}
 
-// Process incoming requests - unchanged
+// The client connection a request arrived on, subscriptions are capped per
+// connection
+#[derive(Clone)]
+struct Connection {
+    id: u64,
+    subscriptions: Arc<SubscriptionLimiter>,
+}
+
+// Process incoming requests, attaching the route's CORS headers to every
+// response except preflights, which carry their own
 async fn handle_request(
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
     gateway: Arc<FederationGateway>,
+    config: Arc<GatewayConfig>,
+    connection: Connection,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
+    let cors_headers = if req.method() == Method::OPTIONS {
+        Vec::new()
+    } else {
+        config
+            .cors
+            .response_headers(req.uri().path(), req.headers())
+    };
+
+    // Legacy endpoints are served as `/graphql`, their responses wrapped in
+    // the endpoint's envelope
+    let envelope = config.envelopes.get(req.uri().path()).cloned();
+    if envelope.is_some() {
+        let uri = match req.uri().query() {
+            Some(query) => format!("/graphql?{}", query),
+            None => "/graphql".to_string(),
+        };
+        if let Ok(uri) = uri.parse() {
+            *req.uri_mut() = uri;
+        }
+    }
+
+    let mut response = route_request(req, gateway, config, connection).await?;
+    if let Some(envelope) = envelope {
+        response = wrap_in_envelope(response, &envelope).await;
+    }
+    for (name, value) in cors_headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().append(name, value);
+        }
+    }
+
+    Ok(response)
+}
+
+async fn route_request(
+    mut req: Request<Incoming>,
+    gateway: Arc<FederationGateway>,
+    config: Arc<GatewayConfig>,
+    connection: Connection,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, Infallible> {
     let auth_headers = extract_auth_headers(&req);
+    let journal_headers = gateway
+        .request_journal()
+        .map(|journal| journal.allowed_headers(req.headers()));
+    let api_key = auth_headers
+        .as_ref()
+        .and_then(|headers| headers.get("x-api-key").cloned());
+    let client_name = extract_client_name(&req);
+    let accept_language = req
+        .headers()
+        .get("Accept-Language")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let no_cache = req
+        .headers()
+        .get("Cache-Control")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').map(str::trim).any(|directive| {
+                directive.eq_ignore_ascii_case("no-cache") || directive == "max-age=0"
+            })
+        });
+
+    let region = req
+        .headers()
+        .get(config.regions.hint_header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let experiments = extract_experiments(&req);
+
+    // Clients asking for server-sent events get subscriptions streamed back
+    let event_stream = req
+        .headers()
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/event-stream"));
+    let multipart = req
+        .headers()
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(incremental::accepts_multipart);
+
+    // File uploads are read from `multipart/form-data` bodies as they arrive
+    let upload_boundary = config
+        .uploads
+        .enabled
+        .then(|| upload::boundary(req.headers()))
+        .flatten();
+
+    // In dev mode, `/plan` and `x-portkey-explain: true` return the query plan
+    // instead of executing it
+    let explain = config.dev_mode
+        && (req.uri().path() == "/plan"
+            || req
+                .headers()
+                .get("x-portkey-explain")
+                .is_some_and(|value| value == "true"));
 
     let result = match (req.method(), req.uri().path()) {
-        (&Method::POST, "/graphql") => {
+        // Clients speaking graphql-transport-ws, with the credentials of the
+        // upgrade request applying to the whole connection
+        (&Method::GET, "/graphql")
+            if config.websocket.enabled && websocket::is_upgrade(req.headers()) =>
+        {
+            let accept_key = match websocket::accept_key(req.headers()) {
+                Ok(accept_key) => accept_key,
+                Err(e) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full(e))
+                        .unwrap());
+                }
+            };
+            let context = ConnectionContext {
+                id: connection.id,
+                auth_headers,
+                client_name,
+                accept_language,
+                region,
+            };
+            let upgrade = hyper::upgrade::on(&mut req);
+            let websocket_config = config.websocket.clone();
+            tokio::spawn(async move {
+                match upgrade.await {
+                    Ok(upgraded) => {
+                        websocket::serve(
+                            TokioIo::new(upgraded),
+                            gateway,
+                            connection.subscriptions,
+                            context,
+                            &websocket_config,
+                        )
+                        .await
+                    }
+                    Err(e) => warn!("WebSocket upgrade failed: {}", e),
+                }
+            });
+
+            Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header("Upgrade", "websocket")
+                .header("Connection", "Upgrade")
+                .header("Sec-WebSocket-Accept", accept_key)
+                .header("Sec-WebSocket-Protocol", GRAPHQL_TRANSPORT_WS)
+                .body(full(""))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        // Uploads are never collected whole, their files are kept in memory
+        // up to a threshold and spooled to disk beyond it, then streamed to
+        // the services whose fetches use them
+        (&Method::POST, "/graphql") if upload_boundary.is_some() => {
+            let boundary = upload_boundary.unwrap_or_default();
+            let body = req.into_body().into_data_stream();
+            let (mut graphql_req, uploads) =
+                match upload::parse(body, boundary, &config.uploads).await {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        let error_json = serde_json::to_string(&json!({
+                            "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                        }))
+                        .unwrap_or_default();
+
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .header("Content-Type", "application/json")
+                            .body(full(error_json))
+                            .unwrap_or_else(|_| internal_server_error()));
+                    }
+                };
+            graphql_req.auth_headers = auth_headers;
+            graphql_req.client_name = client_name;
+            graphql_req.accept_language = accept_language.clone();
+            graphql_req.no_cache = no_cache;
+            graphql_req.region = region;
+            join_experiments(&mut graphql_req, experiments);
+            let deprecation_headers = gateway.deprecation_headers(&graphql_req);
+
+            let response = match gateway.process_upload(graphql_req, uploads).await {
+                Ok(result) => {
+                    let json = serde_json::to_string(&result).unwrap_or_default();
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(full(json))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+                Err(e) => {
+                    let error_json = serde_json::to_string(&json!({
+                        "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                    }))
+                    .unwrap_or_default();
+
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(full(error_json))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+            };
+            with_headers(response, deprecation_headers)
+        }
+
+        (&Method::POST, "/graphql") | (&Method::POST, "/plan")
+            if req.uri().path() == "/graphql" || explain =>
+        {
             let body_bytes = match req.collect().await {
                 Ok(collected) => collected.to_bytes(),
                 Err(_) => {
@@ -124,69 +385,646 @@ async fn handle_request(
                 }
             };
 
+            // A list of operation names runs each of them, even for clients
+            // asking for an event stream
+            if !explain && let Ok(batch) = serde_json::from_slice::<BatchRequest>(&body_bytes) {
+                let mut graphql_req = GraphQLRequest {
+                    query: batch.query,
+                    variables: batch.variables,
+                    operation_name: None,
+                    auth_headers,
+                    client_name,
+                    accept_language: accept_language.clone(),
+                    no_cache,
+                    region,
+                    extensions: batch.extensions,
+                };
+                join_experiments(&mut graphql_req, experiments);
+
+                // Resolved up front so deprecations see the full query, then
+                // journaled and checked for deprecations operation by operation
+                let resolved = gateway.resolve_persisted_query(&mut graphql_req).await;
+                let mut deprecation_headers = Vec::new();
+                if resolved.is_ok() {
+                    for operation_name in &batch.operation_names {
+                        let operation = GraphQLRequest {
+                            operation_name: Some(operation_name.clone()),
+                            ..graphql_req.clone()
+                        };
+                        if let (Some(journal), Some(headers)) =
+                            (gateway.request_journal(), &journal_headers)
+                        {
+                            journal.record(&operation, headers.clone());
+                        }
+                        for header in gateway.deprecation_headers(&operation) {
+                            if !deprecation_headers.contains(&header) {
+                                deprecation_headers.push(header);
+                            }
+                        }
+                    }
+                }
+
+                let result = match resolved {
+                    Ok(()) => gateway
+                        .process_batch(graphql_req, batch.operation_names)
+                        .await
+                        .map_err(|e| (StatusCode::BAD_REQUEST, e)),
+                    Err(e) => Err((StatusCode::OK, e)),
+                };
+                let (status, json) = match result {
+                    Ok(responses) => (StatusCode::OK, responses),
+                    Err((status, e)) => (
+                        status,
+                        json!({
+                            "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                        }),
+                    ),
+                };
+                let response = Response::builder()
+                    .status(status)
+                    .header("Content-Type", "application/json")
+                    .body(full(json.to_string()))
+                    .unwrap_or_else(|_| internal_server_error());
+                return Ok(with_headers(response, deprecation_headers));
+            }
+
             match serde_json::from_slice::<GraphQLRequest>(&body_bytes) {
                 Ok(mut graphql_req) => {
                     graphql_req.auth_headers = auth_headers;
+                    graphql_req.client_name = client_name;
+                    graphql_req.accept_language = accept_language.clone();
+                    graphql_req.no_cache = no_cache;
+                    graphql_req.region = region;
+                    join_experiments(&mut graphql_req, experiments);
+
+                    // Resolved up front so deprecations see the full query
+                    if let Err(e) = gateway.resolve_persisted_query(&mut graphql_req).await {
+                        let error_json = serde_json::to_string(&json!({
+                            "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                        }))
+                        .unwrap_or_default();
+
+                        return Ok(Response::builder()
+                            .header("Content-Type", "application/json")
+                            .body(full(error_json))
+                            .unwrap_or_else(|_| internal_server_error()));
+                    }
+                    if let (Some(journal), Some(headers)) =
+                        (gateway.request_journal(), journal_headers)
+                    {
+                        journal.record(&graphql_req, headers);
+                    }
+                    let deprecation_headers = gateway.deprecation_headers(&graphql_req);
 
-                    match gateway.process_request(graphql_req).await {
-                        Ok(result) => {
+                    if event_stream && !explain {
+                        let permit = if is_subscription(
+                            &graphql_req.query,
+                            graphql_req.operation_name.as_deref(),
+                        ) {
+                            match connection
+                                .subscriptions
+                                .acquire(connection.id, api_key.as_deref())
+                            {
+                                Ok(permit) => Some(permit),
+                                Err(e) => {
+                                    let error_json = serde_json::to_string(&json!({
+                                        "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                                    }))
+                                    .unwrap_or_default();
+
+                                    let response = Response::builder()
+                                        .status(StatusCode::TOO_MANY_REQUESTS)
+                                        .header("Content-Type", "application/json")
+                                        .body(full(error_json))
+                                        .unwrap_or_else(|_| internal_server_error());
+                                    return Ok(with_headers(response, deprecation_headers));
+                                }
+                            }
+                        } else {
+                            None
+                        };
+
+                        let response = match gateway.subscribe(graphql_req).await {
+                            Ok(events) => {
+                                let events = match permit {
+                                    Some(permit) => permit.hold(events),
+                                    None => events,
+                                };
+                                Response::builder()
+                                    .header("Content-Type", "text/event-stream")
+                                    .header("Cache-Control", "no-cache")
+                                    .body(event_stream_body(events))
+                                    .unwrap_or_else(|_| internal_server_error())
+                            }
+                            Err(e) => {
+                                let error_json = serde_json::to_string(&json!({
+                                    "errors": [gateway.messages().error(&e, accept_language.as_deref())]
+                                }))
+                                .unwrap_or_default();
+
+                                Response::builder()
+                                    .header("Content-Type", "application/json")
+                                    .body(full(error_json))
+                                    .unwrap_or_else(|_| internal_server_error())
+                            }
+                        };
+                        return Ok(with_headers(response, deprecation_headers));
+                    }
+
+                    // Clients taking multipart responses get deferred
+                    // fragments as they're resolved
+                    let result = if explain {
+                        gateway
+                            .explain_request(graphql_req)
+                            .await
+                            .map(|result| (result, None))
+                    } else if multipart {
+                        gateway.process_incremental(graphql_req).await
+                    } else {
+                        gateway
+                            .process_request(graphql_req)
+                            .await
+                            .map(|result| (result, None))
+                    };
+
+                    let response = match result {
+                        Ok((initial, Some(rest))) => Response::builder()
+                            .header("Content-Type", MULTIPART_CONTENT_TYPE)
+                            .body(multipart_body(
+                                stream::once(async { initial }).chain(rest).boxed(),
+                            ))
+                            .unwrap_or_else(|_| internal_server_error()),
+                        Ok((result, None))
+                            if multipart && config.list_streaming.enabled && !explain =>
+                        {
+                            let mut payloads = config.list_streaming.chunk_lists(result);
+                            if payloads.len() == 1 {
+                                Response::builder()
+                                    .header("Content-Type", "application/json")
+                                    .body(full(payloads.remove(0).to_string()))
+                                    .unwrap_or_else(|_| internal_server_error())
+                            } else {
+                                Response::builder()
+                                    .header("Content-Type", MULTIPART_CONTENT_TYPE)
+                                    .body(multipart_body(stream::iter(payloads).boxed()))
+                                    .unwrap_or_else(|_| internal_server_error())
+                            }
+                        }
+                        Ok((result, None)) => {
                             let json = serde_json::to_string(&result).unwrap_or_default();
                             Response::builder()
                                 .header("Content-Type", "application/json")
-                                .header("Access-Control-Allow-Origin", "*")
                                 .body(full(json))
                                 .unwrap_or_else(|_| internal_server_error())
                         }
                         Err(e) => {
                             let error_json = serde_json::to_string(&json!({
-                                "errors": [{
-                                    "message": e
-                                }]
+                                "errors": [gateway.messages().error(&e, accept_language.as_deref())]
                             }))
                             .unwrap_or_default();
 
                             Response::builder()
                                 .header("Content-Type", "application/json")
-                                .header("Access-Control-Allow-Origin", "*")
                                 .body(full(error_json))
                                 .unwrap_or_else(|_| internal_server_error())
                         }
-                    }
+                    };
+                    with_headers(response, deprecation_headers)
                 }
                 Err(e) => Response::builder()
                     .status(StatusCode::BAD_REQUEST)
-                    .header("Access-Control-Allow-Origin", "*")
                     .body(full(format!("Invalid JSON request: {}", e)))
                     .unwrap_or_else(|_| internal_server_error()),
             }
         }
 
-        (&Method::GET, "/graphiql") => Response::builder()
-            .header("Content-Type", "text/html")
-            .header("Access-Control-Allow-Origin", "*")
-            .body(full(GRAPHIQL_HTML))
-            .unwrap_or_else(|_| internal_server_error()),
+        (&Method::POST, "/admin/cache/invalidate") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let body_bytes = match req.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full("Failed to read request body"))
+                        .unwrap());
+                }
+            };
+
+            match serde_json::from_slice::<CacheInvalidation>(&body_bytes) {
+                Ok(invalidation) => {
+                    let json = gateway.invalidate_caches(&invalidation).to_string();
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(full(json))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(format!("Invalid invalidation request: {}", e)))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
+
+        (&Method::GET, "/admin/journal") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let entries = gateway
+                .request_journal()
+                .map(|journal| journal.entries())
+                .unwrap_or_default();
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&entries).unwrap_or_default()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::POST, "/admin/journal/replay") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let body_bytes = match req.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full("Failed to read request body"))
+                        .unwrap());
+                }
+            };
+
+            match serde_json::from_slice::<ReplayRequest>(&body_bytes) {
+                Ok(replay) => {
+                    let json = match gateway.replay(replay).await {
+                        Ok(result) => result,
+                        Err(e) => json!({ "errors": [gateway.messages().error(&e, None)] }),
+                    };
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(full(json.to_string()))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(format!("Invalid replay request: {}", e)))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
+
+        (&Method::GET, "/admin/subgraph-logging") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let settings = gateway
+                .subgraph_logger()
+                .map(|logger| logger.settings())
+                .unwrap_or_default();
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(serde_json::to_string(&settings).unwrap_or_default()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::POST, "/admin/subgraph-logging") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let body_bytes = match req.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full("Failed to read request body"))
+                        .unwrap());
+                }
+            };
+
+            match (
+                serde_json::from_slice::<SubgraphLogUpdate>(&body_bytes),
+                gateway.subgraph_logger(),
+            ) {
+                (Ok(update), Some(logger)) => {
+                    logger.set(&update.service, update.settings);
+                    Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(full(
+                            serde_json::to_string(&logger.settings()).unwrap_or_default(),
+                        ))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+                (Ok(_), None) => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(full("Subgraph logging is not available"))
+                    .unwrap_or_else(|_| internal_server_error()),
+                (Err(e), _) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(format!("Invalid subgraph logging request: {}", e)))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
+
+        (&Method::GET, "/admin/log-level") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let Some(log_control) = gateway.log_control() else {
+                return Ok(log_control_unavailable());
+            };
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(
+                    serde_json::to_string(&log_control.settings()).unwrap_or_default(),
+                ))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::PUT, "/admin/log-level") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let body_bytes = match req.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full("Failed to read request body"))
+                        .unwrap());
+                }
+            };
+            let Some(log_control) = gateway.log_control() else {
+                return Ok(log_control_unavailable());
+            };
+
+            let updated = serde_json::from_slice::<LogLevelUpdate>(&body_bytes)
+                .map_err(|e| format!("Invalid log level request: {}", e))
+                .and_then(|update| log_control.update(update));
+            match updated {
+                Ok(settings) => Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(full(serde_json::to_string(&settings).unwrap_or_default()))
+                    .unwrap_or_else(|_| internal_server_error()),
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(e))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
+
+        (&Method::GET, "/admin/api-keys") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let Some(api_keys) = gateway.api_keys() else {
+                return Ok(api_keys_disabled());
+            };
+            match api_keys.list().await {
+                Ok(keys) => Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(full(serde_json::to_string(&keys).unwrap_or_default()))
+                    .unwrap_or_else(|_| internal_server_error()),
+                Err(e) => {
+                    error!("Failed to list API keys: {}", e);
+                    internal_server_error()
+                }
+            }
+        }
+
+        (&Method::POST, "/admin/api-keys") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let Some(api_keys) = gateway.api_keys() else {
+                return Ok(api_keys_disabled());
+            };
+            let body_bytes = match req.collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(_) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(full("Failed to read request body"))
+                        .unwrap());
+                }
+            };
+
+            match serde_json::from_slice::<MintApiKey>(&body_bytes) {
+                Ok(mint) => match api_keys.mint(mint).await {
+                    Ok(minted) => Response::builder()
+                        .status(StatusCode::CREATED)
+                        .header("Content-Type", "application/json")
+                        .body(full(serde_json::to_string(&minted).unwrap_or_default()))
+                        .unwrap_or_else(|_| internal_server_error()),
+                    Err(e) => {
+                        error!("Failed to mint API key: {}", e);
+                        internal_server_error()
+                    }
+                },
+                Err(e) => Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(format!("Invalid API key request: {}", e)))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
+
+        (&Method::DELETE, path)
+            if path.starts_with("/admin/api-keys/") && config.admin.token.is_some() =>
+        {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let Some(api_keys) = gateway.api_keys() else {
+                return Ok(api_keys_disabled());
+            };
+            let id = &path["/admin/api-keys/".len()..];
+            match api_keys.revoke(id).await {
+                Ok(Some(key)) => Response::builder()
+                    .header("Content-Type", "application/json")
+                    .body(full(serde_json::to_string(&key).unwrap_or_default()))
+                    .unwrap_or_else(|_| internal_server_error()),
+                Ok(None) => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(full(format!("No API key with id {}", id)))
+                    .unwrap_or_else(|_| internal_server_error()),
+                Err(e) => {
+                    error!("Failed to revoke API key: {}", e);
+                    internal_server_error()
+                }
+            }
+        }
+
+        (&Method::GET, "/admin/config") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(config.redacted().to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, "/admin/metrics") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(full(gateway.metrics()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, "/admin/quarantine") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            let quarantined = gateway
+                .quarantine()
+                .map(|quarantine| quarantine.quarantined())
+                .unwrap_or_else(|| json!([]));
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(quarantined.to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, "/admin/version") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(build_info::build_info().to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, "/admin/schema") if config.admin.token.is_some() => {
+            if !is_admin(&req, &config) {
+                return Ok(unauthorized());
+            }
+
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(full(gateway.schema_health().await.to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        // Ready once a schema is served, for load balancers to hold traffic
+        // back while it's loaded
+        (&Method::GET, "/ready") => {
+            let health = gateway.schema_health().await;
+            let status = match health["status"].as_str() {
+                Some("loading") | Some("unavailable") => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            };
+
+            Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(full(json!({ "status": health["status"] }).to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        // Public, so only the status. Composition errors are for the admin API.
+        // Live while the schema is loading, so the gateway isn't restarted
+        // over a schema file that's briefly missing.
+        (&Method::GET, "/health") => {
+            let health = gateway.schema_health().await;
+            let status = match health["status"].as_str() {
+                Some("unavailable") => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            };
+
+            Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(full(json!({ "status": health["status"] }).to_string()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, "/graphiql") => {
+            // Revalidated on every visit, the page being tiny
+            let etag = config.graphiql.etag();
+            let if_none_match = header_value(&req, "if-none-match");
+            if graphiql::is_fresh(if_none_match, &etag) {
+                return Ok(not_modified(&etag, "no-cache"));
+            }
+            Response::builder()
+                .header("Content-Type", "text/html")
+                .header("Cache-Control", "no-cache")
+                .header("ETag", etag)
+                .body(full(config.graphiql.html()))
+                .unwrap_or_else(|_| internal_server_error())
+        }
+
+        (&Method::GET, path) if path.starts_with(graphiql::ASSETS_PATH) => {
+            // Versions are pinned at build time
+            const CACHE_CONTROL: &str = "public, max-age=86400";
+            match graphiql::asset(&path[graphiql::ASSETS_PATH.len()..]) {
+                Some(asset)
+                    if graphiql::is_fresh(header_value(&req, "if-none-match"), asset.etag) =>
+                {
+                    not_modified(asset.etag, CACHE_CONTROL)
+                }
+                Some(asset) => {
+                    let (encoding, content) = asset.encoded(header_value(&req, "accept-encoding"));
+                    let mut response = Response::builder()
+                        .header("Content-Type", asset.content_type)
+                        .header("Cache-Control", CACHE_CONTROL)
+                        .header("ETag", asset.etag)
+                        .header("Vary", "Accept-Encoding");
+                    if let Some(encoding) = encoding {
+                        response = response.header("Content-Encoding", encoding);
+                    }
+                    response
+                        .body(full(content))
+                        .unwrap_or_else(|_| internal_server_error())
+                }
+                None => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(full("Not Found"))
+                    .unwrap_or_else(|_| internal_server_error()),
+            }
+        }
 
         (&Method::GET, "/") => Response::builder()
             .status(StatusCode::FOUND)
             .header("Location", "/graphiql")
-            .header("Access-Control-Allow-Origin", "*")
             .body(full(""))
             .unwrap_or_else(|_| internal_server_error()),
 
-        (&Method::OPTIONS, _) => Response::builder()
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
-            .header(
-                "Access-Control-Allow-Headers",
-                "Content-Type, Authorization",
-            )
-            .body(full(""))
-            .unwrap_or_else(|_| internal_server_error()),
+        (&Method::OPTIONS, path) => match config.cors.preflight(path, req.headers()) {
+            Preflight::Allowed(headers) => {
+                let mut response = Response::builder().status(StatusCode::NO_CONTENT);
+                for (name, value) in headers {
+                    response = response.header(name, value);
+                }
+                response
+                    .body(full(""))
+                    .unwrap_or_else(|_| internal_server_error())
+            }
+            Preflight::Rejected(reason) => Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .header("Vary", PREFLIGHT_VARY)
+                .body(full(reason))
+                .unwrap_or_else(|_| internal_server_error()),
+        },
 
         _ => Response::builder()
             .status(StatusCode::NOT_FOUND)
-            .header("Access-Control-Allow-Origin", "*")
             .body(full("Not Found"))
             .unwrap_or_else(|_| internal_server_error()),
     };
@@ -194,6 +1032,89 @@ async fn handle_request(
     Ok(result)
 }
 
+fn with_headers(
+    mut response: Response<BoxBody<Bytes, hyper::Error>>,
+    headers: Vec<(&'static str, String)>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+// Whether the request carries the admin bearer token
+fn is_admin(req: &Request<Incoming>, config: &GatewayConfig) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| Some(token) == config.admin.token.as_deref())
+}
+
+fn api_keys_disabled() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(full("API keys are not enabled"))
+        .unwrap()
+}
+
+fn log_control_unavailable() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(full("Log levels can't be changed at runtime"))
+        .unwrap()
+}
+
+fn unauthorized() -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .body(full("Unauthorized"))
+        .unwrap()
+}
+
+// Answers a conditional request for content the client holds already
+fn not_modified(etag: &str, cache_control: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header("ETag", etag)
+        .header("Cache-Control", cache_control)
+        .body(full(""))
+        .unwrap_or_else(|_| internal_server_error())
+}
+
+fn header_value<'r>(req: &'r Request<Incoming>, name: &str) -> Option<&'r str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+// Event streams and multipart responses are sent as they are, only JSON
+// responses are wrapped
+async fn wrap_in_envelope(
+    response: Response<BoxBody<Bytes, hyper::Error>>,
+    envelope: &EnvelopeConfig,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let is_json = response
+        .headers()
+        .get("Content-Type")
+        .is_some_and(|value| value == "application/json");
+    if !is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = body.collect().await.map(|collected| collected.to_bytes()) else {
+        return internal_server_error();
+    };
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(json) => full(envelope.wrap(json).to_string()),
+        Err(_) => full(body),
+    };
+    Response::from_parts(parts, body)
+}
+
 // Create a standard internal server error response
 fn internal_server_error() -> Response<BoxBody<Bytes, hyper::Error>> {
     Response::builder()
@@ -206,17 +1127,17 @@ fn internal_server_error() -> Response<BoxBody<Bytes, hyper::Error>> {
 fn extract_auth_headers(req: &Request<Incoming>) -> Option<HashMap<String, String>> {
     let mut auth_headers = HashMap::new();
 
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            auth_headers.insert("Authorization".to_string(), auth_str.to_string());
-        }
+    if let Some(auth_header) = req.headers().get("Authorization")
+        && let Ok(auth_str) = auth_header.to_str()
+    {
+        auth_headers.insert("Authorization".to_string(), auth_str.to_string());
     }
 
     for header_name in ["x-api-key", "x-token"].iter() {
-        if let Some(header_value) = req.headers().get(*header_name) {
-            if let Ok(value_str) = header_value.to_str() {
-                auth_headers.insert(header_name.to_string(), value_str.to_string());
-            }
+        if let Some(header_value) = req.headers().get(*header_name)
+            && let Ok(value_str) = header_value.to_str()
+        {
+            auth_headers.insert(header_name.to_string(), value_str.to_string());
         }
     }
 
@@ -227,6 +1148,65 @@ fn extract_auth_headers(req: &Request<Incoming>) -> Option<HashMap<String, Strin
     }
 }
 
+// Identify the calling client from the conventional client name headers
+fn extract_client_name(req: &Request<Incoming>) -> Option<String> {
+    ["apollographql-client-name", "x-client-name"]
+        .iter()
+        .find_map(|header_name| req.headers().get(*header_name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+// Experiments the client joins with `x-portkey-experiments: a, b`
+fn extract_experiments(req: &Request<Incoming>) -> Vec<String> {
+    req.headers()
+        .get("x-portkey-experiments")
+        .and_then(|value| value.to_str().ok())
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|experiment| !experiment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Adds the experiments joined with the header to `extensions.experiments`
+fn join_experiments(request: &mut GraphQLRequest, experiments: Vec<String>) {
+    if experiments.is_empty() {
+        return;
+    }
+    let extensions = request.extensions.get_or_insert_with(|| json!({}));
+    if !extensions.is_object() {
+        *extensions = json!({});
+    }
+    if !extensions["experiments"].is_array() {
+        extensions["experiments"] = json!([]);
+    }
+    if let Value::Array(joined) = &mut extensions["experiments"] {
+        joined.extend(experiments.into_iter().map(Value::String));
+    }
+}
+
+// Start the example subgraphs in-process and register them with the gateway
+async fn load_demo_services(gateway: &FederationGateway) -> Result<(), String> {
+    for service in start_demo_services().await? {
+        info!(
+            "Demo subgraph {} listening on {}",
+            service.name, service.url
+        );
+        gateway.register_service(service).await?;
+    }
+    Ok(())
+}
+
+async fn load_schemas(gateway: &FederationGateway, demo: bool) -> Result<(), String> {
+    if demo {
+        load_demo_services(gateway).await
+    } else {
+        gateway.load_schemas().await
+    }
+}
+
 #[derive(Clone)]
 // An Executor that uses the tokio runtime.
 pub struct TokioExecutor;
@@ -241,49 +1221,245 @@ where
     }
 }
 
-#[tokio::main]
-async fn main() -> std::result::Result<(), std::boxed::Box<std::io::Error>> {
-    let schema_registry = Box::new(InMemorySchemaRegistry::new());
-    let query_planner = Box::new(SimpleQueryPlanner::new());
-    let query_executor = Box::new(HttpQueryExecutor::new());
+fn main() -> std::result::Result<(), std::boxed::Box<std::io::Error>> {
+    let cli = Cli::parse();
+    let config = cli.load_config().map_err(|e| {
+        eprintln!("{}", e);
+        Box::new(std::io::Error::other(e))
+    })?;
+    let demo = matches!(cli.command, Some(Command::Demo));
+
+    let runtime = config.runtime.build()?;
+    runtime.block_on(run(config, demo))
+}
+
+async fn run(
+    config: GatewayConfig,
+    demo: bool,
+) -> std::result::Result<(), std::boxed::Box<std::io::Error>> {
+    let log_control = match LogControl::init(&config.logging) {
+        Ok(log_control) => Arc::new(log_control),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(Box::new(std::io::Error::other(e)));
+        }
+    };
+
+    let schema_registry = Box::new(
+        InMemorySchemaRegistry::new()
+            .with_service_selection(config.service_selection.clone())
+            .with_schema_trim(config.schema_trim.clone())
+            .with_connections(config.connections.clone()),
+    );
+    let planner = SimpleQueryPlanner::new()
+        .with_forwarded_directives(config.planner.forward_directives.clone())
+        .with_minified_queries(config.planner.minify_queries);
+    let query_planner: Box<dyn QueryPlanner + Send + Sync> =
+        match NonZeroUsize::new(config.planner.cache_size) {
+            Some(capacity) => Box::new(
+                CachingQueryPlanner::new(Box::new(planner), capacity)
+                    .with_query_compression(config.planner.compress_cached_queries),
+            ),
+            None => Box::new(planner),
+        };
+    let mut query_executor = HttpQueryExecutor::new();
+    if let Some(capacity) = NonZeroUsize::new(config.entity_cache.max_entries) {
+        query_executor = query_executor.with_entity_cache(Arc::new(EntityCache::new(
+            capacity,
+            Duration::from_secs(config.entity_cache.ttl_seconds),
+        )));
+    }
+    let quarantine = config
+        .quarantine
+        .enabled
+        .then(|| Arc::new(FieldQuarantine::new(&config.quarantine)));
+    if let Some(quarantine) = &quarantine {
+        query_executor = query_executor.with_quarantine(Arc::clone(quarantine));
+    }
+    let service_health = config
+        .service_health
+        .enabled
+        .then(|| Arc::new(ServiceHealth::new(&config.service_health)));
+    if let Some(service_health) = &service_health {
+        query_executor = query_executor.with_service_health(Arc::clone(service_health));
+    }
+    let subgraph_logger = Arc::new(SubgraphLogger::new(&config.subgraph_logging));
+    let query_executor = query_executor
+        .with_http_client(&config.subgraph_client)
+        .with_partial_results(config.partial_results)
+        .with_fetch_deduplication(config.deduplicate_fetches)
+        .with_auth_forwarding(config.auth_forwarding.clone())
+        .with_memory_limiter(Arc::new(MemoryLimiter::new(&config.memory)))
+        .with_subgraph_logger(subgraph_logger.clone());
+    let query_executor: Box<dyn QueryExecutor + Send + Sync> =
+        match NonZeroUsize::new(config.response_cache.max_entries) {
+            Some(capacity) => Box::new(
+                CachingQueryExecutor::new(Box::new(query_executor), capacity)
+                    .with_default_ttl(Duration::from_secs(
+                        config.response_cache.default_ttl_seconds,
+                    ))
+                    .with_ttl_overrides(config.response_cache.ttl_seconds.clone()),
+            ),
+            None => Box::new(query_executor),
+        };
+
+    let mut gateway = FederationGateway::new(schema_registry, query_planner, query_executor)
+        .with_subgraph_logger(subgraph_logger)
+        .with_log_control(log_control)
+        .with_lazy_schema(config.schema_loading.lazy)
+        .with_supergraph_sources(&config.supergraph);
+
+    let mut messages = MessageCatalog::new();
+    for (language, path) in &config.messages.catalogs {
+        if let Err(e) = messages.load_translations(language, path) {
+            error!("{}", e);
+            return Err(Box::new(std::io::Error::other(e)));
+        }
+    }
+    gateway = gateway
+        .with_message_catalog(messages)
+        .with_cache_bypass(config.cache_bypass.clone())
+        .with_complexity(config.complexity.clone())
+        .with_query_limits(config.query_limits.clone())
+        .with_deprecations(config.deprecations.clone())
+        .with_dev_mode(config.dev_mode)
+        .with_subgraph_timing(config.subgraph_timing)
+        .with_execution_deadline(config.execution_deadline_ms.map(Duration::from_millis))
+        .with_build_extension(config.build_extension)
+        .with_connections(config.connections.clone())
+        .with_pagination(config.pagination.clone());
+
+    if let Some(quarantine) = quarantine {
+        gateway = gateway.with_quarantine(quarantine);
+    }
+
+    if let Some(service_health) = service_health {
+        gateway = gateway.with_service_health(service_health);
+    }
+
+    if config.quota.enabled {
+        gateway = gateway.with_quota(QuotaTracker::new(&config.quota));
+    }
+
+    if config.api_keys.enabled {
+        let store: Box<dyn ApiKeyStore> = match &config.api_keys.file {
+            Some(path) => match FileApiKeyStore::open(path) {
+                Ok(store) => Box::new(store),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(Box::new(std::io::Error::other(e)));
+                }
+            },
+            None => Box::new(InMemoryApiKeyStore::new()),
+        };
+        gateway = gateway.with_api_keys(ApiKeys::new(store));
+    }
+
+    if let Some(capacity) = NonZeroUsize::new(config.persisted_queries.cache_size) {
+        gateway = gateway
+            .with_persisted_query_store(Arc::new(InMemoryPersistedQueryStore::new(capacity)));
+    }
 
-    let gateway = Arc::new(FederationGateway::new(
-        schema_registry,
-        query_planner,
-        query_executor,
-    ));
+    if let Some(region) = &config.regions.local {
+        gateway = gateway.with_default_region(region.clone());
+    }
+
+    if let Some(mirror) = &config.mirror {
+        let sink = Box::new(HttpMirrorSink::new(mirror.url.clone()));
+        gateway = gateway.with_request_mirror(RequestMirror::spawn(
+            sink,
+            mirror.queue_size,
+            mirror.batch_size,
+        ));
+    }
+
+    if let Some(journal) = &config.journal {
+        gateway = gateway.with_request_journal(RequestJournal::new(journal.clone()));
+    }
 
-    if let Err(e) = gateway.load_schemas().await {
+    let gateway = Arc::new(gateway);
+    let subscriptions = Arc::new(SubscriptionLimiter::new(&config.subscriptions));
+    let config = Arc::new(config);
+
+    if config.schema_loading.lazy {
+        let gateway = Arc::clone(&gateway);
+        let retry_interval = Duration::from_millis(config.schema_loading.retry_interval_ms);
+        tokio::spawn(async move {
+            loop {
+                match load_schemas(&gateway, demo).await {
+                    Ok(()) => break,
+                    Err(e) => warn!(
+                        "Failed to load schemas, retrying in {:?}: {}",
+                        retry_interval, e
+                    ),
+                }
+                tokio::time::sleep(retry_interval).await;
+            }
+            gateway.mark_schema_ready();
+            info!("Schema loaded, serving GraphQL requests");
+        });
+    } else if let Err(e) = load_schemas(&gateway, demo).await {
         eprintln!("Failed to load schemas: {}", e);
-        return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, e)));
+        return Err(Box::new(std::io::Error::other(e)));
+    }
+
+    // A gateway serving from a fallback supergraph source keeps retrying the
+    // ones listed before it
+    if !demo && config.supergraph.sources.len() > 1 {
+        let gateway = Arc::clone(&gateway);
+        tokio::spawn(async move {
+            let retry_interval = gateway.supergraph_sources().retry_interval();
+            loop {
+                tokio::time::sleep(retry_interval).await;
+                match gateway.retry_preferred_sources().await {
+                    Ok(Some(source)) => info!("Switched to the supergraph from {}", source),
+                    Ok(None) => {}
+                    Err(e) => warn!("Preferred supergraph sources still failing: {}", e),
+                }
+            }
+        });
     }
 
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 3000));
+    let addr = config.server.addr();
 
-    let listener = TcpListener::bind(addr).await?;
-    println!("GraphQL Federation Gateway starting on http://{}", addr);
-    println!("GraphiQL UI available at http://{}/graphiql", addr);
+    let listener = config.server.bind()?;
+    info!("{}", build_info::banner());
+    info!("GraphQL Federation Gateway starting on http://{}", addr);
+    info!("GraphiQL UI available at http://{}/graphiql", addr);
 
+    let mut next_connection_id: u64 = 0;
     loop {
         let (stream, _addr) = listener.accept().await?;
+        if config.server.tcp_nodelay
+            && let Err(e) = stream.set_nodelay(true)
+        {
+            warn!("Failed to set TCP_NODELAY: {}", e);
+        }
         let io = TokioIo::new(stream);
 
         let gateway_clone = Arc::clone(&gateway);
+        let config_clone = Arc::clone(&config);
+        let connection = Connection {
+            id: next_connection_id,
+            subscriptions: Arc::clone(&subscriptions),
+        };
+        next_connection_id += 1;
 
         let executor = TokioExecutor;
 
         tokio::task::spawn(async move {
             let service = service_fn(move |req| {
                 let gateway = gateway_clone.clone();
-                handle_request(req, gateway)
+                let config = config_clone.clone();
+                handle_request(req, gateway, config, connection.clone())
             });
 
             match hyper_util::server::conn::auto::Builder::new(executor)
-                .serve_connection(io, service)
+                .serve_connection_with_upgrades(io, service)
                 .await
             {
-                Ok(_) => println!("Connection closed"),
-                Err(e) => eprintln!("Error processing connection: {}", e),
+                Ok(_) => debug!("Connection closed"),
+                Err(e) => warn!("Error processing connection: {}", e),
             }
         });
     }
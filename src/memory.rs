@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Write;
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    // Approximate bytes a single request may hold in subgraph response bodies
+    // and intermediate results before it is aborted, 0 disables the cap
+    pub max_request_bytes: usize,
+}
+
+// Accounts the memory held by requests against the configured cap and keeps
+// the figures reported by the metrics endpoint
+#[derive(Debug, Default)]
+pub struct MemoryLimiter {
+    max_request_bytes: usize,
+    in_flight: AtomicUsize,
+    peak_request: AtomicUsize,
+    exceeded: AtomicU64,
+}
+
+impl MemoryLimiter {
+    pub fn new(config: &MemoryConfig) -> Self {
+        MemoryLimiter {
+            max_request_bytes: config.max_request_bytes,
+            ..Default::default()
+        }
+    }
+
+    // A budget for one request, releasing what it accounted when dropped
+    pub fn budget(self: &Arc<Self>) -> MemoryBudget {
+        MemoryBudget {
+            limiter: self.clone(),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    // Bytes accounted to the requests currently being executed
+    pub fn in_flight_bytes(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    // Most bytes a single request has accounted since startup
+    pub fn peak_request_bytes(&self) -> usize {
+        self.peak_request.load(Ordering::Relaxed)
+    }
+
+    pub fn exceeded_requests(&self) -> u64 {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+
+    // The request gauges in the Prometheus text format
+    pub fn render_metrics(&self) -> String {
+        let mut metrics = String::new();
+        write_metric(
+            &mut metrics,
+            "portkey_request_memory_in_flight_bytes",
+            "gauge",
+            "Approximate bytes held by the requests being executed.",
+            self.in_flight_bytes() as u64,
+        );
+        write_metric(
+            &mut metrics,
+            "portkey_request_memory_peak_bytes",
+            "gauge",
+            "Most approximate bytes held by a single request.",
+            self.peak_request_bytes() as u64,
+        );
+        write_metric(
+            &mut metrics,
+            "portkey_request_memory_exceeded_total",
+            "counter",
+            "Requests aborted for exceeding the memory budget.",
+            self.exceeded_requests(),
+        );
+        metrics
+    }
+}
+
+// Memory accounted to one request. Concurrent fetches of the request charge
+// the same budget.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limiter: Arc<MemoryLimiter>,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    // A budget without a cap that isn't reported anywhere
+    pub fn unlimited() -> Self {
+        Arc::new(MemoryLimiter::default()).budget()
+    }
+
+    // Accounts `bytes` more, failing once the request holds more than the cap
+    pub fn charge(&self, bytes: usize) -> Result<(), String> {
+        let previous = self.used.fetch_add(bytes, Ordering::Relaxed);
+        let used = previous + bytes;
+        self.limiter.in_flight.fetch_add(bytes, Ordering::Relaxed);
+        self.limiter.peak_request.fetch_max(used, Ordering::Relaxed);
+
+        let limit = self.limiter.max_request_bytes;
+        if limit == 0 || used <= limit {
+            return Ok(());
+        }
+        // Concurrent fetches keep failing, the request only counts once
+        if previous <= limit {
+            self.limiter.exceeded.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(format!(
+            "Request exceeds the memory budget of {} bytes",
+            limit
+        ))
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        let limit = self.limiter.max_request_bytes;
+        limit != 0 && self.used() > limit
+    }
+}
+
+impl Drop for MemoryBudget {
+    fn drop(&mut self) {
+        self.limiter
+            .in_flight
+            .fetch_sub(self.used(), Ordering::Relaxed);
+    }
+}
+
+// Rough heap footprint of a JSON value, counting the payload of strings and
+// keys plus a fixed overhead per value
+pub fn json_size(value: &Value) -> usize {
+    const VALUE: usize = 32;
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => VALUE,
+        Value::String(s) => VALUE + s.len(),
+        Value::Array(items) => VALUE + items.iter().map(json_size).sum::<usize>(),
+        Value::Object(obj) => {
+            VALUE
+                + obj
+                    .iter()
+                    .map(|(key, value)| VALUE + key.len() + json_size(value))
+                    .sum::<usize>()
+        }
+    }
+}
+
+// Read from `/proc/self/status`, so only available on Linux
+pub fn resident_set_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+// The process gauges in the Prometheus text format. The resident set size is
+// only reported where the platform exposes it.
+pub fn render_process_metrics() -> String {
+    let mut metrics = String::new();
+    if let Some(rss) = resident_set_bytes() {
+        write_metric(
+            &mut metrics,
+            "portkey_process_resident_memory_bytes",
+            "gauge",
+            "Resident set size of the gateway process.",
+            rss,
+        );
+    }
+    metrics
+}
+
+pub fn write_metric(metrics: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(metrics, "# HELP {} {}", name, help);
+    let _ = writeln!(metrics, "# TYPE {} {}", name, kind);
+    let _ = writeln!(metrics, "{} {}", name, value);
+}
@@ -0,0 +1,397 @@
+use serde_json::{Value, json};
+use std::{collections::HashMap, fs, path::Path};
+
+// Gateway-origin errors are produced as English strings throughout the planner
+// and executor. The catalog recognizes them by their English template, which
+// gives every message a stable code, and re-renders them from a translated
+// template when the client asked for another language.
+const ENGLISH: &[(&str, &str)] = &[
+    ("GRAPHQL_PARSE_FAILED", "Failed to parse query: {error}"),
+    ("UNKNOWN_OPERATION", "Unknown operation named \"{name}\"."),
+    (
+        "OPERATION_NAME_REQUIRED",
+        "Must provide operation name if query contains multiple operations.",
+    ),
+    ("NO_OPERATION", "No valid operations found in query"),
+    ("PERSISTED_QUERY_NOT_FOUND", "PersistedQueryNotFound"),
+    (
+        "PERSISTED_QUERY_NOT_SUPPORTED",
+        "PersistedQueryNotSupported",
+    ),
+    (
+        "PERSISTED_QUERY_HASH_MISMATCH",
+        "Provided sha256Hash does not match the query",
+    ),
+    (
+        "BAD_REQUEST",
+        "Persisted query extension is missing the sha256Hash",
+    ),
+    (
+        "OPERATION_NAME_REQUIRED",
+        "Batched requests must name at least one operation",
+    ),
+    ("UNKNOWN_FRAGMENT", "Unknown fragment: {name}"),
+    ("RECURSIVE_FRAGMENT", "Fragment {name} is used recursively"),
+    (
+        "DIRECTIVE_MISSING_CONDITION",
+        "Directive @{directive} requires an `if` argument",
+    ),
+    (
+        "INVALID_DIRECTIVE_VARIABLE",
+        "Variable ${variable} used by @{directive} must be a Boolean",
+    ),
+    (
+        "INVALID_DIRECTIVE_CONDITION",
+        "Directive @{directive} expects a Boolean `if` argument",
+    ),
+    (
+        "INVALID_DIRECTIVE_ARGUMENT",
+        "Directive @{directive} expects a String `label` argument",
+    ),
+    (
+        "INVALID_DIRECTIVE_ARGUMENT",
+        "Directive @{directive} expects a non-negative `initialCount` argument",
+    ),
+    (
+        "NO_SERVICE_FOR_FIELD",
+        "No service found for field: {field} in operation: {operation}",
+    ),
+    (
+        "MISSING_ENTITY_KEY",
+        "Type {type} has fields resolved by other services but no @key",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Cannot query field \"{field}\" on type \"{type}\".",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Unknown argument \"{argument}\" on field \"{field}\".",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Field \"{field}\" argument \"{argument}\" of type \"{type}\" is required, but it was not provided.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Field \"{field}\" must not have a selection since type \"{type}\" has no subfields.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Field \"{field}\" of type \"{type}\" must have a selection of subfields.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Field \"{field}\" is not defined by type \"{type}\".",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Field \"{field}\" of required type \"{type}\" was not provided.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Expected value of type \"{type}\", found {value}.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Variable \"${variable}\" is not defined.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Variable \"${variable}\" of type \"{type}\" used in position expecting type \"{expected}\".",
+    ),
+    ("GRAPHQL_VALIDATION_FAILED", "Unknown type \"{type}\"."),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Schema is not configured for {operation} operations.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Subscription \"{name}\" must select only one top level field.",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Anonymous Subscription must select only one top level field.",
+    ),
+    ("OPERATION_RETIRED", "Operation was retired on {date}"),
+    (
+        "BAD_USER_INPUT",
+        "Variable \"${variable}\" of required type \"{type}\" was not provided.",
+    ),
+    (
+        "BAD_USER_INPUT",
+        "Variable \"${variable}\" of non-null type \"{type}\" must not be null.",
+    ),
+    (
+        "OPERATION_TOO_COMPLEX",
+        "Operation cost {cost} exceeds the maximum cost of {max}",
+    ),
+    (
+        "OPERATION_TOO_LARGE",
+        "Operation has more than {max} tokens, exceeding the limit",
+    ),
+    (
+        "OPERATION_TOO_LARGE",
+        "Operation has {count} aliases, exceeding the limit of {max}",
+    ),
+    (
+        "OPERATION_TOO_LARGE",
+        "Operation has {count} root fields, exceeding the limit of {max}",
+    ),
+    (
+        "OPERATION_TOO_LARGE",
+        "Operation has {count} directives, exceeding the limit of {max}",
+    ),
+    ("UNAUTHENTICATED", "Missing or invalid API key"),
+    (
+        "QUOTA_EXCEEDED",
+        "Quota of {limit} per {window} seconds exceeded",
+    ),
+    ("BAD_USER_INPUT", "Invalid cursor {cursor}"),
+    (
+        "BAD_USER_INPUT",
+        "Argument first of {field} must not be negative",
+    ),
+    (
+        "GRAPHQL_VALIDATION_FAILED",
+        "Fragments on the connection {field} are not supported",
+    ),
+    ("SERVICE_NOT_FOUND", "Service not found: {service}"),
+    (
+        "SCHEMA_NOT_READY",
+        "Schema is not ready yet, it is still being loaded",
+    ),
+    ("JOURNAL_DISABLED", "Request journal is not enabled"),
+    (
+        "JOURNAL_ENTRY_NOT_FOUND",
+        "No journaled request with id {id}",
+    ),
+    (
+        "REPLAY_TARGET_MISSING",
+        "Service {service} has no staging URL to replay against",
+    ),
+    (
+        "MEMORY_BUDGET_EXCEEDED",
+        "Request exceeds the memory budget of {limit} bytes",
+    ),
+    (
+        "SUBSCRIPTIONS_NOT_SUPPORTED",
+        "Service {service} does not support subscriptions",
+    ),
+    (
+        "SUBSCRIPTION_ROOT_FIELD_SHARED",
+        "Subscription field {field} is resolved by more than one service",
+    ),
+    (
+        "SUBSCRIPTION_LIMIT_EXCEEDED",
+        "Subscription limit of {max} per {scope} exceeded",
+    ),
+    (
+        "SUBSCRIPTION_TRANSPORT_REQUIRED",
+        "Subscriptions must be requested with Accept: text/event-stream",
+    ),
+    ("SUBREQUEST_HTTP_ERROR", "HTTP request failed: {error}"),
+    (
+        "SUBREQUEST_HTTP_ERROR",
+        "Service returned error {status}: {body}",
+    ),
+    (
+        "SUBREQUEST_RESPONSE_TOO_LARGE",
+        "Response from service {service} exceeds the limit of {limit} bytes",
+    ),
+    (
+        "SUBREQUEST_INVALID_RESPONSE",
+        "Failed to read response: {error}",
+    ),
+    (
+        "SUBREQUEST_INVALID_RESPONSE",
+        "Failed to parse response: {error}",
+    ),
+    (
+        "SUBREQUEST_INVALID_RESPONSE",
+        "Service {service} returned an invalid value for {field}",
+    ),
+    (
+        "FIELD_QUARANTINED",
+        "Field {field} is quarantined after invalid values from service {service}",
+    ),
+    ("SERVICE_UNAVAILABLE", "Service {service} is unavailable"),
+    (
+        "EXECUTION_DEADLINE_EXCEEDED",
+        "Request exceeded the execution deadline of {ms} ms",
+    ),
+];
+
+const FALLBACK_CODE: &str = "INTERNAL_SERVER_ERROR";
+
+// The code of an English message, whatever the catalog translates
+pub fn code(message: &str) -> &'static str {
+    ENGLISH
+        .iter()
+        .find(|(_, template)| match_template(template, message).is_some())
+        .map_or(FALLBACK_CODE, |(code, _)| code)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LocalizedError {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct MessageCatalog {
+    // Lowercased language tag -> error code -> template
+    translations: HashMap<String, HashMap<String, String>>,
+}
+
+impl MessageCatalog {
+    pub fn new() -> Self {
+        MessageCatalog {
+            translations: HashMap::new(),
+        }
+    }
+
+    pub fn add_translations(&mut self, language: &str, templates: HashMap<String, String>) {
+        self.translations
+            .entry(language.to_lowercase())
+            .or_default()
+            .extend(templates);
+    }
+
+    // Catalog files are YAML maps from error code to template, e.g.
+    // `UNKNOWN_FRAGMENT: "Fragmento desconocido: {name}"`
+    pub fn load_translations(&mut self, language: &str, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read message catalog {:?}: {}", path, e))?;
+        let templates = serde_yaml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse message catalog {:?}: {}", path, e))?;
+        self.add_translations(language, templates);
+        Ok(())
+    }
+
+    pub fn localize(&self, message: &str, accept_language: Option<&str>) -> LocalizedError {
+        let Some((code, args)) = ENGLISH.iter().find_map(|(code, template)| {
+            match_template(template, message).map(|args| (*code, args))
+        }) else {
+            return LocalizedError {
+                code: FALLBACK_CODE.to_string(),
+                message: message.to_string(),
+            };
+        };
+
+        let translated = accept_language
+            .map(preferred_languages)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|language| self.translations.get(language)?.get(code));
+
+        LocalizedError {
+            code: code.to_string(),
+            message: match translated {
+                Some(template) => render_template(template, &args),
+                None => message.to_string(),
+            },
+        }
+    }
+
+    // A GraphQL error object for `message`, with the code under `extensions`
+    pub fn error(&self, message: &str, accept_language: Option<&str>) -> Value {
+        let localized = self.localize(message, accept_language);
+        json!({
+            "message": localized.message,
+            "extensions": { "code": localized.code }
+        })
+    }
+}
+
+enum Segment<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+fn parse_template(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        if start > 0 {
+            segments.push(Segment::Literal(&rest[..start]));
+        }
+        segments.push(Segment::Placeholder(&rest[start + 1..start + len]));
+        rest = &rest[start + len + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+
+    segments
+}
+
+// Matches `message` against an English template, capturing the placeholder
+// values. A placeholder extends to the next occurrence of the literal after it,
+// or to the trailing literal when that one ends the template.
+fn match_template<'a>(template: &str, message: &'a str) -> Option<HashMap<String, &'a str>> {
+    let segments = parse_template(template);
+    let mut args = HashMap::new();
+    let mut rest = message;
+
+    for (i, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Literal(literal) => rest = rest.strip_prefix(literal)?,
+            Segment::Placeholder(name) => {
+                let end = match segments.get(i + 1) {
+                    Some(Segment::Literal(next)) if i + 2 == segments.len() => {
+                        rest.strip_suffix(next)?.len()
+                    }
+                    Some(Segment::Literal(next)) => rest.find(next)?,
+                    _ => rest.len(),
+                };
+                args.insert(name.to_string(), &rest[..end]);
+                rest = &rest[end..];
+            }
+        }
+    }
+
+    rest.is_empty().then_some(args)
+}
+
+fn render_template(template: &str, args: &HashMap<String, &str>) -> String {
+    parse_template(template)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Literal(literal) => literal,
+            Segment::Placeholder(name) => args.get(name).copied().unwrap_or_default(),
+        })
+        .collect()
+}
+
+// Languages from an Accept-Language header, most preferred first. Regional
+// tags are followed by their primary language so `es-MX` falls back to `es`.
+fn preferred_languages(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(f32, String)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse().ok())
+                .unwrap_or(1.0);
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((quality, tag))
+        })
+        .collect();
+    weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut languages = Vec::new();
+    for (_, tag) in weighted {
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        languages.push(tag);
+        if !languages.contains(&primary) {
+            languages.push(primary);
+        }
+    }
+    languages
+}
@@ -0,0 +1,70 @@
+use graphql_parser::query::{Definition, OperationDefinition, Selection, SelectionSet};
+use sha2::{Digest, Sha256};
+
+use crate::GraphQLRequest;
+
+// Root fields named in a synthetic operation name, the hash tells apart
+// operations selecting more
+const NAMED_ROOT_FIELDS: usize = 3;
+
+// The requested operation name, or the name of the document's only operation
+pub fn operation_name(request: &GraphQLRequest) -> Option<String> {
+    if request.operation_name.is_some() {
+        return request.operation_name.clone();
+    }
+    let doc = graphql_parser::query::parse_query::<String>(&request.query).ok()?;
+
+    let mut operations = doc.definitions.into_iter().filter_map(|def| match def {
+        Definition::Operation(operation) => Some(operation),
+        Definition::Fragment(_) => None,
+    });
+    match (operations.next(), operations.next()) {
+        (Some(OperationDefinition::Query(q)), None) => q.name,
+        (Some(OperationDefinition::Mutation(m)), None) => m.name,
+        (Some(OperationDefinition::Subscription(s)), None) => s.name,
+        _ => None,
+    }
+}
+
+// The name logs and the request mirror report the operation under. Anonymous
+// operations get a stable synthetic one from their type, first root fields
+// and the start of the query hash, e.g. `query_products_reviews_1f3a9c2e`.
+pub fn display_name(request: &GraphQLRequest) -> String {
+    if let Some(name) = operation_name(request) {
+        return name;
+    }
+
+    let hash = hex::encode(Sha256::digest(request.query.as_bytes()));
+    let hash = &hash[..8];
+    let Ok(doc) = graphql_parser::query::parse_query::<String>(&request.query) else {
+        return format!("anonymous_{}", hash);
+    };
+    let Some(operation) = doc.definitions.iter().find_map(|def| match def {
+        Definition::Operation(operation) => Some(operation),
+        Definition::Fragment(_) => None,
+    }) else {
+        return format!("anonymous_{}", hash);
+    };
+
+    let (kind, selection_set) = match operation {
+        OperationDefinition::Query(q) => ("query", &q.selection_set),
+        OperationDefinition::SelectionSet(s) => ("query", s),
+        OperationDefinition::Mutation(m) => ("mutation", &m.selection_set),
+        OperationDefinition::Subscription(s) => ("subscription", &s.selection_set),
+    };
+    let mut parts = vec![kind.to_string()];
+    parts.extend(root_fields(selection_set).take(NAMED_ROOT_FIELDS));
+    parts.push(hash.to_string());
+    parts.join("_")
+}
+
+// Field names, not aliases, so renaming a response key keeps the name
+fn root_fields<'a>(selection_set: &'a SelectionSet<String>) -> impl Iterator<Item = String> + 'a {
+    selection_set
+        .items
+        .iter()
+        .filter_map(|selection| match selection {
+            Selection::Field(field) if !field.name.starts_with("__") => Some(field.name.clone()),
+            _ => None,
+        })
+}
@@ -0,0 +1,203 @@
+use graphql_parser::query::{
+    self, Definition, OperationDefinition, Selection, SelectionSet, TypeCondition,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::complexity::named_type;
+use crate::introspection::IntrospectionSchema;
+use crate::schema_trim;
+
+// Rewrites pagination arguments before an operation is planned, so no list
+// query reaches a subgraph unbounded. Rules only apply to fields declaring
+// the argument.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaginationConfig {
+    pub rules: Vec<PaginationRule>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaginationRule {
+    // "Type.field", `*` matching any run of characters, e.g. "Query.*"
+    pub field: String,
+    // e.g. "first" or "limit"
+    pub argument: String,
+    // Passed when the operation leaves the argument out or null
+    pub default: Option<i64>,
+    // Larger values are lowered to this
+    pub max: Option<i64>,
+}
+
+impl PaginationRule {
+    fn applies_to(&self, type_name: &str, field_name: &str) -> bool {
+        self.field
+            .split_once('.')
+            .is_some_and(|(type_pattern, field_pattern)| {
+                schema_trim::matches(type_pattern, type_name)
+                    && schema_trim::matches(field_pattern, field_name)
+            })
+    }
+
+    fn clamp(&self, value: i64) -> i64 {
+        self.max.map_or(value, |max| value.min(max))
+    }
+}
+
+struct Rewrite<'r> {
+    rules: &'r [PaginationRule],
+    schema: &'r IntrospectionSchema,
+    variables: Option<Value>,
+    changed: bool,
+}
+
+impl PaginationConfig {
+    // The operation with its pagination arguments rewritten, and the
+    // variables with the values passed to them clamped. `None` when no rule
+    // changed anything.
+    pub fn rewrite(
+        &self,
+        query: &str,
+        variables: &Option<Value>,
+        schema: &IntrospectionSchema,
+    ) -> Option<(String, Option<Value>)> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        let mut document = query::parse_query::<String>(query).ok()?.into_static();
+
+        let mut rewrite = Rewrite {
+            rules: &self.rules,
+            schema,
+            variables: variables.clone(),
+            changed: false,
+        };
+        for definition in &mut document.definitions {
+            let (type_name, selection_set) = match definition {
+                Definition::Operation(OperationDefinition::SelectionSet(s)) => ("Query", s),
+                Definition::Operation(OperationDefinition::Query(q)) => {
+                    ("Query", &mut q.selection_set)
+                }
+                Definition::Operation(OperationDefinition::Mutation(m)) => {
+                    ("Mutation", &mut m.selection_set)
+                }
+                Definition::Operation(OperationDefinition::Subscription(s)) => {
+                    ("Subscription", &mut s.selection_set)
+                }
+                Definition::Fragment(fragment) => {
+                    let TypeCondition::On(type_name) = &fragment.type_condition;
+                    (type_name.as_str(), &mut fragment.selection_set)
+                }
+            };
+            let type_name = type_name.to_string();
+            rewrite.selection_set(selection_set, &type_name);
+        }
+
+        rewrite
+            .changed
+            .then(|| (document.to_string(), rewrite.variables))
+    }
+}
+
+impl Rewrite<'_> {
+    fn selection_set(&mut self, selection_set: &mut SelectionSet<'static, String>, parent: &str) {
+        for selection in &mut selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    let definition = self
+                        .schema
+                        .get_type(parent)
+                        .and_then(|ty| ty["fields"].as_array())
+                        .and_then(|fields| fields.iter().find(|f| f["name"] == field.name.as_str()))
+                        .cloned();
+                    let Some(definition) = definition else {
+                        continue;
+                    };
+
+                    for rule in self.rules {
+                        let declared = definition["args"]
+                            .as_array()
+                            .is_some_and(|args| args.iter().any(|a| a["name"] == rule.argument));
+                        if declared && rule.applies_to(parent, &field.name) {
+                            self.argument(&mut field.arguments, rule);
+                        }
+                    }
+
+                    let field_type = named_type(&definition["type"]).to_string();
+                    self.selection_set(&mut field.selection_set, &field_type);
+                }
+                Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.clone(),
+                        None => parent.to_string(),
+                    };
+                    self.selection_set(&mut fragment.selection_set, &type_name);
+                }
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+    }
+
+    fn argument(
+        &mut self,
+        arguments: &mut Vec<(String, query::Value<'static, String>)>,
+        rule: &PaginationRule,
+    ) {
+        let default = rule
+            .default
+            .map(|default| query::Value::Int((rule.clamp(default) as i32).into()));
+        let Some(index) = arguments
+            .iter()
+            .position(|(name, _)| name == &rule.argument)
+        else {
+            if let Some(default) = default {
+                arguments.push((rule.argument.clone(), default));
+                self.changed = true;
+            }
+            return;
+        };
+
+        let value = &mut arguments[index].1;
+        match value {
+            query::Value::Int(n) => {
+                if let Some(n_value) = n.as_i64()
+                    && rule.clamp(n_value) != n_value
+                {
+                    *n = (rule.clamp(n_value) as i32).into();
+                    self.changed = true;
+                }
+            }
+            query::Value::Variable(variable) => {
+                let variable = variable.clone();
+                let passed = self
+                    .variables
+                    .as_ref()
+                    .and_then(|variables| variables.get(&variable))
+                    .and_then(Value::as_i64);
+                match passed {
+                    Some(passed) if rule.clamp(passed) != passed => {
+                        if let Some(Value::Object(variables)) = &mut self.variables {
+                            variables.insert(variable, rule.clamp(passed).into());
+                            self.changed = true;
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        if let Some(default) = default {
+                            *value = default;
+                            self.changed = true;
+                        }
+                    }
+                }
+            }
+            query::Value::Null => {
+                if let Some(default) = default {
+                    *value = default;
+                    self.changed = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::GraphQLRequest;
+use crate::plan_cache::{compress, decompress};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedQueryConfig {
+    // Queries remembered by the in-memory store, 0 disables automatic
+    // persisted queries
+    pub cache_size: usize,
+}
+
+impl Default for PersistedQueryConfig {
+    fn default() -> Self {
+        PersistedQueryConfig { cache_size: 1024 }
+    }
+}
+
+// Query texts by the hex SHA-256 clients send in
+// `extensions.persistedQuery.sha256Hash`. Shared stores let a query
+// registered through one gateway instance be found through the others.
+#[async_trait]
+pub trait PersistedQueryStore: Send + Sync {
+    async fn get(&self, hash: &str) -> Option<String>;
+    async fn put(&self, hash: &str, query: String);
+}
+
+// Query texts are kept as zstd frames, decompressed when looked up
+pub struct InMemoryPersistedQueryStore {
+    queries: Mutex<LruCache<String, Vec<u8>>>,
+}
+
+impl InMemoryPersistedQueryStore {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        InMemoryPersistedQueryStore {
+            queries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    // Bytes held by the stored query texts, as compressed
+    pub fn stored_bytes(&self) -> usize {
+        let queries = self.queries.lock().unwrap();
+        queries.iter().map(|(_, query)| query.len()).sum()
+    }
+}
+
+#[async_trait]
+impl PersistedQueryStore for InMemoryPersistedQueryStore {
+    async fn get(&self, hash: &str) -> Option<String> {
+        let compressed = self.queries.lock().unwrap().get(hash).cloned()?;
+        decompress(&compressed)
+    }
+
+    async fn put(&self, hash: &str, query: String) {
+        // A query that fails to compress isn't remembered, the client sends
+        // it again
+        if let Ok(compressed) = compress(&query) {
+            self.queries
+                .lock()
+                .unwrap()
+                .put(hash.to_string(), compressed);
+        }
+    }
+}
+
+// Fills in the query of a request sent as a hash alone, or registers the
+// query of the follow-up request carrying both. Requests without
+// `extensions.persistedQuery` are left alone. The extension is removed once
+// handled, so resolving a request again does nothing.
+pub async fn resolve(
+    store: Option<&dyn PersistedQueryStore>,
+    request: &mut GraphQLRequest,
+) -> Result<(), String> {
+    let Some(persisted_query) = request
+        .extensions
+        .as_mut()
+        .and_then(|extensions| extensions.as_object_mut())
+        .and_then(|extensions| extensions.remove("persistedQuery"))
+    else {
+        return Ok(());
+    };
+    let Some(store) = store else {
+        return Err("PersistedQueryNotSupported".to_string());
+    };
+    let Some(hash) = persisted_query["sha256Hash"].as_str() else {
+        return Err("Persisted query extension is missing the sha256Hash".to_string());
+    };
+
+    if request.query.is_empty() {
+        request.query = store
+            .get(hash)
+            .await
+            .ok_or_else(|| "PersistedQueryNotFound".to_string())?;
+        return Ok(());
+    }
+
+    if !hex::encode(Sha256::digest(request.query.as_bytes())).eq_ignore_ascii_case(hash) {
+        return Err("Provided sha256Hash does not match the query".to_string());
+    }
+    store.put(hash, request.query.clone()).await;
+    Ok(())
+}
@@ -0,0 +1,427 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::{
+    FederatedSchema, FetchNode, PlanNode, QueryPlan, query_planner::QueryPlanner,
+    request_context::RequestContext,
+};
+
+struct PlanCache {
+    generation: u64,
+    plans: LruCache<String, CachedPlan>,
+}
+
+// With compression, the fetch query texts of the plan are kept apart as one
+// zstd frame and the plan holds empty queries until it is handed out again
+struct CachedPlan {
+    plan: QueryPlan,
+    queries: Option<Vec<u8>>,
+    // Size of the query texts as stored, compressed or not
+    query_bytes: usize,
+}
+
+// Queries are joined with a NUL, which GraphQL documents can't contain
+const QUERY_SEPARATOR: char = '\0';
+const COMPRESSION_LEVEL: i32 = 3;
+
+// Query planner decorator that keeps the most recently used plans around so
+// repeated operations skip parsing and planning. Cached plans only live as long
+// as the schema generation they were planned against.
+pub struct CachingQueryPlanner {
+    inner: Box<dyn QueryPlanner + Send + Sync>,
+    cache: Mutex<PlanCache>,
+    compress_queries: bool,
+}
+
+impl CachingQueryPlanner {
+    pub fn new(inner: Box<dyn QueryPlanner + Send + Sync>, capacity: NonZeroUsize) -> Self {
+        CachingQueryPlanner {
+            inner,
+            cache: Mutex::new(PlanCache {
+                generation: 0,
+                plans: LruCache::new(capacity),
+            }),
+            compress_queries: false,
+        }
+    }
+
+    // Trades a little CPU on every cache hit for keeping large subgraph
+    // queries compressed in memory
+    pub fn with_query_compression(mut self, compress_queries: bool) -> Self {
+        self.compress_queries = compress_queries;
+        self
+    }
+
+    // Bytes held by the fetch query texts of the cached plans, as stored
+    pub fn query_bytes(&self) -> usize {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .plans
+            .iter()
+            .map(|(_, cached)| cached.query_bytes)
+            .sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().plans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    async fn plan_and_store(
+        &self,
+        key: String,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        let plan = self
+            .inner
+            .plan_in_context(query, schema, variables, operation_name, context)
+            .await?;
+
+        let cached = self.store(plan.clone())?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation == schema.generation {
+            cache.plans.put(key, cached);
+        }
+
+        Ok(plan)
+    }
+
+    fn store(&self, mut plan: QueryPlan) -> Result<CachedPlan, String> {
+        if !self.compress_queries {
+            let mut query_bytes = 0;
+            for_each_plan_fetch(&mut plan, &mut |fetch| query_bytes += fetch.query.len());
+            return Ok(CachedPlan {
+                plan,
+                queries: None,
+                query_bytes,
+            });
+        }
+
+        let mut queries = String::new();
+        let mut first = true;
+        for_each_plan_fetch(&mut plan, &mut |fetch| {
+            if !first {
+                queries.push(QUERY_SEPARATOR);
+            }
+            first = false;
+            queries.push_str(&std::mem::take(&mut fetch.query));
+        });
+        let queries =
+            compress(&queries).map_err(|e| format!("Failed to compress query plan: {}", e))?;
+
+        Ok(CachedPlan {
+            plan,
+            query_bytes: queries.len(),
+            queries: Some(queries),
+        })
+    }
+}
+
+// One zstd frame holding `text`
+pub(crate) fn compress(text: &str) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(text.as_bytes(), COMPRESSION_LEVEL)
+}
+
+pub(crate) fn decompress(compressed: &[u8]) -> Option<String> {
+    zstd::decode_all(compressed)
+        .ok()
+        .and_then(|text| String::from_utf8(text).ok())
+}
+
+impl CachedPlan {
+    fn restore(&self) -> Result<QueryPlan, String> {
+        let mut plan = self.plan.clone();
+        let Some(compressed) = &self.queries else {
+            return Ok(plan);
+        };
+
+        let queries = decompress(compressed)
+            .ok_or_else(|| "Failed to decompress cached query plan".to_string())?;
+        let mut queries = queries.split(QUERY_SEPARATOR);
+        for_each_plan_fetch(&mut plan, &mut |fetch| {
+            fetch.query = queries.next().unwrap_or_default().to_string();
+        });
+        Ok(plan)
+    }
+}
+
+#[async_trait]
+impl QueryPlanner for CachingQueryPlanner {
+    async fn plan_query(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.plan_in_context(
+            query,
+            schema,
+            variables,
+            operation_name,
+            &RequestContext::default(),
+        )
+        .await
+    }
+
+    async fn plan_query_uncached(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.plan_in_context(
+            query,
+            schema,
+            variables,
+            operation_name,
+            &RequestContext::new(true),
+        )
+        .await
+    }
+
+    // Plans routed apart by the inner planner are cached apart, under the
+    // query hash followed by their routing
+    async fn plan_in_context(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        let mut key = cache_key(query, operation_name, &variables);
+        let routing = self
+            .inner
+            .routing_key(query, schema, operation_name, context);
+        if !routing.is_empty() {
+            key.push(':');
+            key.push_str(&routing);
+        }
+        if context.bypass_cache {
+            return self
+                .plan_and_store(key, query, schema, variables, operation_name, context)
+                .await;
+        }
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.generation != schema.generation {
+                cache.plans.clear();
+                cache.generation = schema.generation;
+            }
+
+            if let Some(cached) = cache.plans.get(&key) {
+                let mut plan = cached.restore()?;
+                for_each_plan_fetch(&mut plan, &mut |fetch| {
+                    bind_fetch_variables(fetch, &variables)
+                });
+                return Ok(plan);
+            }
+        }
+
+        self.plan_and_store(key, query, schema, variables, operation_name, context)
+            .await
+    }
+
+    fn routing_key(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> String {
+        self.inner
+            .routing_key(query, schema, operation_name, context)
+    }
+
+    fn invalidate(&self, prefix: &str) -> usize {
+        let mut cache = self.cache.lock().unwrap();
+        let keys: Vec<String> = cache
+            .plans
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            cache.plans.pop(key);
+        }
+        keys.len()
+    }
+
+    fn is_cached(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: &Option<Value>,
+        operation_name: Option<&str>,
+    ) -> bool {
+        let key = cache_key(query, operation_name, variables);
+        let cache = self.cache.lock().unwrap();
+        cache.generation == schema.generation && cache.plans.contains(&key)
+    }
+
+    fn metrics(&self) -> String {
+        self.inner.metrics()
+    }
+}
+
+// Plans embed the variables each fetch uses, so the key covers which variables
+// were provided and which of them are null, as required variables may not be.
+// @skip/@include can prune the plan based on variable values
+// and @defer/@stream take their arguments from them, in which case the values
+// themselves become part of the key. The hex key doubles as the query hash
+// cache invalidation matches prefixes against.
+pub fn cache_key(query: &str, operation_name: Option<&str>, variables: &Option<Value>) -> String {
+    let normalized = normalize_query(query);
+
+    let mut hasher = Sha256::new();
+    hasher.update(normalized.as_bytes());
+    hasher.update([0]);
+    hasher.update(operation_name.unwrap_or_default().as_bytes());
+
+    if let Some(Value::Object(obj)) = variables {
+        let mut names: Vec<&String> = obj.keys().collect();
+        names.sort();
+        for name in names {
+            hasher.update([0]);
+            hasher.update(name.as_bytes());
+            hasher.update([obj[name].is_null() as u8]);
+        }
+
+        if ["@skip", "@include", "@defer", "@stream"]
+            .iter()
+            .any(|directive| normalized.contains(directive))
+        {
+            hasher.update([0]);
+            hasher.update(Value::Object(obj.clone()).to_string().as_bytes());
+        }
+    }
+
+    hex::encode(hasher.finalize())
+}
+
+// Drop the tokens GraphQL ignores (whitespace, commas and comments) so that
+// formatting differences map to the same cache entry. A single space is kept
+// only where it separates two names.
+pub fn normalize_query(query: &str) -> String {
+    let mut normalized = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    let mut pending_space = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => pending_space = true,
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' || c == '\r' {
+                        break;
+                    }
+                }
+                pending_space = true;
+            }
+            '"' => {
+                pending_space = false;
+                normalized.push(c);
+
+                let rest = chars.clone().take(2).collect::<String>();
+                if rest == "\"\"" {
+                    // Block string, copied verbatim up to the closing quotes
+                    normalized.push_str("\"\"");
+                    chars.nth(1);
+                    while let Some(c) = chars.next() {
+                        normalized.push(c);
+                        if c == '"' && chars.clone().take(2).collect::<String>() == "\"\"" {
+                            normalized.push_str("\"\"");
+                            chars.nth(1);
+                            break;
+                        }
+                    }
+                } else {
+                    let mut escaped = false;
+                    for c in chars.by_ref() {
+                        normalized.push(c);
+                        match c {
+                            '\\' if !escaped => escaped = true,
+                            '"' if !escaped => break,
+                            _ => escaped = false,
+                        }
+                    }
+                }
+            }
+            c => {
+                if pending_space
+                    && is_name_char(c)
+                    && normalized.chars().last().is_some_and(is_name_char)
+                {
+                    normalized.push(' ');
+                }
+                pending_space = false;
+                normalized.push(c);
+            }
+        }
+    }
+
+    normalized
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Visits the fetches of the plan and its deferred fragments, always in the
+// same order
+fn for_each_plan_fetch(plan: &mut QueryPlan, f: &mut impl FnMut(&mut FetchNode)) {
+    for_each_fetch(&mut plan.node, f);
+    for deferred in &mut plan.deferred {
+        for_each_fetch(&mut deferred.node, f);
+    }
+}
+
+fn for_each_fetch(node: &mut PlanNode, f: &mut impl FnMut(&mut FetchNode)) {
+    match node {
+        PlanNode::Fetch(fetch) => f(fetch),
+        PlanNode::Parallel(nodes) | PlanNode::Sequence(nodes) => {
+            for node in nodes {
+                for_each_fetch(node, f);
+            }
+        }
+        PlanNode::Flatten(flatten) => for_each_fetch(&mut flatten.node, f),
+        PlanNode::Merge(merge) => {
+            for node in &mut merge.nodes {
+                for_each_fetch(node, f);
+            }
+        }
+        PlanNode::Subscription(subscription) => {
+            f(&mut subscription.primary);
+            if let Some(rest) = &mut subscription.rest {
+                for_each_fetch(rest, f);
+            }
+        }
+    }
+}
+
+// Refreshes the variables a cached plan was built with from the current
+// request. Variables the request left out keep the declared default the plan
+// was built with, the key guarantees the same variables were left out then.
+fn bind_fetch_variables(fetch: &mut FetchNode, variables: &Option<Value>) {
+    if let Value::Object(fetch_variables) = &mut fetch.variables {
+        for (name, value) in fetch_variables.iter_mut() {
+            if let Some(bound) = variables.as_ref().and_then(|variables| variables.get(name)) {
+                *value = bound.clone();
+            }
+        }
+    }
+}
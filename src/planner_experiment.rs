@@ -0,0 +1,262 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::{
+    FederatedSchema, QueryPlan, query_planner::QueryPlanner, request_context::RequestContext,
+};
+
+// Which requests a variant plans
+#[derive(Clone, Debug, Default)]
+pub struct PlannerSplit {
+    // Share of the requests, out of 100, bucketed by tenant so a client
+    // sticks to one planner. Requests without a tenant are bucketed by
+    // operation.
+    pub percentage: u8,
+    // Requests taking part in this experiment, through `extensions.experiments`
+    // or the `x-portkey-experiments` header, whatever their bucket
+    pub experiment: Option<String>,
+}
+
+struct Planner {
+    name: String,
+    planner: Box<dyn QueryPlanner + Send + Sync>,
+    split: PlannerSplit,
+    plans: AtomicU64,
+    errors: AtomicU64,
+    planning_micros: AtomicU64,
+}
+
+// Query planner decorator running planners side by side: every request is
+// planned by one of the variants it falls in, or else by the control, and
+// each planner's plans, errors and planning time are reported apart so a new
+// planner can be compared against the current one on live traffic. Operations
+// a variant fails to plan are planned by the control instead.
+pub struct ExperimentQueryPlanner {
+    control: Planner,
+    variants: Vec<Planner>,
+}
+
+impl Planner {
+    fn new(name: &str, planner: Box<dyn QueryPlanner + Send + Sync>, split: PlannerSplit) -> Self {
+        Planner {
+            name: name.to_string(),
+            planner,
+            split,
+            plans: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            planning_micros: AtomicU64::new(0),
+        }
+    }
+
+    async fn plan(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        let start = Instant::now();
+        let plan = self
+            .planner
+            .plan_in_context(query, schema, variables, operation_name, context)
+            .await;
+        self.planning_micros
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        match &plan {
+            Ok(_) => self.plans.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.errors.fetch_add(1, Ordering::Relaxed),
+        };
+        plan
+    }
+}
+
+impl ExperimentQueryPlanner {
+    pub fn new(control_name: &str, control: Box<dyn QueryPlanner + Send + Sync>) -> Self {
+        ExperimentQueryPlanner {
+            control: Planner::new(control_name, control, PlannerSplit::default()),
+            variants: Vec::new(),
+        }
+    }
+
+    // Variants are tried in the order they're added, their percentages
+    // taking consecutive buckets
+    pub fn with_variant(
+        mut self,
+        name: &str,
+        planner: Box<dyn QueryPlanner + Send + Sync>,
+        split: PlannerSplit,
+    ) -> Self {
+        self.variants.push(Planner::new(name, planner, split));
+        self
+    }
+
+    // The variant planning the request, if any
+    fn variant(
+        &self,
+        query: &str,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Option<&Planner> {
+        if let Some(variant) = self.variants.iter().find(|variant| {
+            variant
+                .split
+                .experiment
+                .as_ref()
+                .is_some_and(|experiment| context.experiments.contains(experiment))
+        }) {
+            return Some(variant);
+        }
+
+        let mut hasher = Sha256::new();
+        match &context.tenant {
+            Some(tenant) => hasher.update(tenant.as_bytes()),
+            None => {
+                hasher.update(query.as_bytes());
+                hasher.update([0]);
+                hasher.update(operation_name.unwrap_or_default().as_bytes());
+            }
+        }
+        let digest = hasher.finalize();
+        let bucket = u16::from_be_bytes([digest[0], digest[1]]) % 100;
+
+        let mut taken = 0;
+        self.variants.iter().find(|variant| {
+            taken += variant.split.percentage as u16;
+            bucket < taken
+        })
+    }
+
+    fn planners(&self) -> impl Iterator<Item = &Planner> {
+        std::iter::once(&self.control).chain(&self.variants)
+    }
+}
+
+#[async_trait]
+impl QueryPlanner for ExperimentQueryPlanner {
+    async fn plan_query(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.plan_in_context(
+            query,
+            schema,
+            variables,
+            operation_name,
+            &RequestContext::default(),
+        )
+        .await
+    }
+
+    async fn plan_query_uncached(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.plan_in_context(
+            query,
+            schema,
+            variables,
+            operation_name,
+            &RequestContext::new(true),
+        )
+        .await
+    }
+
+    async fn plan_in_context(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        if let Some(variant) = self.variant(query, operation_name, context) {
+            match variant
+                .plan(query, schema, variables.clone(), operation_name, context)
+                .await
+            {
+                Ok(plan) => return Ok(plan),
+                Err(e) => warn!(
+                    planner = %variant.name,
+                    error = %e,
+                    "Planner variant failed, planning with the control"
+                ),
+            }
+        }
+        self.control
+            .plan(query, schema, variables, operation_name, context)
+            .await
+    }
+
+    // Plans of a variant are cached apart from the control's, under its name
+    fn routing_key(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> String {
+        match self.variant(query, operation_name, context) {
+            Some(variant) => {
+                let routing = variant
+                    .planner
+                    .routing_key(query, schema, operation_name, context);
+                format!("{}:{}", variant.name, routing)
+            }
+            None => self
+                .control
+                .planner
+                .routing_key(query, schema, operation_name, context),
+        }
+    }
+
+    fn invalidate(&self, prefix: &str) -> usize {
+        self.planners()
+            .map(|planner| planner.planner.invalidate(prefix))
+            .sum()
+    }
+
+    fn metrics(&self) -> String {
+        let mut metrics = String::new();
+        let counters = [
+            (
+                "portkey_planner_plans_total",
+                "Operations planned, by planner.",
+            ),
+            (
+                "portkey_planner_errors_total",
+                "Operations that failed to plan, by planner.",
+            ),
+            (
+                "portkey_planner_planning_microseconds_total",
+                "Time spent planning operations, by planner.",
+            ),
+        ];
+        for (i, (name, help)) in counters.into_iter().enumerate() {
+            let _ = writeln!(metrics, "# HELP {} {}", name, help);
+            let _ = writeln!(metrics, "# TYPE {} counter", name);
+            for planner in self.planners() {
+                let value = [&planner.plans, &planner.errors, &planner.planning_micros][i];
+                let _ = writeln!(
+                    metrics,
+                    "{}{{planner=\"{}\"}} {}",
+                    name,
+                    planner.name,
+                    value.load(Ordering::Relaxed)
+                );
+            }
+        }
+        metrics
+    }
+}
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+use crate::memory::write_metric;
+
+// Values the subgraphs return are checked against the types the composed
+// schema declares for them. Invalid values are answered as null with an
+// error, and a field whose service keeps returning invalid values is
+// quarantined: it's answered with an error whatever the service returns,
+// until the quarantine is over.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    pub enabled: bool,
+    // Responses with invalid values for a field, within `window_seconds`,
+    // that quarantine it
+    pub threshold: u32,
+    pub window_seconds: u64,
+    // How long a field stays quarantined before it's served again
+    pub duration_seconds: u64,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        QuarantineConfig {
+            enabled: false,
+            threshold: 3,
+            window_seconds: 60,
+            duration_seconds: 300,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldState {
+    service: String,
+    invalid_responses: u32,
+    window_started: Option<Instant>,
+    quarantined_until: Option<Instant>,
+}
+
+pub struct FieldQuarantine {
+    config: QuarantineConfig,
+    // "Type.field" -> what its values have been like lately
+    fields: Mutex<HashMap<String, FieldState>>,
+    quarantines: AtomicU64,
+}
+
+impl FieldQuarantine {
+    pub fn new(config: &QuarantineConfig) -> Self {
+        FieldQuarantine {
+            config: config.clone(),
+            fields: Mutex::new(HashMap::new()),
+            quarantines: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_quarantined(&self, field_key: &str) -> bool {
+        let now = Instant::now();
+        self.fields
+            .lock()
+            .unwrap()
+            .get(field_key)
+            .and_then(|state| state.quarantined_until)
+            .is_some_and(|until| until > now)
+    }
+
+    // Records a response in which `service` returned invalid values for the
+    // field, returning whether this quarantined it
+    pub fn record_invalid(&self, field_key: &str, service: &str) -> bool {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_seconds);
+        let mut fields = self.fields.lock().unwrap();
+        let state = fields.entry(field_key.to_string()).or_default();
+        if state.quarantined_until.is_some_and(|until| until > now) {
+            return false;
+        }
+
+        if state
+            .window_started
+            .is_none_or(|started| now.duration_since(started) > window)
+        {
+            state.window_started = Some(now);
+            state.invalid_responses = 0;
+        }
+        state.service = service.to_string();
+        state.invalid_responses += 1;
+        if state.invalid_responses < self.config.threshold {
+            return false;
+        }
+
+        state.quarantined_until = Some(now + Duration::from_secs(self.config.duration_seconds));
+        state.window_started = None;
+        state.invalid_responses = 0;
+        self.quarantines.fetch_add(1, Ordering::Relaxed);
+        let type_name = field_key.split('.').next().unwrap_or(field_key);
+        error!(
+            service,
+            r#type = type_name,
+            field = field_key,
+            "ALERT: quarantined {} for {}s, service {} returned invalid values for it {} times within {}s",
+            field_key,
+            self.config.duration_seconds,
+            service,
+            self.config.threshold,
+            self.config.window_seconds
+        );
+        true
+    }
+
+    // The fields quarantined right now, with the service returning invalid
+    // values for them and the seconds left, as served by `/admin/quarantine`
+    pub fn quarantined(&self) -> Value {
+        let now = Instant::now();
+        let fields = self.fields.lock().unwrap();
+        let mut quarantined: Vec<(&String, &FieldState, Instant)> = fields
+            .iter()
+            .filter_map(|(field_key, state)| {
+                state
+                    .quarantined_until
+                    .filter(|until| *until > now)
+                    .map(|until| (field_key, state, until))
+            })
+            .collect();
+        quarantined.sort_by(|a, b| a.0.cmp(b.0));
+        Value::Array(
+            quarantined
+                .into_iter()
+                .map(|(field_key, state, until)| {
+                    json!({
+                        "field": field_key,
+                        "service": state.service,
+                        "remainingSeconds": until.duration_since(now).as_secs(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let mut metrics = String::new();
+        write_metric(
+            &mut metrics,
+            "portkey_quarantined_fields",
+            "gauge",
+            "Fields currently quarantined for invalid values from their service.",
+            self.quarantined().as_array().map_or(0, Vec::len) as u64,
+        );
+        write_metric(
+            &mut metrics,
+            "portkey_field_quarantines_total",
+            "counter",
+            "Times a field was quarantined for invalid values from its service.",
+            self.quarantines.load(Ordering::Relaxed),
+        );
+        metrics
+    }
+}
+
+// Whether `value` can be a value of the introspection type reference. Null
+// is always accepted, services null fields out along with an error.
+pub fn is_valid(value: &Value, type_ref: &Value) -> bool {
+    if value.is_null() {
+        return true;
+    }
+    match type_ref["kind"].as_str() {
+        Some("NON_NULL") => is_valid(value, &type_ref["ofType"]),
+        Some("LIST") => value
+            .as_array()
+            .is_some_and(|items| items.iter().all(|item| is_valid(item, &type_ref["ofType"]))),
+        Some("SCALAR") => match type_ref["name"].as_str() {
+            Some("Int") => value.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+            Some("Float") => value.is_number(),
+            Some("String") => value.is_string(),
+            Some("Boolean") => value.is_boolean(),
+            Some("ID") => value.is_string() || value.is_i64() || value.is_u64(),
+            // Custom scalars can be serialized as anything
+            _ => true,
+        },
+        Some("ENUM") => value.is_string(),
+        Some("OBJECT") | Some("INTERFACE") | Some("UNION") => value.is_object(),
+        _ => true,
+    }
+}
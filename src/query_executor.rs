@@ -1,9 +1,39 @@
 use async_trait::async_trait;
-use futures::{FutureExt, future::try_join_all};
+use bytes::Bytes;
+use flate2::{Compression, write::GzEncoder};
+use futures::{
+    FutureExt, StreamExt,
+    future::{BoxFuture, try_join_all},
+    stream::{self, BoxStream, FuturesUnordered},
+};
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
-use crate::{FederatedSchema, QueryPlan};
+use crate::{
+    CacheInvalidation, CompressionConfig, ContentEncoding, DeferredPlan, EntityKey,
+    FederatedSchema, FetchNode, JoinKey, KeyField, PlanNode, QueryPlan, ResponseField,
+    ServiceConfig, broker,
+    entity_cache::EntityCache,
+    inflight::InflightFetches,
+    intern::Name,
+    literal,
+    memory::{MemoryBudget, MemoryLimiter, json_size},
+    messages,
+    quarantine::{self, FieldQuarantine},
+    request_context::RequestContext,
+    schema_trim,
+    service_health::ServiceHealth,
+    subgraph_hook::{SubgraphHook, SubgraphRequest},
+    subgraph_log::SubgraphLogger,
+    upload::Uploads,
+    websocket::SubscriptionExecutor,
+};
 
 #[async_trait]
 pub trait QueryExecutor: Send + Sync {
@@ -13,119 +43,2079 @@ pub trait QueryExecutor: Send + Sync {
         schema: &FederatedSchema,
         auth_headers: Option<HashMap<String, String>>,
     ) -> Result<Value, String>;
+
+    // Executes without answering entities from a cache, while still caching
+    // the entities the services return
+    async fn execute_plan_uncached(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<Value, String> {
+        self.execute_plan(plan, schema, auth_headers).await
+    }
+
+    // Executes with the request's context: its cache bypass is honored and
+    // the time spent in every service is recorded into it, by executors
+    // keeping track of that
+    async fn execute_in_context(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        if context.bypass_cache {
+            self.execute_plan_uncached(plan, schema, auth_headers).await
+        } else {
+            self.execute_plan(plan, schema, auth_headers).await
+        }
+    }
+
+    // Like `execute_in_context`, with the plan's deferred fragments left out
+    // of the response and delivered by the returned stream instead, one
+    // payload per deferred fetch as soon as it's done. Executors without
+    // incremental delivery resolve everything into the response.
+    async fn execute_incremental(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        let response = self
+            .execute_in_context(plan, schema, auth_headers, context)
+            .await?;
+        Ok((response, None))
+    }
+
+    // Drops cached entities matching `invalidation`, returning how many were
+    // removed. Executors without an entity cache have nothing to drop.
+    fn invalidate_entities(&self, _invalidation: &CacheInvalidation) -> usize {
+        0
+    }
+
+    // Metrics in the Prometheus text format, empty for executors keeping none
+    fn metrics(&self) -> String {
+        String::new()
+    }
+
+    // Opens the event stream of a subscription plan. Every item is the
+    // response to one event, the stream ends when the service completes the
+    // subscription or drops the connection.
+    async fn subscribe(
+        &self,
+        _plan: QueryPlan,
+        _schema: &FederatedSchema,
+        _auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        Err("Subscriptions are not supported".to_string())
+    }
 }
 
-pub struct HttpQueryExecutor {}
+// The HTTP client every request to the services goes through, keeping
+// connections to them open between requests
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    // Idle connections kept open to each service
+    pub pool_max_idle_per_host: usize,
+    // Idle connections are closed after this long
+    pub pool_idle_timeout_seconds: u64,
+    // Interval of TCP keep-alive probes on open connections, 0 disables them
+    pub tcp_keepalive_seconds: u64,
+    pub connect_timeout_ms: u64,
+    // Time a query to a service may take, response included. Unset waits as
+    // long as the service does. Subscription streams aren't limited.
+    pub request_timeout_ms: Option<u64>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            pool_max_idle_per_host: 32,
+            pool_idle_timeout_seconds: 90,
+            tcp_keepalive_seconds: 60,
+            connect_timeout_ms: 5000,
+            request_timeout_ms: None,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    fn client(&self, request_timeout: Option<u64>) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout_seconds))
+            .connect_timeout(Duration::from_millis(self.connect_timeout_ms))
+            .tcp_keepalive(
+                (self.tcp_keepalive_seconds > 0)
+                    .then(|| Duration::from_secs(self.tcp_keepalive_seconds)),
+            );
+        if let Some(timeout) = request_timeout {
+            builder = builder.timeout(Duration::from_millis(timeout));
+        }
+        // Like `reqwest::Client::new`, only fails when TLS can't be set up
+        builder.build().expect("Failed to build the HTTP client")
+    }
+}
+
+// Services the client's credentials (`Authorization`, `x-api-key` and
+// `x-token`) are forwarded to, so third-party services never see the tokens
+// of internal users. Patterns are service names, `*` matching any run of
+// characters. By default every service gets them.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthForwardingConfig {
+    // Only these services get the credentials when set
+    pub allow: Option<Vec<String>>,
+    // These services never get them, even when allowed
+    pub deny: Vec<String>,
+}
+
+impl AuthForwardingConfig {
+    pub fn forwards_to(&self, service_name: &str) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| schema_trim::matches(pattern, service_name))
+        };
+        self.allow.as_deref().is_none_or(matches) && !matches(&self.deny)
+    }
+}
+
+// Clones share the entity cache and what was learned about the services
+#[derive(Clone)]
+pub struct HttpQueryExecutor {
+    // Shared by every request, cloning it shares the connection pool
+    client: reqwest::Client,
+    // Without the request timeout, for the long-lived event streams of
+    // subscriptions
+    event_stream_client: reqwest::Client,
+    // For services taking subscriptions over WebSockets
+    subscription_executor: SubscriptionExecutor,
+    entity_cache: Option<Arc<EntityCache>>,
+    // Services configured for persisted queries that turned out not to
+    // support them
+    persisted_queries_unsupported: Arc<Mutex<HashSet<String>>>,
+    memory: Arc<MemoryLimiter>,
+    hooks: Vec<Arc<dyn SubgraphHook>>,
+    logger: Arc<SubgraphLogger>,
+    // A failed fetch leaves its fields null and adds an error, instead of
+    // failing the whole request
+    partial_results: bool,
+    // Checks the values of the services against the schema when set
+    quarantine: Option<Arc<FieldQuarantine>>,
+    // Fetches to unavailable services are failed without being sent when set
+    service_health: Option<Arc<ServiceHealth>>,
+    // Identical fetches in flight at once are sent once when set
+    inflight: Option<Arc<InflightFetches>>,
+    auth_forwarding: AuthForwardingConfig,
+}
 
 impl HttpQueryExecutor {
     pub fn new() -> Self {
-        HttpQueryExecutor {}
+        let client_config = HttpClientConfig::default();
+        HttpQueryExecutor {
+            client: client_config.client(client_config.request_timeout_ms),
+            event_stream_client: client_config.client(None),
+            subscription_executor: SubscriptionExecutor::new(Duration::from_millis(
+                client_config.connect_timeout_ms,
+            )),
+            entity_cache: None,
+            persisted_queries_unsupported: Arc::new(Mutex::new(HashSet::new())),
+            memory: Arc::new(MemoryLimiter::default()),
+            hooks: Vec::new(),
+            logger: Arc::new(SubgraphLogger::default()),
+            partial_results: false,
+            quarantine: None,
+            service_health: None,
+            inflight: None,
+            auth_forwarding: AuthForwardingConfig::default(),
+        }
     }
-}
 
-#[async_trait]
-impl QueryExecutor for HttpQueryExecutor {
-    async fn execute_plan(
+    pub fn with_partial_results(mut self, partial_results: bool) -> Self {
+        self.partial_results = partial_results;
+        self
+    }
+
+    pub fn with_auth_forwarding(mut self, auth_forwarding: AuthForwardingConfig) -> Self {
+        self.auth_forwarding = auth_forwarding;
+        self
+    }
+
+    // Shared like the entity cache, the admin API lists what's quarantined
+    pub fn with_quarantine(mut self, quarantine: Arc<FieldQuarantine>) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    // Concurrent requests making byte-identical fetches, auth headers
+    // included, share a single request to the service. Mutations and fetches
+    // sending files are always sent.
+    pub fn with_fetch_deduplication(mut self, enabled: bool) -> Self {
+        self.inflight = enabled.then(|| Arc::new(InflightFetches::new()));
+        self
+    }
+
+    // Shared with the gateway, which reports the unavailable root fields
+    pub fn with_service_health(mut self, service_health: Arc<ServiceHealth>) -> Self {
+        self.service_health = Some(service_health);
+        self
+    }
+
+    pub fn with_http_client(mut self, client_config: &HttpClientConfig) -> Self {
+        self.client = client_config.client(client_config.request_timeout_ms);
+        self.event_stream_client = client_config.client(None);
+        self.subscription_executor =
+            SubscriptionExecutor::new(Duration::from_millis(client_config.connect_timeout_ms));
+        self
+    }
+
+    // The cache is shared so the caller can keep a handle for invalidation
+    pub fn with_entity_cache(mut self, entity_cache: Arc<EntityCache>) -> Self {
+        self.entity_cache = Some(entity_cache);
+        self
+    }
+
+    // Shared like the entity cache, the caller reports its figures
+    pub fn with_memory_limiter(mut self, memory: Arc<MemoryLimiter>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    // Shared with the admin API, which changes what's logged at runtime
+    pub fn with_subgraph_logger(mut self, logger: Arc<SubgraphLogger>) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    pub fn with_subgraph_hook(mut self, hook: Arc<dyn SubgraphHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    // The request for `query` as the hooks leave it
+    fn subgraph_request(
         &self,
-        query_plan: QueryPlan,
+        service: &ServiceConfig,
         schema: &FederatedSchema,
-        auth_headers: Option<HashMap<String, String>>,
-    ) -> Result<Value, String> {
-        let client = reqwest::Client::new();
+        query: &str,
+        variables: &Value,
+        auth_headers: &Option<HashMap<String, String>>,
+    ) -> Result<SubgraphRequest, String> {
+        let mut request = SubgraphRequest {
+            service_name: service.name.clone(),
+            query: query.to_string(),
+            variables: variables.clone(),
+            url: service.endpoints()[0].to_string(),
+            headers: auth_headers
+                .clone()
+                .filter(|_| self.auth_forwarding.forwards_to(&service.name))
+                .unwrap_or_default(),
+        };
+        for (name, value) in &service.headers {
+            request
+                .headers
+                .retain(|forwarded, _| !forwarded.eq_ignore_ascii_case(name));
+            request.headers.insert(name.clone(), value.clone());
+        }
+        if service.inline_variables {
+            request.query = literal::inline_variables(query, variables, &schema.introspection)?;
+            request.variables = json!({});
+        }
+        for hook in &self.hooks {
+            hook.on_subgraph_request(&mut request);
+        }
+        Ok(request)
+    }
 
-        let futures = query_plan
-            .service_queries
-            .into_iter()
-            .map(|(service_name, query)| {
-                let service = match schema.services.get(&service_name) {
-                    Some(service) => service,
-                    None => {
-                        return futures::future::ready(Err(format!(
-                            "Service not found: {}",
-                            service_name
-                        )))
-                        .left_future();
+    // The JSON body as sent to the service, compressed when the service takes
+    // compressed requests and the body is large enough to be worth it
+    fn encode_body(
+        body: &Value,
+        compression: &CompressionConfig,
+    ) -> Result<(Bytes, Option<ContentEncoding>), String> {
+        let json =
+            serde_json::to_vec(body).map_err(|e| format!("Failed to serialize request: {}", e))?;
+        let encoding = compression
+            .request_encoding
+            .filter(|_| json.len() >= compression.min_request_bytes);
+        let encoded = match encoding {
+            None => json,
+            Some(ContentEncoding::Gzip) => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+                encoder
+                    .write_all(&json)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|e| format!("Failed to compress request: {}", e))?
+            }
+            Some(ContentEncoding::Br) => {
+                let mut encoded = Vec::new();
+                let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+                encoder
+                    .write_all(&json)
+                    .map_err(|e| format!("Failed to compress request: {}", e))?;
+                drop(encoder);
+                encoded
+            }
+        };
+        Ok((Bytes::from(encoded), encoding))
+    }
+
+    fn json_request(
+        request_builder: reqwest::RequestBuilder,
+        (body, encoding): &(Bytes, Option<ContentEncoding>),
+    ) -> reqwest::RequestBuilder {
+        let request_builder = request_builder
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        match encoding {
+            Some(encoding) => {
+                request_builder.header(reqwest::header::CONTENT_ENCODING, encoding.as_str())
+            }
+            None => request_builder,
+        }
+    }
+
+    // Reads the body chunk by chunk as it is decompressed, giving up as soon as
+    // it grows past the service's limit or the request's memory budget
+    async fn read_body(
+        mut response: reqwest::Response,
+        service: &ServiceConfig,
+        budget: &MemoryBudget,
+    ) -> Result<Vec<u8>, String> {
+        let limit = service.compression.max_decompressed_bytes;
+        let too_large = || {
+            format!(
+                "Response from service {} exceeds the limit of {} bytes",
+                service.name, limit
+            )
+        };
+        // Bodies declaring a larger length are refused before any of it is
+        // read. Decompressed bodies have no known length.
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?
+        {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            budget.charge(chunk.len())?;
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn send_query(
+        &self,
+        client: &reqwest::Client,
+        service: &ServiceConfig,
+        schema: &FederatedSchema,
+        query: &str,
+        variables: &Value,
+        auth_headers: &Option<HashMap<String, String>>,
+        budget: &MemoryBudget,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        let request = self.subgraph_request(service, schema, query, variables, auth_headers)?;
+        let response = match &self.service_health {
+            Some(health) if !health.is_available(&service.name) => {
+                Err(format!("Service {} is unavailable", service.name))
+            }
+            health => {
+                let started = Instant::now();
+                let send = self.send_request(client, service, &request, budget, &context.uploads);
+                let response = match &self.inflight {
+                    Some(inflight)
+                        if context.uploads.is_empty() && !is_mutation(&request.query) =>
+                    {
+                        // Shared results count against every request's budget
+                        match inflight.run(fetch_key(&request), send).await {
+                            (Ok(response), true) => {
+                                budget.charge(json_size(&response)).map(|_| response)
+                            }
+                            (response, _) => response,
+                        }
                     }
+                    _ => send.await,
                 };
+                context.record_fetch(&service.name, started.elapsed());
+                // Requests over their memory budget say nothing about the
+                // service
+                if let Some(health) = health
+                    && !budget.exceeded()
+                {
+                    health.record(&service.name, response.is_ok());
+                }
+                response
+            }
+        };
+        let mut response = match response {
+            Ok(response) => response,
+            // Requests over their memory budget are failed whatever the mode
+            Err(e) if self.partial_results && !budget.exceeded() => {
+                warn!("Fetch from service {} failed: {}", service.name, e);
+                json!({ "data": null, "errors": [service_error(&e, &service.name)] })
+            }
+            Err(e) => return Err(e),
+        };
+        for hook in &self.hooks {
+            hook.on_subgraph_response(&service.name, &mut response);
+        }
+        Ok(response)
+    }
 
-                let variables = query_plan
-                    .service_variables
-                    .get(&service_name)
-                    .cloned()
-                    .unwrap_or(json!({}));
+    async fn send_request(
+        &self,
+        client: &reqwest::Client,
+        service: &ServiceConfig,
+        request: &SubgraphRequest,
+        budget: &MemoryBudget,
+        uploads: &Uploads,
+    ) -> Result<Value, String> {
+        let SubgraphRequest {
+            query, variables, ..
+        } = request;
+        self.logger.log(&service.name, query, variables);
 
-                println!("Executing query for service: {}", service_name);
-                println!("Query: {}", query);
-                println!("Variables for service: {}", variables);
+        if !self.supports_persisted_queries(service) {
+            let body = json!({ "query": query, "variables": variables });
+            return Self::post(client, service, request, &body, budget, uploads).await;
+        }
 
-                let mut request_builder = client.post(&service.url).json(&json!({
-                    "query": query,
-                    "variables": variables
-                }));
+        // Automatic persisted queries: try the hash alone and only send the
+        // full query when the service doesn't know it yet
+        let extensions = json!({
+            "persistedQuery": {
+                "version": 1,
+                "sha256Hash": hex::encode(Sha256::digest(query.as_bytes())),
+            }
+        });
+        let body = json!({ "variables": variables, "extensions": extensions });
+        let response = Self::post(client, service, request, &body, budget, uploads).await?;
 
-                if let Some(headers) = &auth_headers {
-                    for (name, value) in headers {
-                        request_builder = request_builder.header(name, value);
+        match persisted_query_error(&response) {
+            Some("PERSISTED_QUERY_NOT_FOUND") => {
+                let body =
+                    json!({ "query": query, "variables": variables, "extensions": extensions });
+                Self::post(client, service, request, &body, budget, uploads).await
+            }
+            Some(_) => {
+                info!(
+                    "Service {} does not support persisted queries, sending full queries",
+                    service.name
+                );
+                self.persisted_queries_unsupported
+                    .lock()
+                    .unwrap()
+                    .insert(service.name.clone());
+                let body = json!({ "query": query, "variables": variables });
+                Self::post(client, service, request, &body, budget, uploads).await
+            }
+            None => Ok(response),
+        }
+    }
+
+    fn supports_persisted_queries(&self, service: &ServiceConfig) -> bool {
+        service.capabilities.persisted_queries
+            && !self
+                .persisted_queries_unsupported
+                .lock()
+                .unwrap()
+                .contains(&service.name)
+    }
+
+    async fn post(
+        client: &reqwest::Client,
+        service: &ServiceConfig,
+        request: &SubgraphRequest,
+        body: &Value,
+        budget: &MemoryBudget,
+        uploads: &Uploads,
+    ) -> Result<Value, String> {
+        let mutation = is_mutation(&request.query);
+        let response =
+            Self::send_with_failover(client, service, request, body, uploads, mutation).await?;
+
+        let status = response.status();
+        let body = Self::read_body(response, service, budget).await;
+
+        if !status.is_success() {
+            let error_text = body
+                .map(|body| String::from_utf8_lossy(&body).into_owned())
+                .unwrap_or_else(|_| "Could not read error response".to_string());
+            return Err(format!("Service returned error {}: {}", status, error_text));
+        }
+
+        let response_json = serde_json::from_slice::<Value>(&body?)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+        if let Some(errors) = response_json.get("errors") {
+            debug!(
+                "Service {} returned GraphQL errors: {}",
+                service.name, errors
+            );
+        }
+
+        Ok(response_json)
+    }
+
+    // Tries the request's URL, then the service's other endpoints in order,
+    // moving on to the next region when one can't be reached or answers with a
+    // server error. The last endpoint's response is returned whatever its
+    // status. Timed out requests may have reached the service and are not
+    // retried, and mutations only fail over when the connection itself
+    // failed, so that no write is applied in two regions.
+    async fn send_with_failover(
+        client: &reqwest::Client,
+        service: &ServiceConfig,
+        request: &SubgraphRequest,
+        body: &Value,
+        uploads: &Uploads,
+        mutation: bool,
+    ) -> Result<reqwest::Response, String> {
+        let mut endpoints = vec![request.url.as_str()];
+        endpoints.extend(
+            service
+                .endpoints()
+                .into_iter()
+                .filter(|url| *url != request.url),
+        );
+        let mut error = None;
+        let encoded = Self::encode_body(body, &service.compression)?;
+
+        for (i, url) in endpoints.iter().enumerate() {
+            // Fetches using uploaded files are sent as multipart requests,
+            // the files streamed from memory or disk on every attempt
+            let mut request_builder = match uploads.form(body) {
+                Some(form) => client.post(*url).multipart(form),
+                None => Self::json_request(client.post(*url), &encoded),
+            };
+
+            if !service.compression.enabled {
+                request_builder =
+                    request_builder.header(reqwest::header::ACCEPT_ENCODING, "identity");
+            }
+
+            if !request.headers.is_empty() {
+                for (name, value) in &request.headers {
+                    request_builder = request_builder.header(name, value);
+                }
+                debug!("Forwarding auth headers to service {}", service.name);
+            }
+
+            let last = i + 1 == endpoints.len();
+            match request_builder.send().await {
+                Ok(response) if last || mutation || !response.status().is_server_error() => {
+                    return Ok(response);
+                }
+                Ok(response) => warn!(
+                    "Service {} returned {} at {}, failing over",
+                    service.name,
+                    response.status(),
+                    url
+                ),
+                Err(e) if !e.is_connect() && (mutation || e.is_timeout()) => {
+                    return Err(format!("HTTP request failed: {}", e));
+                }
+                Err(e) => {
+                    warn!("Service {} unreachable at {}: {}", service.name, url, e);
+                    error = Some(format!("HTTP request failed: {}", e));
+                }
+            }
+        }
+
+        Err(error.unwrap_or_else(|| format!("Service {} has no endpoint", service.name)))
+    }
+
+    // Runs `node` on top of `data`, returning the updated data together with
+    // the GraphQL errors reported by the services.
+    #[allow(clippy::too_many_arguments)]
+    fn execute_node<'a>(
+        &'a self,
+        client: &'a reqwest::Client,
+        node: &'a PlanNode,
+        schema: &'a FederatedSchema,
+        auth_headers: &'a Option<HashMap<String, String>>,
+        budget: &'a MemoryBudget,
+        context: &'a RequestContext,
+        mut data: Value,
+    ) -> BoxFuture<'a, Result<(Value, Vec<Value>), String>> {
+        async move {
+            let mut errors = Vec::new();
+
+            match node {
+                PlanNode::Fetch(fetch) => {
+                    let result = self
+                        .execute_fetch(
+                            client,
+                            fetch,
+                            schema,
+                            auth_headers,
+                            budget,
+                            context,
+                            &Value::Null,
+                        )
+                        .await?;
+                    collect_errors(&result, &fetch.service_name, &mut errors);
+
+                    if let Some(result_data) = result.get("data").filter(|d| d.is_object()) {
+                        merge_values(&mut data, result_data.clone());
+                    }
+                }
+                PlanNode::Flatten(flatten) => {
+                    let fetch = match flatten.node.as_ref() {
+                        PlanNode::Fetch(fetch) => fetch,
+                        _ => return Err("Flatten nodes must wrap a fetch".to_string()),
+                    };
+                    if let Some(join) = &fetch.join {
+                        // The parent objects are collected like entities,
+                        // their "representation" holding the join's arguments
+                        let parent = EntityKey {
+                            type_name: join.type_name.clone(),
+                            key_fields: join
+                                .arguments
+                                .values()
+                                .map(|field| KeyField {
+                                    name: field.clone(),
+                                    selections: Vec::new(),
+                                })
+                                .collect(),
+                        };
+                        let mut parents = Vec::new();
+                        collect_representations(&data, &flatten.path, &parent, &mut parents);
+                        let mut paths = Vec::new();
+                        collect_entity_paths(
+                            &data,
+                            &flatten.path,
+                            Some(&parent),
+                            &mut Vec::new(),
+                            &mut paths,
+                        );
+                        let (parents, positions) = dedup_representations(parents);
+
+                        let (results, join_errors) = self
+                            .fetch_joined(
+                                client,
+                                fetch,
+                                join,
+                                schema,
+                                auth_headers,
+                                budget,
+                                context,
+                                parents,
+                            )
+                            .await?;
+                        // Each parent's errors are relative to the parent
+                        for (i, mut parent_errors) in join_errors.into_iter().enumerate() {
+                            if let Some(j) = positions.iter().position(|&position| position == i) {
+                                prefix_error_paths(&mut parent_errors, &paths[j]);
+                            }
+                            errors.extend(parent_errors);
+                        }
+
+                        let mut results = positions
+                            .iter()
+                            .map(|&position| results.get(position).cloned().unwrap_or(Value::Null));
+                        merge_entities(&mut data, &flatten.path, &parent, &mut results);
+                        return Ok((data, errors));
+                    }
+                    let entity = fetch
+                        .entity
+                        .as_ref()
+                        .ok_or_else(|| "Flatten nodes must wrap an entity fetch".to_string())?;
+
+                    let mut representations = Vec::new();
+                    collect_representations(&data, &flatten.path, entity, &mut representations);
+                    let mut paths = Vec::new();
+                    collect_entity_paths(
+                        &data,
+                        &flatten.path,
+                        Some(entity),
+                        &mut Vec::new(),
+                        &mut paths,
+                    );
+                    let (representations, positions) = dedup_representations(representations);
+
+                    if !representations.is_empty() {
+                        let (entities, entity_errors) = self
+                            .fetch_entities(
+                                client,
+                                fetch,
+                                schema,
+                                auth_headers,
+                                budget,
+                                context,
+                                representations,
+                            )
+                            .await?;
+                        errors.extend(rebase_entity_errors(entity_errors, |i| {
+                            let j = positions.iter().position(|&position| position == i)?;
+                            Some(paths[j].clone())
+                        }));
+
+                        let mut entities = positions.iter().map(|&position| {
+                            entities.get(position).cloned().unwrap_or(Value::Null)
+                        });
+                        merge_entities(&mut data, &flatten.path, entity, &mut entities);
                     }
-                    println!("Forwarding auth headers to service {}", service_name);
                 }
+                PlanNode::Sequence(nodes) => {
+                    for node in nodes {
+                        let (next_data, node_errors) = self
+                            .execute_node(client, node, schema, auth_headers, budget, context, data)
+                            .await?;
+                        data = next_data;
+                        errors.extend(node_errors);
+                    }
+                }
+                PlanNode::Merge(merge) => {
+                    // Every branch works on a copy of the data
+                    budget.charge(json_size(&data) * merge.nodes.len())?;
+                    let results = try_join_all(merge.nodes.iter().map(|node| {
+                        self.execute_node(
+                            client,
+                            node,
+                            schema,
+                            auth_headers,
+                            budget,
+                            context,
+                            data.clone(),
+                        )
+                    }))
+                    .await?;
+
+                    let mut merged = Value::Null;
+                    for (mut node_data, node_errors) in results {
+                        errors.extend(node_errors);
+
+                        match (&mut merged, node_data[&merge.response_key].take()) {
+                            (Value::Array(items), Value::Array(node_items)) => {
+                                items.extend(node_items)
+                            }
+                            (merged @ Value::Null, value) => *merged = value,
+                            _ => {}
+                        }
+                    }
+
+                    if let Value::Object(obj) = &mut data {
+                        obj.insert(merge.response_key.clone(), merged);
+                    }
+                }
+                PlanNode::Parallel(nodes) => {
+                    budget.charge(json_size(&data) * nodes.len())?;
+                    let results = try_join_all(nodes.iter().map(|node| {
+                        self.execute_node(
+                            client,
+                            node,
+                            schema,
+                            auth_headers,
+                            budget,
+                            context,
+                            data.clone(),
+                        )
+                    }))
+                    .await?;
+
+                    for (node_data, node_errors) in results {
+                        merge_values(&mut data, node_data);
+                        errors.extend(node_errors);
+                    }
+                }
+                PlanNode::Subscription(_) => {
+                    return Err(
+                        "Subscriptions must be requested with Accept: text/event-stream"
+                            .to_string(),
+                    );
+                }
+            }
 
-                let request = request_builder.send();
+            Ok((data, errors))
+        }
+        .boxed()
+    }
+
+    async fn run_plan(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        let client = &self.client;
+        let budget = self.memory.budget();
+
+        let (mut data, mut errors) = self
+            .execute_node(
+                client,
+                &query_plan.node,
+                schema,
+                &auth_headers,
+                &budget,
+                context,
+                json!({}),
+            )
+            .await?;
+
+        // Without incremental delivery, deferred fragments are resolved right
+        // away and returned with the rest of the response
+        if !query_plan.deferred.is_empty() {
+            let deferred = PlanNode::Parallel(
+                query_plan
+                    .deferred
+                    .into_iter()
+                    .map(|deferred| deferred.node)
+                    .collect(),
+            );
+            let (deferred_data, deferred_errors) = self
+                .execute_node(
+                    client,
+                    &deferred,
+                    schema,
+                    &auth_headers,
+                    &budget,
+                    context,
+                    data,
+                )
+                .await?;
+            data = deferred_data;
+            errors.extend(deferred_errors);
+        }
 
+        Ok(build_response(
+            data,
+            errors,
+            &query_plan.response_shape,
+            &query_plan.root_type,
+            schema,
+            self.quarantine.as_deref(),
+        ))
+    }
+
+    // Runs the plan's initial node and answers with its response, the
+    // deferred fragments left out. The fragments are then fetched
+    // concurrently on top of the initial data, each one's results delivered
+    // by the returned stream as soon as they're in.
+    async fn run_incremental(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        if query_plan.deferred.is_empty() {
+            let response = self
+                .run_plan(query_plan, schema, auth_headers, context)
+                .await?;
+            return Ok((response, None));
+        }
+
+        // The deferred fetches belong to the same request, they charge its
+        // budget until the last one is done
+        let budget = Arc::new(self.memory.budget());
+        let (data, errors) = self
+            .execute_node(
+                &self.client,
+                &query_plan.node,
+                schema,
+                &auth_headers,
+                &budget,
+                context,
+                json!({}),
+            )
+            .await?;
+
+        let mut initial_shape = query_plan.response_shape.clone();
+        for deferred in &query_plan.deferred {
+            remove_deferred_fields(&mut initial_shape, &deferred.path, &deferred.response_shape);
+        }
+        let mut initial = build_response(
+            data.clone(),
+            errors,
+            &initial_shape,
+            &query_plan.root_type,
+            schema,
+            self.quarantine.as_deref(),
+        );
+        initial["hasNext"] = json!(true);
+
+        let total = query_plan.deferred.len();
+        let data = Arc::new(data);
+        let schema = Arc::new(schema.clone());
+        let auth_headers = Arc::new(auth_headers);
+        let bypass_cache = context.bypass_cache;
+        let root_type = query_plan.root_type;
+        let fragments: FuturesUnordered<_> = query_plan
+            .deferred
+            .into_iter()
+            .map(|deferred| {
+                let executor = self.clone();
+                let (data, schema, auth_headers, budget, root_type) = (
+                    Arc::clone(&data),
+                    Arc::clone(&schema),
+                    Arc::clone(&auth_headers),
+                    Arc::clone(&budget),
+                    root_type.clone(),
+                );
                 async move {
-                    let response = request
+                    executor
+                        .resolve_deferred(
+                            deferred,
+                            &schema,
+                            &auth_headers,
+                            &budget,
+                            &RequestContext::new(bypass_cache),
+                            &root_type,
+                            data.as_ref().clone(),
+                        )
                         .await
-                        .map_err(|e| format!("HTTP request failed: {}", e))?;
-
-                    if !response.status().is_success() {
-                        let status = response.status();
-                        let error_text = response
-                            .text()
-                            .await
-                            .unwrap_or_else(|_| "Could not read error response".to_string());
-                        return Err(format!("Service returned error {}: {}", status, error_text));
+                }
+            })
+            .collect();
+
+        let payloads = fragments
+            .enumerate()
+            .map(move |(i, incremental)| {
+                json!({ "incremental": incremental, "hasNext": i + 1 < total })
+            })
+            .boxed();
+        Ok((initial, Some(payloads)))
+    }
+
+    // The incremental results of a deferred fragment: its fields for every
+    // object at its path, the errors of its fetches going along with the
+    // first
+    #[allow(clippy::too_many_arguments)]
+    async fn resolve_deferred(
+        &self,
+        deferred: DeferredPlan,
+        schema: &FederatedSchema,
+        auth_headers: &Option<HashMap<String, String>>,
+        budget: &MemoryBudget,
+        context: &RequestContext,
+        root_type: &str,
+        data: Value,
+    ) -> Vec<Value> {
+        let with_label = |mut result: Value| {
+            if let Some(label) = &deferred.label {
+                result["label"] = json!(label);
+            }
+            result
+        };
+        let (data, errors) = match self
+            .execute_node(
+                &self.client,
+                &deferred.node,
+                schema,
+                auth_headers,
+                budget,
+                context,
+                data,
+            )
+            .await
+        {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                return vec![with_label(
+                    json!({ "path": deferred.path, "errors": [{ "message": e }] }),
+                )];
+            }
+        };
+
+        let mut paths = Vec::new();
+        collect_entity_paths(&data, &deferred.path, None, &mut Vec::new(), &mut paths);
+        let mut results: Vec<Value> = paths
+            .into_iter()
+            .filter_map(|path| {
+                let object = path
+                    .iter()
+                    .try_fold(&data, |value, segment| match segment {
+                        Value::Number(index) => value.get(index.as_u64()? as usize),
+                        Value::String(key) => value.get(key),
+                        _ => None,
+                    })?;
+                let shaped = build_response(
+                    object.clone(),
+                    Vec::new(),
+                    &deferred.response_shape,
+                    root_type,
+                    schema,
+                    self.quarantine.as_deref(),
+                );
+                let mut result = json!({ "data": shaped["data"], "path": path });
+                if let Some(errors) = shaped.get("errors") {
+                    result["errors"] = errors.clone();
+                }
+                Some(with_label(result))
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            match results.first_mut() {
+                Some(first) => {
+                    let mut all = first["errors"].as_array().cloned().unwrap_or_default();
+                    all.extend(errors);
+                    first["errors"] = Value::Array(all);
+                }
+                None => results.push(with_label(
+                    json!({ "path": deferred.path, "errors": errors }),
+                )),
+            }
+        }
+        results
+    }
+
+    // Sends the subscription to the service owning it, asking for a stream of
+    // events as in GraphQL over server-sent events
+    async fn open_event_stream(
+        &self,
+        client: &reqwest::Client,
+        service: &ServiceConfig,
+        schema: &FederatedSchema,
+        fetch: &FetchNode,
+        auth_headers: &Option<HashMap<String, String>>,
+    ) -> Result<reqwest::Response, String> {
+        let request = self.subgraph_request(
+            service,
+            schema,
+            &fetch.query,
+            &fetch.variables,
+            auth_headers,
+        )?;
+        debug!("Subscribing to service: {}", service.name);
+        self.logger
+            .log(&service.name, &request.query, &request.variables);
+
+        // Subscriptions stay on the preferred endpoint, a long-lived stream
+        // isn't failed over
+        let body = json!({ "query": request.query, "variables": request.variables });
+        let mut request_builder = Self::json_request(
+            client
+                .post(&request.url)
+                .header(reqwest::header::ACCEPT, "text/event-stream"),
+            &Self::encode_body(&body, &service.compression)?,
+        );
+
+        if !service.compression.enabled {
+            request_builder = request_builder.header(reqwest::header::ACCEPT_ENCODING, "identity");
+        }
+
+        for (name, value) in &request.headers {
+            request_builder = request_builder.header(name, value);
+        }
+
+        let response = request_builder
+            .send()
+            .await
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = Self::read_body(response, service, &MemoryBudget::unlimited())
+                .await
+                .map(|body| String::from_utf8_lossy(&body).into_owned())
+                .unwrap_or_else(|_| "Could not read error response".to_string());
+            return Err(format!("Service returned error {}: {}", status, error_text));
+        }
+
+        Ok(response)
+    }
+
+    // Subscribes over a graphql-transport-ws connection of its own, the
+    // client's credentials sent as the connection's
+    async fn open_websocket(
+        &self,
+        service: &ServiceConfig,
+        schema: &FederatedSchema,
+        fetch: &FetchNode,
+        auth_headers: &Option<HashMap<String, String>>,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        let request = self.subgraph_request(
+            service,
+            schema,
+            &fetch.query,
+            &fetch.variables,
+            auth_headers,
+        )?;
+        debug!("Subscribing to service over WebSocket: {}", service.name);
+        self.logger
+            .log(&service.name, &request.query, &request.variables);
+
+        self.subscription_executor
+            .subscribe(&request, service.compression.max_decompressed_bytes)
+            .await
+    }
+
+    // Resolves one entity per representation, in order. Representations found
+    // in the entity cache are answered from it and only the rest are sent to
+    // the service; fetches that reported errors are not cached. Bypassing the
+    // cache sends every representation but still caches the results.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_entities(
+        &self,
+        client: &reqwest::Client,
+        fetch: &FetchNode,
+        schema: &FederatedSchema,
+        auth_headers: &Option<HashMap<String, String>>,
+        budget: &MemoryBudget,
+        context: &RequestContext,
+        representations: Vec<Value>,
+    ) -> Result<(Vec<Value>, Vec<Value>), String> {
+        let cache = self.entity_cache.as_deref();
+        let keys: Vec<_> = match cache {
+            Some(_) => representations
+                .iter()
+                .map(|representation| EntityCache::key(fetch, representation, auth_headers))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut entities: Vec<Option<Value>> = match cache {
+            Some(cache) if !context.bypass_cache => keys
+                .iter()
+                .map(|key| cache.get(key, schema.generation))
+                .collect(),
+            _ => vec![None; representations.len()],
+        };
+
+        let misses: Vec<usize> = (0..entities.len())
+            .filter(|&i| entities[i].is_none())
+            .collect();
+        if misses.is_empty() {
+            return Ok((entities.into_iter().flatten().collect(), Vec::new()));
+        }
+
+        let missing = misses.iter().map(|&i| representations[i].clone()).collect();
+        let result = self
+            .execute_fetch(
+                client,
+                fetch,
+                schema,
+                auth_headers,
+                budget,
+                context,
+                &Value::Array(missing),
+            )
+            .await?;
+        let mut errors = Vec::new();
+        collect_errors(&result, &fetch.service_name, &mut errors);
+        // Only the misses were sent, errors are made relative to every
+        // representation
+        let errors = rebase_entity_errors(errors, |k| {
+            misses.get(k).map(|&i| vec![json!("_entities"), json!(i)])
+        });
+        let cacheable = result.get("errors").is_none();
+
+        let fetched = result
+            .get("data")
+            .and_then(|data| data.get("_entities"))
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        for (&i, entity) in misses.iter().zip(fetched) {
+            if let Some(cache) = cache.filter(|_| cacheable && entity.is_object()) {
+                cache.insert(keys[i].clone(), entity.clone(), schema.generation);
+            }
+            entities[i] = Some(entity);
+        }
+
+        let entities = entities
+            .into_iter()
+            .map(|entity| entity.unwrap_or(Value::Null))
+            .collect();
+        Ok((entities, errors))
+    }
+
+    // Runs a join's fetch once for every parent object, concurrently, and
+    // returns the data and the errors of each. Parents missing an argument
+    // resolve to null without a fetch.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_joined(
+        &self,
+        client: &reqwest::Client,
+        fetch: &FetchNode,
+        join: &JoinKey,
+        schema: &FederatedSchema,
+        auth_headers: &Option<HashMap<String, String>>,
+        budget: &MemoryBudget,
+        context: &RequestContext,
+        parents: Vec<Value>,
+    ) -> Result<(Vec<Value>, Vec<Vec<Value>>), String> {
+        let service = schema
+            .services
+            .get(&fetch.service_name)
+            .ok_or_else(|| format!("Service not found: {}", fetch.service_name))?;
+
+        let results = try_join_all(parents.iter().map(|parent| async move {
+            let mut variables = fetch.variables.clone();
+            if !variables.is_object() {
+                variables = json!({});
+            }
+            for (variable, field) in &join.arguments {
+                match parent.get(field) {
+                    Some(value) if !value.is_null() => variables[variable] = value.clone(),
+                    _ => return Ok(Value::Null),
+                }
+            }
+            self.send_query(
+                client,
+                service,
+                schema,
+                &fetch.query,
+                &variables,
+                auth_headers,
+                budget,
+                context,
+            )
+            .await
+        }))
+        .await?;
+
+        let mut errors = Vec::with_capacity(results.len());
+        let data = results
+            .into_iter()
+            .map(|result| {
+                let mut parent_errors = Vec::new();
+                collect_errors(&result, &service.name, &mut parent_errors);
+                errors.push(parent_errors);
+                result.get("data").cloned().unwrap_or(Value::Null)
+            })
+            .collect();
+        Ok((data, errors))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn execute_fetch(
+        &self,
+        client: &reqwest::Client,
+        fetch: &FetchNode,
+        schema: &FederatedSchema,
+        auth_headers: &Option<HashMap<String, String>>,
+        budget: &MemoryBudget,
+        context: &RequestContext,
+        representations: &Value,
+    ) -> Result<Value, String> {
+        let service = schema
+            .services
+            .get(&fetch.service_name)
+            .ok_or_else(|| format!("Service not found: {}", fetch.service_name))?;
+
+        if representations.is_null() {
+            return self
+                .send_query(
+                    client,
+                    service,
+                    schema,
+                    &fetch.query,
+                    &fetch.variables,
+                    auth_headers,
+                    budget,
+                    context,
+                )
+                .await;
+        }
+
+        let mut variables = fetch.variables.clone();
+        if !variables.is_object() {
+            variables = json!({});
+        }
+        variables["representations"] = representations.clone();
+
+        self.send_query(
+            client,
+            service,
+            schema,
+            &fetch.query,
+            &variables,
+            auth_headers,
+            budget,
+            context,
+        )
+        .await
+    }
+}
+
+impl Default for HttpQueryExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Walks `path` from `value`, flattening lists, and builds one representation
+// per entity object found at the end of it.
+fn collect_representations(
+    value: &Value,
+    path: &[String],
+    entity: &EntityKey,
+    representations: &mut Vec<Value>,
+) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect_representations(item, path, entity, representations);
+            }
+        }
+        Value::Object(obj) => match path.split_first() {
+            Some((key, rest)) => {
+                if let Some(child) = obj.get(key) {
+                    collect_representations(child, rest, entity, representations);
+                }
+            }
+            None if is_entity_of(obj, entity) => {
+                let mut representation =
+                    serde_json::Map::with_capacity(entity.key_fields.len() + 1);
+                representation.insert("__typename".to_string(), json!(entity.type_name));
+                for key_field in &entity.key_fields {
+                    representation.insert(key_field.name.clone(), key_field.extract(obj));
+                }
+                representations.push(Value::Object(representation));
+            }
+            None => {}
+        },
+        _ => {}
+    }
+}
+
+// Leaves the fields of a deferred fragment at `path` out of the shape of the
+// initial response. Each is removed once, so a field also selected outside
+// the fragment stays.
+fn remove_deferred_fields(
+    shape: &mut Vec<ResponseField>,
+    path: &[String],
+    fields: &[ResponseField],
+) {
+    match path.split_first() {
+        Some((key, rest)) => {
+            for field in shape.iter_mut().filter(|field| &field.response_key == key) {
+                remove_deferred_fields(&mut field.selections, rest, fields);
+            }
+        }
+        None => {
+            for deferred in fields {
+                if let Some(i) = shape.iter().rposition(|field| {
+                    field.response_key == deferred.response_key
+                        && field.field_name == deferred.field_name
+                }) {
+                    shape.remove(i);
+                }
+            }
+        }
+    }
+}
+
+// Mirrors `collect_representations`, collecting the path in the response of
+// every entity it finds, list indexes included. Without `entity`, the path of
+// every object at `path` is collected.
+fn collect_entity_paths(
+    value: &Value,
+    path: &[String],
+    entity: Option<&EntityKey>,
+    current: &mut Vec<Value>,
+    paths: &mut Vec<Vec<Value>>,
+) {
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                current.push(json!(i));
+                collect_entity_paths(item, path, entity, current, paths);
+                current.pop();
+            }
+        }
+        Value::Object(obj) => match path.split_first() {
+            Some((key, rest)) => {
+                if let Some(child) = obj.get(key) {
+                    current.push(json!(key));
+                    collect_entity_paths(child, rest, entity, current, paths);
+                    current.pop();
+                }
+            }
+            None if entity.is_none_or(|entity| is_entity_of(obj, entity)) => {
+                paths.push(current.clone())
+            }
+            None => {}
+        },
+        _ => {}
+    }
+}
+
+// Every distinct representation is sent once, e.g. the product shared by many
+// reviews. Returns the distinct representations and, for each collected one,
+// the position of its distinct copy, to hand its entity to every object.
+fn dedup_representations(representations: Vec<Value>) -> (Vec<Value>, Vec<usize>) {
+    let mut distinct = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let positions = representations
+        .into_iter()
+        .map(|representation| {
+            *seen.entry(representation.to_string()).or_insert_with(|| {
+                distinct.push(representation);
+                distinct.len() - 1
+            })
+        })
+        .collect();
+    (distinct, positions)
+}
+
+// Mirrors `collect_representations`, merging the `_entities` results back into
+// the entity objects in the order their representations were sent.
+fn merge_entities(
+    value: &mut Value,
+    path: &[String],
+    entity: &EntityKey,
+    entities: &mut impl Iterator<Item = Value>,
+) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                merge_entities(item, path, entity, entities);
+            }
+        }
+        Value::Object(obj) => match path.split_first() {
+            Some((key, rest)) => {
+                if let Some(child) = obj.get_mut(key) {
+                    merge_entities(child, rest, entity, entities);
+                }
+            }
+            None if is_entity_of(obj, entity) => {
+                if let Some(Value::Object(result)) = entities.next() {
+                    obj.extend(result);
+                }
+            }
+            None => {}
+        },
+        _ => {}
+    }
+}
+
+// Objects of other concrete types can share the path of an abstract field
+fn is_entity_of(obj: &serde_json::Map<String, Value>, entity: &EntityKey) -> bool {
+    obj.get("__typename")
+        .and_then(Value::as_str)
+        .is_none_or(|type_name| type_name == entity.type_name)
+}
+
+// Deep-merges `source` into `target`. Objects are merged key by key and lists
+// of the same length item by item, so results produced by parallel branches
+// of a plan can be combined.
+fn merge_values(target: &mut Value, source: Value) {
+    match (target, source) {
+        (Value::Object(target), Value::Object(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        target.insert(key, value);
                     }
+                }
+            }
+        }
+        (Value::Array(target), Value::Array(source)) if target.len() == source.len() => {
+            for (existing, value) in target.iter_mut().zip(source) {
+                merge_values(existing, value);
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
 
-                    let response_json = response
-                        .json::<Value>()
-                        .await
-                        .map_err(|e| format!("Failed to parse response: {}", e))?;
+// Checks the values of the fields being shaped against their declared types,
+// answering invalid and quarantined ones with null and an error
+struct ResponseCheck<'q> {
+    quarantine: &'q FieldQuarantine,
+    path: Vec<Value>,
+    errors: Vec<Value>,
+    // "Type.field" -> service, of the fields with invalid values
+    invalid: BTreeMap<String, String>,
+}
 
-                    if let Some(errors) = response_json.get("errors") {
-                        println!(
-                            "Service {} returned GraphQL errors: {}",
-                            service_name, errors
-                        );
+impl ResponseCheck<'_> {
+    fn field_value(
+        &mut self,
+        value: Value,
+        type_name: &str,
+        field: &ResponseField,
+        schema: &FederatedSchema,
+    ) -> Value {
+        let field_key = format!("{}.{}", type_name, field.field_name);
+        let service = schema
+            .type_to_service_map
+            .get(field_key.as_str())
+            .and_then(|services| services.first())
+            .map(Name::as_str)
+            .unwrap_or("unknown");
+
+        let message = if self.quarantine.is_quarantined(&field_key) {
+            format!(
+                "Field {} is quarantined after invalid values from service {}",
+                field_key, service
+            )
+        } else {
+            let type_ref = schema
+                .introspection
+                .get_type(type_name)
+                .and_then(|ty| ty["fields"].as_array())
+                .and_then(|fields| {
+                    fields
+                        .iter()
+                        .find(|f| f["name"] == field.field_name.as_str())
+                })
+                .map(|f| &f["type"]);
+            if type_ref.is_none_or(|type_ref| quarantine::is_valid(&value, type_ref)) {
+                return value;
+            }
+            self.invalid.insert(field_key.clone(), service.to_string());
+            format!(
+                "Service {} returned an invalid value for {}",
+                service, field_key
+            )
+        };
+
+        let mut error = service_error(&message, service);
+        error["path"] = json!(self.path);
+        self.errors.push(error);
+        Value::Null
+    }
+}
+
+// Rebuilds `value` with exactly the fields the client selected: fields the
+// gateway added for entity resolution are dropped, type conditions are applied
+// using `__typename`, and `__typename` itself is answered from the parent type
+// when the subgraphs didn't return it.
+fn shape_response(
+    value: &Value,
+    fields: &[ResponseField],
+    parent_type: &str,
+    schema: &FederatedSchema,
+    check: &mut Option<ResponseCheck>,
+) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    if let Some(check) = check {
+                        check.path.push(json!(i));
+                    }
+                    let shaped = shape_response(item, fields, parent_type, schema, check);
+                    if let Some(check) = check {
+                        check.path.pop();
                     }
+                    shaped
+                })
+                .collect(),
+        ),
+        Value::Object(obj) => {
+            let type_name = obj
+                .get("__typename")
+                .and_then(Value::as_str)
+                .unwrap_or(parent_type);
+            let mut shaped = Value::Object(serde_json::Map::new());
 
-                    Ok((service_name, response_json))
+            for field in fields {
+                if !field
+                    .type_conditions
+                    .iter()
+                    .all(|condition| is_possible_type(type_name, condition, schema))
+                {
+                    continue;
                 }
-                .right_future()
-            });
 
-        let results = try_join_all(futures).await?;
+                let key = field.source_key.as_ref().unwrap_or(&field.response_key);
+                let value = match obj.get(key) {
+                    Some(value) => value.clone(),
+                    None if field.field_name == "__typename" => json!(type_name),
+                    None => Value::Null,
+                };
+                if let Some(check) = check {
+                    check.path.push(json!(field.response_key));
+                }
+                let value = match check {
+                    Some(check) if !field.field_name.starts_with("__") => {
+                        check.field_value(value, type_name, field, schema)
+                    }
+                    _ => value,
+                };
+                let value = if field.selections.is_empty() {
+                    value
+                } else {
+                    let field_type = schema
+                        .field_types
+                        .get(&format!("{}.{}", type_name, field.field_name))
+                        .map(String::as_str)
+                        .unwrap_or_default();
+                    shape_response(&value, &field.selections, field_type, schema, check)
+                };
+                if let Some(check) = check {
+                    check.path.pop();
+                }
+
+                merge_values(&mut shaped, json!({ field.response_key.clone(): value }));
+            }
 
-        let mut data_map = serde_json::Map::new();
-        let mut all_errors = Vec::new();
+            shaped
+        }
+        other => other.clone(),
+    }
+}
 
-        for (_service_name, result) in results {
-            if let Some(data) = result.get("data").and_then(Value::as_object) {
-                data_map.extend(data.clone());
+fn is_possible_type(type_name: &str, condition: &str, schema: &FederatedSchema) -> bool {
+    type_name == condition
+        || schema
+            .possible_types
+            .get(condition)
+            .is_some_and(|types| types.iter().any(|t| t == type_name))
+        // Without a concrete `__typename` the object can't be told apart
+        || schema.possible_types.contains_key(type_name)
+}
+
+// The merged data shaped to the client's selection, with the errors the
+// services reported
+fn build_response(
+    mut data: Value,
+    mut errors: Vec<Value>,
+    response_shape: &[ResponseField],
+    root_type: &str,
+    schema: &FederatedSchema,
+    quarantine: Option<&FieldQuarantine>,
+) -> Value {
+    if !response_shape.is_empty() {
+        let mut check = quarantine.map(|quarantine| ResponseCheck {
+            quarantine,
+            path: Vec::new(),
+            errors: Vec::new(),
+            invalid: BTreeMap::new(),
+        });
+        data = shape_response(&data, response_shape, root_type, schema, &mut check);
+        if let Some(check) = check {
+            // A response counts once for every field it had invalid values of
+            for (field_key, service) in &check.invalid {
+                check.quarantine.record_invalid(field_key, service);
+            }
+            errors.extend(check.errors);
+        }
+    }
+
+    let mut response = json!({"data": data});
+
+    if !errors.is_empty() {
+        response["errors"] = Value::Array(errors);
+    }
+
+    response
+}
+
+// The state of an open subscription: the service's event stream and what's
+// needed to resolve the rest of the selection for every event
+struct SubscriptionEvents {
+    executor: HttpQueryExecutor,
+    schema: FederatedSchema,
+    service: ServiceConfig,
+    auth_headers: Option<HashMap<String, String>>,
+    rest: Option<Box<PlanNode>>,
+    response_shape: Vec<ResponseField>,
+    source: EventSource,
+}
+
+// How the service delivers the events
+enum EventSource {
+    ServerSentEvents(ServerSentEvents),
+    // Payloads of graphql-transport-ws `next` messages
+    WebSocket(BoxStream<'static, Value>),
+    // Values of the root field, published to a topic, under the key the
+    // field's data is read from
+    Topic(String, BoxStream<'static, Result<Value, String>>),
+}
+
+struct ServerSentEvents {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl ServerSentEvents {
+    // Reads the next `next` event, skipping comments and keep-alives. Returns
+    // `None` once the service sent `complete` or closed the stream.
+    async fn next_event(&mut self, service: &ServiceConfig) -> Option<Value> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let block = String::from_utf8_lossy(&block);
+
+                let mut event = None;
+                let mut data = String::new();
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("event:") {
+                        event = Some(value.trim().to_string());
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        if !data.is_empty() {
+                            data.push('\n');
+                        }
+                        data.push_str(value.strip_prefix(' ').unwrap_or(value));
+                    }
+                }
+
+                match event.as_deref() {
+                    Some("complete") => return None,
+                    None | Some("next") if !data.is_empty() => {
+                        return Some(serde_json::from_str(&data).unwrap_or_else(|e| {
+                            json!({ "errors": [{
+                                "message": format!("Failed to parse response: {}", e)
+                            }] })
+                        }));
+                    }
+                    _ => continue,
+                }
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => {
+                    self.buffer
+                        .extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+                    let limit = service.compression.max_decompressed_bytes;
+                    if self.buffer.len() > limit {
+                        self.done = true;
+                        self.buffer.clear();
+                        return Some(json!({ "errors": [{
+                            "message": format!(
+                                "Response from service {} exceeds the limit of {} bytes",
+                                service.name, limit
+                            )
+                        }] }));
+                    }
+                }
+                Ok(None) | Err(_) => return None,
             }
+        }
+    }
+}
+
+impl SubscriptionEvents {
+    async fn next_event(&mut self) -> Option<Value> {
+        match &mut self.source {
+            EventSource::ServerSentEvents(events) => events.next_event(&self.service).await,
+            EventSource::WebSocket(events) => events.next().await,
+            EventSource::Topic(key, messages) => Some(match messages.next().await? {
+                Ok(value) => json!({ "data": { key.as_str(): value } }),
+                Err(e) => json!({ "errors": [{ "message": e }] }),
+            }),
+        }
+    }
+
+    // Borrows mutably so the future is `Send` without the event source being
+    // `Sync`
+    async fn resolve(&mut self, event: Value) -> Value {
+        let mut errors = Vec::new();
+        collect_errors(&event, &self.service.name, &mut errors);
 
-            if let Some(errors) = result.get("errors").and_then(Value::as_array) {
-                for error in errors {
-                    all_errors.push(error.clone());
+        let Some(mut data) = event.get("data").filter(|data| data.is_object()).cloned() else {
+            return json!({ "data": null, "errors": errors });
+        };
+
+        // Every event is accounted as a request of its own
+        if let Some(rest) = &self.rest {
+            let budget = self.executor.memory.budget();
+            match self
+                .executor
+                .execute_node(
+                    &self.executor.client,
+                    rest,
+                    &self.schema,
+                    &self.auth_headers,
+                    &budget,
+                    &RequestContext::default(),
+                    data.clone(),
+                )
+                .await
+            {
+                Ok((rest_data, rest_errors)) => {
+                    data = rest_data;
+                    errors.extend(rest_errors);
                 }
+                Err(e) => errors.push(json!({ "message": e })),
             }
         }
 
-        let mut response = json!({"data": data_map});
+        build_response(
+            data,
+            errors,
+            &self.response_shape,
+            "Subscription",
+            &self.schema,
+            self.executor.quarantine.as_deref(),
+        )
+    }
+}
 
-        if !all_errors.is_empty() {
-            response["errors"] = Value::Array(all_errors);
+// The APQ error code a service answered with, if any. Older servers only
+// report the error message.
+fn persisted_query_error(response: &Value) -> Option<&'static str> {
+    response["errors"].as_array()?.iter().find_map(|error| {
+        let code = error["extensions"]["code"].as_str();
+        let message = error["message"].as_str();
+        if code == Some("PERSISTED_QUERY_NOT_FOUND") || message == Some("PersistedQueryNotFound") {
+            Some("PERSISTED_QUERY_NOT_FOUND")
+        } else if code == Some("PERSISTED_QUERY_NOT_SUPPORTED")
+            || message == Some("PersistedQueryNotSupported")
+        {
+            Some("PERSISTED_QUERY_NOT_SUPPORTED")
+        } else {
+            None
         }
+    })
+}
 
-        Ok(response)
+// Fetches of mutations, as the planner writes them
+fn is_mutation(query: &str) -> bool {
+    query.trim_start().starts_with("mutation")
+}
+
+// The same for fetches sending the service byte-identical requests
+fn fetch_key(request: &SubgraphRequest) -> String {
+    let headers: BTreeMap<&String, &String> = request.headers.iter().collect();
+    let mut hasher = Sha256::new();
+    for part in [
+        request.service_name.as_str(),
+        request.url.as_str(),
+        request.query.as_str(),
+        &request.variables.to_string(),
+        &json!(headers).to_string(),
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// An error about what a service answered, with the service's name
+fn service_error(message: &str, service_name: &str) -> Value {
+    json!({
+        "message": message,
+        "extensions": {
+            "code": messages::code(message),
+            "serviceName": service_name,
+        },
+    })
+}
+
+// Code of the service errors that don't carry one
+const DOWNSTREAM_ERROR_CODE: &str = "DOWNSTREAM_SERVICE_ERROR";
+
+// The errors a service reported, telling the client which service reported
+// them. Errors without a code get a generic one.
+fn collect_errors(result: &Value, service_name: &str, errors: &mut Vec<Value>) {
+    let Some(result_errors) = result.get("errors").and_then(Value::as_array) else {
+        return;
+    };
+    for error in result_errors {
+        let mut error = error.clone();
+        if !error.is_object() {
+            error = json!({ "message": error });
+        }
+        if !error["extensions"].is_object() {
+            error["extensions"] = json!({});
+        }
+        let extensions = &mut error["extensions"];
+        if extensions.get("code").is_none() {
+            extensions["code"] = json!(DOWNSTREAM_ERROR_CODE);
+        }
+        extensions["serviceName"] = json!(service_name);
+        errors.push(error);
+    }
+}
+
+// Prepends `prefix` to the paths of the errors reporting one
+fn prefix_error_paths(errors: &mut [Value], prefix: &[Value]) {
+    for error in errors {
+        if let Some(path) = error["path"].as_array() {
+            let mut prefixed = prefix.to_vec();
+            prefixed.extend(path.iter().cloned());
+            error["path"] = Value::Array(prefixed);
+        }
+    }
+}
+
+// Replaces the `_entities` and index an entity fetch's error paths start with
+// by the path `locate` gives for the index. Errors it can't locate keep their
+// path.
+fn rebase_entity_errors(
+    mut errors: Vec<Value>,
+    locate: impl Fn(usize) -> Option<Vec<Value>>,
+) -> Vec<Value> {
+    for error in &mut errors {
+        let Some(path) = error["path"].as_array() else {
+            continue;
+        };
+        let (Some("_entities"), Some(index)) = (
+            path.first().and_then(Value::as_str),
+            path.get(1).and_then(Value::as_u64),
+        ) else {
+            continue;
+        };
+        if let Some(mut rebased) = locate(index as usize) {
+            rebased.extend(path[2..].iter().cloned());
+            error["path"] = Value::Array(rebased);
+        }
+    }
+    errors
+}
+
+#[async_trait]
+impl QueryExecutor for HttpQueryExecutor {
+    async fn execute_plan(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<Value, String> {
+        self.run_plan(query_plan, schema, auth_headers, &RequestContext::default())
+            .await
+    }
+
+    async fn execute_plan_uncached(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<Value, String> {
+        self.run_plan(query_plan, schema, auth_headers, &RequestContext::new(true))
+            .await
+    }
+
+    async fn execute_in_context(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        self.run_plan(query_plan, schema, auth_headers, context)
+            .await
+    }
+
+    async fn execute_incremental(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        self.run_incremental(query_plan, schema, auth_headers, context)
+            .await
+    }
+
+    fn invalidate_entities(&self, invalidation: &CacheInvalidation) -> usize {
+        let Some(cache) = &self.entity_cache else {
+            return 0;
+        };
+
+        let by_type: usize = invalidation
+            .types
+            .iter()
+            .map(|type_name| cache.invalidate_type(type_name))
+            .sum();
+        let by_key: usize = invalidation
+            .keys
+            .iter()
+            .map(|entity| cache.invalidate(&entity.type_name, &entity.key))
+            .sum();
+        by_type + by_key
+    }
+
+    fn metrics(&self) -> String {
+        let mut metrics = self.memory.render_metrics();
+        if let Some(quarantine) = &self.quarantine {
+            metrics.push_str(&quarantine.render_metrics());
+        }
+        if let Some(service_health) = &self.service_health {
+            metrics.push_str(&service_health.render_metrics());
+        }
+        if let Some(inflight) = &self.inflight {
+            metrics.push_str(&inflight.render_metrics());
+        }
+        metrics
+    }
+
+    async fn subscribe(
+        &self,
+        query_plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        let PlanNode::Subscription(subscription) = query_plan.node else {
+            return Err("Only subscriptions can be streamed".to_string());
+        };
+        let service = schema
+            .services
+            .get(&subscription.primary.service_name)
+            .ok_or_else(|| format!("Service not found: {}", subscription.primary.service_name))?
+            .clone();
+
+        let topic = query_plan.response_shape.first().and_then(|field| {
+            let topic = service
+                .topics
+                .get(&format!("Subscription.{}", field.field_name))?;
+            let key = field.source_key.as_ref().unwrap_or(&field.response_key);
+            Some((key.clone(), topic))
+        });
+        let source = if let Some((key, topic)) = topic {
+            EventSource::Topic(
+                key,
+                broker::subscribe(topic, service.compression.max_decompressed_bytes).await?,
+            )
+        } else if service.capabilities.websocket_subscriptions {
+            EventSource::WebSocket(
+                self.open_websocket(&service, schema, &subscription.primary, &auth_headers)
+                    .await?,
+            )
+        } else {
+            let response = self
+                .open_event_stream(
+                    &self.event_stream_client,
+                    &service,
+                    schema,
+                    &subscription.primary,
+                    &auth_headers,
+                )
+                .await?;
+            EventSource::ServerSentEvents(ServerSentEvents {
+                response,
+                buffer: Vec::new(),
+                done: false,
+            })
+        };
+
+        let events = SubscriptionEvents {
+            executor: self.clone(),
+            schema: schema.clone(),
+            service,
+            auth_headers,
+            rest: subscription.rest,
+            response_shape: query_plan.response_shape,
+            source,
+        };
+
+        Ok(stream::unfold(events, |mut events| async move {
+            let event = events.next_event().await?;
+            let response = events.resolve(event).await;
+            Some((response, events))
+        })
+        .boxed())
     }
 }
@@ -0,0 +1,232 @@
+use graphql_parser::query::{
+    self, Definition, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::literal;
+
+// Structural limits checked ahead of validation and planning, against
+// operations built to be expensive rather than useful, like a field selected
+// under thousands of aliases. Fragments count as many times as they're
+// spread. Unset limits aren't checked.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryLimitsConfig {
+    // Lexical tokens of the whole document, counted before it's parsed
+    pub max_tokens: Option<u64>,
+    pub max_aliases: Option<u64>,
+    pub max_root_fields: Option<u64>,
+    pub max_directives: Option<u64>,
+}
+
+// What a selection set holds, its fragments expanded. `fields` only counts
+// the fields at the top of the set.
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    fields: u64,
+    aliases: u64,
+    directives: u64,
+}
+
+impl Counts {
+    fn add_nested(&mut self, nested: Counts) {
+        self.aliases = self.aliases.saturating_add(nested.aliases);
+        self.directives = self.directives.saturating_add(nested.directives);
+    }
+}
+
+struct Counter<'q> {
+    fragments: HashMap<&'q str, &'q FragmentDefinition<'q, String>>,
+    counted: HashMap<&'q str, Counts>,
+    visiting: HashSet<&'q str>,
+}
+
+impl QueryLimitsConfig {
+    pub fn check(&self, query: &str, operation_name: Option<&str>) -> Result<(), String> {
+        if let Some(max) = self.max_tokens {
+            let tokens = count_tokens(query, max);
+            if tokens > max {
+                return Err(format!(
+                    "Operation has more than {} tokens, exceeding the limit",
+                    max
+                ));
+            }
+        }
+        if self.max_aliases.is_none()
+            && self.max_root_fields.is_none()
+            && self.max_directives.is_none()
+        {
+            return Ok(());
+        }
+
+        // Operations that don't parse are left to validation to report
+        let Ok(doc) = query::parse_query::<String>(query) else {
+            return Ok(());
+        };
+        let operation = doc.definitions.iter().find_map(|def| match def {
+            Definition::Operation(OperationDefinition::SelectionSet(s))
+                if operation_name.is_none() =>
+            {
+                Some((s, 0))
+            }
+            Definition::Operation(OperationDefinition::Query(q))
+                if operation_name.is_none() || q.name.as_deref() == operation_name =>
+            {
+                Some((&q.selection_set, q.directives.len()))
+            }
+            Definition::Operation(OperationDefinition::Mutation(m))
+                if operation_name.is_none() || m.name.as_deref() == operation_name =>
+            {
+                Some((&m.selection_set, m.directives.len()))
+            }
+            Definition::Operation(OperationDefinition::Subscription(s))
+                if operation_name.is_none() || s.name.as_deref() == operation_name =>
+            {
+                Some((&s.selection_set, s.directives.len()))
+            }
+            _ => None,
+        });
+        let Some((selection_set, operation_directives)) = operation else {
+            return Ok(());
+        };
+
+        let mut counter = Counter {
+            fragments: doc
+                .definitions
+                .iter()
+                .filter_map(|def| match def {
+                    Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                    _ => None,
+                })
+                .collect(),
+            counted: HashMap::new(),
+            visiting: HashSet::new(),
+        };
+        let mut counts = counter.selection_set(selection_set);
+        counts.directives = counts
+            .directives
+            .saturating_add(operation_directives as u64);
+
+        for (count, max, what) in [
+            (counts.aliases, self.max_aliases, "aliases"),
+            (counts.fields, self.max_root_fields, "root fields"),
+            (counts.directives, self.max_directives, "directives"),
+        ] {
+            if let Some(max) = max
+                && count > max
+            {
+                return Err(format!(
+                    "Operation has {} {}, exceeding the limit of {}",
+                    count, what, max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'q> Counter<'q> {
+    fn selection_set(&mut self, selection_set: &'q SelectionSet<'q, String>) -> Counts {
+        let mut counts = Counts::default();
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    counts.fields = counts.fields.saturating_add(1);
+                    counts.aliases = counts
+                        .aliases
+                        .saturating_add(u64::from(field.alias.is_some()));
+                    counts.directives = counts
+                        .directives
+                        .saturating_add(field.directives.len() as u64);
+                    let nested = self.selection_set(&field.selection_set);
+                    counts.add_nested(nested);
+                }
+                Selection::InlineFragment(fragment) => {
+                    counts.directives = counts
+                        .directives
+                        .saturating_add(fragment.directives.len() as u64);
+                    let nested = self.selection_set(&fragment.selection_set);
+                    counts.fields = counts.fields.saturating_add(nested.fields);
+                    counts.add_nested(nested);
+                }
+                Selection::FragmentSpread(spread) => {
+                    counts.directives = counts
+                        .directives
+                        .saturating_add(spread.directives.len() as u64);
+                    let nested = self.fragment(&spread.fragment_name);
+                    counts.fields = counts.fields.saturating_add(nested.fields);
+                    counts.add_nested(nested);
+                }
+            }
+        }
+        counts
+    }
+
+    // A fragment is counted once and its counts added for every spread, so
+    // fragments spreading each other many times over are cheap to count.
+    // Cycles are left to validation.
+    fn fragment(&mut self, name: &'q str) -> Counts {
+        if let Some(counts) = self.counted.get(name) {
+            return *counts;
+        }
+        let Some(fragment) = self.fragments.get(name).copied() else {
+            return Counts::default();
+        };
+        if !self.visiting.insert(name) {
+            return Counts::default();
+        }
+
+        let mut counts = self.selection_set(&fragment.selection_set);
+        counts.directives = counts
+            .directives
+            .saturating_add(fragment.directives.len() as u64);
+        self.visiting.remove(name);
+        self.counted.insert(name, counts);
+        counts
+    }
+}
+
+// Lexical tokens of `query`, punctuators, names, numbers and strings, ignoring
+// whitespace, commas and comments. Counting stops past `max`.
+fn count_tokens(query: &str, max: u64) -> u64 {
+    let bytes = query.as_bytes();
+    let mut tokens = 0;
+    let mut i = 0;
+    while i < bytes.len() && tokens <= max {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' | b',' => {
+                i += 1;
+                continue;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'"' => i = literal::string_end(query, i),
+            b'.' if bytes[i..].starts_with(b"...") => i += 3,
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+            }
+            // Numbers take their fraction and exponent along
+            c if c.is_ascii_digit() || c == b'-' => {
+                i += 1;
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_digit()
+                        || matches!(bytes[i], b'.' | b'e' | b'E')
+                        || (matches!(bytes[i], b'+' | b'-') && matches!(bytes[i - 1], b'e' | b'E')))
+                {
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+        tokens += 1;
+    }
+    tokens
+}
@@ -1,170 +1,2147 @@
 use async_trait::async_trait;
 use graphql_parser::query::{
-    self, Definition, OperationDefinition, SelectionSet, VariableDefinition,
+    self, Definition, FragmentDefinition, OperationDefinition, SelectionSet, TypeCondition,
+    VariableDefinition,
 };
 use serde_json::{Value, json};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Write;
+use std::sync::Arc;
+use tracing::debug;
 
-use crate::{FederatedSchema, QueryPlan};
+use crate::{
+    DeferredPlan, EntityKey, FederatedSchema, FetchNode, FlattenNode, JoinField, JoinKey, KeyField,
+    MergeNode, PlanNode, QueryPlan, ResponseField, StreamField, SubscriptionNode, intern::Name,
+    introspection, literal, request_context::RequestContext, service_selector::ServiceSelector,
+};
+
+#[async_trait]
+pub trait QueryPlanner: Send + Sync {
+    async fn plan_query(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String>;
+
+    // Plans without looking the operation up in a cache, while still caching
+    // the new plan for later requests
+    async fn plan_query_uncached(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.plan_query(query, schema, variables, operation_name)
+            .await
+    }
+
+    // Plans with the request's context: its cache bypass is honored, and
+    // planners routing by request consult their service selector with it
+    async fn plan_in_context(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        if context.bypass_cache {
+            self.plan_query_uncached(query, schema, variables, operation_name)
+                .await
+        } else {
+            self.plan_query(query, schema, variables, operation_name)
+                .await
+        }
+    }
+
+    // The services the context routes the operation's root fields to, as a
+    // string telling apart plans that differ only in their routing. Empty
+    // for planners that don't route by request.
+    fn routing_key(
+        &self,
+        _query: &str,
+        _schema: &FederatedSchema,
+        _operation_name: Option<&str>,
+        _context: &RequestContext,
+    ) -> String {
+        String::new()
+    }
+
+    // Drops cached plans whose query hash starts with `prefix`, returning how
+    // many were removed. Planners without a cache have nothing to drop.
+    fn invalidate(&self, _prefix: &str) -> usize {
+        0
+    }
+
+    // Whether `plan_query` would answer the operation from a cache
+    fn is_cached(
+        &self,
+        _query: &str,
+        _schema: &FederatedSchema,
+        _variables: &Option<Value>,
+        _operation_name: Option<&str>,
+    ) -> bool {
+        false
+    }
+
+    // Metrics in the Prometheus text format, empty for planners keeping none
+    fn metrics(&self) -> String {
+        String::new()
+    }
+}
+
+// Directives the gateway resolves itself, they never reach a service
+const GATEWAY_DIRECTIVES: &[&str] = &["skip", "include", "defer", "stream"];
+
+pub struct SimpleQueryPlanner {
+    // Field and inline fragment directives copied into the subqueries, every
+    // other client directive is dropped
+    forwarded_directives: Vec<String>,
+    // Subqueries are written on a single line without indentation
+    minify_queries: bool,
+    service_selector: Option<Arc<dyn ServiceSelector>>,
+}
+
+impl Default for SimpleQueryPlanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Fields of a type without `@key` that another service resolves. They're
+// fetched through the closest field above them that the service resolves too.
+struct HoistedFields<'a> {
+    type_name: String,
+    owner: String,
+    items: Vec<query::Selection<'a, String>>,
+}
+
+impl SimpleQueryPlanner {
+    pub fn new() -> Self {
+        SimpleQueryPlanner {
+            forwarded_directives: Vec::new(),
+            minify_queries: false,
+            service_selector: None,
+        }
+    }
+
+    pub fn with_service_selector(mut self, selector: Arc<dyn ServiceSelector>) -> Self {
+        self.service_selector = Some(selector);
+        self
+    }
+
+    // Root field ("Query.search") -> service the selector routes it to, for
+    // the root fields of the operation several services declare
+    fn routes(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> BTreeMap<String, String> {
+        let mut routes = BTreeMap::new();
+        let Some(selector) = &self.service_selector else {
+            return routes;
+        };
+        let Ok(doc) = graphql_parser::query::parse_query::<String>(query) else {
+            return routes;
+        };
+        let fragments: HashMap<&str, &FragmentDefinition<String>> = doc
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
+        let (operation_type, selection_set) = match Self::select_operation(&doc, operation_name) {
+            Ok(OperationDefinition::SelectionSet(selection_set)) => ("Query", selection_set),
+            Ok(OperationDefinition::Query(q)) => ("Query", &q.selection_set),
+            Ok(OperationDefinition::Mutation(m)) => ("Mutation", &m.selection_set),
+            Ok(OperationDefinition::Subscription(s)) => ("Subscription", &s.selection_set),
+            Err(_) => return routes,
+        };
+        let Ok(selection_set) = Self::inline_fragments(selection_set, &fragments, &mut Vec::new())
+        else {
+            return routes;
+        };
+
+        for field in Self::extract_fields(&selection_set) {
+            let field_key = format!("{}.{}", operation_type, field.name);
+            if routes.contains_key(&field_key) {
+                continue;
+            }
+            let Some(candidates) = schema
+                .type_to_service_map
+                .get(field_key.as_str())
+                .filter(|candidates| candidates.len() > 1)
+            else {
+                continue;
+            };
+            let candidates: Vec<String> = candidates.iter().map(Name::to_string).collect();
+            if let Some(service) = selector.select(&field_key, &candidates, context)
+                && candidates.contains(&service)
+            {
+                routes.insert(field_key, service);
+            }
+        }
+        routes
+    }
+
+    // The schema as the routed request sees it, every routed root field
+    // being declared by its chosen service alone
+    fn routed_schema(
+        schema: &FederatedSchema,
+        routes: &BTreeMap<String, String>,
+    ) -> FederatedSchema {
+        let mut routed = schema.clone();
+        for (field_key, service) in routes {
+            routed.type_to_service_map.insert(
+                Name::from(field_key.as_str()),
+                vec![Name::from(service.as_str())],
+            );
+            routed.ambiguous_root_fields.remove(field_key);
+        }
+        routed
+    }
+
+    pub fn with_forwarded_directives(mut self, directives: Vec<String>) -> Self {
+        self.forwarded_directives = directives;
+        self
+    }
+
+    pub fn with_minified_queries(mut self, minify: bool) -> Self {
+        self.minify_queries = minify;
+        self
+    }
+
+    fn find_variables_in_field(field: &query::Field<String>) -> HashSet<String> {
+        let mut variables = HashSet::new();
+        Self::collect_variables_from_field(field, &mut variables);
+        variables
+    }
+
+    fn collect_variables_from_field(field: &query::Field<String>, variables: &mut HashSet<String>) {
+        for (_, value) in &field.arguments {
+            Self::extract_variables_from_value(value, variables);
+        }
+        Self::collect_variables_from_directives(&field.directives, variables);
+
+        Self::collect_variables_from_selection_set(&field.selection_set, variables);
+    }
+
+    fn collect_variables_from_selection_set(
+        selection_set: &SelectionSet<String>,
+        variables: &mut HashSet<String>,
+    ) {
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(nested_field) => {
+                    Self::collect_variables_from_field(nested_field, variables);
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    Self::collect_variables_from_directives(&fragment.directives, variables);
+                    Self::collect_variables_from_selection_set(&fragment.selection_set, variables);
+                }
+                query::Selection::FragmentSpread(_) => {}
+            }
+        }
+    }
+
+    // Subqueries only declare the variables the operation defines, so a
+    // variable used without a definition, as in a shorthand `{ user(id: $id) }`
+    // which can't declare any, would reach the service undeclared
+    fn check_variables_defined(
+        selection_set: &SelectionSet<String>,
+        variable_defs: &[VariableDefinition<String>],
+    ) -> Result<(), String> {
+        let mut used_variables = HashSet::new();
+        Self::collect_variables_from_selection_set(selection_set, &mut used_variables);
+
+        let mut undefined: Vec<&String> = used_variables
+            .iter()
+            .filter(|name| !variable_defs.iter().any(|def| &def.name == *name))
+            .collect();
+        undefined.sort();
+        match undefined.first() {
+            Some(name) => Err(format!("Variable \"${}\" is not defined.", name)),
+            None => Ok(()),
+        }
+    }
+
+    // Absent variables take their declared default, so every fetch is sent
+    // the value the service would otherwise have to infer, and required ones
+    // must be provided
+    fn coerce_variables(
+        variable_defs: &[VariableDefinition<String>],
+        variables: Option<Value>,
+    ) -> Result<Option<Value>, String> {
+        if variable_defs.is_empty() {
+            return Ok(variables);
+        }
+        let mut values = match variables {
+            Some(Value::Object(obj)) => obj,
+            _ => serde_json::Map::new(),
+        };
+
+        for def in variable_defs {
+            let required = matches!(def.var_type, query::Type::NonNullType(_));
+            match values.get(&def.name) {
+                Some(Value::Null) if required => {
+                    return Err(format!(
+                        "Variable \"${}\" of non-null type \"{}\" must not be null.",
+                        def.name, def.var_type
+                    ));
+                }
+                Some(_) => {}
+                None => match &def.default_value {
+                    Some(default) => {
+                        values.insert(def.name.clone(), literal::const_value(default));
+                    }
+                    None if required => {
+                        return Err(format!(
+                            "Variable \"${}\" of required type \"{}\" was not provided.",
+                            def.name, def.var_type
+                        ));
+                    }
+                    None => {}
+                },
+            }
+        }
+
+        Ok(Some(Value::Object(values)))
+    }
+
+    // Default values are constants, variables can't appear in them
+    // Only forwarded directives end up in subqueries, the variables of the
+    // ones the gateway resolves aren't sent
+    fn collect_variables_from_directives(
+        directives: &[query::Directive<String>],
+        variables: &mut HashSet<String>,
+    ) {
+        for directive in directives {
+            if !GATEWAY_DIRECTIVES.contains(&directive.name.as_str()) {
+                for (_, value) in &directive.arguments {
+                    Self::extract_variables_from_value(value, variables);
+                }
+            }
+        }
+    }
+
+    fn select_variables(used_variables: &HashSet<String>, variables: &Option<Value>) -> Value {
+        match variables {
+            Some(Value::Object(obj)) if !used_variables.is_empty() => {
+                let mut field_vars = serde_json::Map::with_capacity(used_variables.len());
+
+                for var_name in used_variables {
+                    if let Some(var_value) = obj.get(var_name) {
+                        field_vars.insert(var_name.clone(), var_value.clone());
+                    }
+                }
+
+                Value::Object(field_vars)
+            }
+            _ => json!({}),
+        }
+    }
+
+    fn extract_variables_from_value(value: &query::Value<String>, variables: &mut HashSet<String>) {
+        match value {
+            query::Value::Variable(var_name) => {
+                variables.insert(var_name.clone());
+            }
+            query::Value::List(items) => {
+                for item in items {
+                    Self::extract_variables_from_value(item, variables);
+                }
+            }
+            query::Value::Object(obj) => {
+                for val in obj.values() {
+                    Self::extract_variables_from_value(val, variables);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn extract_fields<'a, 'b>(
+        selection_set: &'b SelectionSet<'a, String>,
+    ) -> Vec<&'b query::Field<'a, String>> {
+        let mut fields = Vec::with_capacity(selection_set.items.len());
+
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => fields.push(field),
+                // Root-level fragments always apply to the operation type
+                query::Selection::InlineFragment(fragment) => {
+                    fields.extend(Self::extract_fields(&fragment.selection_set));
+                }
+                query::Selection::FragmentSpread(_) => {}
+            }
+        }
+
+        fields
+    }
+
+    // Replaces named fragment spreads with equivalent inline fragments so each
+    // generated subquery is self-contained and can be split between services.
+    fn inline_fragments<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        fragments: &HashMap<&str, &FragmentDefinition<'a, String>>,
+        visiting: &mut Vec<String>,
+    ) -> Result<SelectionSet<'a, String>, String> {
+        let mut items = Vec::with_capacity(selection_set.items.len());
+
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => {
+                    let mut field = field.clone();
+                    field.selection_set =
+                        Self::inline_fragments(&field.selection_set, fragments, visiting)?;
+                    items.push(query::Selection::Field(field));
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    let mut fragment = fragment.clone();
+                    fragment.selection_set =
+                        Self::inline_fragments(&fragment.selection_set, fragments, visiting)?;
+                    items.push(query::Selection::InlineFragment(fragment));
+                }
+                query::Selection::FragmentSpread(spread) => {
+                    let fragment = fragments
+                        .get(spread.fragment_name.as_str())
+                        .ok_or_else(|| format!("Unknown fragment: {}", spread.fragment_name))?;
+
+                    if visiting.contains(&spread.fragment_name) {
+                        return Err(format!(
+                            "Fragment {} is used recursively",
+                            spread.fragment_name
+                        ));
+                    }
+
+                    visiting.push(spread.fragment_name.clone());
+                    let fragment_selection =
+                        Self::inline_fragments(&fragment.selection_set, fragments, visiting)?;
+                    visiting.pop();
+
+                    items.push(query::Selection::InlineFragment(query::InlineFragment {
+                        position: spread.position,
+                        type_condition: Some(fragment.type_condition.clone()),
+                        directives: spread.directives.clone(),
+                        selection_set: fragment_selection,
+                    }));
+                }
+            }
+        }
+
+        Ok(SelectionSet {
+            span: selection_set.span,
+            items,
+        })
+    }
+
+    // Picks the operation to execute: the one named `operation_name`, or the
+    // only operation of the document.
+    fn select_operation<'a, 'b>(
+        doc: &'b query::Document<'a, String>,
+        operation_name: Option<&str>,
+    ) -> Result<&'b OperationDefinition<'a, String>, String> {
+        let mut operations = doc.definitions.iter().filter_map(|def| match def {
+            Definition::Operation(operation) => Some(operation),
+            Definition::Fragment(_) => None,
+        });
+
+        match operation_name {
+            Some(operation_name) => operations
+                .find(|operation| {
+                    let name = match operation {
+                        OperationDefinition::SelectionSet(_) => None,
+                        OperationDefinition::Query(q) => q.name.as_deref(),
+                        OperationDefinition::Mutation(m) => m.name.as_deref(),
+                        OperationDefinition::Subscription(s) => s.name.as_deref(),
+                    };
+                    name == Some(operation_name)
+                })
+                .ok_or_else(|| format!("Unknown operation named \"{}\".", operation_name)),
+            None => match (operations.next(), operations.next()) {
+                (Some(operation), None) => Ok(operation),
+                (Some(_), Some(_)) => Err(
+                    "Must provide operation name if query contains multiple operations."
+                        .to_string(),
+                ),
+                (None, _) => Err("No valid operations found in query".to_string()),
+            },
+        }
+    }
+
+    // Drops the directives of fields and inline fragments that are neither
+    // forwarded nor resolved by the gateway
+    fn retain_directives<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        forwarded: &[String],
+    ) -> SelectionSet<'a, String> {
+        let keep = |directive: &query::Directive<String>| {
+            GATEWAY_DIRECTIVES.contains(&directive.name.as_str())
+                || forwarded.contains(&directive.name)
+        };
+
+        let items = selection_set
+            .items
+            .iter()
+            .map(|selection| match selection {
+                query::Selection::Field(field) => {
+                    let mut field = field.clone();
+                    field.directives.retain(keep);
+                    field.selection_set = Self::retain_directives(&field.selection_set, forwarded);
+                    query::Selection::Field(field)
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    let mut fragment = fragment.clone();
+                    fragment.directives.retain(keep);
+                    fragment.selection_set =
+                        Self::retain_directives(&fragment.selection_set, forwarded);
+                    query::Selection::InlineFragment(fragment)
+                }
+                query::Selection::FragmentSpread(_) => selection.clone(),
+            })
+            .collect();
+
+        SelectionSet {
+            span: selection_set.span,
+            items,
+        }
+    }
+
+    // Drops the selections excluded by `@skip`/`@include` for the request
+    // variables. A composite field left without selections keeps `__typename`
+    // so the subquery stays valid.
+    fn apply_conditional_directives<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        variable_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+    ) -> Result<SelectionSet<'a, String>, String> {
+        let mut items = Vec::with_capacity(selection_set.items.len());
+
+        for selection in &selection_set.items {
+            let directives = match selection {
+                query::Selection::Field(field) => &field.directives,
+                query::Selection::InlineFragment(fragment) => &fragment.directives,
+                query::Selection::FragmentSpread(spread) => &spread.directives,
+            };
+
+            if !Self::is_included(directives, variable_defs, variables)? {
+                continue;
+            }
+
+            match selection {
+                query::Selection::Field(field) => {
+                    let mut field = field.clone();
+                    field
+                        .directives
+                        .retain(|d| d.name != "skip" && d.name != "include");
+
+                    if !field.selection_set.items.is_empty() {
+                        field.selection_set = Self::apply_conditional_directives(
+                            &field.selection_set,
+                            variable_defs,
+                            variables,
+                        )?;
+
+                        if field.selection_set.items.is_empty() {
+                            field
+                                .selection_set
+                                .items
+                                .push(query::Selection::Field(query::Field {
+                                    position: field.position,
+                                    alias: None,
+                                    name: "__typename".to_string(),
+                                    arguments: Vec::new(),
+                                    directives: Vec::new(),
+                                    selection_set: SelectionSet {
+                                        span: Default::default(),
+                                        items: Vec::new(),
+                                    },
+                                }));
+                        }
+                    }
+                    items.push(query::Selection::Field(field));
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    let mut fragment = fragment.clone();
+                    fragment
+                        .directives
+                        .retain(|d| d.name != "skip" && d.name != "include");
+                    fragment.selection_set = Self::apply_conditional_directives(
+                        &fragment.selection_set,
+                        variable_defs,
+                        variables,
+                    )?;
+
+                    if !fragment.selection_set.items.is_empty() {
+                        items.push(query::Selection::InlineFragment(fragment));
+                    }
+                }
+                query::Selection::FragmentSpread(_) => items.push(selection.clone()),
+            }
+        }
+
+        Ok(SelectionSet {
+            span: selection_set.span,
+            items,
+        })
+    }
+
+    fn is_included(
+        directives: &[query::Directive<String>],
+        variable_defs: &[VariableDefinition<String>],
+        variables: &Option<Value>,
+    ) -> Result<bool, String> {
+        for directive in directives {
+            let skip = match directive.name.as_str() {
+                "skip" => true,
+                "include" => false,
+                _ => continue,
+            };
+
+            let condition = directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "if")
+                .map(|(_, value)| value)
+                .ok_or_else(|| {
+                    format!("Directive @{} requires an `if` argument", directive.name)
+                })?;
+
+            let value = match condition {
+                query::Value::Boolean(value) => *value,
+                query::Value::Variable(var_name) => {
+                    let provided = variables
+                        .as_ref()
+                        .and_then(|vars| vars.get(var_name))
+                        .and_then(Value::as_bool);
+                    let default = variable_defs
+                        .iter()
+                        .find(|def| &def.name == var_name)
+                        .and_then(|def| match &def.default_value {
+                            Some(query::Value::Boolean(value)) => Some(*value),
+                            _ => None,
+                        });
+
+                    provided.or(default).ok_or_else(|| {
+                        format!(
+                            "Variable ${} used by @{} must be a Boolean",
+                            var_name, directive.name
+                        )
+                    })?
+                }
+                _ => {
+                    return Err(format!(
+                        "Directive @{} expects a Boolean `if` argument",
+                        directive.name
+                    ));
+                }
+            };
+
+            if value == skip {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn find_service_for_field(
+        field_name: &str,
+        operation_type: &str,
+        schema: &FederatedSchema,
+    ) -> Result<String, String> {
+        let type_key = format!("{}.{}", operation_type, field_name);
+
+        if let Some(service_names) = schema.type_to_service_map.get(type_key.as_str())
+            && !service_names.is_empty()
+        {
+            if schema.ambiguous_root_fields.contains(&type_key) {
+                return Err(format!(
+                    "Field {} is resolved by services {} with equal priority, configure an owner",
+                    type_key,
+                    service_names.join(", ")
+                ));
+            }
+            return Ok(service_names[0].to_string());
+        }
+
+        Err(format!(
+            "No service found for field: {} in operation: {}",
+            field_name, operation_type
+        ))
+    }
+
+    // For a root field declared by several services whose selection narrows an
+    // abstract type, returns the services defining at least one of the
+    // selected concrete types so the field can be fanned out to all of them.
+    // Where the services declare their own members of the abstract type, the
+    // field goes to every service returning objects the selection applies to.
+    fn find_services_for_abstract_field(
+        field: &query::Field<String>,
+        operation_type: &str,
+        schema: &FederatedSchema,
+    ) -> Vec<String> {
+        let field_key = format!("{}.{}", operation_type, field.name);
+        let owners = match schema.type_to_service_map.get(field_key.as_str()) {
+            Some(owners) if owners.len() > 1 => owners,
+            _ => return Vec::new(),
+        };
+        let field_type = schema.field_types.get(&field_key);
+
+        if let Some(field_type) = field_type
+            && let Some(by_service) = schema.service_possible_types.get(field_type)
+        {
+            let conditions: Vec<&str> = field
+                .selection_set
+                .items
+                .iter()
+                .map(|selection| match selection {
+                    query::Selection::InlineFragment(query::InlineFragment {
+                        type_condition: Some(TypeCondition::On(type_name)),
+                        ..
+                    }) => type_name.as_str(),
+                    _ => field_type.as_str(),
+                })
+                .collect();
+
+            return owners
+                .iter()
+                .filter(|owner| {
+                    by_service
+                        .get(owner.as_str())
+                        .is_some_and(|members| !members.is_empty())
+                        && conditions.iter().any(|condition| {
+                            schema.service_matches_condition(owner, field_type, condition)
+                        })
+                })
+                .map(Name::to_string)
+                .collect();
+        }
+
+        let type_conditions: Vec<&str> = field
+            .selection_set
+            .items
+            .iter()
+            .filter_map(|selection| match selection {
+                query::Selection::InlineFragment(query::InlineFragment {
+                    type_condition: Some(TypeCondition::On(type_name)),
+                    ..
+                }) if Some(type_name) != field_type => Some(type_name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        if type_conditions.is_empty() {
+            return Vec::new();
+        }
+
+        owners
+            .iter()
+            .filter(|owner| {
+                type_conditions.iter().any(|type_name| {
+                    schema
+                        .type_to_service_map
+                        .get(*type_name)
+                        .is_some_and(|services| services.contains(owner))
+                })
+            })
+            .map(Name::to_string)
+            .collect()
+    }
+
+    fn plan_root_field<'a>(
+        field: &query::Field<'a, String>,
+        operation_type: &str,
+        service_name: &str,
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<PlanNode, String> {
+        let (fetch, dependents) = Self::plan_root_fetch(
+            field,
+            operation_type,
+            service_name,
+            schema,
+            var_defs,
+            variables,
+            minify,
+        )?;
+
+        if dependents.is_empty() {
+            Ok(PlanNode::Fetch(fetch))
+        } else {
+            Ok(PlanNode::Sequence(vec![
+                PlanNode::Fetch(fetch),
+                PlanNode::parallel(dependents),
+            ]))
+        }
+    }
+
+    // The fetch resolving `field` on `service_name`, together with the entity
+    // fetches that depend on its result
+    fn plan_root_fetch<'a>(
+        field: &query::Field<'a, String>,
+        operation_type: &str,
+        service_name: &str,
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<(FetchNode, Vec<PlanNode>), String> {
+        if operation_type == "Subscription"
+            && !schema.services.get(service_name).is_some_and(|service| {
+                service.capabilities.subscriptions
+                    || service.capabilities.websocket_subscriptions
+                    || service
+                        .topics
+                        .contains_key(&format!("Subscription.{}", field.name))
+            })
+        {
+            return Err(format!(
+                "Service {} does not support subscriptions",
+                service_name
+            ));
+        }
+
+        let mut dependents = Vec::new();
+        let original = field;
+        let mut field = field.clone();
+        let field_key = format!("{}.{}", operation_type, field.name);
+        if let Some(field_type) = schema.field_types.get(&field_key)
+            && !field.selection_set.items.is_empty()
+        {
+            let path = vec![field.alias.clone().unwrap_or_else(|| field.name.clone())];
+            let mut hoisted = Vec::new();
+            field.selection_set = Self::split_selection_set_hoisting(
+                &field.selection_set,
+                field_type,
+                service_name,
+                &path,
+                &Self::provided_fields(schema, &field_key, service_name)?,
+                schema,
+                var_defs,
+                variables,
+                minify,
+                &mut dependents,
+                &mut hoisted,
+            )?;
+
+            // Queries resolve fields of types without `@key` from another
+            // service by querying its own root field too, the results being
+            // merged. Mutations are never sent twice.
+            for nested in hoisted {
+                if operation_type != "Query"
+                    || !schema
+                        .type_to_service_map
+                        .get(field_key.as_str())
+                        .is_some_and(|owners| owners.iter().any(|owner| *owner == nested.owner))
+                {
+                    return Err(Self::missing_key(&nested.type_name));
+                }
+                let mut foreign = original.clone();
+                foreign.selection_set.items = nested.items;
+                dependents.push(Self::plan_root_field(
+                    &foreign,
+                    operation_type,
+                    &nested.owner,
+                    schema,
+                    var_defs,
+                    variables,
+                    minify,
+                )?);
+            }
+            if field.selection_set.items.is_empty() {
+                Self::select_leaf_field(&mut field.selection_set.items, "__typename");
+            }
+        }
+
+        // Namespaced services know the field without its prefix, the alias
+        // keeps the response key the client expects
+        if let Some(name) = schema
+            .services
+            .get(service_name)
+            .and_then(|service| service.namespace.as_deref())
+            .and_then(|prefix| field.name.strip_prefix(prefix))
+        {
+            field.alias.get_or_insert_with(|| field.name.clone());
+            field.name = name.to_string();
+        }
+
+        let field_variables = Self::find_variables_in_field(&field);
+
+        let field_query =
+            Self::create_field_query(&field, operation_type, var_defs, &field_variables, minify);
+        let fetch = FetchNode {
+            service_name: service_name.to_string(),
+            query: field_query,
+            variables: Self::select_variables(&field_variables, variables),
+            entity: None,
+            join: None,
+        };
+
+        Ok((fetch, dependents))
+    }
+
+    // Subscriptions stream a single root field from the service owning it.
+    // Fields other services contribute to the events are resolved per event.
+    fn plan_subscription<'a>(
+        field: &query::Field<'a, String>,
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<PlanNode, String> {
+        let mut services = Self::find_services_for_abstract_field(field, "Subscription", schema);
+        if services.len() > 1 {
+            return Err(format!(
+                "Subscription field {} is resolved by more than one service",
+                field.name
+            ));
+        }
+        let service_name = match services.pop() {
+            Some(service_name) => service_name,
+            None => Self::find_service_for_field(&field.name, "Subscription", schema)?,
+        };
+
+        let (primary, dependents) = Self::plan_root_fetch(
+            field,
+            "Subscription",
+            &service_name,
+            schema,
+            var_defs,
+            variables,
+            minify,
+        )?;
+
+        Ok(PlanNode::Subscription(SubscriptionNode {
+            primary,
+            rest: (!dependents.is_empty()).then(|| Box::new(PlanNode::parallel(dependents))),
+        }))
+    }
+
+    fn create_field_query(
+        field: &query::Field<String>,
+        operation_type: &str,
+        variable_defs: &[VariableDefinition<String>],
+        used_variables: &HashSet<String>,
+        minify: bool,
+    ) -> String {
+        let estimated_size = 100
+            + field.name.len() * 2
+            + used_variables.len() * 20
+            + field.selection_set.items.len() * 30;
+
+        let mut query_str = String::with_capacity(estimated_size);
+
+        match operation_type {
+            "Query" => query_str.push_str("query"),
+            "Mutation" => query_str.push_str("mutation"),
+            "Subscription" => query_str.push_str("subscription"),
+            _ => query_str.push_str("query"),
+        }
+
+        if !used_variables.is_empty() {
+            query_str.push('(');
+            Self::append_variable_definitions(&mut query_str, variable_defs, used_variables, true);
+            query_str.push(')');
+        }
+
+        query_str.push_str(if minify { " { " } else { " {\n  " });
+        if let Some(alias) = &field.alias {
+            query_str.push_str(alias);
+            query_str.push_str(": ");
+        }
+        query_str.push_str(&field.name);
+
+        if !field.arguments.is_empty() {
+            query_str.push('(');
+
+            let mut first = true;
+            for (name, value) in &field.arguments {
+                if !first {
+                    query_str.push_str(", ");
+                }
+                first = false;
+
+                query_str.push_str(name);
+                query_str.push_str(": ");
+                Self::append_value(&mut query_str, value);
+            }
+
+            query_str.push(')');
+        }
+        Self::append_directives(&mut query_str, &field.directives);
+
+        if minify {
+            if !field.selection_set.items.is_empty() {
+                query_str.push_str(" { ");
+                Self::append_selection_set(&mut query_str, &field.selection_set, None);
+                query_str.push('}');
+            }
+            query_str.push_str(" }");
+        } else {
+            if !field.selection_set.items.is_empty() {
+                query_str.push_str(" {\n");
+                Self::append_selection_set(&mut query_str, &field.selection_set, Some(4));
+                query_str.push_str("  }\n");
+            }
+            query_str.push_str("}\n");
+        }
+        query_str
+    }
+
+    // Merges repeated selections so the subqueries ask for every field once:
+    // fields with the same response key, arguments and directives, and
+    // fragments on the same type with the same directives, have their
+    // selections combined. Fragments without a type condition or directives
+    // are unwrapped into the enclosing selection. Deferred fragments are
+    // kept apart, each being delivered on its own.
+    fn merge_selections<'a>(selection_set: &SelectionSet<'a, String>) -> SelectionSet<'a, String> {
+        let mut items = Vec::with_capacity(selection_set.items.len());
+        for selection in &selection_set.items {
+            Self::merge_selection(&mut items, selection);
+        }
+
+        for item in &mut items {
+            match item {
+                query::Selection::Field(field) => {
+                    field.selection_set = Self::merge_selections(&field.selection_set);
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    fragment.selection_set = Self::merge_selections(&fragment.selection_set);
+                }
+                query::Selection::FragmentSpread(_) => {}
+            }
+        }
+
+        SelectionSet {
+            span: selection_set.span,
+            items,
+        }
+    }
+
+    fn merge_selection<'a>(
+        items: &mut Vec<query::Selection<'a, String>>,
+        selection: &query::Selection<'a, String>,
+    ) {
+        match selection {
+            query::Selection::Field(field) => {
+                let same = items.iter_mut().find_map(|item| match item {
+                    query::Selection::Field(f)
+                        if f.alias == field.alias
+                            && f.name == field.name
+                            && f.arguments == field.arguments
+                            && Self::same_directives(&f.directives, &field.directives) =>
+                    {
+                        Some(f)
+                    }
+                    _ => None,
+                });
+                match same {
+                    Some(f) => f
+                        .selection_set
+                        .items
+                        .extend(field.selection_set.items.iter().cloned()),
+                    None => items.push(selection.clone()),
+                }
+            }
+            query::Selection::InlineFragment(fragment)
+                if fragment.type_condition.is_none() && fragment.directives.is_empty() =>
+            {
+                for selection in &fragment.selection_set.items {
+                    Self::merge_selection(items, selection);
+                }
+            }
+            query::Selection::InlineFragment(fragment)
+                if fragment.directives.iter().any(|d| d.name == "defer") =>
+            {
+                items.push(selection.clone());
+            }
+            query::Selection::InlineFragment(fragment) => {
+                let same = items.iter_mut().find_map(|item| match item {
+                    query::Selection::InlineFragment(f)
+                        if f.type_condition == fragment.type_condition
+                            && Self::same_directives(&f.directives, &fragment.directives) =>
+                    {
+                        Some(f)
+                    }
+                    _ => None,
+                });
+                match same {
+                    Some(f) => f
+                        .selection_set
+                        .items
+                        .extend(fragment.selection_set.items.iter().cloned()),
+                    None => items.push(selection.clone()),
+                }
+            }
+            query::Selection::FragmentSpread(spread) => {
+                let repeated = items.iter().any(|item| {
+                    matches!(item, query::Selection::FragmentSpread(s)
+                        if s.fragment_name == spread.fragment_name
+                            && Self::same_directives(&s.directives, &spread.directives))
+                });
+                if !repeated {
+                    items.push(selection.clone());
+                }
+            }
+        }
+    }
+
+    // Drops root fields identical to an earlier sibling but for their alias,
+    // as codegen tends to produce, so each is fetched once. Their values are
+    // copied from the sibling's key when the response is shaped.
+    fn dedup_root_fields<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        response_shape: &mut [ResponseField],
+    ) -> SelectionSet<'a, String> {
+        let mut kept: Vec<(String, String)> = Vec::new();
+        let mut items = Vec::with_capacity(selection_set.items.len());
+        for selection in &selection_set.items {
+            let query::Selection::Field(field) = selection else {
+                items.push(selection.clone());
+                continue;
+            };
+            // Streamed fields are delivered by their own key
+            if field.name.starts_with("__") || field.directives.iter().any(|d| d.name == "stream") {
+                items.push(selection.clone());
+                continue;
+            }
+
+            let response_key = field.alias.as_ref().unwrap_or(&field.name).clone();
+            let mut unaliased = field.clone();
+            unaliased.alias = None;
+            let identity = unaliased.to_string();
+            match kept.iter().find(|(kept, _)| *kept == identity) {
+                Some((_, source_key)) if *source_key != response_key => {
+                    for shaped in response_shape
+                        .iter_mut()
+                        .filter(|shaped| shaped.response_key == response_key)
+                    {
+                        shaped.source_key = Some(source_key.clone());
+                    }
+                }
+                _ => {
+                    kept.push((identity, response_key));
+                    items.push(selection.clone());
+                }
+            }
+        }
+
+        SelectionSet {
+            span: selection_set.span,
+            items,
+        }
+    }
+
+    // Directives compared without their position in the document
+    fn same_directives<'a>(
+        a: &[query::Directive<'a, String>],
+        b: &[query::Directive<'a, String>],
+    ) -> bool {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(a, b)| a.name == b.name && a.arguments == b.arguments)
+    }
+
+    fn create_entity_query(
+        type_name: &str,
+        selection_set: &SelectionSet<String>,
+        variable_defs: &[VariableDefinition<String>],
+        used_variables: &HashSet<String>,
+        minify: bool,
+    ) -> String {
+        let mut query_str = String::with_capacity(200 + selection_set.items.len() * 30);
+
+        query_str.push_str("query($representations: [_Any!]!");
+        Self::append_variable_definitions(&mut query_str, variable_defs, used_variables, false);
+        if minify {
+            query_str.push_str(") { _entities(representations: $representations) { ... on ");
+            query_str.push_str(type_name);
+            query_str.push_str(" { ");
+            Self::append_selection_set(&mut query_str, selection_set, None);
+            query_str.push_str("} } }");
+        } else {
+            query_str.push_str(") {\n  _entities(representations: $representations) {\n");
+            query_str.push_str("    ... on ");
+            query_str.push_str(type_name);
+            query_str.push_str(" {\n");
+            Self::append_selection_set(&mut query_str, selection_set, Some(6));
+            query_str.push_str("    }\n  }\n}\n");
+        }
+        query_str
+    }
+
+    fn append_variable_definitions(
+        out: &mut String,
+        variable_defs: &[VariableDefinition<String>],
+        used_variables: &HashSet<String>,
+        mut first: bool,
+    ) {
+        for def in variable_defs {
+            if used_variables.contains(&def.name) {
+                if !first {
+                    out.push_str(", ");
+                }
+                first = false;
+
+                write!(out, "${}: {}", def.name, def.var_type).unwrap();
+
+                if let Some(default_value) = &def.default_value {
+                    out.push_str(" = ");
+                    Self::append_value(out, default_value);
+                }
+            }
+        }
+    }
+
+    // Keeps the selections `service_name` can resolve itself and turns fields
+    // owned by other services into `_entities` fetches against the entity at
+    // `path`, pushed to `dependents` as they can only run once the fetch for
+    // this selection has completed. The entity's key fields are added to the
+    // local selection so the executor can build the representations.
+    // `provided` fields are resolved locally whoever owns them, as the field
+    // returning this selection is `@provides` on `service_name`.
+    #[allow(clippy::too_many_arguments)]
+    fn split_selection_set<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        parent_type: &str,
+        service_name: &str,
+        path: &[String],
+        provided: &[KeyField],
+        schema: &FederatedSchema,
+        variable_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+        dependents: &mut Vec<PlanNode>,
+    ) -> Result<SelectionSet<'a, String>, String> {
+        let mut hoisted = Vec::new();
+        let selection_set = Self::split_selection_set_hoisting(
+            selection_set,
+            parent_type,
+            service_name,
+            path,
+            provided,
+            schema,
+            variable_defs,
+            variables,
+            minify,
+            dependents,
+            &mut hoisted,
+        )?;
+        match hoisted.first() {
+            Some(hoisted) => Err(Self::missing_key(&hoisted.type_name)),
+            None => Ok(selection_set),
+        }
+    }
+
+    // Splits like `split_selection_set`, except that foreign fields of types
+    // without `@key` are handed back in `hoisted` rather than failing, for
+    // the caller to fetch through the field returning the type
+    #[allow(clippy::too_many_arguments)]
+    fn split_selection_set_hoisting<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        parent_type: &str,
+        service_name: &str,
+        path: &[String],
+        provided: &[KeyField],
+        schema: &FederatedSchema,
+        variable_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+        dependents: &mut Vec<PlanNode>,
+        hoisted: &mut Vec<HoistedFields<'a>>,
+    ) -> Result<SelectionSet<'a, String>, String> {
+        let mut local_items = Vec::with_capacity(selection_set.items.len());
+        let mut foreign_fields: Vec<(String, Vec<query::Selection<'a, String>>)> = Vec::new();
+
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => {
+                    let field_key = format!("{}.{}", parent_type, field.name);
+                    let provided_field = provided.iter().find(|p| p.name == field.name);
+
+                    if let Some(join) = schema.joins.get(&field_key) {
+                        for argument in &join.arguments {
+                            Self::select_leaf_field(&mut local_items, &argument.field);
+                        }
+                        dependents.push(Self::plan_join(
+                            field,
+                            parent_type,
+                            join,
+                            path,
+                            schema,
+                            variable_defs,
+                            variables,
+                            minify,
+                        )?);
+                        continue;
+                    }
+
+                    if provided_field.is_none()
+                        && let Some(owners) = schema.type_to_service_map.get(field_key.as_str())
+                        && !owners.is_empty()
+                        && !owners.iter().any(|owner| owner == service_name)
+                    {
+                        Self::add_foreign_field(&mut foreign_fields, &owners[0], selection.clone());
+                        continue;
+                    }
+
+                    let original = field;
+                    let mut field = field.clone();
+                    if let Some(field_type) = schema.field_types.get(&field_key)
+                        && !field.selection_set.items.is_empty()
+                    {
+                        let mut field_path = path.to_vec();
+                        field_path.push(field.alias.clone().unwrap_or_else(|| field.name.clone()));
+
+                        let nested_provided = match provided_field {
+                            Some(provided_field) => provided_field.selections.clone(),
+                            None => Self::provided_fields(schema, &field_key, service_name)?,
+                        };
+                        let mut nested_hoisted = Vec::new();
+                        field.selection_set = Self::split_selection_set_hoisting(
+                            &field.selection_set,
+                            field_type,
+                            service_name,
+                            &field_path,
+                            &nested_provided,
+                            schema,
+                            variable_defs,
+                            variables,
+                            minify,
+                            dependents,
+                            &mut nested_hoisted,
+                        )?;
+
+                        // The owner of the nested fields fetches them through
+                        // this field, when it resolves it too
+                        for nested in nested_hoisted {
+                            if !schema
+                                .type_to_service_map
+                                .get(field_key.as_str())
+                                .is_some_and(|owners| {
+                                    owners.iter().any(|owner| *owner == nested.owner)
+                                })
+                            {
+                                return Err(Self::missing_key(&nested.type_name));
+                            }
+                            let mut foreign = original.clone();
+                            foreign.selection_set.items = nested.items;
+                            Self::add_foreign_field(
+                                &mut foreign_fields,
+                                &nested.owner,
+                                query::Selection::Field(foreign),
+                            );
+                        }
+                        if field.selection_set.items.is_empty() {
+                            continue;
+                        }
+                    }
+                    local_items.push(query::Selection::Field(field));
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                        None => parent_type,
+                    };
+
+                    // A service never returns objects of a type it doesn't
+                    // define, and would reject the fragment when validating.
+                    // Nor does it return objects outside its own members of
+                    // the abstract type.
+                    if let Some(owners) = schema.type_to_service_map.get(type_name)
+                        && !owners.iter().any(|owner| owner == service_name)
+                    {
+                        continue;
+                    }
+                    if !schema.service_matches_condition(service_name, parent_type, type_name) {
+                        continue;
+                    }
+
+                    let mut nested_hoisted = Vec::new();
+                    let mut fragment = fragment.clone();
+                    fragment.selection_set = Self::split_selection_set_hoisting(
+                        &fragment.selection_set,
+                        type_name,
+                        service_name,
+                        path,
+                        provided,
+                        schema,
+                        variable_defs,
+                        variables,
+                        minify,
+                        dependents,
+                        &mut nested_hoisted,
+                    )?;
+                    for nested in nested_hoisted {
+                        let mut foreign = fragment.clone();
+                        foreign.selection_set.items = nested.items;
+                        Self::add_foreign_field(
+                            &mut foreign_fields,
+                            &nested.owner,
+                            query::Selection::InlineFragment(foreign),
+                        );
+                    }
+                    local_items.push(query::Selection::InlineFragment(fragment));
+                }
+                query::Selection::FragmentSpread(_) => local_items.push(selection.clone()),
+            }
+        }
 
-#[async_trait]
-pub trait QueryPlanner: Send + Sync {
-    async fn plan_query(
-        &self,
-        query: &str,
-        schema: &FederatedSchema,
-        variables: Option<Value>,
-    ) -> Result<QueryPlan, String>;
-}
+        if !schema.entity_keys.contains_key(parent_type) {
+            hoisted.extend(
+                foreign_fields
+                    .drain(..)
+                    .map(|(owner, items)| HoistedFields {
+                        type_name: parent_type.to_string(),
+                        owner,
+                        items,
+                    }),
+            );
+        }
 
-pub struct SimpleQueryPlanner {}
+        if !foreign_fields.is_empty() {
+            let key_fields = KeyField::parse(
+                schema
+                    .entity_keys
+                    .get(parent_type)
+                    .ok_or_else(|| Self::missing_key(parent_type))?,
+            )?;
 
-impl SimpleQueryPlanner {
-    pub fn new() -> Self {
-        SimpleQueryPlanner {}
+            // `__typename` tells the executor which objects at `path` are
+            // entities of `parent_type` when the path holds an abstract type
+            Self::select_leaf_field(&mut local_items, "__typename");
+            Self::select_key_fields(&mut local_items, &key_fields);
+
+            // Fields with `@requires` get the required fields sent along with
+            // the key. Required fields this service can't resolve are fetched
+            // from their owner first, holding back the dependent fetch.
+            let requirements: Vec<(String, String)> = foreign_fields
+                .iter()
+                .flat_map(|(owner, items)| {
+                    items.iter().filter_map(move |item| match item {
+                        query::Selection::Field(field) => schema
+                            .requires
+                            .get(&format!("{}.{}", parent_type, field.name))
+                            .map(|fields| (owner.clone(), fields.clone())),
+                        _ => None,
+                    })
+                })
+                .collect();
+            let mut required_fields: HashMap<String, Vec<KeyField>> = HashMap::new();
+            let mut waiting = HashSet::new();
+            for (owner, fields) in requirements {
+                for required in KeyField::parse(&fields)? {
+                    let field_key = format!("{}.{}", parent_type, required.name);
+                    let required_owner = schema
+                        .type_to_service_map
+                        .get(field_key.as_str())
+                        .filter(|owners| {
+                            !owners.is_empty() && !owners.iter().any(|o| o == service_name)
+                        })
+                        .map(|owners| owners[0].to_string());
+
+                    match required_owner {
+                        Some(required_owner) if required_owner == owner => {}
+                        Some(required_owner) => {
+                            match foreign_fields
+                                .iter_mut()
+                                .find(|(owner, _)| owner == &required_owner)
+                            {
+                                Some((_, items)) => {
+                                    Self::select_key_fields(items, std::slice::from_ref(&required))
+                                }
+                                None => {
+                                    let mut items = Vec::new();
+                                    Self::select_key_fields(
+                                        &mut items,
+                                        std::slice::from_ref(&required),
+                                    );
+                                    foreign_fields.push((required_owner, items));
+                                }
+                            }
+                            waiting.insert(owner.clone());
+                        }
+                        None => Self::select_key_fields(
+                            &mut local_items,
+                            std::slice::from_ref(&required),
+                        ),
+                    }
+
+                    let fields = required_fields.entry(owner.clone()).or_default();
+                    if !key_fields
+                        .iter()
+                        .chain(fields.iter())
+                        .any(|f| f.name == required.name)
+                    {
+                        fields.push(required);
+                    }
+                }
+            }
+
+            let dependents_before = dependents.len();
+            let mut held_back = Vec::new();
+            for (owner, items) in foreign_fields {
+                let mut nested_dependents = Vec::new();
+                let entity_selection = Self::split_selection_set(
+                    &SelectionSet {
+                        span: selection_set.span,
+                        items,
+                    },
+                    parent_type,
+                    &owner,
+                    path,
+                    &[],
+                    schema,
+                    variable_defs,
+                    variables,
+                    minify,
+                    &mut nested_dependents,
+                )?;
+
+                let mut used_variables = HashSet::new();
+                Self::collect_variables_from_selection_set(&entity_selection, &mut used_variables);
+
+                let flatten = PlanNode::Flatten(FlattenNode {
+                    path: path.to_vec(),
+                    node: Box::new(PlanNode::Fetch(FetchNode {
+                        query: Self::create_entity_query(
+                            parent_type,
+                            &entity_selection,
+                            variable_defs,
+                            &used_variables,
+                            minify,
+                        ),
+                        variables: Self::select_variables(&used_variables, variables),
+                        service_name: owner.clone(),
+                        entity: Some(EntityKey {
+                            type_name: parent_type.to_string(),
+                            key_fields: key_fields
+                                .iter()
+                                .chain(required_fields.get(&owner).into_iter().flatten())
+                                .cloned()
+                                .collect(),
+                        }),
+                        join: None,
+                    })),
+                });
+
+                let node = if nested_dependents.is_empty() {
+                    flatten
+                } else {
+                    PlanNode::Sequence(vec![flatten, PlanNode::parallel(nested_dependents)])
+                };
+                if waiting.contains(&owner) {
+                    held_back.push(node);
+                } else {
+                    dependents.push(node);
+                }
+            }
+
+            if !held_back.is_empty() {
+                let ready = dependents.split_off(dependents_before);
+                dependents.push(PlanNode::Sequence(vec![
+                    PlanNode::parallel(ready),
+                    PlanNode::parallel(held_back),
+                ]));
+            }
+        }
+
+        // Objects of an abstract type are told apart by their `__typename`
+        // when shaping the response against the client's type conditions
+        if schema.possible_types.contains_key(parent_type) {
+            Self::select_leaf_field(&mut local_items, "__typename");
+        }
+
+        Ok(SelectionSet {
+            span: selection_set.span,
+            items: local_items,
+        })
     }
 
-    fn find_variables_in_field(field: &query::Field<String>) -> HashSet<String> {
-        let mut variables = HashSet::new();
-        Self::collect_variables_from_field(field, &mut variables);
-        variables
+    fn missing_key(type_name: &str) -> String {
+        format!(
+            "Type {} has fields resolved by other services but no @key",
+            type_name
+        )
     }
 
-    fn collect_variables_from_field(field: &query::Field<String>, variables: &mut HashSet<String>) {
-        for (_, value) in &field.arguments {
-            Self::extract_variables_from_value(value, variables);
+    fn add_foreign_field<'a>(
+        foreign_fields: &mut Vec<(String, Vec<query::Selection<'a, String>>)>,
+        owner: &str,
+        selection: query::Selection<'a, String>,
+    ) {
+        match foreign_fields.iter_mut().find(|(o, _)| o == owner) {
+            Some((_, items)) => items.push(selection),
+            None => foreign_fields.push((owner.to_string(), vec![selection])),
         }
+    }
 
-        for selection in &field.selection_set.items {
-            if let query::Selection::Field(nested_field) = selection {
-                Self::collect_variables_from_field(nested_field, variables);
-            }
+    // The fetch resolving a joined field for every object at `path`: the
+    // join's root field, aliased to the field's response key, with the
+    // parent object's fields passed as variables. Fields of the joined
+    // type other services resolve are fetched after it.
+    #[allow(clippy::too_many_arguments)]
+    fn plan_join<'a>(
+        field: &query::Field<'a, String>,
+        parent_type: &str,
+        join: &JoinField,
+        path: &[String],
+        schema: &FederatedSchema,
+        variable_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<PlanNode, String> {
+        let response_key = field.alias.clone().unwrap_or_else(|| field.name.clone());
+        let mut join_field = field.clone();
+        join_field.alias = Some(response_key.clone());
+        join_field.name = join.resolver.clone();
+
+        let mut join_defs = variable_defs.to_vec();
+        let mut arguments = BTreeMap::new();
+        for argument in &join.arguments {
+            let variable = format!("_join_{}", argument.name);
+            join_field.arguments.push((
+                argument.name.clone(),
+                query::Value::Variable(variable.clone()),
+            ));
+            join_defs.push(VariableDefinition {
+                position: field.position,
+                name: variable.clone(),
+                var_type: Self::variable_type(&argument.value_type),
+                default_value: None,
+            });
+            arguments.insert(variable, argument.field.clone());
+        }
+
+        let mut nested_dependents = Vec::new();
+        if let Some(field_type) = schema
+            .field_types
+            .get(&format!("{}.{}", parent_type, field.name))
+            && !field.selection_set.items.is_empty()
+        {
+            let mut field_path = path.to_vec();
+            field_path.push(response_key);
+            join_field.selection_set = Self::split_selection_set(
+                &field.selection_set,
+                field_type,
+                &join.service_name,
+                &field_path,
+                &[],
+                schema,
+                variable_defs,
+                variables,
+                minify,
+                &mut nested_dependents,
+            )?;
         }
+
+        let used_variables = Self::find_variables_in_field(&join_field);
+        let flatten = PlanNode::Flatten(FlattenNode {
+            path: path.to_vec(),
+            node: Box::new(PlanNode::Fetch(FetchNode {
+                service_name: join.service_name.clone(),
+                query: Self::create_field_query(
+                    &join_field,
+                    "Query",
+                    &join_defs,
+                    &used_variables,
+                    minify,
+                ),
+                variables: Self::select_variables(&used_variables, variables),
+                entity: None,
+                join: Some(JoinKey {
+                    type_name: parent_type.to_string(),
+                    arguments,
+                }),
+            })),
+        });
+
+        Ok(if nested_dependents.is_empty() {
+            flatten
+        } else {
+            PlanNode::Sequence(vec![flatten, PlanNode::parallel(nested_dependents)])
+        })
     }
 
-    fn extract_variables_from_value(value: &query::Value<String>, variables: &mut HashSet<String>) {
-        match value {
-            query::Value::Variable(var_name) => {
-                variables.insert(var_name.clone());
+    // The schema's type of an argument as the type of a variable
+    fn variable_type<'a>(value_type: &query::Type<'static, String>) -> query::Type<'a, String> {
+        match value_type {
+            query::Type::NamedType(name) => query::Type::NamedType(name.clone()),
+            query::Type::ListType(inner) => {
+                query::Type::ListType(Box::new(Self::variable_type(inner)))
             }
-            query::Value::List(items) => {
-                for item in items {
-                    Self::extract_variables_from_value(item, variables);
-                }
+            query::Type::NonNullType(inner) => {
+                query::Type::NonNullType(Box::new(Self::variable_type(inner)))
             }
-            query::Value::Object(obj) => {
-                for val in obj.values() {
-                    Self::extract_variables_from_value(val, variables);
+        }
+    }
+
+    // The fields `service_name` resolves along with the field at `field_key`
+    fn provided_fields(
+        schema: &FederatedSchema,
+        field_key: &str,
+        service_name: &str,
+    ) -> Result<Vec<KeyField>, String> {
+        match schema
+            .provides
+            .get(field_key)
+            .and_then(|by_service| by_service.get(service_name))
+        {
+            Some(fields) => KeyField::parse(fields),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Makes sure the parent fetch returns every key field, merging nested key
+    // selections into fields the client already selected
+    fn select_key_fields(items: &mut Vec<query::Selection<String>>, key_fields: &[KeyField]) {
+        for key_field in key_fields {
+            Self::select_leaf_field(items, &key_field.name);
+            if key_field.selections.is_empty() {
+                continue;
+            }
+
+            for item in items.iter_mut() {
+                if let query::Selection::Field(field) = item
+                    && field.alias.is_none()
+                    && field.name == key_field.name
+                {
+                    Self::select_key_fields(&mut field.selection_set.items, &key_field.selections);
                 }
             }
-            _ => {}
         }
     }
 
-    fn extract_fields<'a>(
-        selection_set: &'a SelectionSet<'a, String>,
-    ) -> impl Iterator<Item = &'a query::Field<'a, String>> + 'a {
-        selection_set.items.iter().filter_map(|selection| {
-            if let query::Selection::Field(field) = selection {
-                Some(field)
-            } else {
-                None
-            }
-        })
+    fn select_leaf_field(items: &mut Vec<query::Selection<String>>, field_name: &str) {
+        let already_selected = items.iter().any(|item| {
+            matches!(item, query::Selection::Field(f) if f.alias.is_none() && f.name == field_name)
+        });
+
+        if !already_selected {
+            items.push(query::Selection::Field(query::Field {
+                position: Default::default(),
+                alias: None,
+                name: field_name.to_string(),
+                arguments: Vec::new(),
+                directives: Vec::new(),
+                selection_set: SelectionSet {
+                    span: Default::default(),
+                    items: Vec::new(),
+                },
+            }));
+        }
     }
 
-    fn find_service_for_field(
-        field_name: &str,
-        operation_type: &str,
-        schema: &FederatedSchema,
-    ) -> Result<String, String> {
-        let type_key = format!("{}.{}", operation_type, field_name);
+    // Describes the fields the client selected, with @skip/@include applied,
+    // so the executor can drop whatever the gateway added for its own use
+    fn response_shape<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        type_conditions: &[String],
+        variable_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+    ) -> Result<Vec<ResponseField>, String> {
+        let mut fields = Vec::with_capacity(selection_set.items.len());
+
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => {
+                    if !Self::is_included(&field.directives, variable_defs, variables)? {
+                        continue;
+                    }
+
+                    fields.push(ResponseField {
+                        response_key: field.alias.clone().unwrap_or_else(|| field.name.clone()),
+                        source_key: None,
+                        field_name: field.name.clone(),
+                        type_conditions: type_conditions.to_vec(),
+                        selections: Self::response_shape(
+                            &field.selection_set,
+                            &[],
+                            variable_defs,
+                            variables,
+                        )?,
+                    });
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    if !Self::is_included(&fragment.directives, variable_defs, variables)? {
+                        continue;
+                    }
 
-        if let Some((_, service_names)) = schema.type_to_service_map.get_key_value(&type_key) {
-            if !service_names.is_empty() {
-                return Ok(service_names[0].clone());
+                    let mut conditions = type_conditions.to_vec();
+                    if let Some(TypeCondition::On(type_name)) = &fragment.type_condition {
+                        conditions.push(type_name.clone());
+                    }
+                    fields.extend(Self::response_shape(
+                        &fragment.selection_set,
+                        &conditions,
+                        variable_defs,
+                        variables,
+                    )?);
+                }
+                // Named fragments are inlined before shaping
+                query::Selection::FragmentSpread(_) => {}
             }
         }
 
-        Err(format!(
-            "No service found for field: {} in operation: {}",
-            field_name, operation_type
-        ))
+        Ok(fields)
     }
 
-    fn create_field_query(
-        field: &query::Field<String>,
+    fn plan_root_fields<'a>(
+        selection_set: &SelectionSet<'a, String>,
         operation_type: &str,
-        variable_defs: &[VariableDefinition<String>],
-        used_variables: &HashSet<String>,
-    ) -> String {
-        let estimated_size = 100
-            + field.name.len() * 2
-            + used_variables.len() * 20
-            + field.selection_set.items.len() * 30;
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<Vec<PlanNode>, String> {
+        let mut root_nodes = Vec::with_capacity(4);
 
-        let mut query_str = String::with_capacity(estimated_size);
+        for field in Self::extract_fields(selection_set) {
+            // Answered by the gateway while shaping the response, or from the
+            // composed schema for introspection fields
+            if field.name == "__typename"
+                || (operation_type == "Query" && introspection::is_introspection_field(&field.name))
+            {
+                continue;
+            }
 
-        match operation_type {
-            "Query" => query_str.push_str("query"),
-            "Mutation" => query_str.push_str("mutation"),
-            "Subscription" => query_str.push_str("subscription"),
-            _ => query_str.push_str("query"),
+            if operation_type == "Subscription" {
+                root_nodes.push(Self::plan_subscription(
+                    field, schema, var_defs, variables, minify,
+                )?);
+                continue;
+            }
+
+            let mut services =
+                Self::find_services_for_abstract_field(field, operation_type, schema);
+
+            let node = if services.len() > 1 {
+                let nodes = services
+                    .iter()
+                    .map(|service_name| {
+                        Self::plan_root_field(
+                            field,
+                            operation_type,
+                            service_name,
+                            schema,
+                            var_defs,
+                            variables,
+                            minify,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+
+                PlanNode::Merge(MergeNode {
+                    response_key: field.alias.clone().unwrap_or_else(|| field.name.clone()),
+                    nodes,
+                })
+            } else {
+                let service_name = match services.pop() {
+                    Some(service_name) => service_name,
+                    None => Self::find_service_for_field(&field.name, operation_type, schema)?,
+                };
+
+                Self::plan_root_field(
+                    field,
+                    operation_type,
+                    &service_name,
+                    schema,
+                    var_defs,
+                    variables,
+                    minify,
+                )?
+            };
+
+            root_nodes.push(node);
         }
 
-        if !used_variables.is_empty() {
-            query_str.push('(');
+        Ok(root_nodes)
+    }
 
-            let mut first = true;
-            for def in variable_defs {
-                if used_variables.contains(&def.name) {
-                    if !first {
-                        query_str.push_str(", ");
+    // Pulls the active `@defer`red fragments out of `selection_set` and plans
+    // each one on its own: fragments on the root type as root fetches, the
+    // others as entity fetches against the objects at `path`, whose key fields
+    // are added to the initial selection. Fragments that can't be fetched as
+    // entities stay in place, as does anything nested in a deferred fragment.
+    // Active `@stream`s are recorded along the way.
+    #[allow(clippy::too_many_arguments)]
+    fn split_deferred<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        parent_type: &str,
+        path: &[String],
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+        deferred: &mut Vec<DeferredPlan>,
+        streams: &mut Vec<StreamField>,
+    ) -> Result<SelectionSet<'a, String>, String> {
+        let mut items = Vec::with_capacity(selection_set.items.len());
+        let mut deferred_entities: Vec<String> = Vec::new();
+
+        for selection in &selection_set.items {
+            match selection {
+                query::Selection::Field(field) => {
+                    let mut field = field.clone();
+                    let mut field_path = path.to_vec();
+                    field_path.push(field.alias.clone().unwrap_or_else(|| field.name.clone()));
+
+                    if let Some((label, initial_count)) = Self::incremental_directive(
+                        &field.directives,
+                        "stream",
+                        var_defs,
+                        variables,
+                    )? {
+                        streams.push(StreamField {
+                            label,
+                            path: field_path.clone(),
+                            initial_count,
+                        });
                     }
-                    first = false;
 
-                    write!(query_str, "${}: {}", def.name, def.var_type).unwrap();
+                    if let Some(field_type) = schema
+                        .field_types
+                        .get(&format!("{}.{}", parent_type, field.name))
+                        && !field.selection_set.items.is_empty()
+                    {
+                        field.selection_set = Self::split_deferred(
+                            &field.selection_set,
+                            field_type,
+                            &field_path,
+                            schema,
+                            var_defs,
+                            variables,
+                            minify,
+                            deferred,
+                            streams,
+                        )?;
+                    }
+                    items.push(query::Selection::Field(field));
+                }
+                query::Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                        None => parent_type,
+                    };
 
-                    if let Some(default_value) = &def.default_value {
-                        query_str.push_str(" = ");
-                        Self::append_value(&mut query_str, default_value);
+                    if let Some((label, _)) = Self::incremental_directive(
+                        &fragment.directives,
+                        "defer",
+                        var_defs,
+                        variables,
+                    )? && let Some(node) = Self::plan_deferred(
+                        &fragment.selection_set,
+                        type_name,
+                        path,
+                        schema,
+                        var_defs,
+                        variables,
+                        minify,
+                    )? {
+                        let response_shape = Self::response_shape(
+                            &SelectionSet {
+                                span: selection_set.span,
+                                items: vec![selection.clone()],
+                            },
+                            &[],
+                            var_defs,
+                            variables,
+                        )?;
+                        deferred.push(DeferredPlan {
+                            label,
+                            path: path.to_vec(),
+                            node,
+                            response_shape,
+                        });
+                        if !path.is_empty() && !deferred_entities.iter().any(|t| t == type_name) {
+                            deferred_entities.push(type_name.to_string());
+                        }
+                        continue;
                     }
+
+                    let mut fragment = fragment.clone();
+                    fragment.selection_set = Self::split_deferred(
+                        &fragment.selection_set,
+                        type_name,
+                        path,
+                        schema,
+                        var_defs,
+                        variables,
+                        minify,
+                        deferred,
+                        streams,
+                    )?;
+                    items.push(query::Selection::InlineFragment(fragment));
                 }
+                query::Selection::FragmentSpread(_) => items.push(selection.clone()),
             }
+        }
 
-            query_str.push(')');
+        // The deferred entity fetches build their representations from the
+        // objects the initial fetches return
+        for type_name in deferred_entities {
+            let key_fields = KeyField::parse(&schema.entity_keys[&type_name])?;
+            Self::select_leaf_field(&mut items, "__typename");
+
+            if type_name == parent_type {
+                Self::select_key_fields(&mut items, &key_fields);
+            } else {
+                let mut key_items = Vec::new();
+                Self::select_key_fields(&mut key_items, &key_fields);
+                items.push(query::Selection::InlineFragment(query::InlineFragment {
+                    position: selection_set.span.0,
+                    type_condition: Some(TypeCondition::On(type_name)),
+                    directives: Vec::new(),
+                    selection_set: SelectionSet {
+                        span: selection_set.span,
+                        items: key_items,
+                    },
+                }));
+            }
         }
 
-        query_str.push_str(" {\n  ");
-        query_str.push_str(&field.name);
+        Ok(SelectionSet {
+            span: selection_set.span,
+            items,
+        })
+    }
 
-        if !field.arguments.is_empty() {
-            query_str.push('(');
+    // The fetches resolving a deferred fragment, or `None` when it has to stay
+    // in the initial response: its objects can't be fetched again as entities,
+    // or it holds nested fragments or nothing to fetch.
+    fn plan_deferred<'a>(
+        selection_set: &SelectionSet<'a, String>,
+        type_name: &str,
+        path: &[String],
+        schema: &FederatedSchema,
+        var_defs: &[VariableDefinition<'a, String>],
+        variables: &Option<Value>,
+        minify: bool,
+    ) -> Result<Option<PlanNode>, String> {
+        if selection_set
+            .items
+            .iter()
+            .any(|item| !matches!(item, query::Selection::Field(_)))
+        {
+            return Ok(None);
+        }
 
-            let mut first = true;
-            for (name, value) in &field.arguments {
-                if !first {
-                    query_str.push_str(", ");
-                }
-                first = false;
+        let nodes = if path.is_empty() {
+            Self::plan_root_fields(selection_set, "Query", schema, var_defs, variables, minify)?
+        } else if schema.entity_keys.contains_key(type_name) {
+            // No service is local to a deferred fragment, so every field turns
+            // into an entity fetch against the service resolving it
+            let mut dependents = Vec::new();
+            Self::split_selection_set(
+                selection_set,
+                type_name,
+                "",
+                path,
+                &[],
+                schema,
+                var_defs,
+                variables,
+                minify,
+                &mut dependents,
+            )?;
+            dependents
+        } else {
+            return Ok(None);
+        };
 
-                query_str.push_str(name);
-                query_str.push_str(": ");
-                Self::append_value(&mut query_str, value);
-            }
+        Ok((!nodes.is_empty()).then(|| PlanNode::parallel(nodes)))
+    }
 
-            query_str.push(')');
-        }
+    // The label and initial count of an `@defer` or `@stream` directive named
+    // `name`, unless it's missing or disabled through its `if` argument
+    fn incremental_directive(
+        directives: &[query::Directive<String>],
+        name: &str,
+        var_defs: &[VariableDefinition<String>],
+        variables: &Option<Value>,
+    ) -> Result<Option<(Option<String>, usize)>, String> {
+        let Some(directive) = directives.iter().find(|d| d.name == name) else {
+            return Ok(None);
+        };
+        let argument = |argument_name: &str| {
+            directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == argument_name)
+                .and_then(|(_, value)| Self::argument_value(value, var_defs, variables))
+        };
 
-        if !field.selection_set.items.is_empty() {
-            query_str.push_str(" {\n");
-            Self::append_selection_set(&mut query_str, &field.selection_set, 4);
-            query_str.push_str("  }\n");
+        match argument("if") {
+            None | Some(Value::Bool(true)) => {}
+            Some(Value::Bool(false)) => return Ok(None),
+            Some(_) => {
+                return Err(format!(
+                    "Directive @{} expects a Boolean `if` argument",
+                    name
+                ));
+            }
         }
 
-        query_str.push_str("}\n");
-        query_str
+        let label = match argument("label") {
+            None => None,
+            Some(Value::String(label)) => Some(label),
+            Some(_) => {
+                return Err(format!(
+                    "Directive @{} expects a String `label` argument",
+                    name
+                ));
+            }
+        };
+
+        let initial_count = match argument("initialCount") {
+            None => 0,
+            Some(count) => count.as_u64().ok_or_else(|| {
+                format!(
+                    "Directive @{} expects a non-negative `initialCount` argument",
+                    name
+                )
+            })? as usize,
+        };
+
+        Ok(Some((label, initial_count)))
+    }
+
+    // A directive argument as JSON, taking variables from the request or their
+    // default value. Null and unset variables give `None`.
+    fn argument_value(
+        value: &query::Value<String>,
+        var_defs: &[VariableDefinition<String>],
+        variables: &Option<Value>,
+    ) -> Option<Value> {
+        match value {
+            query::Value::Variable(var_name) => variables
+                .as_ref()
+                .and_then(|vars| vars.get(var_name))
+                .filter(|value| !value.is_null())
+                .cloned()
+                .or_else(|| {
+                    var_defs
+                        .iter()
+                        .find(|def| &def.name == var_name)
+                        .and_then(|def| def.default_value.as_ref())
+                        .and_then(|default| Self::argument_value(default, &[], &None))
+                }),
+            query::Value::Boolean(value) => Some(json!(value)),
+            query::Value::Int(value) => value.as_i64().map(|value| json!(value)),
+            query::Value::String(value) => Some(json!(value)),
+            _ => None,
+        }
     }
 
     fn append_value(out: &mut String, value: &query::Value<String>) {
@@ -173,19 +2150,9 @@ impl SimpleQueryPlanner {
                 out.push('$');
                 out.push_str(var_name);
             }
-            query::Value::String(s) => {
-                out.push('"');
-
-                for c in s.chars() {
-                    if c == '"' {
-                        out.push('\\');
-                    }
-                    out.push(c);
-                }
-                out.push('"');
-            }
+            query::Value::String(s) => literal::write_string(out, s),
             query::Value::Int(i) => {
-                write!(out, "{:?}", i).unwrap();
+                write!(out, "{}", i.as_i64().unwrap_or_default()).unwrap();
             }
             query::Value::Float(f) => {
                 write!(out, "{}", f).unwrap();
@@ -228,17 +2195,54 @@ impl SimpleQueryPlanner {
         }
     }
 
+    // Skips the directives the gateway resolves, only forwarded ones are left
+    // in the selection by the time subqueries are built
+    fn append_directives(query_str: &mut String, directives: &[query::Directive<String>]) {
+        for directive in directives {
+            if GATEWAY_DIRECTIVES.contains(&directive.name.as_str()) {
+                continue;
+            }
+            query_str.push_str(" @");
+            query_str.push_str(&directive.name);
+
+            if !directive.arguments.is_empty() {
+                query_str.push('(');
+                for (i, (name, value)) in directive.arguments.iter().enumerate() {
+                    if i > 0 {
+                        query_str.push_str(", ");
+                    }
+                    query_str.push_str(name);
+                    query_str.push_str(": ");
+                    Self::append_value(query_str, value);
+                }
+                query_str.push(')');
+            }
+        }
+    }
+
+    // Writes one selection per line at `indent`, or all of them on the
+    // current line, each followed by a space, when there's no indent
     fn append_selection_set(
         query_str: &mut String,
         selection_set: &SelectionSet<String>,
-        indent: usize,
+        indent: Option<usize>,
     ) {
-        let indent_str = " ".repeat(indent);
+        let indent_str = " ".repeat(indent.unwrap_or_default());
+        let (open, end) = if indent.is_some() {
+            ("{\n", "\n")
+        } else {
+            ("{ ", " ")
+        };
+        let nested = indent.map(|indent| indent + 2);
 
         for selection in &selection_set.items {
             match selection {
                 query::Selection::Field(field) => {
                     query_str.push_str(&indent_str);
+                    if let Some(alias) = &field.alias {
+                        query_str.push_str(alias);
+                        query_str.push_str(": ");
+                    }
                     query_str.push_str(&field.name);
 
                     if !field.arguments.is_empty() {
@@ -256,36 +2260,44 @@ impl SimpleQueryPlanner {
                         }
                         query_str.push(')');
                     }
+                    Self::append_directives(query_str, &field.directives);
 
                     if !field.selection_set.items.is_empty() {
-                        query_str.push_str(" {\n");
-                        Self::append_selection_set(query_str, &field.selection_set, indent + 2);
+                        query_str.push(' ');
+                        query_str.push_str(open);
+                        Self::append_selection_set(query_str, &field.selection_set, nested);
                         query_str.push_str(&indent_str);
-                        query_str.push_str("}\n");
-                    } else {
-                        query_str.push('\n');
+                        query_str.push('}');
                     }
+                    query_str.push_str(end);
                 }
                 query::Selection::FragmentSpread(fragment) => {
                     query_str.push_str(&indent_str);
                     query_str.push_str("...");
                     query_str.push_str(&fragment.fragment_name);
-                    query_str.push('\n');
+                    query_str.push_str(end);
                 }
                 query::Selection::InlineFragment(fragment) => {
                     query_str.push_str(&indent_str);
                     query_str.push_str("... ");
 
-                    if let Some(type_condition) = &fragment.type_condition {
+                    if let Some(TypeCondition::On(type_name)) = &fragment.type_condition {
                         query_str.push_str("on ");
-                        query_str.push_str(&type_condition.to_string());
+                        query_str.push_str(type_name);
+                        query_str.push(' ');
+                    }
+                    let mut directives = String::new();
+                    Self::append_directives(&mut directives, &fragment.directives);
+                    if !directives.is_empty() {
+                        query_str.push_str(directives.trim_start());
                         query_str.push(' ');
                     }
 
-                    query_str.push_str("{\n");
-                    Self::append_selection_set(query_str, &fragment.selection_set, indent + 2);
+                    query_str.push_str(open);
+                    Self::append_selection_set(query_str, &fragment.selection_set, nested);
                     query_str.push_str(&indent_str);
-                    query_str.push_str("}\n");
+                    query_str.push('}');
+                    query_str.push_str(end);
                 }
             }
         }
@@ -294,126 +2306,145 @@ impl SimpleQueryPlanner {
 
 #[async_trait]
 impl QueryPlanner for SimpleQueryPlanner {
+    async fn plan_in_context(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> Result<QueryPlan, String> {
+        let routes = self.routes(query, schema, operation_name, context);
+        if routes.is_empty() {
+            return self
+                .plan_query(query, schema, variables, operation_name)
+                .await;
+        }
+        let routed = Self::routed_schema(schema, &routes);
+        self.plan_query(query, &routed, variables, operation_name)
+            .await
+    }
+
+    fn routing_key(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        operation_name: Option<&str>,
+        context: &RequestContext,
+    ) -> String {
+        self.routes(query, schema, operation_name, context)
+            .iter()
+            .map(|(field_key, service)| format!("{}={}", field_key, service))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
     async fn plan_query(
         &self,
         query: &str,
         schema: &FederatedSchema,
         variables: Option<Value>,
+        operation_name: Option<&str>,
     ) -> Result<QueryPlan, String> {
         let doc = match graphql_parser::query::parse_query::<String>(query) {
             Ok(doc) => doc,
             Err(e) => return Err(format!("Failed to parse query: {}", e)),
         };
 
-        let mut service_queries = HashMap::with_capacity(4);
-        let mut service_variables = HashMap::with_capacity(4);
-
-        for def in &doc.definitions {
-            match def {
-                Definition::Operation(OperationDefinition::SelectionSet(selection_set)) => {
-                    for field in Self::extract_fields(selection_set) {
-                        let service_name =
-                            Self::find_service_for_field(&field.name, "Query", schema)?;
-                        let field_variables = Self::find_variables_in_field(field);
-
-                        let field_query =
-                            Self::create_field_query(field, "Query", &[], &field_variables);
-                        service_queries.insert(service_name.clone(), field_query);
-
-                        if let Some(var_values) = &variables {
-                            if field_variables.is_empty() {
-                                service_variables.insert(service_name, json!({}));
-                            } else if let Value::Object(obj) = var_values {
-                                let mut field_vars =
-                                    serde_json::Map::with_capacity(field_variables.len());
-
-                                for var_name in &field_variables {
-                                    if let Some(var_value) = obj.get(var_name) {
-                                        field_vars.insert(var_name.clone(), var_value.clone());
-                                    }
-                                }
+        let fragments: HashMap<&str, &FragmentDefinition<String>> = doc
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
 
-                                service_variables.insert(service_name, Value::Object(field_vars));
-                            } else {
-                                service_variables.insert(service_name, json!({}));
-                            }
-                        } else {
-                            service_variables.insert(service_name, json!({}));
-                        }
-                    }
+        let (operation_type, selection_set, var_defs) =
+            match Self::select_operation(&doc, operation_name)? {
+                OperationDefinition::SelectionSet(selection_set) => {
+                    ("Query", selection_set, &[][..])
                 }
+                OperationDefinition::Query(q) => {
+                    ("Query", &q.selection_set, &q.variable_definitions[..])
+                }
+                OperationDefinition::Mutation(m) => {
+                    ("Mutation", &m.selection_set, &m.variable_definitions[..])
+                }
+                OperationDefinition::Subscription(s) => (
+                    "Subscription",
+                    &s.selection_set,
+                    &s.variable_definitions[..],
+                ),
+            };
 
-                Definition::Operation(op) => {
-                    let (operation_type, selection_set, var_defs) = match op {
-                        OperationDefinition::Query(q) => {
-                            ("Query", &q.selection_set, &q.variable_definitions[..])
-                        }
-                        OperationDefinition::Mutation(m) => {
-                            ("Mutation", &m.selection_set, &m.variable_definitions[..])
-                        }
-                        OperationDefinition::Subscription(s) => (
-                            "Subscription",
-                            &s.selection_set,
-                            &s.variable_definitions[..],
-                        ),
-                        _ => continue,
-                    };
-
-                    for field in Self::extract_fields(selection_set) {
-                        let service_name =
-                            Self::find_service_for_field(&field.name, operation_type, schema)?;
-                        let field_variables = Self::find_variables_in_field(field);
-
-                        let field_query = Self::create_field_query(
-                            field,
-                            operation_type,
-                            var_defs,
-                            &field_variables,
-                        );
-                        service_queries.insert(service_name.clone(), field_query);
+        let selection_set = Self::inline_fragments(selection_set, &fragments, &mut Vec::new())?;
+        Self::check_variables_defined(&selection_set, var_defs)?;
+        let variables = Self::coerce_variables(var_defs, variables)?;
+        let mut response_shape = Self::response_shape(&selection_set, &[], var_defs, &variables)?;
+        let selection_set =
+            Self::apply_conditional_directives(&selection_set, var_defs, &variables)?;
+        let selection_set = Self::retain_directives(&selection_set, &self.forwarded_directives);
+        let selection_set = Self::merge_selections(&selection_set);
+        let minify = self.minify_queries;
 
-                        if let Some(var_values) = &variables {
-                            if field_variables.is_empty() {
-                                service_variables.insert(service_name, json!({}));
-                                continue;
-                            }
+        // Deferred fragments and streamed fields only apply to queries, for
+        // other operations they're resolved along with everything else
+        let mut deferred = Vec::new();
+        let mut streams = Vec::new();
+        let selection_set = if operation_type == "Query" {
+            Self::split_deferred(
+                &selection_set,
+                operation_type,
+                &[],
+                schema,
+                var_defs,
+                &variables,
+                minify,
+                &mut deferred,
+                &mut streams,
+            )?
+        } else {
+            selection_set
+        };
 
-                            if let Value::Object(obj) = var_values {
-                                let mut field_vars =
-                                    serde_json::Map::with_capacity(field_variables.len());
+        // Only queries, repeating a mutation field repeats its side effects
+        let selection_set = if operation_type == "Query" {
+            Self::dedup_root_fields(&selection_set, &mut response_shape)
+        } else {
+            selection_set
+        };
 
-                                for var_name in &field_variables {
-                                    if let Some(var_value) = obj.get(var_name) {
-                                        field_vars.insert(var_name.clone(), var_value.clone());
-                                    }
-                                }
+        let root_nodes = Self::plan_root_fields(
+            &selection_set,
+            operation_type,
+            schema,
+            var_defs,
+            &variables,
+            minify,
+        )?;
 
-                                service_variables.insert(service_name, Value::Object(field_vars));
-                            } else {
-                                service_variables.insert(service_name, json!({}));
-                            }
-                        } else {
-                            service_variables.insert(service_name, json!({}));
-                        }
-                    }
-                }
-                _ => continue,
-            }
-        }
+        // Top-level mutation fields must run one after the other in document
+        // order, everything else is independent
+        let node = if operation_type == "Mutation" {
+            PlanNode::sequence(root_nodes)
+        } else {
+            PlanNode::parallel(root_nodes)
+        };
 
-        if service_queries.is_empty() {
-            return Err("No valid operations found in query".to_string());
-        }
+        let plan = QueryPlan {
+            node,
+            root_type: operation_type.to_string(),
+            response_shape,
+            deferred,
+            streams,
+        };
 
         #[cfg(debug_assertions)]
         {
-            println!("Generated service queries: {:?}", service_queries);
-            println!("Variable distribution: {:?}", service_variables);
+            debug!("Generated query plan: {:#?}", plan);
         }
 
-        Ok(QueryPlan {
-            service_queries,
-            service_variables,
-        })
+        Ok(plan)
     }
 }
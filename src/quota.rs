@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Cost every client may spend per window, clients being told by their
+// `x-api-key` or, without one, their client name. Operations cost their
+// complexity estimate, or 1 when complexity estimation is disabled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub enabled: bool,
+    pub limit: u64,
+    pub window_seconds: u64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            enabled: false,
+            limit: 10000,
+            window_seconds: 60,
+        }
+    }
+}
+
+struct Usage {
+    window_start: Instant,
+    spent: u64,
+}
+
+// Fixed windows of spent cost per client
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: &QuotaConfig) -> Self {
+        QuotaTracker {
+            config: config.clone(),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Charges `cost` to the client, returning what is left of its quota.
+    // Operations the remaining quota can't cover are refused and not charged.
+    // `limit` replaces the configured limit for this client.
+    pub fn charge(&self, client: &str, cost: u64, limit: Option<u64>) -> Result<u64, String> {
+        let limit = limit.unwrap_or(self.config.limit);
+        let mut usage = self.usage.lock().unwrap();
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_seconds);
+        let usage = usage.entry(client.to_string()).or_insert(Usage {
+            window_start: now,
+            spent: 0,
+        });
+        if now.duration_since(usage.window_start) >= window {
+            usage.window_start = now;
+            usage.spent = 0;
+        }
+
+        let remaining = limit.saturating_sub(usage.spent);
+        if cost > remaining {
+            return Err(format!(
+                "Quota of {} per {} seconds exceeded",
+                limit, self.config.window_seconds
+            ));
+        }
+        usage.spent += cost;
+        Ok(remaining - cost)
+    }
+
+    // What the client has left in the current window
+    pub fn remaining(&self, client: &str, limit: Option<u64>) -> u64 {
+        let limit = limit.unwrap_or(self.config.limit);
+        let usage = self.usage.lock().unwrap();
+        match usage.get(client) {
+            Some(usage)
+                if usage.window_start.elapsed()
+                    < Duration::from_secs(self.config.window_seconds) =>
+            {
+                limit.saturating_sub(usage.spent)
+            }
+            _ => limit,
+        }
+    }
+}
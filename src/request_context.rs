@@ -0,0 +1,78 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::upload::Uploads;
+
+// Time a request spent waiting on one service, summed over its fetches
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubgraphTiming {
+    pub requests: u64,
+    pub duration_ms: u64,
+}
+
+// State of a single request, set up by the gateway before planning, filled
+// in by the executor and turned into response extensions once the response
+// is assembled
+#[derive(Debug, Default)]
+pub struct RequestContext {
+    // Skips cache lookups, the caches are still refreshed
+    pub bypass_cache: bool,
+    // `extensions.cost`, the estimated and maximum cost of the operation
+    pub cost: Option<Value>,
+    // `extensions.remainingQuota`, what the client has left to spend in the
+    // current quota window
+    pub remaining_quota: Option<u64>,
+    // Reports `extensions.timing` with the time spent in every service
+    pub report_timing: bool,
+    // Who the request is made for: its API key, or else the client's name
+    pub tenant: Option<String>,
+    // Tier of the request's API key
+    pub tier: Option<String>,
+    // Region hint of the request, or the gateway's default region
+    pub region: Option<String>,
+    // Experiments the request takes part in, from `extensions.experiments`
+    pub experiments: Vec<String>,
+    // Plan cache hash of the operation, which cache invalidations match
+    // query hash prefixes against
+    pub query_hash: Option<String>,
+    // Files of a multipart request, sent on with the fetches using them
+    pub uploads: Uploads,
+    timing: Mutex<BTreeMap<String, SubgraphTiming>>,
+}
+
+impl RequestContext {
+    pub fn new(bypass_cache: bool) -> Self {
+        RequestContext {
+            bypass_cache,
+            ..Default::default()
+        }
+    }
+
+    pub fn record_fetch(&self, service: &str, duration: Duration) {
+        let mut timing = self.timing.lock().unwrap();
+        let entry = timing.entry(service.to_string()).or_default();
+        entry.requests += 1;
+        entry.duration_ms += duration.as_millis() as u64;
+    }
+
+    pub fn timing(&self) -> BTreeMap<String, SubgraphTiming> {
+        self.timing.lock().unwrap().clone()
+    }
+
+    // Adds the context's extensions to the response
+    pub fn extend_response(&self, response: &mut Value) {
+        if let Some(cost) = &self.cost {
+            response["extensions"]["cost"] = cost.clone();
+        }
+        if let Some(remaining_quota) = self.remaining_quota {
+            response["extensions"]["remainingQuota"] = json!(remaining_quota);
+        }
+        if self.report_timing {
+            response["extensions"]["timing"] = json!(self.timing());
+        }
+    }
+}
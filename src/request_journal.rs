@@ -0,0 +1,198 @@
+use chrono::{DateTime, Utc};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::GraphQLRequest;
+use crate::request_mirror::variables_shape;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalConfig {
+    // Most recent requests kept in memory for replay
+    #[serde(default = "JournalConfig::default_capacity")]
+    pub capacity: usize,
+    // Requests older than this are dropped from memory
+    #[serde(default = "JournalConfig::default_window_seconds")]
+    pub window_seconds: u64,
+    // Also appends every entry to this file, one JSON object per line
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    // Request headers journaled and sent along on replay, e.g. `x-tenant`.
+    // Nothing else is kept, so credentials are only journaled when listed.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    // Service name -> staging URL replays are sent to. Requests touching a
+    // service without one aren't replayed.
+    #[serde(default)]
+    pub staging: HashMap<String, String>,
+}
+
+impl JournalConfig {
+    fn default_capacity() -> usize {
+        1000
+    }
+
+    fn default_window_seconds() -> u64 {
+        3600
+    }
+}
+
+// A request as journaled: variable values are reduced to their shape
+#[derive(Clone, Debug, Serialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub recorded_at: DateTime<Utc>,
+    pub query: String,
+    pub operation_name: Option<String>,
+    pub variables_shape: Value,
+    pub headers: BTreeMap<String, String>,
+}
+
+// The body of a replay, variables stand in for the values the journal left out
+#[derive(Debug, Deserialize)]
+pub struct ReplayRequest {
+    pub id: u64,
+    #[serde(default)]
+    pub variables: Option<Value>,
+}
+
+struct Entries {
+    next_id: u64,
+    entries: VecDeque<JournalEntry>,
+    // Whether the last append to the file failed, so that failures are only
+    // logged once until it works again
+    file_failing: bool,
+}
+
+// Recent requests kept for reproducing incidents against staging subgraphs
+pub struct RequestJournal {
+    config: JournalConfig,
+    entries: Mutex<Entries>,
+}
+
+impl RequestJournal {
+    pub fn new(config: JournalConfig) -> Self {
+        RequestJournal {
+            config,
+            entries: Mutex::new(Entries {
+                next_id: 1,
+                entries: VecDeque::new(),
+                file_failing: false,
+            }),
+        }
+    }
+
+    pub fn staging(&self) -> &HashMap<String, String> {
+        &self.config.staging
+    }
+
+    // The allow-listed headers of a client request
+    pub fn allowed_headers(&self, headers: &HeaderMap) -> BTreeMap<String, String> {
+        self.config
+            .headers
+            .iter()
+            .filter_map(|name| {
+                let value = headers.get(name.as_str())?.to_str().ok()?;
+                Some((name.to_ascii_lowercase(), value.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn record(&self, request: &GraphQLRequest, headers: BTreeMap<String, String>) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = JournalEntry {
+            id: entries.next_id,
+            recorded_at: Utc::now(),
+            query: request.query.clone(),
+            operation_name: request.operation_name.clone(),
+            variables_shape: request
+                .variables
+                .as_ref()
+                .map(variables_shape)
+                .unwrap_or(Value::Null),
+            headers,
+        };
+        entries.next_id += 1;
+
+        if let Some(path) = &self.config.file {
+            match append_line(path, &entry) {
+                Err(e) if !entries.file_failing => {
+                    warn!("Failed to journal requests to {:?}: {}", path, e);
+                    entries.file_failing = true;
+                }
+                Ok(()) if entries.file_failing => {
+                    info!("Journaling requests to {:?} again", path);
+                    entries.file_failing = false;
+                }
+                _ => {}
+            }
+        }
+
+        let id = entry.id;
+        entries.entries.push_back(entry);
+        while entries.entries.len() > self.config.capacity {
+            entries.entries.pop_front();
+        }
+        self.expire(&mut entries);
+        id
+    }
+
+    // The journaled requests still within the window, oldest first
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries);
+        entries.entries.iter().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<JournalEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries);
+        entries.entries.iter().find(|entry| entry.id == id).cloned()
+    }
+
+    fn expire(&self, entries: &mut Entries) {
+        let window = chrono::Duration::seconds(self.config.window_seconds as i64);
+        let oldest = Utc::now() - window;
+        while entries
+            .entries
+            .front()
+            .is_some_and(|entry| entry.recorded_at < oldest)
+        {
+            entries.entries.pop_front();
+        }
+    }
+}
+
+impl JournalEntry {
+    // The request to replay, sending the journaled headers along
+    pub fn replay_request(&self, variables: Option<Value>) -> GraphQLRequest {
+        GraphQLRequest {
+            query: self.query.clone(),
+            variables,
+            operation_name: self.operation_name.clone(),
+            auth_headers: (!self.headers.is_empty())
+                .then(|| self.headers.clone().into_iter().collect()),
+            client_name: None,
+            accept_language: None,
+            no_cache: true,
+            region: None,
+            extensions: None,
+        }
+    }
+}
+
+fn append_line(path: &PathBuf, entry: &JournalEntry) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(|e| e.to_string())
+}
@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::GraphQLRequest;
+use crate::operation;
+
+// Sanitized request metadata: no variable values nor headers leave the gateway
+#[derive(Clone, Debug, Serialize)]
+pub struct MirrorRecord {
+    pub operation_hash: String,
+    // Synthetic for anonymous operations, see `operation::display_name`
+    pub operation_name: Option<String>,
+    pub variables_shape: Value,
+    pub client_name: Option<String>,
+    pub duration_ms: u64,
+    pub success: bool,
+}
+
+impl MirrorRecord {
+    pub fn from_request(request: &GraphQLRequest) -> Self {
+        MirrorRecord {
+            operation_hash: hex::encode(Sha256::digest(request.query.as_bytes())),
+            operation_name: Some(operation::display_name(request)),
+            variables_shape: request
+                .variables
+                .as_ref()
+                .map(variables_shape)
+                .unwrap_or(Value::Null),
+            client_name: request.client_name.clone(),
+            duration_ms: 0,
+            success: false,
+        }
+    }
+}
+
+// Replaces every scalar with the name of its JSON type, lists are described by
+// their first item.
+pub fn variables_shape(value: &Value) -> Value {
+    match value {
+        Value::Null => json!("null"),
+        Value::Bool(_) => json!("boolean"),
+        Value::Number(_) => json!("number"),
+        Value::String(_) => json!("string"),
+        Value::Array(items) => {
+            Value::Array(items.first().map(variables_shape).into_iter().collect())
+        }
+        Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(key, value)| (key.clone(), variables_shape(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+    }
+}
+
+#[async_trait]
+pub trait MirrorSink: Send + Sync {
+    async fn send(&self, records: Vec<MirrorRecord>) -> Result<(), String>;
+}
+
+// Posts each batch as a JSON array
+pub struct HttpMirrorSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpMirrorSink {
+    pub fn new(url: String) -> Self {
+        HttpMirrorSink {
+            client: reqwest::Client::new(),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl MirrorSink for HttpMirrorSink {
+    async fn send(&self, records: Vec<MirrorRecord>) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&records)
+            .send()
+            .await
+            .map_err(|e| format!("Mirror request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("Mirror sink returned {}", response.status()));
+        }
+
+        Ok(())
+    }
+}
+
+// Fire-and-forget mirror of request metadata. Records are queued on a bounded
+// channel and shipped in batches by a background task; when the queue is full
+// they are dropped rather than slowing down requests.
+pub struct RequestMirror {
+    sender: mpsc::Sender<MirrorRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl RequestMirror {
+    // Must be called from within the runtime
+    pub fn spawn(sink: Box<dyn MirrorSink>, queue_size: usize, batch_size: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<MirrorRecord>(queue_size.max(1));
+        let batch_size = batch_size.max(1);
+
+        tokio::spawn(async move {
+            while let Some(record) = receiver.recv().await {
+                let mut batch = Vec::with_capacity(batch_size);
+                batch.push(record);
+
+                while batch.len() < batch_size {
+                    match receiver.try_recv() {
+                        Ok(record) => batch.push(record),
+                        Err(_) => break,
+                    }
+                }
+
+                if let Err(e) = sink.send(batch).await {
+                    warn!("Failed to mirror requests: {}", e);
+                }
+            }
+        });
+
+        RequestMirror {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn record(&self, record: MirrorRecord) {
+        if self.sender.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Number of records dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
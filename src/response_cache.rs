@@ -0,0 +1,359 @@
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{
+    CacheInvalidation, FederatedSchema, QueryPlan, ResponseField, entity_cache::session_hash,
+    memory::write_metric, query_executor::QueryExecutor, request_context::RequestContext,
+};
+
+// Merged responses of queries, answered again without reaching the services
+// until they expire. A response lives as long as the shortest TTL among the
+// fields and types it holds: `@cacheControl(maxAge: ...)` hints of the
+// subgraph schemas, overridden by `ttl_seconds`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    // Number of responses kept in the LRU response cache, 0 disables it
+    pub max_entries: usize,
+    // TTL of responses holding no field or type with one, 0 leaves them
+    // uncached
+    pub default_ttl_seconds: u64,
+    // "Type" or "Type.field" -> TTL of responses holding it
+    pub ttl_seconds: HashMap<String, u64>,
+}
+
+struct CachedResponse {
+    response: Value,
+    expires_at: Instant,
+    // Types of the objects in the response, for entity invalidations
+    types: BTreeSet<String>,
+    // Plan cache hash of the operation, for query hash invalidations
+    query_hash: Option<String>,
+}
+
+struct Entries {
+    generation: u64,
+    responses: LruCache<String, CachedResponse>,
+}
+
+// Query executor decorator caching whole responses, keyed by the plan, which
+// embeds the query and variables, and the request's auth headers so a
+// response is only served to the session it was made for. Mutations,
+// incremental responses and responses with errors are never cached, and
+// bypassing the cache still refreshes it.
+pub struct CachingQueryExecutor {
+    inner: Box<dyn QueryExecutor + Send + Sync>,
+    default_ttl: Duration,
+    ttl_overrides: HashMap<String, u64>,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachingQueryExecutor {
+    pub fn new(inner: Box<dyn QueryExecutor + Send + Sync>, capacity: NonZeroUsize) -> Self {
+        CachingQueryExecutor {
+            inner,
+            default_ttl: Duration::ZERO,
+            ttl_overrides: HashMap::new(),
+            entries: Mutex::new(Entries {
+                generation: 0,
+                responses: LruCache::new(capacity),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_default_ttl(mut self, default_ttl: Duration) -> Self {
+        self.default_ttl = default_ttl;
+        self
+    }
+
+    // "Type" or "Type.field" -> seconds, taking precedence over the hints
+    pub fn with_ttl_overrides(mut self, ttl_overrides: HashMap<String, u64>) -> Self {
+        self.ttl_overrides = ttl_overrides;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    async fn execute_cached(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        if plan.root_type != "Query" || !context.uploads.is_empty() {
+            return self
+                .inner
+                .execute_in_context(plan, schema, auth_headers, context)
+                .await;
+        }
+        let mut types = BTreeSet::new();
+        let mut ttl = None;
+        self.collect_ttl(
+            &plan.response_shape,
+            &plan.root_type,
+            schema,
+            &mut ttl,
+            &mut types,
+        );
+        let ttl = ttl.map_or(self.default_ttl, Duration::from_secs);
+        if ttl.is_zero() {
+            return self
+                .inner
+                .execute_in_context(plan, schema, auth_headers, context)
+                .await;
+        }
+
+        let key = cache_key(&plan, &auth_headers)?;
+        if !context.bypass_cache {
+            if let Some(response) = self.get(&key, schema.generation) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(response);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let response = self
+            .inner
+            .execute_in_context(plan, schema, auth_headers, context)
+            .await?;
+        if response["errors"].as_array().is_none_or(Vec::is_empty) {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.generation == schema.generation {
+                entries.responses.put(
+                    key,
+                    CachedResponse {
+                        response: response.clone(),
+                        expires_at: Instant::now() + ttl,
+                        types,
+                        query_hash: context.query_hash.clone(),
+                    },
+                );
+            }
+        }
+        Ok(response)
+    }
+
+    fn get(&self, key: &str, generation: u64) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            entries.responses.clear();
+            entries.generation = generation;
+            return None;
+        }
+
+        let expired = entries
+            .responses
+            .get(key)
+            .map(|cached| cached.expires_at <= Instant::now())?;
+        if expired {
+            entries.responses.pop(key);
+            return None;
+        }
+        entries
+            .responses
+            .get(key)
+            .map(|cached| cached.response.clone())
+    }
+
+    // The shortest TTL of the selected fields and their types, if any has
+    // one, along with the object types selected
+    fn collect_ttl(
+        &self,
+        fields: &[ResponseField],
+        parent_type: &str,
+        schema: &FederatedSchema,
+        ttl: &mut Option<u64>,
+        types: &mut BTreeSet<String>,
+    ) {
+        for field in fields {
+            if field.field_name.starts_with("__") {
+                continue;
+            }
+            let parents = if field.type_conditions.is_empty() {
+                vec![parent_type.to_string()]
+            } else {
+                field.type_conditions.clone()
+            };
+            for parent in &parents {
+                let field_key = format!("{}.{}", parent, field.field_name);
+                let field_type = schema.field_types.get(&field_key);
+                let max_age = [Some(&field_key), field_type]
+                    .into_iter()
+                    .flatten()
+                    .find_map(|key| self.ttl_overrides.get(key))
+                    .or_else(|| {
+                        [Some(&field_key), field_type]
+                            .into_iter()
+                            .flatten()
+                            .find_map(|key| schema.cache_control.get(key))
+                    });
+                if let Some(&max_age) = max_age {
+                    *ttl = Some(ttl.map_or(max_age, |shortest| shortest.min(max_age)));
+                }
+
+                if let Some(field_type) = field_type
+                    && !field.selections.is_empty()
+                {
+                    types.insert(field_type.clone());
+                    self.collect_ttl(&field.selections, field_type, schema, ttl, types);
+                }
+            }
+        }
+    }
+}
+
+fn cache_key(
+    plan: &QueryPlan,
+    auth_headers: &Option<HashMap<String, String>>,
+) -> Result<String, String> {
+    let plan =
+        serde_json::to_vec(plan).map_err(|e| format!("Failed to serialize query plan: {}", e))?;
+    let mut hasher = Sha256::new();
+    hasher.update(&plan);
+    hasher.update([0]);
+    hasher.update(session_hash(auth_headers).as_bytes());
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[async_trait]
+impl QueryExecutor for CachingQueryExecutor {
+    async fn execute_plan(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<Value, String> {
+        self.execute_cached(plan, schema, auth_headers, &RequestContext::default())
+            .await
+    }
+
+    async fn execute_plan_uncached(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<Value, String> {
+        self.execute_cached(plan, schema, auth_headers, &RequestContext::new(true))
+            .await
+    }
+
+    async fn execute_in_context(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<Value, String> {
+        self.execute_cached(plan, schema, auth_headers, context)
+            .await
+    }
+
+    async fn execute_incremental(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+        context: &RequestContext,
+    ) -> Result<(Value, Option<BoxStream<'static, Value>>), String> {
+        if !plan.deferred.is_empty() || !plan.streams.is_empty() {
+            return self
+                .inner
+                .execute_incremental(plan, schema, auth_headers, context)
+                .await;
+        }
+        let response = self
+            .execute_cached(plan, schema, auth_headers, context)
+            .await?;
+        Ok((response, None))
+    }
+
+    // Cached responses holding objects of an invalidated type, or answering
+    // an operation whose query hash starts with an invalidated prefix, are
+    // dropped along with the entities
+    fn invalidate_entities(&self, invalidation: &CacheInvalidation) -> usize {
+        let invalidated: BTreeSet<&str> = invalidation
+            .types
+            .iter()
+            .chain(invalidation.keys.iter().map(|entity| &entity.type_name))
+            .map(String::as_str)
+            .collect();
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<String> = entries
+            .responses
+            .iter()
+            .filter(|(_, cached)| {
+                cached
+                    .types
+                    .iter()
+                    .any(|type_name| invalidated.contains(type_name.as_str()))
+                    || cached.query_hash.as_ref().is_some_and(|query_hash| {
+                        invalidation
+                            .query_hash_prefixes
+                            .iter()
+                            .any(|prefix| query_hash.starts_with(prefix.as_str()))
+                    })
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &keys {
+            entries.responses.pop(key);
+        }
+        drop(entries);
+        self.inner.invalidate_entities(invalidation)
+    }
+
+    fn metrics(&self) -> String {
+        let mut metrics = self.inner.metrics();
+        write_metric(
+            &mut metrics,
+            "portkey_response_cache_hits_total",
+            "counter",
+            "Queries answered from the response cache.",
+            self.hits.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut metrics,
+            "portkey_response_cache_misses_total",
+            "counter",
+            "Cacheable queries the response cache had no response for.",
+            self.misses.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut metrics,
+            "portkey_response_cache_entries",
+            "gauge",
+            "Responses held by the response cache.",
+            self.len() as u64,
+        );
+        metrics
+    }
+
+    async fn subscribe(
+        &self,
+        plan: QueryPlan,
+        schema: &FederatedSchema,
+        auth_headers: Option<HashMap<String, String>>,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        self.inner.subscribe(plan, schema, auth_headers).await
+    }
+}
@@ -1,113 +1,819 @@
 use async_trait::async_trait;
+use futures::future::try_join_all;
 use graphql_parser::parse_schema;
-use std::collections::HashMap;
+use graphql_parser::schema::{Directive, Document, Type, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
 
-use crate::{FederatedSchema, ServiceConfig, ServiceMap};
+use crate::connections::ConnectionConfig;
+use crate::intern::{Interner, Name};
+use crate::introspection::IntrospectionSchema;
+use crate::schema_trim::SchemaTrimConfig;
+use crate::{
+    CompositionMetrics, FederatedSchema, JoinArgument, JoinConfig, JoinField, ServiceConfig,
+    ServiceMap,
+};
 
 #[async_trait]
 pub trait SchemaRegistry {
     async fn register_service(&mut self, service: ServiceConfig) -> Result<(), String>;
+    async fn unregister_service(&mut self, name: &str) -> Result<(), String>;
     async fn get_schema(&self) -> Result<FederatedSchema, String>;
+
+    // Why the latest registrations failed to compose, while an older schema
+    // keeps being served. Registries that can't fail have nothing to report.
+    async fn composition_error(&self) -> Option<String> {
+        None
+    }
+}
+
+const ROOT_TYPES: [&str; 3] = ["Query", "Mutation", "Subscription"];
+
+// How a root field several services declare is routed. Services are ordered
+// by the explicit owner, then priority, then name, and the first one resolves
+// the field unless the operation fans out across them for an abstract type.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceSelectionConfig {
+    // "Query.search" -> service resolving the root field. Takes precedence
+    // over `@owner` directives.
+    pub owners: HashMap<String, String>,
+    // SDL only read for `@owner(service: "name")` on root fields, for owners
+    // the subgraphs don't declare themselves, e.g.
+    // `extend type Query { search: [Result] @owner(service: "search") }`.
+    // Takes precedence over the subgraphs' own directives.
+    pub overlay: Option<String>,
+    // Rejects operations selecting a root field that has neither an owner
+    // nor a single service with the highest priority
+    pub reject_ambiguous: bool,
+}
+
+// What a single subgraph contributes to the federated schema
+struct SubgraphIndex {
+    service_name: String,
+    type_to_service_map: HashMap<String, Vec<String>>,
+    field_types: HashMap<String, String>,
+    entity_keys: HashMap<String, String>,
+    requires: HashMap<String, String>,
+    provides: HashMap<String, HashMap<String, String>>,
+    // "Type.field" -> service the field is taken over from
+    overrides: HashMap<String, String>,
+    // "Query.field" -> service named by `@owner(service: ...)`
+    owners: HashMap<String, String>,
+    possible_types: HashMap<String, Vec<String>>,
+    joins: HashMap<String, JoinField>,
+    cache_control: HashMap<String, u64>,
+    document: Document<'static, String>,
+}
+
+// The composed schema, recomposed on the first `get_schema` after a
+// registration. A registration that fails to compose leaves the last
+// known-good schema in place, as a warm standby, until one composes again.
+struct Composition {
+    schema: Option<FederatedSchema>,
+    stale: bool,
+    error: Option<String>,
 }
 
 pub struct InMemorySchemaRegistry {
     services: Arc<RwLock<ServiceMap>>,
-    federated_schema: Arc<RwLock<Option<FederatedSchema>>>,
+    composition: Arc<RwLock<Composition>>,
+    generation: u64,
+    service_selection: ServiceSelectionConfig,
+    connections: ConnectionConfig,
+    schema_trim: Arc<SchemaTrimConfig>,
 }
 
 impl InMemorySchemaRegistry {
     pub fn new() -> Self {
         InMemorySchemaRegistry {
             services: Arc::new(RwLock::new(HashMap::new())),
-            federated_schema: Arc::new(RwLock::new(None)),
+            composition: Arc::new(RwLock::new(Composition {
+                schema: None,
+                stale: true,
+                error: None,
+            })),
+            generation: 0,
+            service_selection: ServiceSelectionConfig::default(),
+            connections: ConnectionConfig::default(),
+            schema_trim: Arc::new(SchemaTrimConfig::default()),
         }
     }
 
+    pub fn with_service_selection(mut self, service_selection: ServiceSelectionConfig) -> Self {
+        self.service_selection = service_selection;
+        self
+    }
+
+    pub fn with_schema_trim(mut self, schema_trim: SchemaTrimConfig) -> Self {
+        self.schema_trim = Arc::new(schema_trim);
+        self
+    }
+
+    pub fn with_connections(mut self, connections: ConnectionConfig) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    // Parses and indexes every subgraph on the blocking pool, all at once, then
+    // merges the indexes in service name order so the result doesn't depend on
+    // which subgraph finished first.
     async fn build_federated_schema(
         &self,
         services: &ServiceMap,
     ) -> Result<FederatedSchema, String> {
+        let started = Instant::now();
+
+        let mut subgraphs = try_join_all(services.values().map(|service| {
+            let service_name = service.name.clone();
+            let sdl = service.schema.clone();
+            let namespace = service.namespace.clone();
+            let joins = service.joins.clone();
+            let schema_trim = self.schema_trim.clone();
+            tokio::task::spawn_blocking(move || {
+                Self::index_subgraph(
+                    &service_name,
+                    &sdl,
+                    namespace.as_deref(),
+                    &joins,
+                    &schema_trim,
+                )
+            })
+        }))
+        .await
+        .map_err(|e| format!("Failed to compose schema: {}", e))?
+        .into_iter()
+        .collect::<Result<Vec<_>, String>>()?;
+        let indexed = started.elapsed();
+
+        subgraphs.sort_by(|a, b| a.service_name.cmp(&b.service_name));
+
+        // Every key and service name of the map is allocated once, however
+        // many services and fields share it
+        let mut interner = Interner::default();
+        let mut type_to_service_map: HashMap<Name, Vec<Name>> = HashMap::new();
+        let mut field_types = HashMap::new();
+        let mut entity_keys = HashMap::new();
+        let mut requires = HashMap::new();
+        let mut provides: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut possible_types: HashMap<String, Vec<String>> = HashMap::new();
+        let mut service_possible_types: HashMap<String, HashMap<String, Vec<String>>> =
+            HashMap::new();
+        let mut overrides = Vec::new();
+        let mut owners: HashMap<String, String> = HashMap::new();
+        let mut joins = HashMap::new();
+        let mut cache_control: HashMap<String, u64> = HashMap::new();
+        let mut documents = Vec::with_capacity(subgraphs.len());
+
+        for subgraph in subgraphs {
+            for (key, subgraph_services) in subgraph.type_to_service_map {
+                type_to_service_map
+                    .entry(interner.intern(&key))
+                    .or_default()
+                    .extend(subgraph_services.iter().map(|name| interner.intern(name)));
+            }
+            for (key, field_type) in subgraph.field_types {
+                field_types.entry(key).or_insert(field_type);
+            }
+            for (type_name, fields) in subgraph.entity_keys {
+                entity_keys.entry(type_name).or_insert(fields);
+            }
+            for (field_key, fields) in subgraph.requires {
+                requires.entry(field_key).or_insert(fields);
+            }
+            for (field_key, by_service) in subgraph.provides {
+                provides.entry(field_key).or_default().extend(by_service);
+            }
+            for (field_key, join) in subgraph.joins {
+                joins.entry(field_key).or_insert(join);
+            }
+            for (key, max_age) in subgraph.cache_control {
+                cache_control
+                    .entry(key)
+                    .and_modify(|shortest| *shortest = (*shortest).min(max_age))
+                    .or_insert(max_age);
+            }
+            for (field_key, owner) in subgraph.owners {
+                match owners.get(&field_key) {
+                    Some(existing) if *existing != owner => {
+                        return Err(format!(
+                            "Field {} has conflicting @owner directives: {} and {}",
+                            field_key, existing, owner
+                        ));
+                    }
+                    _ => {
+                        owners.insert(field_key, owner);
+                    }
+                }
+            }
+            for (field_key, from) in subgraph.overrides {
+                overrides.push((field_key, subgraph.service_name.clone(), from));
+            }
+            for (abstract_type, members) in subgraph.possible_types {
+                let types = possible_types.entry(abstract_type.clone()).or_default();
+                for member in &members {
+                    if !types.contains(member) {
+                        types.push(member.clone());
+                    }
+                }
+                service_possible_types
+                    .entry(abstract_type)
+                    .or_default()
+                    .insert(subgraph.service_name.clone(), members);
+            }
+            documents.push(subgraph.document);
+        }
+
+        Self::apply_overrides(&mut type_to_service_map, &overrides);
+        if let Some(overlay) = &self.service_selection.overlay {
+            owners.extend(Self::overlay_owners(overlay)?);
+        }
+        owners.extend(self.service_selection.owners.clone());
+        let ambiguous_root_fields =
+            self.order_root_field_owners(&mut type_to_service_map, services, &owners);
+
+        self.connections.add_connection_types(&mut documents);
+        let introspection = Arc::new(IntrospectionSchema::compose(&documents));
+        let composition = CompositionMetrics {
+            subgraphs: documents.len(),
+            indexing: indexed,
+            total: started.elapsed(),
+        };
+
+        debug!("Type to service map: {:?}", type_to_service_map);
+        info!(
+            "Composed {} subgraphs in {:?} ({:?} parsing and indexing)",
+            composition.subgraphs, composition.total, composition.indexing
+        );
+        Ok(FederatedSchema {
+            services: services.clone(),
+            type_to_service_map,
+            field_types,
+            entity_keys,
+            requires,
+            provides,
+            possible_types,
+            service_possible_types,
+            ambiguous_root_fields,
+            joins,
+            cache_control,
+            introspection,
+            composition,
+            generation: self.generation,
+        })
+    }
+
+    // Orders the services of every shared root field by the selection policy,
+    // returning the fields with nothing to choose between their services when
+    // ambiguity is rejected
+    fn order_root_field_owners(
+        &self,
+        type_to_service_map: &mut HashMap<Name, Vec<Name>>,
+        services: &ServiceMap,
+        explicit_owners: &HashMap<String, String>,
+    ) -> HashSet<String> {
+        let mut ambiguous = HashSet::new();
+
+        for (field_key, owners) in type_to_service_map.iter_mut() {
+            let is_root_field = field_key.split_once('.').is_some_and(|(type_name, field)| {
+                ROOT_TYPES.contains(&type_name) && !field.contains('.')
+            });
+            if !is_root_field || owners.len() < 2 {
+                continue;
+            }
+
+            let owner = explicit_owners.get(field_key.as_str());
+            let is_owner = |name: &Name| owner.is_some_and(|owner| name == owner);
+            let priority = |name: &Name| services.get(name.as_str()).map_or(0, |s| s.priority);
+            owners.sort_by(|a, b| {
+                is_owner(b)
+                    .cmp(&is_owner(a))
+                    .then_with(|| priority(b).cmp(&priority(a)))
+                    .then_with(|| a.cmp(b))
+            });
+
+            let first = &owners[0];
+            if self.service_selection.reject_ambiguous
+                && owner.is_none()
+                && owners
+                    .iter()
+                    .any(|other| other != first && priority(other) == priority(first))
+            {
+                ambiguous.insert(field_key.to_string());
+            }
+        }
+
+        ambiguous
+    }
+
+    // Routes fields marked `@override(from: "old")` away from the service they
+    // are migrated from, which may keep declaring them until the migration is
+    // done. Overrides from a service that doesn't declare the field itself,
+    // or that is overridden in turn, are ignored.
+    fn apply_overrides(
+        type_to_service_map: &mut HashMap<Name, Vec<Name>>,
+        overrides: &[(String, String, String)],
+    ) {
+        for (field_key, service_name, from) in overrides {
+            let is_overridden = overrides
+                .iter()
+                .any(|(key, _, other)| key == field_key && other == service_name);
+            if is_overridden || service_name == from {
+                continue;
+            }
+
+            let argument_prefix = format!("{}.", field_key);
+            for (key, owners) in type_to_service_map.iter_mut() {
+                if key == field_key || key.starts_with(&argument_prefix) {
+                    owners.retain(|owner| owner != from);
+                }
+            }
+        }
+    }
+
+    // The `@owner` directives of the gateway-side overlay SDL
+    fn overlay_owners(overlay: &str) -> Result<HashMap<String, String>, String> {
+        use graphql_parser::schema::{Definition, TypeDefinition, TypeExtension};
+
+        let document = parse_schema::<String>(overlay)
+            .map_err(|e| format!("Failed to parse the owner overlay: {}", e))?;
+        let mut owners = HashMap::new();
+        for definition in &document.definitions {
+            let (type_name, fields) = match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(obj)) => (&obj.name, &obj.fields),
+                Definition::TypeExtension(TypeExtension::Object(ext)) => (&ext.name, &ext.fields),
+                _ => continue,
+            };
+            for field in fields {
+                if let Some(owner) = Self::owner_directive(&field.directives) {
+                    owners.insert(format!("{}.{}", type_name, field.name), owner);
+                }
+            }
+        }
+        Ok(owners)
+    }
+
+    fn owner_directive(directives: &[Directive<String>]) -> Option<String> {
+        directives
+            .iter()
+            .filter(|directive| directive.name == "owner")
+            .flat_map(|directive| &directive.arguments)
+            .find_map(|(name, value)| match (name.as_str(), value) {
+                ("service", Value::String(service)) => Some(service.clone()),
+                _ => None,
+            })
+    }
+
+    fn served_schema(composition: &Composition) -> Result<FederatedSchema, String> {
+        match (&composition.schema, &composition.error) {
+            (Some(schema), _) => Ok(schema.clone()),
+            (None, Some(error)) => Err(error.clone()),
+            (None, None) => Err("No schema has been composed".to_string()),
+        }
+    }
+
+    fn index_subgraph(
+        service_name: &str,
+        sdl: &str,
+        namespace: Option<&str>,
+        join_configs: &[JoinConfig],
+        schema_trim: &SchemaTrimConfig,
+    ) -> Result<SubgraphIndex, String> {
+        let mut schema_document = parse_schema::<String>(sdl)
+            .map_err(|e| format!("Failed to parse schema for service {}: {}", service_name, e))?
+            .into_static();
+        if let Some(prefix) = namespace {
+            Self::prefix_root_fields(&mut schema_document, prefix);
+        }
+        schema_trim.trim(&mut schema_document);
+
         let mut type_to_service_map = HashMap::new();
+        let mut field_types = HashMap::new();
+        let mut entity_keys = HashMap::new();
+        let mut requires = HashMap::new();
+        let mut provides = HashMap::new();
+        let mut overrides = HashMap::new();
+        let mut owners = HashMap::new();
+        let mut possible_types = HashMap::new();
+        let mut cache_control = HashMap::new();
 
-        for (service_name, service_config) in services {
-            let schema_document = parse_schema::<String>(&service_config.schema).map_err(|e| {
-                format!("Failed to parse schema for service {}: {}", service_name, e)
-            })?;
+        for definition in &schema_document.definitions {
+            match definition {
+                graphql_parser::schema::Definition::TypeDefinition(typedef) => match typedef {
+                    graphql_parser::schema::TypeDefinition::Object(obj) => {
+                        let type_name = obj.name.clone();
+                        type_to_service_map
+                            .entry(type_name.clone())
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
 
-            for definition in &schema_document.definitions {
-                if let graphql_parser::schema::Definition::TypeDefinition(typedef) = definition {
-                    match typedef {
-                        graphql_parser::schema::TypeDefinition::Object(obj) => {
-                            let type_name = obj.name.clone();
-                            type_to_service_map
-                                .entry(type_name.clone())
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
-
-                            for field in &obj.fields {
-                                let field_key = format!("{}.{}", type_name, field.name);
-                                type_to_service_map
-                                    .entry(field_key)
-                                    .or_insert_with(Vec::new)
-                                    .push(service_name.clone());
-
-                                for arg in &field.arguments {
-                                    let arg_key =
-                                        format!("{}.{}.{}", type_name, field.name, arg.name);
-                                    type_to_service_map
-                                        .entry(arg_key)
-                                        .or_insert_with(Vec::new)
-                                        .push(service_name.clone());
-                                }
-                            }
-                        }
-                        graphql_parser::schema::TypeDefinition::Interface(iface) => {
-                            let type_name = iface.name.clone();
-                            type_to_service_map
-                                .entry(type_name)
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
-                        }
-                        graphql_parser::schema::TypeDefinition::InputObject(input) => {
-                            let type_name = input.name.clone();
-                            type_to_service_map
-                                .entry(type_name)
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
+                        Self::index_entity_key(&type_name, &obj.directives, &mut entity_keys);
+                        Self::index_cache_control(&type_name, &obj.directives, &mut cache_control);
+                        Self::index_possible_type(
+                            &type_name,
+                            &obj.implements_interfaces,
+                            &mut possible_types,
+                        );
+                        Self::index_object_fields(
+                            &type_name,
+                            &obj.fields,
+                            service_name,
+                            &mut type_to_service_map,
+                            &mut field_types,
+                            &mut requires,
+                            &mut provides,
+                            &mut overrides,
+                            &mut owners,
+                            &mut cache_control,
+                        );
+                    }
+                    graphql_parser::schema::TypeDefinition::Interface(iface) => {
+                        let type_name = iface.name.clone();
+                        type_to_service_map
+                            .entry(type_name)
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
+                    }
+                    graphql_parser::schema::TypeDefinition::InputObject(input) => {
+                        let type_name = input.name.clone();
+                        type_to_service_map
+                            .entry(type_name)
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
+                    }
+                    graphql_parser::schema::TypeDefinition::Enum(enum_type) => {
+                        let type_name = enum_type.name.clone();
+                        type_to_service_map
+                            .entry(type_name)
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
+                    }
+                    graphql_parser::schema::TypeDefinition::Scalar(scalar) => {
+                        let type_name = scalar.name.clone();
+                        type_to_service_map
+                            .entry(type_name)
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
+                    }
+                    graphql_parser::schema::TypeDefinition::Union(union_type) => {
+                        let type_name = union_type.name.clone();
+                        for member in &union_type.types {
+                            Self::index_possible_type(
+                                member,
+                                std::slice::from_ref(&type_name),
+                                &mut possible_types,
+                            );
                         }
-                        graphql_parser::schema::TypeDefinition::Enum(enum_type) => {
-                            let type_name = enum_type.name.clone();
-                            type_to_service_map
-                                .entry(type_name)
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
+                        type_to_service_map
+                            .entry(type_name)
+                            .or_insert_with(Vec::new)
+                            .push(service_name.to_string());
+                    }
+                },
+                // Federation v1 subgraphs contribute fields to entities owned
+                // elsewhere through `extend type Product @key(fields: "id")`.
+                graphql_parser::schema::Definition::TypeExtension(
+                    graphql_parser::schema::TypeExtension::Object(ext),
+                ) => {
+                    let type_name = ext.name.clone();
+                    type_to_service_map
+                        .entry(type_name.clone())
+                        .or_insert_with(Vec::new)
+                        .push(service_name.to_string());
+
+                    Self::index_entity_key(&type_name, &ext.directives, &mut entity_keys);
+                    Self::index_cache_control(&type_name, &ext.directives, &mut cache_control);
+                    Self::index_possible_type(
+                        &type_name,
+                        &ext.implements_interfaces,
+                        &mut possible_types,
+                    );
+                    Self::index_object_fields(
+                        &type_name,
+                        &ext.fields,
+                        service_name,
+                        &mut type_to_service_map,
+                        &mut field_types,
+                        &mut requires,
+                        &mut provides,
+                        &mut overrides,
+                        &mut owners,
+                        &mut cache_control,
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let mut joins = HashMap::new();
+        for join in join_configs {
+            Self::index_join(
+                service_name,
+                join,
+                namespace,
+                &mut schema_document,
+                &mut field_types,
+                &mut joins,
+            )?;
+        }
+
+        Ok(SubgraphIndex {
+            service_name: service_name.to_string(),
+            type_to_service_map,
+            field_types,
+            entity_keys,
+            requires,
+            provides,
+            overrides,
+            owners,
+            possible_types,
+            joins,
+            cache_control,
+            document: schema_document,
+        })
+    }
+
+    // Indexes a join resolved by `service_name`. The joined field is added to
+    // the subgraph's document, so it's part of the composed schema, with the
+    // resolver's type and no arguments: those come from the parent object.
+    fn index_join(
+        service_name: &str,
+        join: &JoinConfig,
+        namespace: Option<&str>,
+        document: &mut Document<'static, String>,
+        field_types: &mut HashMap<String, String>,
+        joins: &mut HashMap<String, JoinField>,
+    ) -> Result<(), String> {
+        use graphql_parser::query::{self, Definition as QueryDefinition, OperationDefinition};
+        use graphql_parser::schema::{
+            Definition, Field, ObjectTypeExtension, TypeDefinition, TypeExtension,
+        };
+
+        let Some((type_name, field_name)) = join.field.split_once('.') else {
+            return Err(format!(
+                "Join field {} must be given as Type.field",
+                join.field
+            ));
+        };
+        if ROOT_TYPES.contains(&type_name) {
+            return Err(format!("Join field {} can't be a root field", join.field));
+        }
+
+        // `$Product.id` names the parent's `id`, which parses as `$id`
+        let resolver = join.resolver.replace(&format!("${}.", type_name), "$");
+        let invalid = |e: String| format!("Invalid resolver for join {}: {}", join.field, e);
+        let source = format!("{{ {} }}", resolver);
+        let selection =
+            query::parse_query::<String>(&source).map_err(|e| invalid(e.to_string()))?;
+        let call = match selection.definitions.first() {
+            Some(QueryDefinition::Operation(OperationDefinition::SelectionSet(selection_set))) => {
+                match selection_set.items.as_slice() {
+                    [query::Selection::Field(field)] if field.selection_set.items.is_empty() => {
+                        field
+                    }
+                    _ => return Err(invalid(join.resolver.clone())),
+                }
+            }
+            _ => return Err(invalid(join.resolver.clone())),
+        };
+
+        let resolver_name = format!("{}{}", namespace.unwrap_or_default(), call.name);
+        let resolver_field = document
+            .definitions
+            .iter()
+            .flat_map(|definition| match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(obj)) if obj.name == "Query" => {
+                    &obj.fields[..]
+                }
+                Definition::TypeExtension(TypeExtension::Object(ext)) if ext.name == "Query" => {
+                    &ext.fields[..]
+                }
+                _ => &[],
+            })
+            .find(|field| field.name == resolver_name)
+            .ok_or_else(|| {
+                format!(
+                    "Service {} has no Query.{} to resolve join {}",
+                    service_name, call.name, join.field
+                )
+            })?;
+
+        let mut arguments = Vec::with_capacity(call.arguments.len());
+        for (name, value) in &call.arguments {
+            let query::Value::Variable(field) = value else {
+                return Err(format!(
+                    "Argument {} of join {} must take a field of {}, e.g. ${}.id",
+                    name, join.field, type_name, type_name
+                ));
+            };
+            let argument = resolver_field
+                .arguments
+                .iter()
+                .find(|argument| &argument.name == name)
+                .ok_or_else(|| format!("Query.{} has no argument {}", call.name, name))?;
+            arguments.push(JoinArgument {
+                name: name.clone(),
+                field: field.clone(),
+                value_type: argument.value_type.clone(),
+            });
+        }
+
+        field_types.insert(
+            join.field.clone(),
+            Self::named_type(&resolver_field.field_type).to_string(),
+        );
+        let joined_field = Field {
+            position: resolver_field.position,
+            description: resolver_field.description.clone(),
+            name: field_name.to_string(),
+            arguments: Vec::new(),
+            field_type: resolver_field.field_type.clone(),
+            directives: Vec::new(),
+        };
+        joins.insert(
+            join.field.clone(),
+            JoinField {
+                service_name: service_name.to_string(),
+                resolver: call.name.clone(),
+                arguments,
+            },
+        );
+        document
+            .definitions
+            .push(Definition::TypeExtension(TypeExtension::Object(
+                ObjectTypeExtension {
+                    position: joined_field.position,
+                    name: type_name.to_string(),
+                    implements_interfaces: Vec::new(),
+                    directives: Vec::new(),
+                    fields: vec![joined_field],
+                },
+            )));
+        Ok(())
+    }
+
+    // Renames the root fields of a namespaced subgraph to the names clients
+    // see, so both the index and the introspection schema carry the prefix
+    fn prefix_root_fields(document: &mut Document<'static, String>, prefix: &str) {
+        use graphql_parser::schema::{Definition, TypeDefinition, TypeExtension};
+
+        for definition in &mut document.definitions {
+            let (name, fields) = match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(obj)) => {
+                    (&obj.name, &mut obj.fields)
+                }
+                Definition::TypeExtension(TypeExtension::Object(ext)) => {
+                    (&ext.name, &mut ext.fields)
+                }
+                _ => continue,
+            };
+            if !ROOT_TYPES.contains(&name.as_str()) {
+                continue;
+            }
+            for field in fields {
+                field.name = format!("{}{}", prefix, field.name);
+            }
+        }
+    }
+
+    // Records `type_name` as a possible type of each abstract type it belongs to
+    fn index_possible_type(
+        type_name: &str,
+        abstract_types: &[String],
+        possible_types: &mut HashMap<String, Vec<String>>,
+    ) {
+        for abstract_type in abstract_types {
+            let types = possible_types.entry(abstract_type.clone()).or_default();
+            if !types.iter().any(|t| t == type_name) {
+                types.push(type_name.to_string());
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn index_object_fields(
+        type_name: &str,
+        fields: &[graphql_parser::schema::Field<String>],
+        service_name: &str,
+        type_to_service_map: &mut HashMap<String, Vec<String>>,
+        field_types: &mut HashMap<String, String>,
+        requires: &mut HashMap<String, String>,
+        provides: &mut HashMap<String, HashMap<String, String>>,
+        overrides: &mut HashMap<String, String>,
+        owners: &mut HashMap<String, String>,
+        cache_control: &mut HashMap<String, u64>,
+    ) {
+        for field in fields {
+            let field_key = format!("{}.{}", type_name, field.name);
+            Self::index_cache_control(&field_key, &field.directives, cache_control);
+            // `@owner(service: "search")` routes a root field several services
+            // declare to the named one
+            if let Some(owner) = Self::owner_directive(&field.directives) {
+                owners.insert(field_key.clone(), owner);
+            }
+            field_types
+                .entry(field_key.clone())
+                .or_insert_with(|| Self::named_type(&field.field_type).to_string());
+
+            // `@external` fields are only referenced by the service, another
+            // service resolves them.
+            if field.directives.iter().any(|d| d.name == "external") {
+                continue;
+            }
+
+            // `@requires(fields: "weight")` fields are resolved from the
+            // representation, which has to carry the required fields.
+            // `@provides(fields: "name")` fields of the returned entity are
+            // resolved by this service too, saving a fetch to their owner.
+            // `@override(from: "products")` fields are resolved by this
+            // service only, even though the other one still declares them.
+            for directive in &field.directives {
+                for (name, value) in &directive.arguments {
+                    let Value::String(value) = value else {
+                        continue;
+                    };
+                    match (directive.name.as_str(), name.as_str()) {
+                        ("requires", "fields") => {
+                            requires.insert(field_key.clone(), value.clone());
                         }
-                        graphql_parser::schema::TypeDefinition::Scalar(scalar) => {
-                            let type_name = scalar.name.clone();
-                            type_to_service_map
-                                .entry(type_name)
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
+                        ("provides", "fields") => {
+                            provides
+                                .entry(field_key.clone())
+                                .or_default()
+                                .insert(service_name.to_string(), value.clone());
                         }
-                        graphql_parser::schema::TypeDefinition::Union(union_type) => {
-                            let type_name = union_type.name.clone();
-                            type_to_service_map
-                                .entry(type_name)
-                                .or_insert_with(Vec::new)
-                                .push(service_name.clone());
+                        ("override", "from") => {
+                            overrides.insert(field_key.clone(), value.clone());
                         }
+                        _ => {}
                     }
                 }
             }
+
+            type_to_service_map
+                .entry(field_key)
+                .or_default()
+                .push(service_name.to_string());
+
+            for arg in &field.arguments {
+                let arg_key = format!("{}.{}.{}", type_name, field.name, arg.name);
+                type_to_service_map
+                    .entry(arg_key)
+                    .or_default()
+                    .push(service_name.to_string());
+            }
         }
+    }
 
-        println!("Type to service map: {:?}", type_to_service_map);
-        Ok(FederatedSchema {
-            services: services.clone(),
-            type_to_service_map,
-        })
+    fn index_entity_key(
+        type_name: &str,
+        directives: &[Directive<String>],
+        entity_keys: &mut HashMap<String, String>,
+    ) {
+        for directive in directives.iter().filter(|d| d.name == "key") {
+            for (name, value) in &directive.arguments {
+                if let (true, Value::String(fields)) = (name == "fields", value) {
+                    entity_keys
+                        .entry(type_name.to_string())
+                        .or_insert_with(|| fields.clone());
+                }
+            }
+        }
+    }
+
+    // `@cacheControl(maxAge: 60)` on a type or field lets responses holding it
+    // be cached for that many seconds
+    fn index_cache_control(
+        key: &str,
+        directives: &[Directive<String>],
+        cache_control: &mut HashMap<String, u64>,
+    ) {
+        for directive in directives.iter().filter(|d| d.name == "cacheControl") {
+            for (name, value) in &directive.arguments {
+                if let (true, Value::Int(max_age)) = (name == "maxAge", value)
+                    && let Some(max_age) = max_age.as_i64()
+                {
+                    cache_control.insert(key.to_string(), max_age.max(0) as u64);
+                }
+            }
+        }
+    }
+
+    fn named_type<'a>(field_type: &'a Type<String>) -> &'a str {
+        match field_type {
+            Type::NamedType(name) => name,
+            Type::ListType(inner) | Type::NonNullType(inner) => Self::named_type(inner),
+        }
+    }
+}
+
+impl Default for InMemorySchemaRegistry {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -117,25 +823,51 @@ impl SchemaRegistry for InMemorySchemaRegistry {
         let mut services = self.services.write().await;
         services.insert(service.name.clone(), service);
 
-        let mut federated_schema = self.federated_schema.write().await;
-        *federated_schema = None;
+        self.composition.write().await.stale = true;
+        self.generation += 1;
 
         Ok(())
     }
 
+    async fn unregister_service(&mut self, name: &str) -> Result<(), String> {
+        if self.services.write().await.remove(name).is_some() {
+            self.composition.write().await.stale = true;
+            self.generation += 1;
+        }
+        Ok(())
+    }
+
     async fn get_schema(&self) -> Result<FederatedSchema, String> {
-        let cached_schema = self.federated_schema.read().await;
-        if let Some(schema) = &*cached_schema {
-            return Ok(schema.clone());
+        let composition = self.composition.read().await;
+        if !composition.stale {
+            return Self::served_schema(&composition);
         }
-        drop(cached_schema);
+        drop(composition);
 
-        let services = self.services.read().await;
-        let schema = self.build_federated_schema(&services).await?;
+        // Concurrent requests wait for a single composition
+        let mut composition = self.composition.write().await;
+        if composition.stale {
+            let services = self.services.read().await;
+            match self.build_federated_schema(&services).await {
+                Ok(schema) => {
+                    composition.schema = Some(schema);
+                    composition.error = None;
+                }
+                Err(e) => {
+                    warn!(
+                        "Schema composition failed, serving the last good schema: {}",
+                        e
+                    );
+                    composition.error = Some(e);
+                }
+            }
+            composition.stale = false;
+        }
 
-        let mut federated_schema = self.federated_schema.write().await;
-        *federated_schema = Some(schema.clone());
+        Self::served_schema(&composition)
+    }
 
-        Ok(schema)
+    async fn composition_error(&self) -> Option<String> {
+        self.composition.read().await.error.clone()
     }
 }
@@ -0,0 +1,130 @@
+use graphql_parser::schema::{Definition, Document, Type, TypeDefinition, TypeExtension};
+use serde::{Deserialize, Serialize};
+
+// Types and fields of the subgraphs kept out of the composed schema, so they
+// can neither be introspected nor queried through the gateway. Patterns are
+// "Type" or "Type.field", `*` matching any run of characters, e.g.
+// "Query.debug*" or "Internal*".
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchemaTrimConfig {
+    pub hide: Vec<String>,
+}
+
+impl SchemaTrimConfig {
+    pub fn hides_type(&self, type_name: &str) -> bool {
+        self.hide
+            .iter()
+            .any(|pattern| !pattern.contains('.') && matches(pattern, type_name))
+    }
+
+    pub fn hides_field(&self, type_name: &str, field_name: &str) -> bool {
+        self.hide.iter().any(|pattern| {
+            pattern
+                .split_once('.')
+                .is_some_and(|(type_pattern, field_pattern)| {
+                    matches(type_pattern, type_name) && matches(field_pattern, field_name)
+                })
+        })
+    }
+
+    // Removes the hidden types from a subgraph's document, along with the
+    // hidden fields and every field, argument, member or interface referring
+    // to a hidden type
+    pub fn trim(&self, document: &mut Document<'static, String>) {
+        if self.hide.is_empty() {
+            return;
+        }
+
+        document.definitions.retain(|definition| match definition {
+            Definition::TypeDefinition(typedef) => !self.hides_type(type_definition_name(typedef)),
+            Definition::TypeExtension(ext) => !self.hides_type(type_extension_name(ext)),
+            _ => true,
+        });
+
+        let hidden = |field_type: &Type<String>| self.hides_type(named_type(field_type));
+        for definition in &mut document.definitions {
+            match definition {
+                Definition::TypeDefinition(TypeDefinition::Object(obj)) => {
+                    obj.fields.retain(|field| {
+                        !self.hides_field(&obj.name, &field.name) && !hidden(&field.field_type)
+                    });
+                    for field in &mut obj.fields {
+                        field.arguments.retain(|arg| !hidden(&arg.value_type));
+                    }
+                    obj.implements_interfaces
+                        .retain(|name| !self.hides_type(name));
+                }
+                Definition::TypeExtension(TypeExtension::Object(ext)) => {
+                    ext.fields.retain(|field| {
+                        !self.hides_field(&ext.name, &field.name) && !hidden(&field.field_type)
+                    });
+                    for field in &mut ext.fields {
+                        field.arguments.retain(|arg| !hidden(&arg.value_type));
+                    }
+                    ext.implements_interfaces
+                        .retain(|name| !self.hides_type(name));
+                }
+                Definition::TypeDefinition(TypeDefinition::Interface(iface)) => {
+                    iface.fields.retain(|field| {
+                        !self.hides_field(&iface.name, &field.name) && !hidden(&field.field_type)
+                    });
+                    for field in &mut iface.fields {
+                        field.arguments.retain(|arg| !hidden(&arg.value_type));
+                    }
+                }
+                Definition::TypeDefinition(TypeDefinition::InputObject(input)) => {
+                    input.fields.retain(|field| {
+                        !self.hides_field(&input.name, &field.name) && !hidden(&field.value_type)
+                    });
+                }
+                Definition::TypeDefinition(TypeDefinition::Union(union)) => {
+                    union.types.retain(|name| !self.hides_type(name));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn type_definition_name<'a>(typedef: &'a TypeDefinition<'static, String>) -> &'a str {
+    match typedef {
+        TypeDefinition::Scalar(t) => &t.name,
+        TypeDefinition::Object(t) => &t.name,
+        TypeDefinition::Interface(t) => &t.name,
+        TypeDefinition::Union(t) => &t.name,
+        TypeDefinition::Enum(t) => &t.name,
+        TypeDefinition::InputObject(t) => &t.name,
+    }
+}
+
+fn type_extension_name<'a>(ext: &'a TypeExtension<'static, String>) -> &'a str {
+    match ext {
+        TypeExtension::Scalar(t) => &t.name,
+        TypeExtension::Object(t) => &t.name,
+        TypeExtension::Interface(t) => &t.name,
+        TypeExtension::Union(t) => &t.name,
+        TypeExtension::Enum(t) => &t.name,
+        TypeExtension::InputObject(t) => &t.name,
+    }
+}
+
+fn named_type<'a>(field_type: &'a Type<String>) -> &'a str {
+    match field_type {
+        Type::NamedType(name) => name,
+        Type::ListType(inner) | Type::NonNullType(inner) => named_type(inner),
+    }
+}
+
+// Glob matching where `*` stands for any run of characters
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    (0..=name.len())
+        .filter(|&i| name.is_char_boundary(i))
+        .any(|i| matches(rest, &name[i..]))
+}
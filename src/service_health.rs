@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::FederatedSchema;
+use crate::memory::write_metric;
+
+const ROOT_TYPES: [&str; 3] = ["Query", "Mutation", "Subscription"];
+
+// Services are judged by the fetches sent to them. One failing
+// `failure_threshold` fetches in a row is unavailable for `recovery_seconds`:
+// its fetches are answered with a `SERVICE_UNAVAILABLE` error without being
+// sent, and introspection responses list the root fields it resolves in
+// `extensions.unavailableFields`. The first fetch after that decides whether
+// it's available again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceHealthConfig {
+    pub enabled: bool,
+    pub failure_threshold: u32,
+    pub recovery_seconds: u64,
+}
+
+impl Default for ServiceHealthConfig {
+    fn default() -> Self {
+        ServiceHealthConfig {
+            enabled: false,
+            failure_threshold: 5,
+            recovery_seconds: 30,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServiceState {
+    consecutive_failures: u32,
+    unavailable_until: Option<Instant>,
+}
+
+pub struct ServiceHealth {
+    config: ServiceHealthConfig,
+    services: Mutex<HashMap<String, ServiceState>>,
+}
+
+impl ServiceHealth {
+    pub fn new(config: &ServiceHealthConfig) -> Self {
+        ServiceHealth {
+            config: config.clone(),
+            services: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_available(&self, service: &str) -> bool {
+        let now = Instant::now();
+        self.services
+            .lock()
+            .unwrap()
+            .get(service)
+            .and_then(|state| state.unavailable_until)
+            .is_none_or(|until| until <= now)
+    }
+
+    // Records the outcome of a fetch sent to `service`
+    pub fn record(&self, service: &str, success: bool) {
+        let now = Instant::now();
+        let mut services = self.services.lock().unwrap();
+        let state = services.entry(service.to_string()).or_default();
+
+        if success {
+            if state.unavailable_until.take().is_some() {
+                info!("Service {} is available again", service);
+            }
+            state.consecutive_failures = 0;
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.config.failure_threshold
+            && state.unavailable_until.is_none_or(|until| until <= now)
+        {
+            state.unavailable_until = Some(now + Duration::from_secs(self.config.recovery_seconds));
+            warn!(
+                "ALERT: service {} is unavailable for {}s after {} failed fetches in a row",
+                service, self.config.recovery_seconds, state.consecutive_failures
+            );
+        }
+    }
+
+    // The unavailable services, by name
+    pub fn unavailable(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut unavailable: Vec<String> = self
+            .services
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| state.unavailable_until.is_some_and(|until| until > now))
+            .map(|(service, _)| service.clone())
+            .collect();
+        unavailable.sort();
+        unavailable
+    }
+
+    // The root fields whose service is unavailable, with the service, as
+    // reported in `extensions.unavailableFields`
+    pub fn unavailable_fields(&self, schema: &FederatedSchema) -> Vec<Value> {
+        let unavailable = self.unavailable();
+        if unavailable.is_empty() {
+            return Vec::new();
+        }
+        let mut fields: Vec<(&str, &str)> = schema
+            .type_to_service_map
+            .iter()
+            .filter(|(field_key, _)| {
+                field_key.split_once('.').is_some_and(|(type_name, field)| {
+                    ROOT_TYPES.contains(&type_name) && !field.contains('.')
+                })
+            })
+            .filter_map(|(field_key, owners)| {
+                let owner = owners.first()?;
+                unavailable
+                    .iter()
+                    .any(|service| owner == service)
+                    .then_some((field_key.as_str(), owner.as_str()))
+            })
+            .collect();
+        fields.sort();
+        fields
+            .into_iter()
+            .map(|(field, service)| json!({ "field": field, "service": service }))
+            .collect()
+    }
+
+    pub fn render_metrics(&self) -> String {
+        let mut metrics = String::new();
+        write_metric(
+            &mut metrics,
+            "portkey_unavailable_services",
+            "gauge",
+            "Services currently unavailable after failing fetches in a row.",
+            self.unavailable().len() as u64,
+        );
+        metrics
+    }
+}
@@ -0,0 +1,17 @@
+use crate::request_context::RequestContext;
+
+// Picks, per request, the service resolving a root field several services
+// declare, e.g. to send premium tenants to a dedicated deployment or an
+// experiment's traffic to a new backend. The planner consults it before the
+// registry's order of preference applies.
+pub trait ServiceSelector: Send + Sync {
+    // `field` as in "Query.search", `candidates` the services declaring it in
+    // order of preference. `None`, or a service that isn't a candidate,
+    // leaves the choice to the planner.
+    fn select(
+        &self,
+        field: &str,
+        candidates: &[String],
+        context: &RequestContext,
+    ) -> Option<String>;
+}
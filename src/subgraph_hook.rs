@@ -0,0 +1,26 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+// A request the executor is about to send to a subgraph. Hooks may rewrite any
+// part of it, e.g. to route a share of the traffic to a canary deployment or
+// to inject the client's locale into the variables.
+#[derive(Clone, Debug)]
+pub struct SubgraphRequest {
+    pub service_name: String,
+    pub query: String,
+    pub variables: Value,
+    // Tried first, the service's other endpoints stay failover targets
+    pub url: String,
+    // Sent along with the request, initially the forwarded client headers
+    pub headers: HashMap<String, String>,
+}
+
+// Per-service overrides without forking the executor. Hooks run in the order
+// they were added to the executor, each seeing the changes of the previous.
+pub trait SubgraphHook: Send + Sync {
+    // Runs before every fetch and before opening a subscription stream
+    fn on_subgraph_request(&self, _request: &mut SubgraphRequest) {}
+
+    // Runs on the response of every fetch, before it is merged
+    fn on_subgraph_response(&self, _service_name: &str, _response: &mut Value) {}
+}
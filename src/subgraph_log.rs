@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::info;
+
+// Debug logging of the exact queries and variables sent to the services.
+// Only the services listed are logged.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubgraphLogConfig {
+    pub services: HashMap<String, SubgraphLogSettings>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubgraphLogSettings {
+    // Share of the requests logged, from 0 (none) to 1 (all)
+    pub sample_rate: f64,
+    // The query and the variables are each cut to this many bytes, 0 keeps
+    // them whole
+    pub max_body_bytes: usize,
+}
+
+impl Default for SubgraphLogSettings {
+    fn default() -> Self {
+        SubgraphLogSettings {
+            sample_rate: 1.0,
+            max_body_bytes: 4096,
+        }
+    }
+}
+
+// Body of `POST /admin/subgraph-logging`, replacing the settings of one
+// service. A sample rate of 0 turns its logging off.
+#[derive(Debug, Deserialize)]
+pub struct SubgraphLogUpdate {
+    pub service: String,
+    #[serde(flatten)]
+    pub settings: SubgraphLogSettings,
+}
+
+// A sampled request, its query and variables cut to the configured size
+#[derive(Debug, PartialEq)]
+pub struct SubgraphLogEntry {
+    pub service: String,
+    pub query: String,
+    pub variables: String,
+}
+
+struct ServiceLog {
+    settings: SubgraphLogSettings,
+    requests: u64,
+}
+
+// Shared by the executor, which logs through it, and the admin API changing
+// the settings at runtime
+#[derive(Default)]
+pub struct SubgraphLogger {
+    services: Mutex<HashMap<String, ServiceLog>>,
+}
+
+impl SubgraphLogger {
+    pub fn new(config: &SubgraphLogConfig) -> Self {
+        let logger = SubgraphLogger::default();
+        for (service, settings) in &config.services {
+            logger.set(service, settings.clone());
+        }
+        logger
+    }
+
+    pub fn settings(&self) -> HashMap<String, SubgraphLogSettings> {
+        let services = self.services.lock().unwrap();
+        services
+            .iter()
+            .map(|(service, log)| (service.clone(), log.settings.clone()))
+            .collect()
+    }
+
+    pub fn set(&self, service: &str, settings: SubgraphLogSettings) {
+        let mut services = self.services.lock().unwrap();
+        let log = services
+            .entry(service.to_string())
+            .or_insert_with(|| ServiceLog {
+                settings: settings.clone(),
+                requests: 0,
+            });
+        log.settings = settings;
+    }
+
+    pub fn log(&self, service: &str, query: &str, variables: &Value) {
+        if let Some(entry) = self.entry(service, query, variables) {
+            info!(
+                target: "portkey::subgraph",
+                service = %entry.service,
+                query = %entry.query,
+                variables = %entry.variables,
+                "Query for service {}",
+                entry.service
+            );
+        }
+    }
+
+    // The log entry for a request to `service`, `None` when it isn't
+    // sampled. Sampling is spread evenly: at a rate of 0.25 every fourth
+    // request is logged.
+    pub fn entry(&self, service: &str, query: &str, variables: &Value) -> Option<SubgraphLogEntry> {
+        let mut services = self.services.lock().unwrap();
+        let log = services.get_mut(service)?;
+        let rate = log.settings.sample_rate.clamp(0.0, 1.0);
+        log.requests += 1;
+        let sampled =
+            (log.requests as f64 * rate).floor() > ((log.requests - 1) as f64 * rate).floor();
+        if !sampled {
+            return None;
+        }
+
+        let max = log.settings.max_body_bytes;
+        Some(SubgraphLogEntry {
+            service: service.to_string(),
+            query: truncate(query, max),
+            variables: truncate(&variables.to_string(), max),
+        })
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if max == 0 || text.len() <= max {
+        return text.to_string();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes)", &text[..end], text.len())
+}
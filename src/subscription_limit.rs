@@ -0,0 +1,127 @@
+use futures::{StreamExt, stream::BoxStream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionLimitConfig {
+    // Active subscriptions a single client connection may hold, 0 disables
+    // the cap
+    pub max_per_connection: usize,
+    // Active subscriptions across all connections sending the same
+    // `x-api-key`, 0 disables the cap
+    pub max_per_api_key: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Holder {
+    Connection(u64),
+    ApiKey(String),
+}
+
+// Counts the active subscriptions of every connection and API key, rejecting
+// new ones past the configured caps before anything is sent upstream
+#[derive(Debug, Default)]
+pub struct SubscriptionLimiter {
+    config: SubscriptionLimitConfig,
+    active: Mutex<HashMap<Holder, usize>>,
+}
+
+impl SubscriptionLimiter {
+    pub fn new(config: &SubscriptionLimitConfig) -> Self {
+        SubscriptionLimiter {
+            config: config.clone(),
+            ..Default::default()
+        }
+    }
+
+    // A permit for one more subscription, held until the event stream ends
+    pub fn acquire(
+        self: &Arc<Self>,
+        connection: u64,
+        api_key: Option<&str>,
+    ) -> Result<SubscriptionPermit, String> {
+        let mut holders = vec![(
+            Holder::Connection(connection),
+            self.config.max_per_connection,
+        )];
+        if let Some(api_key) = api_key {
+            holders.push((
+                Holder::ApiKey(api_key.to_string()),
+                self.config.max_per_api_key,
+            ));
+        }
+
+        let mut active = self.active.lock().unwrap();
+        for (holder, max) in &holders {
+            if *max > 0 && active.get(holder).copied().unwrap_or_default() >= *max {
+                let scope = match holder {
+                    Holder::Connection(_) => "connection",
+                    Holder::ApiKey(_) => "API key",
+                };
+                return Err(format!(
+                    "Subscription limit of {} per {} exceeded",
+                    max, scope
+                ));
+            }
+        }
+        for (holder, _) in &holders {
+            *active.entry(holder.clone()).or_default() += 1;
+        }
+
+        Ok(SubscriptionPermit {
+            limiter: self.clone(),
+            holders: holders.into_iter().map(|(holder, _)| holder).collect(),
+        })
+    }
+
+    // Subscriptions currently open on the connection
+    pub fn active_for_connection(&self, connection: u64) -> usize {
+        self.active_for(&Holder::Connection(connection))
+    }
+
+    // Subscriptions currently open with the API key, over all connections
+    pub fn active_for_api_key(&self, api_key: &str) -> usize {
+        self.active_for(&Holder::ApiKey(api_key.to_string()))
+    }
+
+    fn active_for(&self, holder: &Holder) -> usize {
+        let active = self.active.lock().unwrap();
+        active.get(holder).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub struct SubscriptionPermit {
+    limiter: Arc<SubscriptionLimiter>,
+    holders: Vec<Holder>,
+}
+
+impl SubscriptionPermit {
+    // Releases the permit once the event stream is dropped, whether it
+    // completed or the client went away
+    pub fn hold(self, events: BoxStream<'static, Value>) -> BoxStream<'static, Value> {
+        events
+            .map(move |event| {
+                let _permit = &self;
+                event
+            })
+            .boxed()
+    }
+}
+
+impl Drop for SubscriptionPermit {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        for holder in &self.holders {
+            if let Some(count) = active.get_mut(holder) {
+                *count -= 1;
+                if *count == 0 {
+                    active.remove(holder);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,759 @@
+use graphql_parser::schema::{
+    self, Definition, Directive, Document, EnumType, InputObjectType, InterfaceType, ObjectType,
+    TypeDefinition, UnionType, Value,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::{
+    CompressionConfig, JoinConfig, ServiceCapabilities, ServiceConfig, broker::TopicConfig,
+    s3::S3Config,
+};
+
+const DEFAULT_SUPERGRAPH: &str = "./schemas/supergraph.yaml";
+
+const UPLINK_ENDPOINTS: [&str; 2] = [
+    "https://uplink.api.apollographql.com/",
+    "https://aws.uplink.api.apollographql.com/",
+];
+
+const UPLINK_QUERY: &str = r#"query SupergraphSdlQuery($apiKey: String!, $graph_ref: String!) {
+    routerConfig(ref: $graph_ref, apiKey: $apiKey) {
+        __typename
+        ... on RouterConfigResult { id supergraphSdl: supergraphSDL }
+        ... on FetchError { code message }
+    }
+}"#;
+
+// Where the gateway takes its subgraphs from, tried in the listed order until
+// one loads. A gateway serving from a fallback source retries the sources
+// listed before it every `retry_interval_ms`, switching back to the first
+// that loads again. Without sources, `./schemas/supergraph.yaml` is read.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SupergraphSourceConfig {
+    pub sources: Vec<SupergraphSource>,
+    pub retry_interval_ms: u64,
+    // Applies to every request made to URL and Uplink sources
+    pub timeout_ms: u64,
+    // Access to `s3://` URLs, of sources and of schema files
+    pub s3: S3Config,
+}
+
+impl Default for SupergraphSourceConfig {
+    fn default() -> Self {
+        SupergraphSourceConfig {
+            sources: Vec::new(),
+            retry_interval_ms: 60_000,
+            timeout_ms: 10_000,
+            s3: S3Config::default(),
+        }
+    }
+}
+
+// A `supergraph.yaml` listing the subgraphs and their schema files, or a
+// composed supergraph schema whose `join__Graph` values are the subgraphs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SupergraphSource {
+    // Apollo Uplink, delivering the supergraph schema of `graph_ref`, e.g.
+    // `my-graph@production`. Endpoints are tried in order, Apollo's own when
+    // none are listed.
+    Uplink {
+        graph_ref: String,
+        api_key: String,
+        #[serde(default)]
+        endpoints: Vec<String>,
+    },
+    // An `http://`, `https://` or `s3://bucket/key` URL. Schema files are
+    // resolved relative to it, and only requests to its own origin carry
+    // `headers`.
+    Url {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    // Schema files may be URLs too
+    File {
+        path: PathBuf,
+    },
+}
+
+// Reads the URLs of sources and schema files
+struct Remote {
+    client: reqwest::Client,
+    s3: S3Config,
+}
+
+impl SupergraphSource {
+    pub fn describe(&self) -> String {
+        match self {
+            SupergraphSource::Uplink { graph_ref, .. } => format!("uplink {}", graph_ref),
+            SupergraphSource::Url { url, .. } => format!("url {}", url),
+            SupergraphSource::File { path } => format!("file {}", path.display()),
+        }
+    }
+
+    async fn fetch(&self, remote: &Remote) -> Result<Vec<ServiceConfig>, String> {
+        match self {
+            SupergraphSource::Uplink {
+                graph_ref,
+                api_key,
+                endpoints,
+            } => {
+                let sdl = fetch_from_uplink(&remote.client, graph_ref, api_key, endpoints).await?;
+                split_supergraph(&sdl)
+            }
+            SupergraphSource::Url { url, headers } => {
+                let base = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+                let contents = remote.read(&base, headers).await?;
+                if is_supergraph_schema(&contents) {
+                    return split_supergraph(&contents);
+                }
+                let mut schemas = HashMap::new();
+                for file in schema_files(&contents)? {
+                    let url = base
+                        .join(&file)
+                        .map_err(|e| format!("Invalid schema file {}: {}", file, e))?;
+                    let headers = if url.origin() == base.origin() {
+                        headers
+                    } else {
+                        &HashMap::new()
+                    };
+                    schemas.insert(file, remote.read(&url, headers).await?);
+                }
+                parse_supergraph_config(&contents, &schemas)
+            }
+            SupergraphSource::File { path } => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read config file: {}", e))?;
+                if is_supergraph_schema(&contents) {
+                    return split_supergraph(&contents);
+                }
+                let dir = path.parent().unwrap_or_else(|| Path::new(""));
+                let mut schemas = HashMap::new();
+                for file in schema_files(&contents)? {
+                    if let Some(url) = remote_url(&file) {
+                        let schema = remote.read(&url, &HashMap::new()).await?;
+                        schemas.insert(file, schema);
+                        continue;
+                    }
+                    let full_path = dir.join(&file);
+                    info!("Reading schema file: {:?}", full_path);
+                    let schema = std::fs::read_to_string(full_path)
+                        .map_err(|e| format!("Failed to read schema file: {}", e))?;
+                    schemas.insert(file, schema);
+                }
+                parse_supergraph_config(&contents, &schemas)
+            }
+        }
+    }
+}
+
+// The configured sources and which of them the schema was last loaded from
+pub struct SupergraphSources {
+    sources: Vec<SupergraphSource>,
+    retry_interval: Duration,
+    remote: Remote,
+    active: Mutex<ActiveSource>,
+}
+
+#[derive(Default)]
+struct ActiveSource {
+    index: Option<usize>,
+    // Services registered from the source, unregistered once another source
+    // no longer has them
+    services: Vec<String>,
+}
+
+impl SupergraphSources {
+    pub fn new(config: &SupergraphSourceConfig) -> Self {
+        let sources = if config.sources.is_empty() {
+            vec![SupergraphSource::File {
+                path: PathBuf::from(DEFAULT_SUPERGRAPH),
+            }]
+        } else {
+            config.sources.clone()
+        };
+        SupergraphSources {
+            sources,
+            retry_interval: Duration::from_millis(config.retry_interval_ms),
+            remote: Remote {
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_millis(config.timeout_ms))
+                    .build()
+                    .unwrap_or_default(),
+                s3: config.s3.clone(),
+            },
+            active: Mutex::new(ActiveSource::default()),
+        }
+    }
+
+    pub fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    // The source the schema was last loaded from
+    pub fn active(&self) -> Option<&SupergraphSource> {
+        let index = self.active.lock().unwrap().index?;
+        self.sources.get(index)
+    }
+
+    // The services of the first source before `until` that loads, along with
+    // its position, or every source's error
+    pub async fn fetch(&self, until: usize) -> Result<(usize, Vec<ServiceConfig>), String> {
+        let mut errors = Vec::new();
+        for (index, source) in self.sources.iter().enumerate().take(until) {
+            match source.fetch(&self.remote).await {
+                Ok(services) => return Ok((index, services)),
+                Err(e) => {
+                    warn!(
+                        "Failed to load the supergraph from {}: {}",
+                        source.describe(),
+                        e
+                    );
+                    errors.push(format!("{}: {}", source.describe(), e));
+                }
+            }
+        }
+        Err(errors.join("; "))
+    }
+
+    pub fn active_index(&self) -> Option<usize> {
+        self.active.lock().unwrap().index
+    }
+
+    // Records `index` as the active source with its services, returning the
+    // services the previous source had that it doesn't
+    pub fn activate(&self, index: usize, services: &[ServiceConfig]) -> Vec<String> {
+        let mut active = self.active.lock().unwrap();
+        let names: Vec<String> = services
+            .iter()
+            .map(|service| service.name.clone())
+            .collect();
+        let removed = active
+            .services
+            .iter()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect();
+        *active = ActiveSource {
+            index: Some(index),
+            services: names,
+        };
+        removed
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SupergraphConfig {
+    subgraphs: HashMap<String, SubgraphConfig>,
+    // Fields resolved across subgraphs without federation, e.g.
+    // `Product.reviews -> reviews.reviewsByProductId(productId: $Product.id)`
+    #[serde(default)]
+    joins: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphConfig {
+    routing_url: String,
+    schema: SchemaConfig,
+    #[serde(default)]
+    compression: CompressionConfig,
+    #[serde(default)]
+    capabilities: ServiceCapabilities,
+    // Region -> routing URL of the subgraph's deployment in that region
+    #[serde(default)]
+    regions: BTreeMap<String, String>,
+    // Prefix for the subgraph's root fields, e.g. `users_`
+    #[serde(default)]
+    namespace: Option<String>,
+    // Wins shared root fields over subgraphs with a lower priority
+    #[serde(default)]
+    priority: i32,
+    // Sends variables inlined as literals
+    #[serde(default)]
+    inline_variables: bool,
+    // Static headers sent with every request to the subgraph
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    // Subscription fields whose events the subgraph publishes to a broker
+    #[serde(default)]
+    topics: HashMap<String, TopicConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaConfig {
+    file: String,
+}
+
+fn is_supergraph_schema(contents: &str) -> bool {
+    contents.contains("join__Graph")
+}
+
+fn supergraph_config(contents: &str) -> Result<SupergraphConfig, String> {
+    serde_yaml::from_str(contents).map_err(|e| format!("Failed to parse config file: {}", e))
+}
+
+// The schema files a `supergraph.yaml` refers to
+fn schema_files(contents: &str) -> Result<Vec<String>, String> {
+    Ok(supergraph_config(contents)?
+        .subgraphs
+        .into_values()
+        .map(|subgraph| subgraph.schema.file)
+        .collect())
+}
+
+// The services of a `supergraph.yaml`, with `schemas` holding the contents of
+// its schema files
+fn parse_supergraph_config(
+    contents: &str,
+    schemas: &HashMap<String, String>,
+) -> Result<Vec<ServiceConfig>, String> {
+    let config = supergraph_config(contents)?;
+
+    let mut joins: HashMap<String, Vec<JoinConfig>> = HashMap::new();
+    for declaration in &config.joins {
+        let (service, join) = JoinConfig::parse(declaration)?;
+        if !config.subgraphs.contains_key(&service) {
+            return Err(format!(
+                "Join {} is resolved by unknown subgraph {}",
+                join.field, service
+            ));
+        }
+        joins.entry(service).or_default().push(join);
+    }
+
+    let mut services = Vec::new();
+    for (name, subgraph_config) in config.subgraphs {
+        let schema = schemas
+            .get(&subgraph_config.schema.file)
+            .cloned()
+            .unwrap_or_default();
+        let service_joins = joins.remove(&name).unwrap_or_default();
+        services.push(ServiceConfig {
+            name,
+            url: subgraph_config.routing_url,
+            schema,
+            compression: subgraph_config.compression,
+            capabilities: subgraph_config.capabilities,
+            regions: subgraph_config.regions,
+            namespace: subgraph_config.namespace,
+            priority: subgraph_config.priority,
+            joins: service_joins,
+            inline_variables: subgraph_config.inline_variables,
+            headers: subgraph_config.headers,
+            topics: subgraph_config.topics,
+            preferred_region: None,
+        });
+    }
+    Ok(services)
+}
+
+impl Remote {
+    async fn read(
+        &self,
+        url: &reqwest::Url,
+        headers: &HashMap<String, String>,
+    ) -> Result<String, String> {
+        let mut request = match url.scheme() {
+            "http" | "https" => self.client.get(url.clone()),
+            "s3" => self.s3.get(&self.client, url)?,
+            scheme => return Err(format!("Unsupported scheme {} of {}", scheme, url)),
+        };
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("Fetching {} returned {}", url, response.status()));
+        }
+        response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", url, e))
+    }
+}
+
+// Schema files given as URLs rather than paths
+fn remote_url(file: &str) -> Option<reqwest::Url> {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| file.starts_with(scheme))
+        .then(|| reqwest::Url::parse(file).ok())
+        .flatten()
+}
+
+async fn fetch_from_uplink(
+    client: &reqwest::Client,
+    graph_ref: &str,
+    api_key: &str,
+    endpoints: &[String],
+) -> Result<String, String> {
+    let endpoints: Vec<&str> = if endpoints.is_empty() {
+        UPLINK_ENDPOINTS.to_vec()
+    } else {
+        endpoints.iter().map(String::as_str).collect()
+    };
+    let body = json!({
+        "query": UPLINK_QUERY,
+        "operationName": "SupergraphSdlQuery",
+        "variables": { "apiKey": api_key, "graph_ref": graph_ref },
+    });
+
+    let mut error = String::from("No Uplink endpoints");
+    for endpoint in endpoints {
+        let response = match client.post(endpoint).json(&body).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                error = format!("Failed to reach Uplink at {}: {}", endpoint, e);
+                continue;
+            }
+        };
+        let response: serde_json::Value = match response.json().await {
+            Ok(response) => response,
+            Err(e) => {
+                error = format!("Invalid response from Uplink at {}: {}", endpoint, e);
+                continue;
+            }
+        };
+
+        // Fetch errors are the graph's, another endpoint would answer the same
+        let config = &response["data"]["routerConfig"];
+        return match config["__typename"].as_str() {
+            Some("RouterConfigResult") => config["supergraphSdl"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or("Uplink returned no supergraph schema".to_string()),
+            Some("FetchError") => Err(format!(
+                "Uplink refused to deliver {}: {} ({})",
+                graph_ref,
+                config["message"].as_str().unwrap_or_default(),
+                config["code"].as_str().unwrap_or_default(),
+            )),
+            _ => Err(format!("Unexpected response from Uplink: {}", response)),
+        };
+    }
+    Err(error)
+}
+
+// Splits a composed supergraph schema back into the schemas of its
+// subgraphs, along the `@join__*` directives composition left on its types
+// and fields. Types without `@join__type` belong to every subgraph, fields
+// without `@join__field` to every subgraph of their type. `@key`,
+// `@external`, `@requires`, `@provides` and `@override` are restored.
+pub fn split_supergraph(sdl: &str) -> Result<Vec<ServiceConfig>, String> {
+    let document = schema::parse_schema::<String>(sdl)
+        .map_err(|e| format!("Failed to parse supergraph schema: {}", e))?;
+
+    // `join__Graph` value -> (name, routing URL)
+    let graphs: BTreeMap<String, (String, String)> = document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            Definition::TypeDefinition(TypeDefinition::Enum(graphs))
+                if graphs.name == "join__Graph" =>
+            {
+                Some(graphs)
+            }
+            _ => None,
+        })
+        .ok_or("Supergraph schema has no join__Graph enum".to_string())?
+        .values
+        .iter()
+        .map(|value| {
+            let directive = value
+                .directives
+                .iter()
+                .find(|directive| directive.name == "join__graph");
+            let name = directive.and_then(|directive| string_argument(directive, "name"));
+            let url = directive.and_then(|directive| string_argument(directive, "url"));
+            match (name, url) {
+                (Some(name), Some(url)) => Ok((value.name.clone(), (name, url))),
+                _ => Err(format!(
+                    "Subgraph {} of the supergraph schema has no name or URL",
+                    value.name
+                )),
+            }
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(graphs
+        .iter()
+        .map(|(graph, (name, url))| ServiceConfig {
+            name: name.clone(),
+            url: url.clone(),
+            schema: subgraph_schema(&document, graph).to_string(),
+            ..Default::default()
+        })
+        .collect())
+}
+
+fn subgraph_schema<'a>(supergraph: &Document<'a, String>, graph: &str) -> Document<'a, String> {
+    let definitions = supergraph
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::TypeDefinition(definition)
+                if !is_join_type(type_name(definition))
+                    && in_graph(type_directives(definition), graph) =>
+            {
+                Some(Definition::TypeDefinition(subgraph_type(definition, graph)))
+            }
+            _ => None,
+        })
+        .collect();
+    Document { definitions }
+}
+
+fn subgraph_type<'a>(
+    definition: &TypeDefinition<'a, String>,
+    graph: &str,
+) -> TypeDefinition<'a, String> {
+    match definition {
+        TypeDefinition::Object(object) => TypeDefinition::Object(ObjectType {
+            implements_interfaces: implemented(
+                &object.directives,
+                &object.implements_interfaces,
+                graph,
+            ),
+            directives: keys(&object.directives, graph),
+            fields: subgraph_fields(&object.fields, graph),
+            ..object.clone()
+        }),
+        TypeDefinition::Interface(interface) => TypeDefinition::Interface(InterfaceType {
+            implements_interfaces: implemented(
+                &interface.directives,
+                &interface.implements_interfaces,
+                graph,
+            ),
+            directives: keys(&interface.directives, graph),
+            fields: subgraph_fields(&interface.fields, graph),
+            ..interface.clone()
+        }),
+        TypeDefinition::Union(union) => {
+            let members: Vec<&Directive<String>> = union
+                .directives
+                .iter()
+                .filter(|directive| directive.name == "join__unionMember")
+                .collect();
+            TypeDefinition::Union(UnionType {
+                directives: Vec::new(),
+                types: union
+                    .types
+                    .iter()
+                    .filter(|member| {
+                        members.is_empty()
+                            || members.iter().any(|directive| {
+                                is_graph(directive, graph)
+                                    && string_argument(directive, "member").as_deref()
+                                        == Some(member.as_str())
+                            })
+                    })
+                    .cloned()
+                    .collect(),
+                ..union.clone()
+            })
+        }
+        TypeDefinition::Enum(enum_type) => TypeDefinition::Enum(EnumType {
+            directives: Vec::new(),
+            values: enum_type
+                .values
+                .iter()
+                .filter(|value| in_graph_of(&value.directives, "join__enumValue", graph))
+                .map(|value| schema::EnumValue {
+                    directives: kept(&value.directives),
+                    ..value.clone()
+                })
+                .collect(),
+            ..enum_type.clone()
+        }),
+        TypeDefinition::InputObject(input) => TypeDefinition::InputObject(InputObjectType {
+            directives: Vec::new(),
+            fields: input
+                .fields
+                .iter()
+                .filter(|field| in_graph_of(&field.directives, "join__field", graph))
+                .map(|field| schema::InputValue {
+                    directives: kept(&field.directives),
+                    ..field.clone()
+                })
+                .collect(),
+            ..input.clone()
+        }),
+        TypeDefinition::Scalar(scalar) => TypeDefinition::Scalar(schema::ScalarType {
+            directives: Vec::new(),
+            ..scalar.clone()
+        }),
+    }
+}
+
+fn subgraph_fields<'a>(
+    fields: &[schema::Field<'a, String>],
+    graph: &str,
+) -> Vec<schema::Field<'a, String>> {
+    fields
+        .iter()
+        .filter(|field| in_graph_of(&field.directives, "join__field", graph))
+        .map(|field| {
+            let mut directives = kept(&field.directives);
+            let join = field
+                .directives
+                .iter()
+                .find(|directive| directive.name == "join__field" && is_graph(directive, graph));
+            if let Some(join) = join {
+                if join
+                    .arguments
+                    .iter()
+                    .any(|(name, value)| name == "external" && *value == Value::Boolean(true))
+                {
+                    directives.push(directive(join, "external", None));
+                }
+                for (argument, name, parameter) in [
+                    ("requires", "requires", "fields"),
+                    ("provides", "provides", "fields"),
+                    ("override", "override", "from"),
+                ] {
+                    if let Some(value) = string_argument(join, argument) {
+                        directives.push(directive(join, name, Some((parameter, value))));
+                    }
+                }
+            }
+            schema::Field {
+                directives,
+                arguments: field
+                    .arguments
+                    .iter()
+                    .map(|argument| schema::InputValue {
+                        directives: kept(&argument.directives),
+                        ..argument.clone()
+                    })
+                    .collect(),
+                ..field.clone()
+            }
+        })
+        .collect()
+}
+
+// `@key`s of the type in `graph`
+fn keys<'a>(directives: &[Directive<'a, String>], graph: &str) -> Vec<Directive<'a, String>> {
+    directives
+        .iter()
+        .filter(|join| join.name == "join__type" && is_graph(join, graph))
+        .filter_map(|join| {
+            string_argument(join, "key").map(|key| directive(join, "key", Some(("fields", key))))
+        })
+        .collect()
+}
+
+// Interfaces the type implements in `graph`, all of them without
+// `@join__implements`
+fn implemented(
+    directives: &[Directive<String>],
+    interfaces: &[String],
+    graph: &str,
+) -> Vec<String> {
+    let joins: Vec<&Directive<String>> = directives
+        .iter()
+        .filter(|directive| directive.name == "join__implements")
+        .collect();
+    if joins.is_empty() {
+        return interfaces.to_vec();
+    }
+    joins
+        .iter()
+        .filter(|directive| is_graph(directive, graph))
+        .filter_map(|directive| string_argument(directive, "interface"))
+        .collect()
+}
+
+// Directives subgraphs declare themselves, the supergraph's own are dropped
+fn kept<'a>(directives: &[Directive<'a, String>]) -> Vec<Directive<'a, String>> {
+    directives
+        .iter()
+        .filter(|directive| directive.name == "deprecated")
+        .cloned()
+        .collect()
+}
+
+fn directive<'a>(
+    at: &Directive<'a, String>,
+    name: &str,
+    argument: Option<(&str, String)>,
+) -> Directive<'a, String> {
+    Directive {
+        position: at.position,
+        name: name.to_string(),
+        arguments: argument
+            .map(|(name, value)| (name.to_string(), Value::String(value)))
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn in_graph(directives: &[Directive<String>], graph: &str) -> bool {
+    in_graph_of(directives, "join__type", graph)
+}
+
+// Whether directives named `join` place their element in `graph`, or there
+// are none of them
+fn in_graph_of(directives: &[Directive<String>], join: &str, graph: &str) -> bool {
+    let mut joins = directives
+        .iter()
+        .filter(|directive| directive.name == join)
+        .peekable();
+    joins.peek().is_none() || joins.any(|directive| is_graph(directive, graph))
+}
+
+fn is_graph(directive: &Directive<String>, graph: &str) -> bool {
+    directive.arguments.iter().any(|(name, value)| {
+        name == "graph" && matches!(value, Value::Enum(value) if value == graph)
+    })
+}
+
+fn string_argument(directive: &Directive<String>, argument: &str) -> Option<String> {
+    directive
+        .arguments
+        .iter()
+        .find_map(|(name, value)| match value {
+            Value::String(value) if name == argument => Some(value.clone()),
+            _ => None,
+        })
+}
+
+// Types composition adds for its own directives
+fn is_join_type(name: &str) -> bool {
+    name.starts_with("join__") || name.starts_with("link__")
+}
+
+fn type_name<'d>(definition: &'d TypeDefinition<String>) -> &'d str {
+    match definition {
+        TypeDefinition::Scalar(t) => &t.name,
+        TypeDefinition::Object(t) => &t.name,
+        TypeDefinition::Interface(t) => &t.name,
+        TypeDefinition::Union(t) => &t.name,
+        TypeDefinition::Enum(t) => &t.name,
+        TypeDefinition::InputObject(t) => &t.name,
+    }
+}
+
+fn type_directives<'d, 'a>(
+    definition: &'d TypeDefinition<'a, String>,
+) -> &'d [Directive<'a, String>] {
+    match definition {
+        TypeDefinition::Scalar(t) => &t.directives,
+        TypeDefinition::Object(t) => &t.directives,
+        TypeDefinition::Interface(t) => &t.directives,
+        TypeDefinition::Union(t) => &t.directives,
+        TypeDefinition::Enum(t) => &t.directives,
+        TypeDefinition::InputObject(t) => &t.directives,
+    }
+}
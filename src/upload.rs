@@ -0,0 +1,364 @@
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt, stream};
+use http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::GraphQLRequest;
+
+// Stands in for a file in the operation's variables until it's sent on to a
+// service, since `Upload!` variables can't be null while planning
+const PLACEHOLDER_PREFIX: &str = "portkey-upload:";
+
+// Spooled files are read back in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+static SPOOLED: AtomicU64 = AtomicU64::new(0);
+
+// Requests following the GraphQL multipart request spec: an `operations`
+// field, a `map` field naming the variables each file goes to, then the
+// files. Files are kept in memory up to `max_in_memory_bytes` and spooled to
+// disk beyond it, and are streamed on to the services whose fetches use them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub enabled: bool,
+    pub max_in_memory_bytes: usize,
+    // Where files are spooled, the system's temporary directory when unset
+    pub spool_dir: Option<PathBuf>,
+    // Requests with a larger file are rejected, unset doesn't limit them
+    pub max_file_bytes: Option<u64>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            enabled: true,
+            max_in_memory_bytes: 1024 * 1024,
+            spool_dir: None,
+            max_file_bytes: None,
+        }
+    }
+}
+
+// The files of a request, by the name of their field
+#[derive(Debug, Default)]
+pub struct Uploads {
+    files: BTreeMap<String, Upload>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    file_name: Option<String>,
+    content_type: Option<String>,
+    content: Content,
+}
+
+#[derive(Debug)]
+enum Content {
+    Memory(Bytes),
+    Spooled(SpooledFile),
+}
+
+// A file on disk, removed once the request is done with it
+#[derive(Debug)]
+struct SpooledFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// The multipart boundary of `multipart/form-data` requests
+pub fn boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get("Content-Type")?.to_str().ok()?;
+    multer::parse_boundary(content_type).ok()
+}
+
+// Reads the request and its files from a multipart body as it arrives. The
+// files' variables hold placeholders until the files are sent on.
+pub async fn parse<S, O, E>(
+    body: S,
+    boundary: String,
+    config: &UploadConfig,
+) -> Result<(GraphQLRequest, Uploads), String>
+where
+    S: Stream<Item = Result<O, E>> + Send + 'static,
+    O: Into<Bytes> + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+{
+    let mut multipart = multer::Multipart::new(body, boundary);
+    let mut request: Option<GraphQLRequest> = None;
+    let mut map: Option<HashMap<String, Vec<String>>> = None;
+    let mut uploads = Uploads::default();
+
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "operations" => {
+                let operations = field.text().await.map_err(invalid)?;
+                request = Some(serde_json::from_str(&operations).map_err(|e| {
+                    format!(
+                        "Invalid multipart request: `operations` must be a single operation: {}",
+                        e
+                    )
+                })?);
+            }
+            "map" => {
+                let text = field.text().await.map_err(invalid)?;
+                map = Some(
+                    serde_json::from_str(&text)
+                        .map_err(|e| format!("Invalid multipart request: invalid `map`: {}", e))?,
+                );
+            }
+            _ => {
+                let Some(map) = &map else {
+                    return Err(
+                        "Invalid multipart request: `operations` and `map` must precede the files"
+                            .to_string(),
+                    );
+                };
+                // Files nothing refers to are skipped unread
+                if map.contains_key(&name) {
+                    let upload = Upload::read(field, &name, config).await?;
+                    uploads.files.insert(name, upload);
+                }
+            }
+        }
+    }
+
+    let mut request =
+        request.ok_or("Invalid multipart request: missing `operations`".to_string())?;
+    let map = map.ok_or("Invalid multipart request: missing `map`".to_string())?;
+    for (name, paths) in &map {
+        if !uploads.files.contains_key(name) {
+            return Err(format!(
+                "Invalid multipart request: file {} is mapped but wasn't sent",
+                name
+            ));
+        }
+        for path in paths {
+            let variable = path
+                .strip_prefix("variables.")
+                .and_then(|path| variable_at(&mut request.variables, path))
+                .filter(|value| value.is_null())
+                .ok_or(format!(
+                    "Invalid multipart request: {} in `map` isn't a null variable",
+                    path
+                ))?;
+            *variable = Value::String(format!("{}{}", PLACEHOLDER_PREFIX, name));
+        }
+    }
+
+    Ok((request, uploads))
+}
+
+impl Uploads {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Paths of the files spooled to disk
+    pub fn spooled(&self) -> Vec<PathBuf> {
+        self.files
+            .values()
+            .filter_map(|upload| match &upload.content {
+                Content::Spooled(file) => Some(file.path.clone()),
+                Content::Memory(_) => None,
+            })
+            .collect()
+    }
+
+    // A multipart body for the request body a service would be sent, when
+    // its variables hold any of the files. Only those files are sent, each
+    // read anew from memory or disk.
+    pub fn form(&self, body: &Value) -> Option<reqwest::multipart::Form> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let mut operations = body.clone();
+        let mut map: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        if let Some(variables) = operations.get_mut("variables") {
+            self.take_files(variables, "variables".to_string(), &mut map);
+        }
+        if map.is_empty() {
+            return None;
+        }
+
+        let mut form = reqwest::multipart::Form::new()
+            .text("operations", operations.to_string())
+            .text("map", json!(map).to_string());
+        for name in map.keys() {
+            form = form.part(name.to_string(), self.files[*name].part());
+        }
+        Some(form)
+    }
+
+    // Puts back the nulls the placeholders stood in for, noting their paths
+    fn take_files<'a>(
+        &'a self,
+        value: &mut Value,
+        path: String,
+        map: &mut BTreeMap<&'a str, Vec<String>>,
+    ) {
+        match value {
+            Value::String(placeholder) => {
+                if let Some((name, _)) = placeholder
+                    .strip_prefix(PLACEHOLDER_PREFIX)
+                    .and_then(|name| self.files.get_key_value(name))
+                {
+                    map.entry(name.as_str()).or_default().push(path);
+                    *value = Value::Null;
+                }
+            }
+            Value::Object(fields) => {
+                for (key, field) in fields {
+                    self.take_files(field, format!("{}.{}", path, key), map);
+                }
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    self.take_files(item, format!("{}.{}", path, i), map);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Upload {
+    async fn read(
+        mut field: multer::Field<'static>,
+        name: &str,
+        config: &UploadConfig,
+    ) -> Result<Self, String> {
+        let file_name = field.file_name().map(str::to_string);
+        let content_type = field.content_type().map(ToString::to_string);
+        let mut buffer = BytesMut::new();
+        let mut spooled: Option<(tokio::fs::File, SpooledFile)> = None;
+        let mut len = 0;
+
+        while let Some(chunk) = field.chunk().await.map_err(invalid)? {
+            len += chunk.len() as u64;
+            if let Some(max) = config.max_file_bytes
+                && len > max
+            {
+                return Err(format!(
+                    "File {} is larger than the limit of {} bytes",
+                    name, max
+                ));
+            }
+
+            if let Some((file, _)) = &mut spooled {
+                file.write_all(&chunk).await.map_err(spool_error)?;
+            } else if buffer.len() + chunk.len() > config.max_in_memory_bytes {
+                let (mut file, spooled_file) = SpooledFile::create(config).await?;
+                file.write_all(&buffer).await.map_err(spool_error)?;
+                file.write_all(&chunk).await.map_err(spool_error)?;
+                buffer = BytesMut::new();
+                spooled = Some((file, spooled_file));
+            } else {
+                buffer.extend_from_slice(&chunk);
+            }
+        }
+
+        let content = match spooled {
+            Some((mut file, mut spooled_file)) => {
+                file.flush().await.map_err(spool_error)?;
+                spooled_file.len = len;
+                Content::Spooled(spooled_file)
+            }
+            None => Content::Memory(buffer.freeze()),
+        };
+        Ok(Upload {
+            file_name,
+            content_type,
+            content,
+        })
+    }
+
+    fn part(&self) -> reqwest::multipart::Part {
+        let mut part = match &self.content {
+            Content::Memory(bytes) => reqwest::multipart::Part::stream_with_length(
+                reqwest::Body::from(bytes.clone()),
+                bytes.len() as u64,
+            ),
+            Content::Spooled(file) => reqwest::multipart::Part::stream_with_length(
+                reqwest::Body::wrap_stream(read_file(file.path.clone())),
+                file.len,
+            ),
+        };
+        if let Some(content_type) = self
+            .content_type
+            .as_deref()
+            .and_then(|content_type| HeaderValue::from_str(content_type).ok())
+        {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type", content_type);
+            part = part.headers(headers);
+        }
+        match &self.file_name {
+            Some(file_name) => part.file_name(file_name.clone()),
+            None => part,
+        }
+    }
+}
+
+impl SpooledFile {
+    async fn create(config: &UploadConfig) -> Result<(tokio::fs::File, Self), String> {
+        let dir = config.spool_dir.clone().unwrap_or_else(std::env::temp_dir);
+        let path = dir.join(format!(
+            "portkey-upload-{}-{}",
+            std::process::id(),
+            SPOOLED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .map_err(spool_error)?;
+        Ok((file, SpooledFile { path, len: 0 }))
+    }
+}
+
+fn read_file(path: PathBuf) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    stream::once(tokio::fs::File::open(path))
+        .map_ok(|file| {
+            stream::try_unfold(file, |mut file| async move {
+                let mut chunk = BytesMut::with_capacity(CHUNK_SIZE);
+                match file.read_buf(&mut chunk).await? {
+                    0 => Ok(None),
+                    _ => Ok(Some((chunk.freeze(), file))),
+                }
+            })
+        })
+        .try_flatten()
+}
+
+// The value at a dotted path into the variables, list items by their index
+fn variable_at<'v>(variables: &'v mut Option<Value>, path: &str) -> Option<&'v mut Value> {
+    path.split('.')
+        .try_fold(variables.as_mut()?, |value, segment| match value {
+            Value::Object(fields) => fields.get_mut(segment),
+            Value::Array(items) => items.get_mut(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+fn invalid(e: multer::Error) -> String {
+    format!("Invalid multipart request: {}", e)
+}
+
+fn spool_error(e: io::Error) -> String {
+    format!("Failed to spool upload to disk: {}", e)
+}
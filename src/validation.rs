@@ -0,0 +1,521 @@
+use graphql_parser::Pos;
+use graphql_parser::query::{
+    self, Definition, Document, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+    TypeCondition, VariableDefinition,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::FederatedSchema;
+use crate::introspection::{IntrospectionSchema, is_introspection_field};
+
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ValidationError {
+    pub fn new(position: Pos, message: String) -> Self {
+        ValidationError {
+            message,
+            line: position.line,
+            column: position.column,
+        }
+    }
+}
+
+// A custom static check, e.g. requiring pagination arguments on list fields or
+// banning fields. Rules run on operations passing the built-in validation,
+// before they are planned, and reject them by returning errors.
+pub trait ValidationRule: Send + Sync {
+    fn validate(
+        &self,
+        document: &Document<'_, String>,
+        operation_name: Option<&str>,
+        schema: &FederatedSchema,
+    ) -> Vec<ValidationError>;
+}
+
+// Runs the custom rules over the query, in order, collecting all their errors
+pub fn validate_rules(
+    query: &str,
+    operation_name: Option<&str>,
+    schema: &FederatedSchema,
+    rules: &[Arc<dyn ValidationRule>],
+) -> Vec<ValidationError> {
+    if rules.is_empty() {
+        return Vec::new();
+    }
+    let Ok(doc) = query::parse_query::<String>(query) else {
+        return Vec::new();
+    };
+
+    rules
+        .iter()
+        .flat_map(|rule| rule.validate(&doc, operation_name, schema))
+        .collect()
+}
+
+// Checks the selected operation against the composed schema: fields must exist
+// on their parent type, arguments must exist and hold values of the right type,
+// and variables must be defined with a type that fits where they're used.
+// Queries that don't parse or don't select an operation are left for the
+// planner to reject.
+pub fn validate(
+    query: &str,
+    operation_name: Option<&str>,
+    schema: &IntrospectionSchema,
+) -> Vec<ValidationError> {
+    let Ok(doc) = query::parse_query::<String>(query) else {
+        return Vec::new();
+    };
+
+    let fragments: HashMap<&str, &FragmentDefinition<String>> = doc
+        .definitions
+        .iter()
+        .filter_map(|def| match def {
+            Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+            _ => None,
+        })
+        .collect();
+
+    let operations: Vec<&OperationDefinition<String>> = doc
+        .definitions
+        .iter()
+        .filter_map(|def| match def {
+            Definition::Operation(operation) => Some(operation),
+            _ => None,
+        })
+        .collect();
+    let operation = match operation_name {
+        Some(name) => operations.into_iter().find(|operation| match operation {
+            OperationDefinition::Query(q) => q.name.as_deref() == Some(name),
+            OperationDefinition::Mutation(m) => m.name.as_deref() == Some(name),
+            OperationDefinition::Subscription(s) => s.name.as_deref() == Some(name),
+            OperationDefinition::SelectionSet(_) => false,
+        }),
+        None if operations.len() == 1 => operations.into_iter().next(),
+        None => None,
+    };
+
+    let (root_type, selection_set, variable_defs) = match operation {
+        Some(OperationDefinition::SelectionSet(s)) => ("Query", s, &[][..]),
+        Some(OperationDefinition::Query(q)) => {
+            ("Query", &q.selection_set, &q.variable_definitions[..])
+        }
+        Some(OperationDefinition::Mutation(m)) => {
+            ("Mutation", &m.selection_set, &m.variable_definitions[..])
+        }
+        Some(OperationDefinition::Subscription(s)) => (
+            "Subscription",
+            &s.selection_set,
+            &s.variable_definitions[..],
+        ),
+        None => return Vec::new(),
+    };
+
+    let mut validator = Validator {
+        schema,
+        fragments: &fragments,
+        variable_defs,
+        visited_fragments: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for definition in variable_defs {
+        let type_name = named_type(&definition.var_type);
+        if schema.get_type(type_name).is_none() {
+            validator.error(
+                definition.position,
+                format!("Unknown type \"{}\".", type_name),
+            );
+        }
+    }
+
+    // A subscription streams the events of exactly one root field
+    if let Some(OperationDefinition::Subscription(subscription)) = operation {
+        let mut root_fields = Vec::new();
+        collect_root_fields(selection_set, &fragments, &mut Vec::new(), &mut root_fields);
+        if let Some(extra) = root_fields.get(1) {
+            let message = match &subscription.name {
+                Some(name) => format!(
+                    "Subscription \"{}\" must select only one top level field.",
+                    name
+                ),
+                None => "Anonymous Subscription must select only one top level field.".to_string(),
+            };
+            validator.error(extra.position, message);
+        }
+    }
+
+    match schema.get_type(root_type) {
+        Some(_) => validator.validate_selection_set(selection_set, root_type),
+        None => validator.error(
+            selection_set.span.0,
+            format!(
+                "Schema is not configured for {} operations.",
+                root_type.to_lowercase()
+            ),
+        ),
+    }
+
+    validator.errors
+}
+
+struct Validator<'v, 'q> {
+    schema: &'v IntrospectionSchema,
+    fragments: &'v HashMap<&'q str, &'v FragmentDefinition<'q, String>>,
+    variable_defs: &'v [VariableDefinition<'q, String>],
+    // Guards against fragments spreading themselves, which the planner reports
+    visited_fragments: Vec<&'v str>,
+    errors: Vec<ValidationError>,
+}
+
+impl<'v, 'q> Validator<'v, 'q> {
+    fn error(&mut self, position: Pos, message: String) {
+        self.errors.push(ValidationError::new(position, message));
+    }
+
+    fn validate_selection_set(
+        &mut self,
+        selection_set: &'v SelectionSet<'q, String>,
+        parent_type: &str,
+    ) {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => self.validate_field(field, parent_type),
+                Selection::InlineFragment(fragment) => {
+                    let type_name = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_name)) => type_name.as_str(),
+                        None => parent_type,
+                    };
+                    if self.schema.get_type(type_name).is_none() {
+                        self.error(
+                            fragment.position,
+                            format!("Unknown type \"{}\".", type_name),
+                        );
+                        continue;
+                    }
+                    self.validate_selection_set(&fragment.selection_set, type_name);
+                }
+                Selection::FragmentSpread(spread) => {
+                    let Some(fragment) = self.fragments.get(spread.fragment_name.as_str()) else {
+                        continue;
+                    };
+                    if self.visited_fragments.contains(&fragment.name.as_str()) {
+                        continue;
+                    }
+
+                    let TypeCondition::On(type_name) = &fragment.type_condition;
+                    if self.schema.get_type(type_name).is_none() {
+                        self.error(
+                            fragment.position,
+                            format!("Unknown type \"{}\".", type_name),
+                        );
+                        continue;
+                    }
+
+                    self.visited_fragments.push(&fragment.name);
+                    self.validate_selection_set(&fragment.selection_set, type_name);
+                    self.visited_fragments.pop();
+                }
+            }
+        }
+    }
+
+    fn validate_field(&mut self, field: &'v query::Field<'q, String>, parent_type: &str) {
+        // Introspection selections are resolved by the gateway itself
+        if field.name == "__typename"
+            || (parent_type == "Query" && is_introspection_field(&field.name))
+        {
+            return;
+        }
+
+        let Some(definition) = self.field_definition(parent_type, &field.name) else {
+            self.error(
+                field.position,
+                format!(
+                    "Cannot query field \"{}\" on type \"{}\".",
+                    field.name, parent_type
+                ),
+            );
+            return;
+        };
+        let coordinate = format!("{}.{}", parent_type, field.name);
+
+        let args = definition["args"].as_array().cloned().unwrap_or_default();
+        for (name, value) in &field.arguments {
+            match args.iter().find(|arg| arg["name"] == name.as_str()) {
+                Some(arg) => self.validate_value(field.position, value, &arg["type"]),
+                None => self.error(
+                    field.position,
+                    format!("Unknown argument \"{}\" on field \"{}\".", name, coordinate),
+                ),
+            }
+        }
+        for arg in &args {
+            let provided = field
+                .arguments
+                .iter()
+                .any(|(name, _)| arg["name"] == name.as_str());
+            if !provided && is_required(arg) {
+                self.error(
+                    field.position,
+                    format!(
+                        "Field \"{}\" argument \"{}\" of type \"{}\" is required, but it was not provided.",
+                        coordinate,
+                        arg["name"].as_str().unwrap_or_default(),
+                        type_ref_string(&arg["type"])
+                    ),
+                );
+            }
+        }
+
+        let field_type = &definition["type"];
+        let type_name = named_type_ref(field_type);
+        let is_leaf = self
+            .schema
+            .get_type(type_name)
+            .is_none_or(|ty| ty["kind"] == "SCALAR" || ty["kind"] == "ENUM");
+
+        match (is_leaf, field.selection_set.items.is_empty()) {
+            (true, false) => self.error(
+                field.position,
+                format!(
+                    "Field \"{}\" must not have a selection since type \"{}\" has no subfields.",
+                    field.name,
+                    type_ref_string(field_type)
+                ),
+            ),
+            (false, true) => self.error(
+                field.position,
+                format!(
+                    "Field \"{}\" of type \"{}\" must have a selection of subfields.",
+                    field.name,
+                    type_ref_string(field_type)
+                ),
+            ),
+            (false, false) => self.validate_selection_set(&field.selection_set, type_name),
+            (true, true) => {}
+        }
+    }
+
+    fn field_definition(&self, type_name: &str, field_name: &str) -> Option<Value> {
+        self.schema.get_type(type_name)?["fields"]
+            .as_array()?
+            .iter()
+            .find(|field| field["name"] == field_name)
+            .cloned()
+    }
+
+    // Checks an argument or input field value against its expected type ref
+    fn validate_value(
+        &mut self,
+        position: Pos,
+        value: &query::Value<'q, String>,
+        expected: &Value,
+    ) {
+        if let query::Value::Variable(name) = value {
+            let Some(definition) = self.variable_defs.iter().find(|def| &def.name == name) else {
+                self.error(position, format!("Variable \"${}\" is not defined.", name));
+                return;
+            };
+
+            let nullable_with_default = definition.default_value.is_some()
+                && !matches!(definition.var_type, query::Type::NonNullType(_));
+            let expected = match expected["kind"].as_str() {
+                Some("NON_NULL") if nullable_with_default => &expected["ofType"],
+                _ => expected,
+            };
+            if !is_compatible(&definition.var_type, expected) {
+                self.error(
+                    position,
+                    format!(
+                        "Variable \"${}\" of type \"{}\" used in position expecting type \"{}\".",
+                        name,
+                        definition.var_type,
+                        type_ref_string(expected)
+                    ),
+                );
+            }
+            return;
+        }
+
+        match (expected["kind"].as_str(), value) {
+            (Some("NON_NULL"), query::Value::Null) => self.invalid_value(position, value, expected),
+            (Some("NON_NULL"), _) => self.validate_value(position, value, &expected["ofType"]),
+            (_, query::Value::Null) => {}
+            (Some("LIST"), query::Value::List(items)) => {
+                for item in items {
+                    self.validate_value(position, item, &expected["ofType"]);
+                }
+            }
+            // A single value is coerced to a one-item list
+            (Some("LIST"), _) => self.validate_value(position, value, &expected["ofType"]),
+            (_, _) => {
+                let type_name = expected["name"].as_str().unwrap_or_default();
+                let Some(ty) = self.schema.get_type(type_name).cloned() else {
+                    return;
+                };
+
+                match (ty["kind"].as_str(), value) {
+                    (Some("SCALAR"), _) => {
+                        let valid = match (type_name, value) {
+                            ("Int", query::Value::Int(_)) => true,
+                            ("Float", query::Value::Int(_) | query::Value::Float(_)) => true,
+                            ("String", query::Value::String(_)) => true,
+                            ("Boolean", query::Value::Boolean(_)) => true,
+                            ("ID", query::Value::Int(_) | query::Value::String(_)) => true,
+                            ("Int" | "Float" | "String" | "Boolean" | "ID", _) => false,
+                            // Custom scalars define their own literals
+                            _ => true,
+                        };
+                        if !valid {
+                            self.invalid_value(position, value, expected);
+                        }
+                    }
+                    (Some("ENUM"), query::Value::Enum(name)) => {
+                        let known = ty["enumValues"].as_array().is_some_and(|values| {
+                            values.iter().any(|v| v["name"] == name.as_str())
+                        });
+                        if !known {
+                            self.invalid_value(position, value, expected);
+                        }
+                    }
+                    (Some("INPUT_OBJECT"), query::Value::Object(fields)) => {
+                        let input_fields =
+                            ty["inputFields"].as_array().cloned().unwrap_or_default();
+                        for (name, field_value) in fields {
+                            match input_fields.iter().find(|f| f["name"] == name.as_str()) {
+                                Some(input_field) => {
+                                    self.validate_value(position, field_value, &input_field["type"])
+                                }
+                                None => self.error(
+                                    position,
+                                    format!(
+                                        "Field \"{}\" is not defined by type \"{}\".",
+                                        name, type_name
+                                    ),
+                                ),
+                            }
+                        }
+                        for input_field in &input_fields {
+                            let name = input_field["name"].as_str().unwrap_or_default();
+                            if !fields.contains_key(name) && is_required(input_field) {
+                                self.error(
+                                    position,
+                                    format!(
+                                        "Field \"{}.{}\" of required type \"{}\" was not provided.",
+                                        type_name,
+                                        name,
+                                        type_ref_string(&input_field["type"])
+                                    ),
+                                );
+                            }
+                        }
+                    }
+                    _ => self.invalid_value(position, value, expected),
+                }
+            }
+        }
+    }
+
+    fn invalid_value(&mut self, position: Pos, value: &query::Value<'q, String>, expected: &Value) {
+        self.error(
+            position,
+            format!(
+                "Expected value of type \"{}\", found {}.",
+                type_ref_string(expected),
+                value
+            ),
+        );
+    }
+}
+
+// The root fields of an operation by response key, looking through fragments
+fn collect_root_fields<'v, 'q>(
+    selection_set: &'v SelectionSet<'q, String>,
+    fragments: &HashMap<&'q str, &'v FragmentDefinition<'q, String>>,
+    visited_fragments: &mut Vec<&'v str>,
+    root_fields: &mut Vec<&'v query::Field<'q, String>>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            Selection::Field(field) => {
+                let response_key = field.alias.as_ref().unwrap_or(&field.name);
+                if !root_fields
+                    .iter()
+                    .any(|f| f.alias.as_ref().unwrap_or(&f.name) == response_key)
+                {
+                    root_fields.push(field);
+                }
+            }
+            Selection::InlineFragment(fragment) => {
+                collect_root_fields(
+                    &fragment.selection_set,
+                    fragments,
+                    visited_fragments,
+                    root_fields,
+                );
+            }
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = fragments.get(spread.fragment_name.as_str())
+                    && !visited_fragments.contains(&fragment.name.as_str())
+                {
+                    visited_fragments.push(&fragment.name);
+                    collect_root_fields(
+                        &fragment.selection_set,
+                        fragments,
+                        visited_fragments,
+                        root_fields,
+                    );
+                    visited_fragments.pop();
+                }
+            }
+        }
+    }
+}
+
+fn is_required(input: &Value) -> bool {
+    input["type"]["kind"] == "NON_NULL" && input["defaultValue"].is_null()
+}
+
+// Whether a variable of `var_type` may be used where `expected` is expected
+fn is_compatible(var_type: &query::Type<String>, expected: &Value) -> bool {
+    match (var_type, expected["kind"].as_str()) {
+        (query::Type::NonNullType(inner), Some("NON_NULL")) => {
+            is_compatible(inner, &expected["ofType"])
+        }
+        (_, Some("NON_NULL")) => false,
+        (query::Type::NonNullType(inner), _) => is_compatible(inner, expected),
+        (query::Type::ListType(inner), Some("LIST")) => is_compatible(inner, &expected["ofType"]),
+        (query::Type::ListType(_), _) | (_, Some("LIST")) => false,
+        (query::Type::NamedType(name), _) => expected["name"] == name.as_str(),
+    }
+}
+
+fn named_type<'a>(var_type: &'a query::Type<String>) -> &'a str {
+    match var_type {
+        query::Type::NamedType(name) => name,
+        query::Type::ListType(inner) | query::Type::NonNullType(inner) => named_type(inner),
+    }
+}
+
+fn named_type_ref(type_ref: &Value) -> &str {
+    match type_ref["kind"].as_str() {
+        Some("NON_NULL" | "LIST") => named_type_ref(&type_ref["ofType"]),
+        _ => type_ref["name"].as_str().unwrap_or_default(),
+    }
+}
+
+// `[Int!]!` style rendering of an introspection type ref
+fn type_ref_string(type_ref: &Value) -> String {
+    match type_ref["kind"].as_str() {
+        Some("NON_NULL") => format!("{}!", type_ref_string(&type_ref["ofType"])),
+        Some("LIST") => format!("[{}]", type_ref_string(&type_ref["ofType"])),
+        _ => type_ref["name"].as_str().unwrap_or_default().to_string(),
+    }
+}
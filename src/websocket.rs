@@ -0,0 +1,500 @@
+use futures::{
+    SinkExt, StreamExt,
+    stream::{self, BoxStream},
+};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{
+    Message,
+    client::IntoClientRequest,
+    handshake::derive_accept_key,
+    http::{HeaderName, HeaderValue},
+    protocol::{CloseFrame, Role, WebSocketConfig as ProtocolConfig, frame::coding::CloseCode},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    GraphQLRequest,
+    federation_gateway::{FederationGateway, is_subscription},
+    subgraph_hook::SubgraphRequest,
+    subscription_limit::SubscriptionLimiter,
+};
+
+// The subprotocol of GraphQL over WebSocket, spoken with clients on
+// `/graphql` and with services taking subscriptions over WebSockets
+pub const GRAPHQL_TRANSPORT_WS: &str = "graphql-transport-ws";
+
+// The only subscription on a connection to a service
+const SUBSCRIPTION_ID: &str = "1";
+
+// Credentials a client may pass in the payload of `connection_init`, for
+// clients that can't set headers on the upgrade request
+const AUTH_HEADERS: [&str; 3] = ["Authorization", "x-api-key", "x-token"];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    // Accept WebSocket upgrades on `/graphql`
+    pub enabled: bool,
+    // Clients not sending `connection_init` within this long are disconnected
+    pub connection_init_timeout_ms: u64,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            enabled: true,
+            connection_init_timeout_ms: 3000,
+        }
+    }
+}
+
+// Opens subscriptions on services speaking graphql-transport-ws. Every
+// subscription gets a connection of its own, so closing the connection ends
+// the subscription at the service.
+#[derive(Clone, Debug)]
+pub struct SubscriptionExecutor {
+    // Bounds the handshake and the wait for `connection_ack`
+    connect_timeout: Duration,
+}
+
+impl SubscriptionExecutor {
+    pub fn new(connect_timeout: Duration) -> Self {
+        SubscriptionExecutor { connect_timeout }
+    }
+
+    // Connects to the request's URL, its http scheme swapped for ws, and
+    // subscribes. The request's headers go along with the handshake and as
+    // the payload of `connection_init`. Every item is the payload of a `next`
+    // message, or the errors of an `error` message ending the stream.
+    // Dropping the stream completes the subscription and closes the
+    // connection.
+    pub async fn subscribe(
+        &self,
+        request: &SubgraphRequest,
+        max_message_size: usize,
+    ) -> Result<BoxStream<'static, Value>, String> {
+        let url = websocket_url(&request.url);
+        let mut handshake = url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| format!("Invalid WebSocket URL {}: {}", url, e))?;
+        handshake.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(GRAPHQL_TRANSPORT_WS),
+        );
+        for (name, value) in &request.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                handshake.headers_mut().insert(name, value);
+            }
+        }
+
+        let config = ProtocolConfig::default().max_message_size(Some(max_message_size));
+        let connect = tokio_tungstenite::connect_async_with_config(handshake, Some(config), false);
+        let (mut socket, _) = tokio::time::timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", url))?
+            .map_err(|e| format!("WebSocket connection failed: {}", e))?;
+
+        send(
+            &mut socket,
+            json!({ "type": "connection_init", "payload": request.headers }),
+        )
+        .await?;
+        tokio::time::timeout(self.connect_timeout, async {
+            loop {
+                let message = receive(&mut socket).await?;
+                match message["type"].as_str() {
+                    Some("connection_ack") => return Ok(()),
+                    Some("ping") => send(&mut socket, json!({ "type": "pong" })).await?,
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            format!(
+                "Timed out waiting for {} to acknowledge the connection",
+                url
+            )
+        })
+        .and_then(|acknowledged: Result<(), String>| acknowledged)?;
+
+        send(
+            &mut socket,
+            json!({
+                "id": SUBSCRIPTION_ID,
+                "type": "subscribe",
+                "payload": { "query": request.query, "variables": request.variables },
+            }),
+        )
+        .await?;
+        debug!("Subscribed over WebSocket: {}", request.service_name);
+
+        // Forwarded from a task so the subscription is completed as soon as
+        // the stream is dropped, not once the next event arrives
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(forward_events(socket, sender));
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            let event = receiver.recv().await?;
+            Some((event, receiver))
+        })
+        .boxed())
+    }
+}
+
+async fn forward_events<S>(mut socket: WebSocketStream<S>, events: mpsc::Sender<Value>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let message = tokio::select! {
+            message = receive(&mut socket) => message,
+            _ = events.closed() => {
+                let _ = send(&mut socket, json!({ "id": SUBSCRIPTION_ID, "type": "complete" })).await;
+                break;
+            }
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Subscription connection ended: {}", e);
+                break;
+            }
+        };
+
+        let event = match message["type"].as_str() {
+            Some("next") => message["payload"].clone(),
+            Some("error") => json!({ "errors": message["payload"] }),
+            Some("complete") => break,
+            Some("ping") => {
+                if send(&mut socket, json!({ "type": "pong" })).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let last = message["type"] == "error";
+        if events.send(event).await.is_err() || last {
+            break;
+        }
+    }
+    let _ = socket.close(None).await;
+}
+
+// The next protocol message, skipping control frames
+async fn receive<S>(socket: &mut WebSocketStream<S>) -> Result<Value, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Text(text))) => {
+                return serde_json::from_str(text.as_str())
+                    .map_err(|e| format!("Invalid message: {}", e));
+            }
+            Some(Ok(Message::Close(_))) | None => return Err("Connection closed".to_string()),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.to_string()),
+        }
+    }
+}
+
+async fn send<S>(socket: &mut WebSocketStream<S>, message: Value) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    socket
+        .send(Message::text(message.to_string()))
+        .await
+        .map_err(|e| format!("Failed to send message: {}", e))
+}
+
+fn websocket_url(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        url.to_string()
+    }
+}
+
+// Whether the request asks to upgrade the connection to a WebSocket
+pub fn is_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get("Upgrade")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+// The `Sec-WebSocket-Accept` value answering an upgrade request, which must
+// offer graphql-transport-ws
+pub fn accept_key(headers: &HeaderMap) -> Result<String, String> {
+    let offers_protocol = headers
+        .get_all("Sec-WebSocket-Protocol")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == GRAPHQL_TRANSPORT_WS);
+    if !offers_protocol {
+        return Err(format!(
+            "WebSocket clients must use the {} subprotocol",
+            GRAPHQL_TRANSPORT_WS
+        ));
+    }
+    headers
+        .get("Sec-WebSocket-Key")
+        .map(|key| derive_accept_key(key.as_bytes()))
+        .ok_or_else(|| "Missing Sec-WebSocket-Key header".to_string())
+}
+
+// What the upgrade request carried, applying to every operation on the
+// connection
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionContext {
+    // Subscriptions are limited per connection like over HTTP
+    pub id: u64,
+    pub auth_headers: Option<HashMap<String, String>>,
+    pub client_name: Option<String>,
+    pub accept_language: Option<String>,
+    pub region: Option<String>,
+}
+
+impl ConnectionContext {
+    // Credentials in the `connection_init` payload, at its top level or under
+    // `headers`, take precedence over the upgrade request's
+    fn authenticate(&mut self, payload: &Value) {
+        for credentials in [payload, &payload["headers"]] {
+            let Some(credentials) = credentials.as_object() else {
+                continue;
+            };
+            for (name, value) in credentials {
+                let (Some(header), Some(value)) = (
+                    AUTH_HEADERS
+                        .iter()
+                        .find(|header| header.eq_ignore_ascii_case(name)),
+                    value.as_str(),
+                ) else {
+                    continue;
+                };
+                self.auth_headers
+                    .get_or_insert_with(HashMap::new)
+                    .insert(header.to_string(), value.to_string());
+            }
+        }
+    }
+
+    fn request(&self, payload: &Value) -> Option<GraphQLRequest> {
+        let mut request = serde_json::from_value::<GraphQLRequest>(payload.clone()).ok()?;
+        request.auth_headers = self.auth_headers.clone();
+        request.client_name = self.client_name.clone();
+        request.accept_language = self.accept_language.clone();
+        request.region = self.region.clone();
+        Some(request)
+    }
+}
+
+// Speaks graphql-transport-ws with a client over `stream`, the connection as
+// upgraded once the handshake is answered. Every `subscribe` message runs
+// its operation until it completes, the client completes it or the
+// connection closes.
+pub async fn serve<S>(
+    stream: S,
+    gateway: Arc<FederationGateway>,
+    subscriptions: Arc<SubscriptionLimiter>,
+    mut connection: ConnectionContext,
+    config: &WebSocketConfig,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+
+    // Messages of the operations, tagged so none of an operation the client
+    // completed reaches it once its id is reused
+    let (outbound_sender, mut outbound) = mpsc::channel::<(u64, Value)>(64);
+    let mut operations: HashMap<String, (u64, AbortHandle)> = HashMap::new();
+    let mut next_operation: u64 = 0;
+    let mut acknowledged = false;
+    let init_timeout = tokio::time::sleep(Duration::from_millis(config.connection_init_timeout_ms));
+    tokio::pin!(init_timeout);
+
+    let close: Option<(u16, String)> = loop {
+        tokio::select! {
+            _ = &mut init_timeout, if !acknowledged => {
+                break Some((4408, "Connection initialisation timeout".to_string()));
+            }
+            Some((operation, message)) = outbound.recv() => {
+                let id = message["id"].as_str().unwrap_or_default();
+                if operations.get(id).map(|(current, _)| *current) != Some(operation) {
+                    continue;
+                }
+                if matches!(message["type"].as_str(), Some("complete" | "error")) {
+                    operations.remove(id);
+                }
+                if send(&mut socket, message).await.is_err() {
+                    break None;
+                }
+            }
+            message = socket.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Binary(_))) => {
+                        break Some((4400, "Binary messages are not supported".to_string()));
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                    Some(Ok(_)) => continue,
+                };
+                let Ok(message) = serde_json::from_str::<Value>(text.as_str()) else {
+                    break Some((4400, "Invalid message".to_string()));
+                };
+
+                match message["type"].as_str() {
+                    Some("connection_init") => {
+                        if acknowledged {
+                            break Some((4429, "Too many initialisation requests".to_string()));
+                        }
+                        connection.authenticate(&message["payload"]);
+                        acknowledged = true;
+                        if send(&mut socket, json!({ "type": "connection_ack" })).await.is_err() {
+                            break None;
+                        }
+                    }
+                    Some("ping") => {
+                        if send(&mut socket, json!({ "type": "pong" })).await.is_err() {
+                            break None;
+                        }
+                    }
+                    Some("pong") => {}
+                    Some("subscribe") => {
+                        if !acknowledged {
+                            break Some((4401, "Unauthorized".to_string()));
+                        }
+                        let (Some(id), Some(request)) =
+                            (message["id"].as_str(), connection.request(&message["payload"]))
+                        else {
+                            break Some((4400, "Invalid subscribe message".to_string()));
+                        };
+                        if operations.contains_key(id) {
+                            break Some((4409, format!("Subscriber for {} already exists", id)));
+                        }
+
+                        let operation = next_operation;
+                        next_operation += 1;
+                        let task = tokio::spawn(run_operation(
+                            Arc::clone(&gateway),
+                            Arc::clone(&subscriptions),
+                            connection.clone(),
+                            id.to_string(),
+                            request,
+                            operation,
+                            outbound_sender.clone(),
+                        ));
+                        operations.insert(id.to_string(), (operation, task.abort_handle()));
+                    }
+                    Some("complete") => {
+                        if let Some((_, task)) = message["id"]
+                            .as_str()
+                            .and_then(|id| operations.remove(id))
+                        {
+                            task.abort();
+                        }
+                    }
+                    _ => break Some((4400, "Invalid message".to_string())),
+                }
+            }
+        }
+    };
+
+    // Dropping the event streams closes the subscriptions at the services
+    for (_, task) in operations.into_values() {
+        task.abort();
+    }
+    if let Some((code, reason)) = close {
+        debug!("Closing WebSocket connection: {} {}", code, reason);
+        let _ = socket
+            .close(Some(CloseFrame {
+                code: CloseCode::from(code),
+                reason: reason.into(),
+            }))
+            .await;
+    }
+}
+
+// Runs the operation of a `subscribe` message, sending its responses as
+// `next` messages and ending with `complete`. Errors raised before the
+// operation runs, like validation errors, end it with an `error` message.
+async fn run_operation(
+    gateway: Arc<FederationGateway>,
+    subscriptions: Arc<SubscriptionLimiter>,
+    connection: ConnectionContext,
+    id: String,
+    request: GraphQLRequest,
+    operation: u64,
+    outbound: mpsc::Sender<(u64, Value)>,
+) {
+    let accept_language = connection.accept_language.as_deref();
+    let error = |errors: Value| {
+        (
+            operation,
+            json!({ "id": id, "type": "error", "payload": errors }),
+        )
+    };
+
+    let _permit = if is_subscription(&request.query, request.operation_name.as_deref()) {
+        let api_key = connection
+            .auth_headers
+            .as_ref()
+            .and_then(|headers| headers.get("x-api-key"));
+        match subscriptions.acquire(connection.id, api_key.map(String::as_str)) {
+            Ok(permit) => Some(permit),
+            Err(e) => {
+                let _ = outbound
+                    .send(error(json!([gateway
+                        .messages()
+                        .error(&e, accept_language)])))
+                    .await;
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut responses = match gateway.subscribe(request).await {
+        Ok(responses) => responses,
+        Err(e) => {
+            let _ = outbound
+                .send(error(json!([gateway
+                    .messages()
+                    .error(&e, accept_language)])))
+                .await;
+            return;
+        }
+    };
+    while let Some(response) = responses.next().await {
+        if response.get("data").is_none() && response["errors"].is_array() {
+            let _ = outbound.send(error(response["errors"].clone())).await;
+            return;
+        }
+        let next = json!({ "id": id, "type": "next", "payload": response });
+        if outbound.send((operation, next)).await.is_err() {
+            return;
+        }
+    }
+    let _ = outbound
+        .send((operation, json!({ "id": id, "type": "complete" })))
+        .await;
+}
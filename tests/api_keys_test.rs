@@ -0,0 +1,275 @@
+mod common;
+
+use common::MockService;
+use futures::StreamExt;
+use portkey::api_keys::{ApiKeyLimits, ApiKeys, FileApiKeyStore, InMemoryApiKeyStore, MintApiKey};
+use portkey::complexity::ComplexityConfig;
+use portkey::quota::{QuotaConfig, QuotaTracker};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry,
+    ServiceCapabilities, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+
+const SCHEMA: &str = r#"
+type Query {
+    me: User
+}
+
+type User {
+    id: ID!
+    name: String!
+}
+"#;
+
+fn mint(name: &str, limits: ApiKeyLimits) -> MintApiKey {
+    MintApiKey {
+        name: name.to_string(),
+        tier: Some("free".to_string()),
+        limits,
+    }
+}
+
+fn request(query: &str, api_key: Option<&str>) -> GraphQLRequest {
+    GraphQLRequest {
+        auth_headers: api_key
+            .map(|api_key| HashMap::from([("x-api-key".to_string(), api_key.to_string())])),
+        ..common::request(query)
+    }
+}
+
+#[tokio::test]
+async fn test_mints_lists_and_revokes_keys() {
+    let api_keys = ApiKeys::new(Box::new(InMemoryApiKeyStore::new()));
+
+    let minted = api_keys
+        .mint(mint("dashboard", ApiKeyLimits::default()))
+        .await
+        .unwrap();
+    assert!(minted.secret.starts_with("pk_"));
+    assert_eq!(minted.key.name, "dashboard");
+    assert_eq!(minted.key.tier.as_deref(), Some("free"));
+    assert_eq!(api_keys.list().await.unwrap(), vec![minted.key.clone()]);
+
+    let other = api_keys
+        .mint(mint("mobile", ApiKeyLimits::default()))
+        .await
+        .unwrap();
+    assert_ne!(other.secret, minted.secret);
+    assert_ne!(other.key.id, minted.key.id);
+
+    assert_eq!(
+        api_keys.authenticate(Some(&minted.secret)).await.unwrap(),
+        minted.key
+    );
+    assert!(api_keys.authenticate(Some("pk_unknown")).await.is_err());
+    assert!(api_keys.authenticate(None).await.is_err());
+
+    let revoked = api_keys.revoke(&minted.key.id).await.unwrap().unwrap();
+    assert!(revoked.revoked_at.is_some());
+    assert_eq!(
+        api_keys.authenticate(Some(&minted.secret)).await,
+        Err("Missing or invalid API key".to_string())
+    );
+    assert!(api_keys.authenticate(Some(&other.secret)).await.is_ok());
+    assert!(api_keys.revoke("key_missing").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_file_store_keeps_keys_across_restarts() {
+    let path = std::env::temp_dir().join(format!("portkey-api-keys-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let api_keys = ApiKeys::new(Box::new(FileApiKeyStore::open(&path).unwrap()));
+    let minted = api_keys
+        .mint(mint("dashboard", ApiKeyLimits::default()))
+        .await
+        .unwrap();
+    let revoked = api_keys
+        .mint(mint("legacy", ApiKeyLimits::default()))
+        .await
+        .unwrap();
+    api_keys.revoke(&revoked.key.id).await.unwrap();
+
+    // Only the hash of the secret is written
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(!contents.contains(&minted.secret));
+
+    let reopened = ApiKeys::new(Box::new(FileApiKeyStore::open(&path).unwrap()));
+    assert_eq!(reopened.list().await.unwrap().len(), 2);
+    assert_eq!(
+        reopened.authenticate(Some(&minted.secret)).await.unwrap(),
+        minted.key
+    );
+    assert!(reopened.authenticate(Some(&revoked.secret)).await.is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_gateway_requires_valid_keys_and_applies_their_limits() {
+    let users =
+        MockService::start(|_| json!({ "data": { "me": { "id": "1", "name": "Ada" } } })).await;
+    let api_keys = ApiKeys::new(Box::new(InMemoryApiKeyStore::new()));
+    let limited = api_keys
+        .mint(mint(
+            "limited",
+            ApiKeyLimits {
+                max_cost: Some(2),
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap();
+    let unlimited = api_keys
+        .mint(mint("unlimited", ApiKeyLimits::default()))
+        .await
+        .unwrap();
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_complexity(ComplexityConfig {
+        enabled: true,
+        ..Default::default()
+    })
+    .with_api_keys(api_keys);
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(request("{ me { id } }", None))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "Missing or invalid API key",
+                "extensions": { "code": "UNAUTHENTICATED" },
+            }]
+        })
+    );
+
+    let response = gateway
+        .process_request(request("{ me { id } }", Some(&limited.secret)))
+        .await
+        .unwrap();
+    assert_eq!(response["data"], json!({ "me": { "id": "1" } }));
+
+    // me (1) + id (1) + name (1) is over the key's own maximum
+    let response = gateway
+        .process_request(request("{ me { id name } }", Some(&limited.secret)))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"],
+        "OPERATION_TOO_COMPLEX"
+    );
+
+    let response = gateway
+        .process_request(request("{ me { id name } }", Some(&unlimited.secret)))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "me": { "id": "1", "name": "Ada" } })
+    );
+    assert_eq!(users.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_subscriptions_are_held_to_key_limits() {
+    const SUBSCRIPTION_SCHEMA: &str = r#"
+type Query {
+    me: User
+}
+
+type Subscription {
+    userUpdated: User
+}
+
+type User {
+    id: ID!
+    name: String!
+}
+"#;
+    let users = MockService::start(|_| json!([])).await;
+    let api_keys = ApiKeys::new(Box::new(InMemoryApiKeyStore::new()));
+    let limited = api_keys
+        .mint(mint(
+            "limited",
+            ApiKeyLimits {
+                max_cost: Some(2),
+                quota: Some(3),
+            },
+        ))
+        .await
+        .unwrap();
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_complexity(ComplexityConfig {
+        enabled: true,
+        ..Default::default()
+    })
+    .with_quota(QuotaTracker::new(&QuotaConfig {
+        enabled: true,
+        limit: 100,
+        window_seconds: 60,
+    }))
+    .with_api_keys(api_keys);
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SUBSCRIPTION_SCHEMA.to_string(),
+            capabilities: ServiceCapabilities {
+                subscriptions: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // userUpdated (1) + id (1) + name (1) is over the key's own maximum
+    let mut events = gateway
+        .subscribe(request(
+            "subscription { userUpdated { id name } }",
+            Some(&limited.secret),
+        ))
+        .await
+        .unwrap();
+    let event = events.next().await.unwrap();
+    assert_eq!(
+        event["errors"][0]["extensions"]["code"],
+        "OPERATION_TOO_COMPLEX"
+    );
+
+    // The first subscription fits the key's quota, the second is over it
+    let subscription = "subscription { userUpdated { id } }";
+    let _subscribed = gateway
+        .subscribe(request(subscription, Some(&limited.secret)))
+        .await
+        .unwrap();
+    let mut events = gateway
+        .subscribe(request(subscription, Some(&limited.secret)))
+        .await
+        .unwrap();
+    let event = events.next().await.unwrap();
+    assert_eq!(event["errors"][0]["extensions"]["code"], "QUOTA_EXCEEDED");
+    assert_eq!(users.requests().len(), 1);
+}
@@ -0,0 +1,123 @@
+use futures::StreamExt;
+use portkey::broker::{Broker, TopicConfig};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Query {
+    reviews: [Review]
+}
+
+type Subscription {
+    reviewAdded: Review
+}
+
+type Review {
+    id: ID!
+    body: String!
+}
+"#;
+
+const INFO: &str = concat!(
+    r#"INFO {"server_id":"fake","server_name":"fake","version":"2.10.0","go":"go1.22","#,
+    r#""host":"127.0.0.1","port":4222,"headers":true,"max_payload":1048576,"proto":1}"#,
+    "\r\n"
+);
+
+// Speaks just enough of the NATS protocol to publish `messages` to the first
+// subscription made
+async fn fake_nats(messages: Vec<&'static str>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("nats://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        writer.write_all(INFO.as_bytes()).await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.starts_with("PING") {
+                writer.write_all(b"PONG\r\n").await.unwrap();
+            } else if let Some(subscription) = line.strip_prefix("SUB ") {
+                let parts: Vec<&str> = subscription.split_whitespace().collect();
+                let (subject, sid) = (parts[0], parts[parts.len() - 1]);
+                for message in &messages {
+                    let frame = format!(
+                        "MSG {} {} {}\r\n{}\r\n",
+                        subject,
+                        sid,
+                        message.len(),
+                        message
+                    );
+                    writer.write_all(frame.as_bytes()).await.unwrap();
+                }
+            }
+        }
+    });
+    url
+}
+
+#[tokio::test]
+async fn test_streams_messages_published_to_a_topic() {
+    let url = fake_nats(vec![
+        r#"{"id":"1","body":"Great","rating":5}"#,
+        "not json",
+        r#"{"id":"2","body":"Fine"}"#,
+    ])
+    .await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "reviews".to_string(),
+            url: "http://127.0.0.1:9/graphql".to_string(),
+            schema: REVIEWS_SCHEMA.to_string(),
+            topics: HashMap::from([(
+                "Subscription.reviewAdded".to_string(),
+                TopicConfig {
+                    broker: Broker::Nats,
+                    url,
+                    topic: "reviews.added".to_string(),
+                },
+            )]),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let events: Vec<Value> = gateway
+        .subscribe(GraphQLRequest {
+            query: "subscription { added: reviewAdded { body } }".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .take(3)
+        .collect()
+        .await;
+
+    assert_eq!(
+        events[0],
+        json!({ "data": { "added": { "body": "Great" } } })
+    );
+    assert_eq!(events[1]["data"], Value::Null);
+    assert!(
+        events[1]["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse message on reviews.added")
+    );
+    assert_eq!(
+        events[2],
+        json!({ "data": { "added": { "body": "Fine" } } })
+    );
+}
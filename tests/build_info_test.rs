@@ -0,0 +1,54 @@
+mod common;
+
+use common::{MockService, request};
+use portkey::build_info::{self, build_info};
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+#[test]
+fn test_reports_the_build() {
+    let info = build_info();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert!(info["rustc"].as_str().unwrap().starts_with("rustc"));
+    assert!(!info["gitSha"].as_str().unwrap().is_empty());
+    assert!(
+        chrono::DateTime::parse_from_rfc3339(info["builtAt"].as_str().unwrap()).is_ok(),
+        "{}",
+        info
+    );
+    assert_eq!(
+        info["features"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("test-suite")),
+        cfg!(feature = "test-suite")
+    );
+    assert!(build_info::banner().starts_with(&format!("portkey {}", build_info::VERSION)));
+}
+
+#[tokio::test]
+async fn test_reports_the_build_in_response_extensions() {
+    let products = MockService::start(|_| json!({ "data": { "hello": "world" } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_build_extension(true);
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: "type Query { hello: String }".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway.process_request(request("{ hello }")).await.unwrap();
+    assert_eq!(response["data"], json!({ "hello": "world" }));
+    assert_eq!(response["extensions"]["portkey"]["build"], build_info());
+}
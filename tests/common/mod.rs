@@ -0,0 +1,265 @@
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use http_body_util::{BodyExt, Full};
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use portkey::schema_registry::SchemaRegistry;
+use portkey::{
+    FederatedSchema, FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry,
+    ServiceConfig, SimpleQueryPlanner,
+};
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+type Handler = dyn Fn(&Value) -> Value + Send + Sync;
+
+// A client request for `query`, nothing else set
+pub fn request(query: &str) -> GraphQLRequest {
+    GraphQLRequest {
+        query: query.to_string(),
+        ..Default::default()
+    }
+}
+
+// Configs of the services, given as name, URL and schema
+fn service_configs(services: &[(&str, &str, &str)]) -> Vec<ServiceConfig> {
+    services
+        .iter()
+        .map(|(name, url, schema)| ServiceConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            schema: schema.to_string(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+// The schema composed of the services, given as name, URL and schema
+pub async fn build_schema(services: &[(&str, &str, &str)]) -> FederatedSchema {
+    let mut registry = InMemorySchemaRegistry::new();
+    for service in service_configs(services) {
+        registry.register_service(service).await.unwrap();
+    }
+    registry.get_schema().await.unwrap()
+}
+
+// A gateway with the default registry, planner and executor, serving the
+// services given as name, URL and schema
+pub async fn gateway(services: &[(&str, &str, &str)]) -> FederationGateway {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for service in service_configs(services) {
+        gateway.register_service(service).await.unwrap();
+    }
+    gateway
+}
+
+// A minimal GraphQL service answering every request with `handler`, recording
+// the request bodies and headers it received and counting the connections it
+// accepted. Handlers answering an event stream request with a list stream the
+// items as server-sent events. Multipart requests are seen as their
+// `operations`, `map` and `files`, by field name, and gzip or brotli request
+// bodies as they were before compression.
+pub struct MockService {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<Value>>>,
+    pub headers: Arc<Mutex<Vec<HeaderMap>>>,
+    pub connections: Arc<AtomicUsize>,
+}
+
+impl MockService {
+    pub async fn start(handler: impl Fn(&Value) -> Value + Send + Sync + 'static) -> Self {
+        Self::start_with(handler, false, StatusCode::OK).await
+    }
+
+    // Like `start`, but gzips responses for clients accepting it
+    pub async fn start_gzip(handler: impl Fn(&Value) -> Value + Send + Sync + 'static) -> Self {
+        Self::start_with(handler, true, StatusCode::OK).await
+    }
+
+    // Like `start`, but answers every request with `status`
+    pub async fn start_with_status(
+        status: StatusCode,
+        handler: impl Fn(&Value) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        Self::start_with(handler, false, status).await
+    }
+
+    async fn start_with(
+        handler: impl Fn(&Value) -> Value + Send + Sync + 'static,
+        gzip: bool,
+        status: StatusCode,
+    ) -> Self {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        let recorded_headers = Arc::clone(&headers);
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(_) => return,
+                };
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                let recorded_headers = Arc::clone(&recorded_headers);
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let handler = Arc::clone(&handler);
+                        let recorded = Arc::clone(&recorded);
+                        let recorded_headers = Arc::clone(&recorded_headers);
+                        async move {
+                            let request_headers = req.headers().clone();
+                            let body = req.collect().await.unwrap().to_bytes();
+                            let body = decode(body, &request_headers);
+                            let body: Value = match request_headers
+                                .get("content-type")
+                                .and_then(|value| value.to_str().ok())
+                                .and_then(|value| multer::parse_boundary(value).ok())
+                            {
+                                Some(boundary) => multipart_body(body, boundary).await,
+                                None => serde_json::from_slice(&body).unwrap(),
+                            };
+                            let response = handler(&body);
+                            recorded.lock().unwrap().push(body);
+
+                            // Subscriptions answer with a list of events,
+                            // sent as server-sent events
+                            let event_stream = request_headers
+                                .get("accept")
+                                .is_some_and(|value| value == "text/event-stream");
+                            if let (true, Value::Array(events)) = (event_stream, &response) {
+                                recorded_headers.lock().unwrap().push(request_headers);
+                                let mut body = String::from(": keep-alive\n\n");
+                                for event in events {
+                                    body.push_str(&format!(
+                                        "event: next\r\ndata: {}\r\n\r\n",
+                                        event
+                                    ));
+                                }
+                                body.push_str("event: complete\ndata:\n\n");
+                                return Ok::<_, Infallible>(
+                                    Response::builder()
+                                        .header("Content-Type", "text/event-stream")
+                                        .body(Full::new(Bytes::from(body)))
+                                        .unwrap(),
+                                );
+                            }
+                            let response = response.to_string();
+
+                            let accepts_gzip = request_headers
+                                .get("accept-encoding")
+                                .and_then(|value| value.to_str().ok())
+                                .is_some_and(|value| value.contains("gzip"));
+                            recorded_headers.lock().unwrap().push(request_headers);
+
+                            let response = if gzip && accepts_gzip {
+                                let mut encoder =
+                                    GzEncoder::new(Vec::new(), Compression::default());
+                                encoder.write_all(response.as_bytes()).unwrap();
+                                Response::builder()
+                                    .status(status)
+                                    .header("Content-Type", "application/json")
+                                    .header("Content-Encoding", "gzip")
+                                    .body(Full::new(Bytes::from(encoder.finish().unwrap())))
+                            } else {
+                                Response::builder()
+                                    .status(status)
+                                    .header("Content-Type", "application/json")
+                                    .body(Full::new(Bytes::from(response)))
+                            };
+
+                            Ok::<_, Infallible>(response.unwrap())
+                        }
+                    });
+
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        MockService {
+            url,
+            requests,
+            headers,
+            connections,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn headers(&self) -> Vec<HeaderMap> {
+        self.headers.lock().unwrap().clone()
+    }
+}
+
+fn decode(body: Bytes, headers: &HeaderMap) -> Bytes {
+    let mut decoded = Vec::new();
+    match headers
+        .get("content-encoding")
+        .and_then(|value| value.to_str().ok())
+    {
+        Some("gzip") => GzDecoder::new(&body[..]).read_to_end(&mut decoded),
+        Some("br") => brotli::Decompressor::new(&body[..], 4096).read_to_end(&mut decoded),
+        _ => return body,
+    }
+    .unwrap();
+    Bytes::from(decoded)
+}
+
+async fn multipart_body(body: Bytes, boundary: String) -> Value {
+    let mut multipart = multer::Multipart::new(
+        futures::stream::once(async { Ok::<_, Infallible>(body) }),
+        boundary,
+    );
+    let mut fields = serde_json::Map::new();
+    let mut files = serde_json::Map::new();
+    while let Some(field) = multipart.next_field().await.unwrap() {
+        let name = field.name().unwrap().to_string();
+        match name.as_str() {
+            "operations" | "map" => {
+                let value = serde_json::from_str(&field.text().await.unwrap()).unwrap();
+                fields.insert(name, value);
+            }
+            _ => {
+                let file = json!({
+                    "fileName": field.file_name(),
+                    "contentType": field.content_type().map(ToString::to_string),
+                    "content": field.text().await.unwrap(),
+                });
+                files.insert(name, file);
+            }
+        }
+    }
+    fields.insert("files".to_string(), Value::Object(files));
+    Value::Object(fields)
+}
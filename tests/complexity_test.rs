@@ -0,0 +1,142 @@
+mod common;
+
+use common::{MockService, request};
+use graphql_parser::parse_schema;
+use portkey::complexity::ComplexityConfig;
+use portkey::introspection::IntrospectionSchema;
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+
+const SCHEMA: &str = r#"
+type Query {
+    me: User
+    users(first: Int): [User!]!
+}
+
+type User {
+    id: ID!
+    name: String!
+    friends: [User]
+    avatar: Image
+}
+
+type Image {
+    url: String!
+}
+"#;
+
+fn schema() -> IntrospectionSchema {
+    IntrospectionSchema::compose(&[parse_schema::<String>(SCHEMA).unwrap()])
+}
+
+fn config() -> ComplexityConfig {
+    ComplexityConfig {
+        enabled: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_multiplies_list_selections_by_size() {
+    let schema = schema();
+    let config = config();
+
+    // me (1) + id (1) + name (1)
+    assert_eq!(
+        config.estimate("{ me { id name } }", None, &None, &schema),
+        3
+    );
+    // users (1) + 5 * (id (1) + friends (1) + 10 * name (1))
+    assert_eq!(
+        config.estimate(
+            "{ users(first: 5) { id friends { name } } }",
+            None,
+            &None,
+            &schema
+        ),
+        61
+    );
+    assert_eq!(
+        config.estimate(
+            "query($n: Int) { users(first: $n) { ...Fields __typename } } fragment Fields on User { id }",
+            None,
+            &Some(json!({ "n": 3 })),
+            &schema
+        ),
+        4
+    );
+}
+
+#[test]
+fn test_applies_field_and_type_weights() {
+    let config = ComplexityConfig {
+        weights: HashMap::from([("User.friends".to_string(), 20), ("Image".to_string(), 5)]),
+        default_list_size: 2,
+        ..config()
+    };
+
+    // me (1) + friends (20 + 2 * id (1)) + avatar (5 + url (1))
+    assert_eq!(
+        config.estimate(
+            "{ me { friends { id } avatar { url } } }",
+            None,
+            &None,
+            &schema()
+        ),
+        29
+    );
+}
+
+#[tokio::test]
+async fn test_gateway_rejects_operations_over_budget_and_reports_cost() {
+    let users = MockService::start(|_| json!({ "data": { "me": { "id": "1" } } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_complexity(ComplexityConfig {
+        max_cost: Some(10),
+        ..config()
+    });
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let response = gateway
+        .process_request(request("{ me { id } }"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "data": { "me": { "id": "1" } },
+            "extensions": { "cost": { "estimated": 2, "max": 10 } }
+        })
+    );
+
+    let response = gateway
+        .process_request(request("{ users { id name } }"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "Operation cost 21 exceeds the maximum cost of 10",
+                "extensions": { "code": "OPERATION_TOO_COMPLEX" }
+            }],
+            "extensions": { "cost": { "estimated": 21, "max": 10 } }
+        })
+    );
+    assert_eq!(users.requests().len(), 1);
+}
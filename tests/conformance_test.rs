@@ -0,0 +1,35 @@
+#![cfg(feature = "test-suite")]
+
+use portkey::conformance::{check_query_executor, check_query_planner, check_schema_registry};
+use portkey::plan_cache::CachingQueryPlanner;
+use portkey::{HttpQueryExecutor, InMemorySchemaRegistry, SimpleQueryPlanner};
+use std::num::NonZeroUsize;
+
+#[tokio::test]
+async fn test_in_memory_registry_conforms() {
+    check_schema_registry(&mut InMemorySchemaRegistry::new())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_planners_conform() {
+    check_query_planner(&SimpleQueryPlanner::new())
+        .await
+        .unwrap();
+
+    let caching = CachingQueryPlanner::new(
+        Box::new(SimpleQueryPlanner::new()),
+        NonZeroUsize::new(16).unwrap(),
+    );
+    check_query_planner(&caching).await.unwrap();
+    // Answered from the cache the second time around
+    check_query_planner(&caching).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_http_executor_conforms() {
+    check_query_executor(&HttpQueryExecutor::new())
+        .await
+        .unwrap();
+}
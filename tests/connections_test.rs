@@ -0,0 +1,194 @@
+mod common;
+
+use common::MockService;
+use portkey::connections::{ConnectionConfig, encode_cursor};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+
+const SCHEMA: &str = r#"
+type Query {
+    products: [Product!]!
+}
+
+type Product {
+    upc: ID!
+    name: String!
+}
+"#;
+
+fn request(query: &str, variables: Option<Value>) -> GraphQLRequest {
+    GraphQLRequest {
+        variables,
+        ..common::request(query)
+    }
+}
+
+async fn gateway(products: &MockService) -> FederationGateway {
+    let connections = ConnectionConfig {
+        fields: vec!["Query.products".to_string()],
+        default_page_size: 2,
+        max_page_size: 3,
+    };
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new().with_connections(connections.clone())),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_connections(connections);
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    gateway
+}
+
+async fn products() -> MockService {
+    MockService::start(|_| {
+        let products: Vec<Value> = (1..=5)
+            .map(|upc| json!({ "upc": upc.to_string(), "name": format!("Product {}", upc) }))
+            .collect();
+        json!({ "data": { "products": products } })
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_exposes_lists_as_connections() {
+    let products = products().await;
+    let gateway = gateway(&products).await;
+
+    let response = gateway
+        .process_request(request(
+            r#"{
+                products: __type(name: "Query") { fields { name type { ofType { name } } args { name } } }
+                edge: __type(name: "ProductEdge") { fields { name } }
+            }"#,
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"]["products"]["fields"],
+        json!([{
+            "name": "products",
+            "type": { "ofType": { "name": "ProductConnection" } },
+            "args": [{ "name": "first" }, { "name": "after" }],
+        }])
+    );
+    assert_eq!(
+        response["data"]["edge"]["fields"],
+        json!([{ "name": "cursor" }, { "name": "node" }])
+    );
+}
+
+#[tokio::test]
+async fn test_pages_through_lists() {
+    let products = products().await;
+    let gateway = gateway(&products).await;
+
+    let query = r#"query ($after: String) {
+        products(first: 2, after: $after) {
+            edges { cursor node { name } }
+            info: pageInfo { hasNextPage hasPreviousPage endCursor }
+        }
+    }"#;
+    let response = gateway.process_request(request(query, None)).await.unwrap();
+    assert_eq!(
+        response["data"],
+        json!({
+            "products": {
+                "edges": [
+                    { "cursor": encode_cursor(0), "node": { "name": "Product 1" } },
+                    { "cursor": encode_cursor(1), "node": { "name": "Product 2" } },
+                ],
+                "info": {
+                    "hasNextPage": true,
+                    "hasPreviousPage": false,
+                    "endCursor": encode_cursor(1),
+                },
+            },
+        })
+    );
+
+    // The subgraph is asked for the plain list
+    let sent = products.requests()[0]["query"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(sent.contains("products"));
+    assert!(!sent.contains("edges") && !sent.contains("first"));
+
+    let response = gateway
+        .process_request(request(query, Some(json!({ "after": encode_cursor(3) }))))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({
+            "products": {
+                "edges": [{ "cursor": encode_cursor(4), "node": { "name": "Product 5" } }],
+                "info": {
+                    "hasNextPage": false,
+                    "hasPreviousPage": true,
+                    "endCursor": encode_cursor(4),
+                },
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_caps_page_size_and_rejects_bad_cursors() {
+    let products = products().await;
+    let gateway = gateway(&products).await;
+
+    // Without `first` a page holds the default size, and at most the maximum
+    let response = gateway
+        .process_request(request("{ products { edges { node { upc } } } }", None))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"]["products"]["edges"]
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    let response = gateway
+        .process_request(request(
+            "{ products(first: 10) { pageInfo { hasNextPage } } }",
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": { "pageInfo": { "hasNextPage": true } } })
+    );
+
+    let response = gateway
+        .process_request(request(
+            r#"{ products(after: "nope") { edges { cursor } } }"#,
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "Invalid cursor nope",
+                "extensions": { "code": "BAD_USER_INPUT" },
+            }],
+        })
+    );
+}
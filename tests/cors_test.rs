@@ -0,0 +1,121 @@
+use http::HeaderMap;
+use portkey::{
+    config::GatewayConfig,
+    cors::{CorsConfig, PREFLIGHT_VARY, Preflight},
+};
+use pretty_assertions::assert_eq;
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_allows_configured_preflight() {
+    let config: GatewayConfig = serde_yaml::from_str(
+        r#"
+cors:
+  routes:
+    /graphql:
+      allowed_origins: ["https://app.example.com"]
+      allowed_methods: ["POST"]
+      allowed_headers: ["Content-Type", "Authorization"]
+      max_age: 60
+"#,
+    )
+    .unwrap();
+
+    let preflight = config.cors.preflight(
+        "/graphql",
+        &headers(&[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST"),
+            (
+                "access-control-request-headers",
+                "content-type, authorization",
+            ),
+        ]),
+    );
+
+    assert_eq!(
+        preflight,
+        Preflight::Allowed(vec![
+            (
+                "Access-Control-Allow-Origin",
+                "https://app.example.com".to_string()
+            ),
+            ("Access-Control-Allow-Methods", "POST".to_string()),
+            ("Vary", PREFLIGHT_VARY.to_string()),
+            (
+                "Access-Control-Allow-Headers",
+                "Content-Type, Authorization".to_string()
+            ),
+            ("Access-Control-Max-Age", "60".to_string()),
+        ])
+    );
+}
+
+#[test]
+fn test_rejects_preflight_outside_policy() {
+    let cors = CorsConfig::default();
+    let rejected = |path: &str, pairs: &[(&'static str, &str)]| {
+        matches!(
+            cors.preflight(path, &headers(pairs)),
+            Preflight::Rejected(_)
+        )
+    };
+
+    assert!(rejected(
+        "/admin",
+        &[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST")
+        ]
+    ));
+    assert!(rejected(
+        "/graphql",
+        &[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "DELETE")
+        ]
+    ));
+    assert!(rejected(
+        "/graphql",
+        &[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST"),
+            ("access-control-request-headers", "x-internal-secret")
+        ]
+    ));
+    assert!(rejected(
+        "/graphql",
+        &[("access-control-request-method", "POST")]
+    ));
+    assert!(!rejected(
+        "/graphql",
+        &[
+            ("origin", "https://app.example.com"),
+            ("access-control-request-method", "POST"),
+            ("access-control-request-headers", "content-type")
+        ]
+    ));
+}
+
+#[test]
+fn test_response_headers_vary_on_origin() {
+    let cors = CorsConfig::default();
+
+    assert_eq!(
+        cors.response_headers("/graphql", &headers(&[("origin", "https://a.example")])),
+        vec![
+            ("Access-Control-Allow-Origin", "*".to_string()),
+            ("Vary", "Origin".to_string()),
+        ]
+    );
+    assert_eq!(
+        cors.response_headers("/unknown", &headers(&[("origin", "https://a.example")])),
+        vec![("Vary", "Origin".to_string())]
+    );
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry,
+    SimpleQueryPlanner, demo::start_demo_services,
+};
+use serde_json::json;
+
+async fn demo_gateway() -> FederationGateway {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for service in start_demo_services().await.unwrap() {
+        gateway.register_service(service).await.unwrap();
+    }
+    gateway
+}
+
+fn request(query: &str, variables: Option<serde_json::Value>) -> GraphQLRequest {
+    GraphQLRequest {
+        variables,
+        ..common::request(query)
+    }
+}
+
+#[tokio::test]
+async fn demo_services_answer_federated_queries() {
+    let gateway = demo_gateway().await;
+
+    let response = gateway
+        .process_request(request(
+            "{ users { name } firstProduct: product(id: \"1\") { name price } }",
+            None,
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response["data"],
+        json!({
+            "users": [{ "name": "John Doe" }, { "name": "Jane Smith" }],
+            "firstProduct": { "name": "Laptop", "price": 999.99 }
+        })
+    );
+}
+
+#[tokio::test]
+async fn demo_services_apply_mutations() {
+    let gateway = demo_gateway().await;
+
+    let created = gateway
+        .process_request(request(
+            "mutation($input: CreateUserInput!) { createUser(input: $input) { id name } }",
+            Some(json!({ "input": { "name": "Ada", "email": "ada@example.com" } })),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        created["data"]["createUser"],
+        json!({ "id": "3", "name": "Ada" })
+    );
+
+    let deleted = gateway
+        .process_request(request(
+            "mutation { deleteUser(id: \"1\") { success message } }",
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        deleted["data"]["deleteUser"],
+        json!({ "success": true, "message": "User with ID 1 successfully deleted" })
+    );
+}
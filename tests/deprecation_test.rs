@@ -0,0 +1,244 @@
+mod common;
+
+use common::MockService;
+use graphql_parser::parse_schema;
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+    deprecation::{DeprecatedField, DeprecationConfig, deprecated_fields},
+    introspection::IntrospectionSchema,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const DEPRECATIONS: &str = r#"
+operations:
+  - name: "Legacy*Products"
+    deprecated_at: "2025-01-01T00:00:00Z"
+    sunset: "2099-06-30T00:00:00Z"
+    message: "Use CatalogProducts instead"
+  - name: RetiredProducts
+    sunset: "2020-01-01T00:00:00Z"
+    block_after_sunset: true
+  - name: GracefulProducts
+    sunset: "2020-01-01T00:00:00Z"
+"#;
+
+fn request(query: &str, operation_name: Option<&str>) -> GraphQLRequest {
+    GraphQLRequest {
+        operation_name: operation_name.map(str::to_string),
+        ..common::request(query)
+    }
+}
+
+async fn gateway(url: &str, deprecations: DeprecationConfig) -> FederationGateway {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_deprecations(deprecations);
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    gateway
+}
+
+#[test]
+fn test_matches_operations_by_name_pattern_and_hash() {
+    let query = "{ products { id } }";
+    let hash = hex::encode(Sha256::digest(query.as_bytes()));
+    let by_hash = format!(
+        "operations:\n  - hash: {}\n    sunset: \"2099-01-01T00:00:00Z\"\n",
+        hash
+    );
+    let config: DeprecationConfig =
+        serde_yaml::from_str(&DEPRECATIONS.replacen("operations:\n", &by_hash, 1)).unwrap();
+
+    assert!(config.find(&request(query, None)).is_some());
+    assert!(
+        config
+            .find(&request("query LegacyV2Products { products { id } }", None))
+            .is_some()
+    );
+    assert!(
+        config
+            .find(&request("{ products { name } }", Some("LegacyProducts")))
+            .is_some()
+    );
+    assert!(
+        config
+            .find(&request("query LegacyProductsV2 { products { id } }", None))
+            .is_none()
+    );
+    assert!(
+        config
+            .find(&request("{ products { name } }", None))
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_warns_about_deprecated_operations() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Table" }] } })).await;
+    let gateway = gateway(&products.url, serde_yaml::from_str(DEPRECATIONS).unwrap()).await;
+    let request = request("query LegacyProducts { products { name } }", None);
+
+    assert_eq!(
+        gateway.deprecation_headers(&request),
+        vec![
+            ("Sunset", "Tue, 30 Jun 2099 00:00:00 GMT".to_string()),
+            ("Deprecation", "@1735689600".to_string()),
+        ]
+    );
+
+    let response = gateway.process_request(request).await.unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "data": { "products": [{ "name": "Table" }] },
+            "extensions": { "warnings": [{
+                "code": "OPERATION_DEPRECATED",
+                "message": "This operation is deprecated and will be retired on 2099-06-30. Use CatalogProducts instead",
+                "sunset": "2099-06-30T00:00:00+00:00",
+            }] }
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_blocks_operations_past_their_sunset() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Table" }] } })).await;
+    let gateway = gateway(&products.url, serde_yaml::from_str(DEPRECATIONS).unwrap()).await;
+
+    let response = gateway
+        .process_request(request("query RetiredProducts { products { name } }", None))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({ "errors": [{
+            "message": "Operation was retired on 2020-01-01",
+            "extensions": { "code": "OPERATION_RETIRED" }
+        }] })
+    );
+    assert!(products.requests().is_empty());
+
+    // Without blocking, the operation keeps being served past the sunset
+    let response = gateway
+        .process_request(request(
+            "query GracefulProducts { products { name } }",
+            None,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Table" }] })
+    );
+    assert_eq!(
+        response["extensions"]["warnings"][0]["code"],
+        "OPERATION_DEPRECATED"
+    );
+}
+
+const LEGACY_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+    allProducts: [Product] @deprecated(reason: "Use products")
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+    title: String @deprecated
+}
+"#;
+
+#[test]
+fn test_finds_deprecated_fields_in_selections() {
+    let schema = IntrospectionSchema::compose(&[parse_schema::<String>(LEGACY_SCHEMA).unwrap()]);
+
+    assert_eq!(
+        deprecated_fields(
+            "{ allProducts { ...Fields } products { title ... on Product { title name } } } \
+             fragment Fields on Product { id title }",
+            None,
+            &schema
+        ),
+        vec![
+            DeprecatedField {
+                field: "Query.allProducts".to_string(),
+                reason: "Use products".to_string(),
+            },
+            DeprecatedField {
+                field: "Product.title".to_string(),
+                reason: "No longer supported".to_string(),
+            },
+        ]
+    );
+    assert!(deprecated_fields("{ products { id name } }", None, &schema).is_empty());
+}
+
+#[tokio::test]
+async fn test_reports_deprecated_field_usage() {
+    let products =
+        MockService::start(|_| json!({ "data": { "allProducts": [{ "title": "Table" }] } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: LEGACY_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    for _ in 0..2 {
+        let response = gateway
+            .process_request(request("{ allProducts { title } }", None))
+            .await
+            .unwrap();
+        assert_eq!(
+            response["extensions"]["deprecations"],
+            json!([
+                { "field": "Query.allProducts", "reason": "Use products" },
+                { "field": "Product.title", "reason": "No longer supported" },
+            ])
+        );
+    }
+
+    let metrics = gateway.metrics();
+    assert!(
+        metrics
+            .contains("portkey_deprecated_field_selections_total{field=\"Query.allProducts\"} 2\n")
+    );
+    assert!(
+        metrics.contains("portkey_deprecated_field_selections_total{field=\"Product.title\"} 2\n")
+    );
+}
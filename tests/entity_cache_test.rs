@@ -0,0 +1,262 @@
+mod common;
+
+use common::{MockService, build_schema};
+use portkey::{
+    CacheInvalidation, FederatedSchema, FederationGateway, GraphQLRequest, ServiceConfig,
+    config::CacheBypassConfig,
+    entity_cache::EntityCache,
+    plan_cache::{CachingQueryPlanner, cache_key},
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::InMemorySchemaRegistry,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews: [Review]
+}
+"#;
+
+const QUERY: &str = "{ products { name reviews { body } } }";
+
+async fn start_services() -> (MockService, MockService) {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "id": "1", "name": "Table" },
+            { "id": "2", "name": "Chair" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let entities: Vec<Value> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repr| json!({ "reviews": [{ "body": format!("Review of {}", repr["id"]) }] }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+    (products, reviews)
+}
+
+async fn execute(
+    executor: &HttpQueryExecutor,
+    schema: &FederatedSchema,
+    auth_headers: Option<HashMap<String, String>>,
+) -> Value {
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(QUERY, schema, None, None)
+        .await
+        .unwrap();
+    executor
+        .execute_plan(plan, schema, auth_headers)
+        .await
+        .unwrap()
+}
+
+async fn caching_gateway(products: &MockService, reviews: &MockService) -> FederationGateway {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(CachingQueryPlanner::new(
+            Box::new(SimpleQueryPlanner::new()),
+            NonZeroUsize::new(8).unwrap(),
+        )),
+        Box::new(
+            HttpQueryExecutor::new().with_entity_cache(Arc::new(EntityCache::new(
+                NonZeroUsize::new(16).unwrap(),
+                Duration::from_secs(60),
+            ))),
+        ),
+    )
+    .with_cache_bypass(CacheBypassConfig {
+        allowed_clients: vec!["admin-console".to_string()],
+    });
+    for (name, url, schema) in [
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: url.to_string(),
+                schema: schema.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    gateway
+}
+
+fn graphql_request(query: &str, client_name: Option<&str>, no_cache: bool) -> GraphQLRequest {
+    GraphQLRequest {
+        client_name: client_name.map(str::to_string),
+        no_cache,
+        ..common::request(query)
+    }
+}
+
+fn sent_representations(service: &MockService) -> Vec<usize> {
+    service
+        .requests()
+        .iter()
+        .map(|body| {
+            body["variables"]["representations"]
+                .as_array()
+                .unwrap()
+                .len()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_serves_cached_entities_per_session() {
+    let (products, reviews) = start_services().await;
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let cache = Arc::new(EntityCache::new(
+        NonZeroUsize::new(16).unwrap(),
+        Duration::from_secs(60),
+    ));
+    let executor = HttpQueryExecutor::new().with_entity_cache(Arc::clone(&cache));
+
+    let first = execute(&executor, &schema, None).await;
+    let second = execute(&executor, &schema, None).await;
+    assert_eq!(first, second);
+    assert_eq!(
+        second["data"]["products"][1]["reviews"][0]["body"],
+        json!("Review of \"2\"")
+    );
+    assert_eq!(sent_representations(&reviews), vec![2]);
+    assert_eq!(cache.len(), 2);
+
+    let session = HashMap::from([("Authorization".to_string(), "Bearer abc".to_string())]);
+    execute(&executor, &schema, Some(session.clone())).await;
+    execute(&executor, &schema, Some(session)).await;
+    assert_eq!(sent_representations(&reviews), vec![2, 2]);
+
+    cache.invalidate("Product", &json!({ "id": "1" }));
+    execute(&executor, &schema, None).await;
+    assert_eq!(sent_representations(&reviews), vec![2, 2, 1]);
+    assert_eq!(
+        reviews.requests()[2]["variables"]["representations"][0]["id"],
+        "1"
+    );
+
+    cache.invalidate_type("Product");
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn test_refetches_expired_entities() {
+    let (products, reviews) = start_services().await;
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let executor = HttpQueryExecutor::new().with_entity_cache(Arc::new(EntityCache::new(
+        NonZeroUsize::new(16).unwrap(),
+        Duration::ZERO,
+    )));
+
+    execute(&executor, &schema, None).await;
+    execute(&executor, &schema, None).await;
+    assert_eq!(sent_representations(&reviews), vec![2, 2]);
+}
+
+#[tokio::test]
+async fn test_gateway_invalidates_plans_and_entities() {
+    let (products, reviews) = start_services().await;
+    let gateway = caching_gateway(&products, &reviews).await;
+    let request = || graphql_request(QUERY, None, false);
+
+    gateway.process_request(request()).await.unwrap();
+    gateway.process_request(request()).await.unwrap();
+    assert_eq!(sent_representations(&reviews), vec![2]);
+
+    let invalidation: CacheInvalidation = serde_json::from_value(json!({
+        "keys": [{ "typename": "Product", "key": { "id": "2" } }],
+        "queryHashPrefixes": [&cache_key(QUERY, None, &None)[..8]],
+    }))
+    .unwrap();
+    assert_eq!(
+        gateway.invalidate_caches(&invalidation),
+        json!({ "invalidated": { "plans": 1, "entities": 1 } })
+    );
+
+    gateway.process_request(request()).await.unwrap();
+    assert_eq!(sent_representations(&reviews), vec![2, 1]);
+
+    let invalidation = CacheInvalidation {
+        types: vec!["Product".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        gateway.invalidate_caches(&invalidation),
+        json!({ "invalidated": { "plans": 0, "entities": 2 } })
+    );
+}
+
+#[tokio::test]
+async fn test_allowed_clients_bypass_and_refresh_caches() {
+    let (products, reviews) = start_services().await;
+    let gateway = caching_gateway(&products, &reviews).await;
+
+    gateway
+        .process_request(graphql_request(QUERY, None, false))
+        .await
+        .unwrap();
+    gateway
+        .process_request(graphql_request(QUERY, Some("web"), true))
+        .await
+        .unwrap();
+    assert_eq!(sent_representations(&reviews), vec![2]);
+
+    gateway
+        .process_request(graphql_request(QUERY, Some("admin-console"), true))
+        .await
+        .unwrap();
+    gateway
+        .process_request(graphql_request(
+            "query Fresh @noCache { products { name reviews { body } } }",
+            Some("admin-console"),
+            false,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(sent_representations(&reviews), vec![2, 2, 2]);
+
+    // The bypassing requests refreshed the cache for everyone else
+    gateway
+        .process_request(graphql_request(QUERY, None, false))
+        .await
+        .unwrap();
+    assert_eq!(sent_representations(&reviews), vec![2, 2, 2]);
+}
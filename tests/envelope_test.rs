@@ -0,0 +1,61 @@
+use portkey::envelope::EnvelopeConfig;
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+fn legacy() -> EnvelopeConfig {
+    EnvelopeConfig {
+        success_field: Some("success".to_string()),
+        result_field: "result".to_string(),
+        unwrap_single_field: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_wraps_responses_in_the_envelope() {
+    assert_eq!(
+        legacy().wrap(json!({
+            "data": { "product": { "name": "Lamp" } },
+            "extensions": { "timing": {} },
+        })),
+        json!({ "success": true, "result": { "name": "Lamp" }, "errors": [] })
+    );
+
+    // Several fields are kept as they are
+    let errors = json!([{ "message": "Service reviews is unavailable" }]);
+    assert_eq!(
+        legacy().wrap(json!({
+            "data": { "product": { "name": "Lamp" }, "reviews": null },
+            "errors": errors,
+        })),
+        json!({
+            "success": false,
+            "result": { "product": { "name": "Lamp" }, "reviews": null },
+            "errors": errors,
+        })
+    );
+
+    // Failed requests have no data
+    assert_eq!(
+        legacy().wrap(json!({ "errors": errors })),
+        json!({ "success": false, "result": null, "errors": errors })
+    );
+}
+
+#[test]
+fn test_wraps_batched_responses_one_by_one() {
+    let envelope = EnvelopeConfig {
+        extensions_field: Some("meta".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        envelope.wrap(json!([
+            { "data": { "a": 1 }, "extensions": { "cost": 1 } },
+            { "data": { "b": 2 } },
+        ])),
+        json!([
+            { "data": { "a": 1 }, "errors": [], "meta": { "cost": 1 } },
+            { "data": { "b": 2 }, "errors": [] },
+        ])
+    );
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use common::{MockService, gateway};
+use portkey::{
+    BatchRequest, FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry,
+    ServiceConfig, SimpleQueryPlanner, plan_cache::CachingQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews(first: Int): [Review]
+}
+"#;
+
+fn request(query: &str, variables: Option<serde_json::Value>) -> GraphQLRequest {
+    GraphQLRequest {
+        variables,
+        ..common::request(query)
+    }
+}
+
+#[tokio::test]
+async fn test_explains_plan_without_calling_subgraphs() {
+    let products = MockService::start(|_| json!({ "data": null })).await;
+    let reviews = MockService::start(|_| json!({ "data": null })).await;
+    let gateway = gateway(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+
+    let explained = gateway
+        .explain_request(request(
+            "query($n: Int) { products { name reviews(first: $n) { body } } }",
+            Some(json!({ "n": 3 })),
+        ))
+        .await
+        .unwrap();
+
+    let plan = &explained["queryPlan"];
+    assert_eq!(plan["kind"], "Sequence");
+    assert_eq!(plan["nodes"][0]["kind"], "Fetch");
+    assert_eq!(plan["nodes"][0]["serviceName"], "products");
+    assert_eq!(plan["nodes"][1]["kind"], "Flatten");
+    assert_eq!(plan["nodes"][1]["path"], json!(["products"]));
+
+    let entity_fetch = &plan["nodes"][1]["node"];
+    assert_eq!(entity_fetch["serviceName"], "reviews");
+    assert_eq!(entity_fetch["variables"], json!({ "n": 3 }));
+    assert_eq!(
+        entity_fetch["entity"],
+        json!({ "typeName": "Product", "keyFields": ["id"] })
+    );
+
+    assert!(products.requests().is_empty());
+    assert!(reviews.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_executes_batched_operations_in_order() {
+    let products = MockService::start(|body| {
+        let query = body["query"].as_str().unwrap();
+        if query.contains("id") {
+            json!({ "data": { "products": [{ "id": "1" }] } })
+        } else {
+            json!({ "data": { "products": [{ "name": "Table" }] } })
+        }
+    })
+    .await;
+    let gateway = gateway(&[("products", &products.url, PRODUCTS_SCHEMA)]).await;
+
+    let document = "query Names { products { name } } query Ids { products { id } }";
+    let responses = gateway
+        .process_batch(
+            request(document, None),
+            vec![
+                "Ids".to_string(),
+                "Missing".to_string(),
+                "Names".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        responses[0],
+        json!({ "data": { "products": [{ "id": "1" }] } })
+    );
+    assert_eq!(
+        responses[1]["errors"][0]["extensions"]["code"],
+        "UNKNOWN_OPERATION"
+    );
+    assert_eq!(
+        responses[2],
+        json!({ "data": { "products": [{ "name": "Table" }] } })
+    );
+    assert_eq!(products.requests().len(), 2);
+
+    let error = gateway
+        .process_batch(request(document, None), Vec::new())
+        .await
+        .unwrap_err();
+    assert_eq!(error, "Batched requests must name at least one operation");
+}
+
+#[test]
+fn test_parses_batched_requests_with_extensions() {
+    let batch: BatchRequest = serde_json::from_value(json!({
+        "operationName": ["Names", "Ids"],
+        "extensions": { "persistedQuery": { "version": 1, "sha256Hash": "abc" } },
+    }))
+    .unwrap();
+
+    // Only the hash of a persisted query
+    assert_eq!(batch.query, "");
+    assert_eq!(batch.operation_names, vec!["Names", "Ids"]);
+    assert_eq!(
+        batch.extensions.unwrap()["persistedQuery"]["sha256Hash"],
+        "abc"
+    );
+}
+
+#[tokio::test]
+async fn test_explains_plan_in_response_extensions_in_dev_mode() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "name": "Table", "__typename": "Product", "id": "1" }] } })
+    })
+    .await;
+    let reviews = MockService::start(
+        |_| json!({ "data": { "_entities": [{ "reviews": [{ "body": "Sturdy" }] }] } }),
+    )
+    .await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(CachingQueryPlanner::new(
+            Box::new(SimpleQueryPlanner::new()),
+            NonZeroUsize::new(10).unwrap(),
+        )),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_dev_mode(true);
+    for (name, url, schema) in [
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: url.to_string(),
+                schema: schema.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    let explain_request = || GraphQLRequest {
+        extensions: Some(json!({ "explain": true })),
+        ..request("{ products { name reviews { body } } }", None)
+    };
+
+    let response = gateway.process_request(explain_request()).await.unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Table", "reviews": [{ "body": "Sturdy" }] }] })
+    );
+    let explained = &response["extensions"]["portkey"]["explain"];
+    assert_eq!(explained["queryPlan"]["kind"], "Sequence");
+    assert_eq!(explained["cache"], json!({ "plan": "miss" }));
+    assert_eq!(explained["subgraphs"], json!(["products", "reviews"]));
+
+    let response = gateway.process_request(explain_request()).await.unwrap();
+    assert_eq!(
+        response["extensions"]["portkey"]["explain"]["cache"],
+        json!({ "plan": "hit" })
+    );
+
+    // Requests without the extension get the plain response
+    let response = gateway
+        .process_request(request("{ products { name reviews { body } } }", None))
+        .await
+        .unwrap();
+    assert!(response.get("extensions").is_none());
+}
+
+#[tokio::test]
+async fn test_ignores_explain_extension_outside_dev_mode() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Table" }] } })).await;
+    let gateway = gateway(&[("products", &products.url, PRODUCTS_SCHEMA)]).await;
+
+    let response = gateway
+        .process_request(GraphQLRequest {
+            extensions: Some(json!({ "explain": true })),
+            ..request("{ products { name } }", None)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({ "data": { "products": [{ "name": "Table" }] } })
+    );
+}
+
+#[tokio::test]
+async fn test_fails_requests_past_the_execution_deadline() {
+    // Connections are accepted by the kernel and never answered
+    let stalled = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/graphql", stalled.local_addr().unwrap());
+    let gateway = gateway(&[("products", &url, PRODUCTS_SCHEMA)])
+        .await
+        .with_execution_deadline(Some(Duration::from_millis(200)));
+
+    let started = Instant::now();
+    let error = gateway
+        .process_request(request("{ products { name } }", None))
+        .await
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        gateway.messages().error(&error, None),
+        json!({
+            "message": "Request exceeded the execution deadline of 200 ms",
+            "extensions": { "code": "EXECUTION_DEADLINE_EXCEEDED" },
+        })
+    );
+}
@@ -0,0 +1,85 @@
+use portkey::graphiql::{self, GraphiqlAssets, GraphiqlConfig};
+use std::io::Read;
+
+#[test]
+fn test_loads_assets_from_the_cdn_by_default() {
+    let html = GraphiqlConfig::default().html();
+
+    assert!(
+        html.contains("https://unpkg.com/graphiql@1.5.0/graphiql.min.js"),
+        "{}",
+        html
+    );
+    assert!(!html.contains(graphiql::ASSETS_PATH), "{}", html);
+    assert!(GraphiqlConfig::default().validate().is_ok());
+}
+
+#[test]
+fn test_loads_bundled_assets_from_the_gateway() {
+    let config = GraphiqlConfig {
+        assets: GraphiqlAssets::Bundled,
+    };
+    let html = config.html();
+
+    assert!(
+        html.contains(r#"<script src="/graphiql/assets/react.production.min.js">"#),
+        "{}",
+        html
+    );
+    assert!(
+        html.contains(r#"href="/graphiql/assets/graphiql.min.css""#),
+        "{}",
+        html
+    );
+    assert!(!html.contains("unpkg.com"), "{}", html);
+
+    // Builds without fetched assets refuse to serve a broken page
+    let missing = graphiql::missing_assets();
+    match config.validate() {
+        Ok(()) => assert!(missing.is_empty()),
+        Err(e) => assert!(e.contains(missing[0]), "{}", e),
+    }
+    for name in missing {
+        assert!(graphiql::asset(name).is_none());
+    }
+    assert!(graphiql::asset("../Cargo.toml").is_none());
+}
+
+#[test]
+fn test_revalidates_the_page_and_assets_by_etag() {
+    let cdn = GraphiqlConfig::default();
+    let bundled = GraphiqlConfig {
+        assets: GraphiqlAssets::Bundled,
+    };
+    assert_eq!(cdn.etag(), GraphiqlConfig::default().etag());
+    assert_ne!(cdn.etag(), bundled.etag());
+
+    let etag = cdn.etag();
+    assert!(graphiql::is_fresh(Some(&etag), &etag));
+    assert!(graphiql::is_fresh(
+        Some(&format!("\"other\", W/{}", etag)),
+        &etag
+    ));
+    assert!(!graphiql::is_fresh(Some("\"other\""), &etag));
+    assert!(!graphiql::is_fresh(None, &etag));
+}
+
+#[test]
+fn test_serves_bundled_assets_in_the_accepted_encoding() {
+    let bundled = ["graphiql.min.css", "graphiql.min.js"]
+        .into_iter()
+        .filter_map(graphiql::asset);
+    for asset in bundled {
+        let (_, plain) = asset.encoded(None);
+        assert_eq!(asset.encoded(Some("gzip, br")).0, Some("br"));
+        assert_eq!(asset.encoded(Some("br;q=0, *")).0, Some("gzip"));
+        assert_eq!(asset.encoded(Some("identity")).0, None);
+
+        let (_, gzipped) = asset.encoded(Some("gzip"));
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(gzipped)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, plain);
+    }
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{MockService, request};
+use futures::StreamExt;
+use portkey::incremental::{ListStreamingConfig, accepts_multipart, multipart_part};
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews: [Review]
+}
+"#;
+
+fn config(min_items: usize, chunk_size: usize) -> ListStreamingConfig {
+    ListStreamingConfig {
+        enabled: true,
+        min_items,
+        chunk_size,
+    }
+}
+
+#[test]
+fn test_chunks_large_top_level_lists() {
+    let response = json!({
+        "data": {
+            "products": [{ "id": 1 }, { "id": 2 }, { "id": 3 }, { "id": 4 }, { "id": 5 }],
+            "me": { "name": "Ada" },
+        }
+    });
+
+    assert_eq!(
+        config(5, 2).chunk_lists(response),
+        vec![
+            json!({
+                "data": {
+                    "products": [{ "id": 1 }, { "id": 2 }],
+                    "me": { "name": "Ada" },
+                },
+                "hasNext": true,
+            }),
+            json!({
+                "incremental": [{ "items": [{ "id": 3 }, { "id": 4 }], "path": ["products", 2] }],
+                "hasNext": true,
+            }),
+            json!({
+                "incremental": [{ "items": [{ "id": 5 }], "path": ["products", 4] }],
+                "hasNext": false,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn test_sends_small_lists_whole() {
+    let response = json!({ "data": { "products": [1, 2, 3, 4] } });
+
+    assert_eq!(
+        config(5, 2).chunk_lists(response.clone()),
+        vec![response.clone()]
+    );
+    // Lists fitting in a single chunk aren't split either
+    assert_eq!(config(1, 4).chunk_lists(response.clone()), vec![response]);
+
+    let errors = json!({ "data": null, "errors": [{ "message": "boom" }] });
+    assert_eq!(config(1, 1).chunk_lists(errors.clone()), vec![errors]);
+}
+
+#[test]
+fn test_frames_multipart_parts() {
+    assert!(accepts_multipart(
+        "application/json, multipart/mixed;deferSpec=20220824"
+    ));
+    assert!(!accepts_multipart("application/json"));
+
+    assert_eq!(
+        multipart_part(&json!({ "hasNext": false })),
+        "\r\n---\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{\"hasNext\":false}"
+    );
+}
+
+#[tokio::test]
+async fn test_delivers_deferred_fragments_after_the_initial_response() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "__typename": "Product", "id": "1", "name": "Table" },
+            { "__typename": "Product", "id": "2", "name": "Chair" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let entities: Vec<Value> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repr| json!({ "reviews": [{ "body": format!("Review of {}", repr["id"]) }] }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for (name, url, schema) in [
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: url.to_string(),
+                schema: schema.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    let (initial, rest) = gateway
+        .process_incremental(request(
+            r#"{ products { name ... @defer(label: "reviews") { reviews { body } } } }"#,
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        initial,
+        json!({
+            "data": { "products": [{ "name": "Table" }, { "name": "Chair" }] },
+            "hasNext": true,
+        })
+    );
+    let payloads: Vec<Value> = rest.unwrap().collect().await;
+    assert_eq!(
+        payloads,
+        vec![json!({
+            "incremental": [
+                {
+                    "data": { "reviews": [{ "body": "Review of \"1\"" }] },
+                    "path": ["products", 0],
+                    "label": "reviews",
+                },
+                {
+                    "data": { "reviews": [{ "body": "Review of \"2\"" }] },
+                    "path": ["products", 1],
+                    "label": "reviews",
+                },
+            ],
+            "hasNext": false,
+        })]
+    );
+
+    // Operations without deferred fragments are answered whole
+    let (response, rest) = gateway
+        .process_incremental(request("{ products { name } }"))
+        .await
+        .unwrap();
+    assert!(rest.is_none());
+    assert_eq!(response["data"]["products"][1], json!({ "name": "Chair" }));
+}
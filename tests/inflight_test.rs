@@ -0,0 +1,100 @@
+mod common;
+
+use common::MockService;
+use portkey::inflight::InflightFetches;
+use portkey::{
+    FederatedSchema,
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+const SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Mutation {
+    restock: Boolean
+}
+
+type Product {
+    name: String
+}
+"#;
+
+#[tokio::test]
+async fn test_shares_the_result_of_the_fetch_in_flight() {
+    let inflight = InflightFetches::new();
+    let sent = Notify::new();
+
+    let (leader, follower, other, _) = tokio::join!(
+        inflight.run("a".to_string(), async {
+            sent.notified().await;
+            Ok(json!(1))
+        }),
+        inflight.run("a".to_string(), async { Ok(json!(2)) }),
+        inflight.run("b".to_string(), async { Ok(json!(3)) }),
+        async { sent.notify_one() },
+    );
+
+    assert_eq!(leader, (Ok(json!(1)), false));
+    assert_eq!(follower, (Ok(json!(1)), true));
+    assert_eq!(other, (Ok(json!(3)), false));
+
+    // Once done, the key is free again
+    let (again, shared) = inflight.run("a".to_string(), async { Ok(json!(4)) }).await;
+    assert_eq!((again, shared), (Ok(json!(4)), false));
+}
+
+async fn build_schema(url: &str) -> FederatedSchema {
+    common::build_schema(&[("products", url, SCHEMA)]).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sends_identical_concurrent_fetches_once() {
+    // Slow enough for the fetches to overlap
+    let products = MockService::start(|_| {
+        std::thread::sleep(Duration::from_millis(200));
+        json!({ "data": { "products": [{ "name": "Lamp" }], "restock": true } })
+    })
+    .await;
+    let schema = build_schema(&products.url).await;
+    let executor = HttpQueryExecutor::new().with_fetch_deduplication(true);
+    let execute = |query: &'static str, token: &'static str| {
+        let executor = executor.clone();
+        let schema = schema.clone();
+        async move {
+            let plan = SimpleQueryPlanner::new()
+                .plan_query(query, &schema, None, None)
+                .await
+                .unwrap();
+            let headers = HashMap::from([("authorization".to_string(), token.to_string())]);
+            executor
+                .execute_plan(plan, &schema, Some(headers))
+                .await
+                .unwrap()
+        }
+    };
+
+    let responses = futures::future::join_all(
+        ["a", "a", "a", "b"].map(|token| execute("{ products { name } }", token)),
+    )
+    .await;
+    for response in &responses {
+        assert_eq!(
+            response["data"],
+            json!({ "products": [{ "name": "Lamp" }] })
+        );
+    }
+    // One request per set of credentials
+    assert_eq!(products.requests().len(), 2);
+
+    // Mutations are always sent
+    futures::future::join_all(["a", "a"].map(|token| execute("mutation { restock }", token))).await;
+    assert_eq!(products.requests().len(), 4);
+}
@@ -20,7 +20,10 @@ static DOCKER_BUILD: Once = Once::new();
 // Test fixture to manage test resources and setup
 struct TestFixture {
     gateway: FederationGateway,
+    // Held so the containers are stopped only when the fixture is dropped
+    #[allow(dead_code)]
     user_container: ContainerAsync<GenericImage>,
+    #[allow(dead_code)]
     product_container: ContainerAsync<GenericImage>,
     user_id: Option<String>,
     product_id: Option<String>,
@@ -140,12 +143,14 @@ impl TestFixture {
             name: "service_1".to_string(),
             url: user_service_url.to_string(),
             schema: user_schema,
+            ..Default::default()
         };
 
         let product_service = ServiceConfig {
             name: "service_2".to_string(),
             url: product_service_url.to_string(),
             schema: product_schema,
+            ..Default::default()
         };
 
         gateway.register_service(user_service).await.unwrap();
@@ -159,8 +164,7 @@ impl TestFixture {
         let request = portkey::GraphQLRequest {
             query: query.to_string(),
             variables,
-            operation_name: None,
-            auth_headers: None,
+            ..Default::default()
         };
 
         self.gateway.process_request(request).await
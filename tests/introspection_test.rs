@@ -0,0 +1,150 @@
+mod common;
+
+use common::{MockService, gateway, request};
+use graphql_parser::parse_schema;
+use portkey::introspection::IntrospectionSchema;
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+    _entities(representations: [_Any!]!): [_Entity]!
+}
+
+scalar _Any
+
+union _Entity = Product
+
+"A product in the catalog"
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+    sku: String @deprecated(reason: "Use id")
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+interface Node {
+    id: ID!
+}
+
+type Review implements Node {
+    id: ID!
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews(first: Int = 5): [Review!]
+}
+"#;
+
+#[test]
+fn test_composes_types_across_subgraphs() {
+    let documents = [
+        parse_schema::<String>(PRODUCTS_SCHEMA).unwrap(),
+        parse_schema::<String>(REVIEWS_SCHEMA).unwrap(),
+    ];
+    let schema = IntrospectionSchema::compose(&documents);
+
+    let product = schema.get_type("Product").unwrap();
+    assert_eq!(product["kind"], "OBJECT");
+    assert_eq!(product["description"], "A product in the catalog");
+    let field_names: Vec<_> = product["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(field_names, vec!["id", "name", "sku", "reviews"]);
+
+    let reviews = &product["fields"][3];
+    assert_eq!(reviews["args"][0]["defaultValue"], "5");
+    assert_eq!(
+        reviews["type"],
+        json!({
+            "kind": "LIST",
+            "name": null,
+            "ofType": {
+                "kind": "NON_NULL",
+                "name": null,
+                "ofType": { "kind": "OBJECT", "name": "Review", "ofType": null }
+            }
+        })
+    );
+
+    let node = schema.get_type("Node").unwrap();
+    assert_eq!(
+        node["possibleTypes"],
+        json!([{ "kind": "OBJECT", "name": "Review", "ofType": null }])
+    );
+
+    assert!(schema.get_type("_Any").is_none());
+    assert!(schema.get_type("_Entity").is_none());
+    let query = schema.get_type("Query").unwrap();
+    assert_eq!(query["fields"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_answers_introspection_without_calling_subgraphs() {
+    let products = MockService::start(|_| json!({ "data": null })).await;
+    let reviews = MockService::start(|_| json!({ "data": null })).await;
+    let gateway = gateway(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+
+    let response = gateway
+        .process_request(request(
+            r#"
+            query {
+                __schema { queryType { name } types { name } }
+                product: __type(name: "Product") {
+                    __typename
+                    name
+                    kind
+                    fields { name type { ...TypeRef } }
+                    all: fields(includeDeprecated: true) { name isDeprecated deprecationReason }
+                }
+            }
+
+            fragment TypeRef on __Type { kind name ofType { kind name } }
+            "#,
+        ))
+        .await
+        .unwrap();
+
+    let data = &response["data"];
+    assert_eq!(data["__schema"]["queryType"], json!({ "name": "Query" }));
+    assert!(
+        data["__schema"]["types"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "name": "Review" }))
+    );
+
+    let product = &data["product"];
+    assert_eq!(product["__typename"], "__Type");
+    assert_eq!(product["kind"], "OBJECT");
+    assert_eq!(
+        product["fields"][0],
+        json!({
+            "name": "id",
+            "type": {
+                "kind": "NON_NULL",
+                "name": null,
+                "ofType": { "kind": "SCALAR", "name": "ID" }
+            }
+        })
+    );
+    assert_eq!(product["fields"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        product["all"][2],
+        json!({ "name": "sku", "isDeprecated": true, "deprecationReason": "Use id" })
+    );
+
+    assert!(products.requests().is_empty());
+    assert!(reviews.requests().is_empty());
+}
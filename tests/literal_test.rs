@@ -0,0 +1,130 @@
+mod common;
+
+use common::MockService;
+use portkey::introspection::IntrospectionSchema;
+use portkey::literal::{inline_variables, write_string};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const SCHEMA: &str = r#"
+type Query {
+    products(filter: ProductFilter, first: Int): [Product]
+}
+
+type Product {
+    name: String!
+}
+
+input ProductFilter {
+    color: Color
+    name: String
+}
+
+enum Color {
+    RED
+    GREEN
+}
+"#;
+
+fn introspection() -> IntrospectionSchema {
+    let document = graphql_parser::parse_schema::<String>(SCHEMA).unwrap();
+    IntrospectionSchema::compose(&[document])
+}
+
+#[test]
+fn test_inlines_variables_as_literals() {
+    let query = r#"query($filter: ProductFilter, $first: Int = 10) {
+        products(filter: $filter, first: $first) { name }
+    }"#;
+    let inlined = inline_variables(
+        query,
+        &json!({ "filter": { "color": "RED", "name": "Lamp \"XL\"" } }),
+        &introspection(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        inlined,
+        r#"query {
+        products(filter: {color: RED, name: "Lamp \"XL\""}, first: 10) { name }
+    }"#
+    );
+}
+
+#[test]
+fn test_escapes_strings_and_rejects_names_breaking_out() {
+    let mut out = String::new();
+    write_string(&mut out, "a\\\" } evil {\n\u{1}");
+    assert_eq!(out, r#""a\\\" } evil {\n\u0001""#);
+
+    let schema = introspection();
+    let query = "query($filter: ProductFilter) { products(filter: $filter) { name } }";
+    assert_eq!(
+        inline_variables(
+            query,
+            &json!({ "filter": { "color": "RED) { x" } }),
+            &schema
+        ),
+        Err("Variable $filter can't be inlined: RED) { x is not an enum value".to_string())
+    );
+    assert_eq!(
+        inline_variables(query, &json!({ "filter": { "name: 1) {": "x" } }), &schema),
+        Err("Variable $filter can't be inlined: name: 1) { is not a field name".to_string())
+    );
+
+    // `$` inside strings is left alone
+    assert_eq!(
+        inline_variables(
+            r#"{ products(filter: {name: "$x"}) { name } }"#,
+            &json!({}),
+            &schema
+        )
+        .unwrap(),
+        r#"{ products(filter: {name: "$x"}) { name } }"#
+    );
+}
+
+#[tokio::test]
+async fn test_sends_inlined_variables_to_configured_services() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Lamp" }] } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: SCHEMA.to_string(),
+            inline_variables: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(GraphQLRequest {
+            variables: Some(json!({ "color": "GREEN" })),
+            ..common::request(
+                "query($color: Color) { products(filter: { color: $color }) { name } }",
+            )
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Lamp" }] })
+    );
+
+    let request = &products.requests()[0];
+    let query = request["query"].as_str().unwrap();
+    assert!(query.contains("color: GREEN"), "{}", query);
+    assert!(!query.contains('$'), "{}", query);
+    assert_eq!(request["variables"], json!({}));
+}
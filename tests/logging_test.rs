@@ -0,0 +1,85 @@
+use portkey::logging::{LogControl, LogLevelUpdate, LoggingConfig};
+use pretty_assertions::assert_eq;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::layer::SubscriberExt;
+
+// Collects what the logger writes
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn take(&self) -> String {
+        String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+    }
+}
+
+#[test]
+fn test_changes_log_levels_at_runtime() {
+    let (control, layer) = LogControl::new(&LoggingConfig::default()).unwrap();
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::registry().with(layer).with(
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(move || writer.clone()),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::debug!(target: "portkey::query_planner", "planned");
+        tracing::info!(target: "portkey::query_executor", "fetched");
+        let logged = captured.take();
+        assert!(!logged.contains("planned"), "{}", logged);
+        assert!(logged.contains("fetched"), "{}", logged);
+
+        // Debug logging of the planner alone, everything else at warn
+        let settings = control
+            .update(LogLevelUpdate {
+                level: Some("warn".to_string()),
+                debug: BTreeMap::from([("planner".to_string(), true)]),
+            })
+            .unwrap();
+        assert_eq!(
+            settings,
+            LoggingConfig {
+                level: "warn".to_string(),
+                debug: vec!["planner".to_string()],
+            }
+        );
+        tracing::debug!(target: "portkey::query_planner", "planned");
+        tracing::info!(target: "portkey::query_executor", "fetched");
+        let logged = captured.take();
+        assert!(logged.contains("planned"), "{}", logged);
+        assert!(!logged.contains("fetched"), "{}", logged);
+    });
+
+    // Unknown levels and modules change nothing
+    assert!(
+        control
+            .update(LogLevelUpdate {
+                level: Some("loud".to_string()),
+                ..Default::default()
+            })
+            .is_err()
+    );
+    let error = control
+        .update(LogLevelUpdate {
+            debug: BTreeMap::from([("cache".to_string(), true)]),
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert!(error.contains("Unknown module cache"), "{}", error);
+    assert_eq!(control.settings().level, "warn");
+}
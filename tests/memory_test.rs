@@ -0,0 +1,132 @@
+mod common;
+
+use common::MockService;
+use portkey::{
+    ServiceConfig,
+    memory::{MemoryConfig, MemoryLimiter, json_size},
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::sync::Arc;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+#[test]
+fn test_budget_fails_past_the_cap_and_releases_on_drop() {
+    let limiter = Arc::new(MemoryLimiter::new(&MemoryConfig {
+        max_request_bytes: 100,
+    }));
+
+    let budget = limiter.budget();
+    budget.charge(60).unwrap();
+    budget.charge(40).unwrap();
+    assert_eq!(limiter.in_flight_bytes(), 100);
+
+    let error = budget.charge(1).unwrap_err();
+    assert_eq!(error, "Request exceeds the memory budget of 100 bytes");
+    // Further charges fail too, but the request is only counted once
+    assert!(budget.charge(10).is_err());
+    assert_eq!(limiter.exceeded_requests(), 1);
+
+    drop(budget);
+    assert_eq!(limiter.in_flight_bytes(), 0);
+    assert_eq!(limiter.peak_request_bytes(), 111);
+
+    // Without a cap everything is accounted and nothing fails
+    let unlimited = Arc::new(MemoryLimiter::default());
+    unlimited.budget().charge(usize::MAX / 2).unwrap();
+    assert_eq!(unlimited.exceeded_requests(), 0);
+}
+
+#[test]
+fn test_renders_request_memory_metrics() {
+    let limiter = Arc::new(MemoryLimiter::default());
+    let budget = limiter.budget();
+    budget.charge(2048).unwrap();
+
+    let metrics = limiter.render_metrics();
+
+    assert!(
+        metrics.contains("# TYPE portkey_request_memory_in_flight_bytes gauge\n"),
+        "{}",
+        metrics
+    );
+    assert!(metrics.contains("portkey_request_memory_in_flight_bytes 2048\n"));
+    assert!(metrics.contains("portkey_request_memory_peak_bytes 2048\n"));
+    assert!(metrics.contains("portkey_request_memory_exceeded_total 0\n"));
+}
+
+#[test]
+fn test_json_size_grows_with_the_payload() {
+    let small = json!({ "name": "Table" });
+    let large = json!({ "name": "x".repeat(1024) });
+
+    assert!(json_size(&large) - json_size(&small) >= 1024 - "Table".len());
+    assert!(json_size(&json!([small.clone(), small.clone()])) > 2 * json_size(&small));
+}
+
+#[tokio::test]
+async fn test_aborts_requests_over_the_memory_budget() {
+    let products = MockService::start(
+        |_| json!({ "data": { "products": [{ "id": "1", "name": "x".repeat(16 * 1024) }] } }),
+    )
+    .await;
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+
+    let limiter = Arc::new(MemoryLimiter::new(&MemoryConfig {
+        max_request_bytes: 4 * 1024,
+    }));
+    let executor = HttpQueryExecutor::new().with_memory_limiter(limiter.clone());
+    let error = executor
+        .execute_plan(plan.clone(), &schema, None)
+        .await
+        .unwrap_err();
+    assert_eq!(error, "Request exceeds the memory budget of 4096 bytes");
+    assert_eq!(limiter.exceeded_requests(), 1);
+    assert_eq!(limiter.in_flight_bytes(), 0);
+
+    let limiter = Arc::new(MemoryLimiter::new(&MemoryConfig {
+        max_request_bytes: 64 * 1024,
+    }));
+    let executor = HttpQueryExecutor::new().with_memory_limiter(limiter.clone());
+    let result = executor.execute_plan(plan, &schema, None).await.unwrap();
+    assert_eq!(
+        result["data"]["products"][0]["name"]
+            .as_str()
+            .unwrap()
+            .len(),
+        16 * 1024
+    );
+    assert!(limiter.peak_request_bytes() > 16 * 1024);
+    assert!(
+        executor
+            .metrics()
+            .contains("portkey_request_memory_exceeded_total 0\n")
+    );
+}
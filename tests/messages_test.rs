@@ -0,0 +1,87 @@
+use portkey::messages::{self, LocalizedError, MessageCatalog};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+
+fn catalog() -> MessageCatalog {
+    let mut catalog = MessageCatalog::new();
+    catalog.add_translations(
+        "es",
+        HashMap::from([(
+            "UNKNOWN_OPERATION".to_string(),
+            "Operación desconocida \"{name}\".".to_string(),
+        )]),
+    );
+    catalog.add_translations(
+        "fr",
+        HashMap::from([(
+            "NO_SERVICE_FOR_FIELD".to_string(),
+            "Aucun service pour le champ {field} ({operation})".to_string(),
+        )]),
+    );
+    catalog
+}
+
+#[test]
+fn test_assigns_stable_codes_to_english_messages() {
+    let catalog = catalog();
+
+    assert_eq!(
+        catalog.localize("Unknown operation named \"GetUser\".", None),
+        LocalizedError {
+            code: "UNKNOWN_OPERATION".to_string(),
+            message: "Unknown operation named \"GetUser\".".to_string(),
+        }
+    );
+    assert_eq!(
+        catalog.error(
+            "Service returned error 502 Bad Gateway: upstream: down",
+            None
+        ),
+        json!({
+            "message": "Service returned error 502 Bad Gateway: upstream: down",
+            "extensions": { "code": "SUBREQUEST_HTTP_ERROR" }
+        })
+    );
+    assert_eq!(
+        catalog.localize("something unexpected", Some("es")).code,
+        "INTERNAL_SERVER_ERROR"
+    );
+}
+
+#[test]
+fn test_localizes_by_accept_language_preference() {
+    let catalog = catalog();
+
+    let localized = catalog.localize(
+        "Unknown operation named \"GetUser\".",
+        Some("de;q=0.9, es-MX, en;q=0.5"),
+    );
+    assert_eq!(localized.code, "UNKNOWN_OPERATION");
+    assert_eq!(localized.message, "Operación desconocida \"GetUser\".");
+
+    let localized = catalog.localize(
+        "No service found for field: users in operation: Query",
+        Some("es, fr;q=0.8"),
+    );
+    assert_eq!(localized.code, "NO_SERVICE_FOR_FIELD");
+    assert_eq!(
+        localized.message,
+        "Aucun service pour le champ users (Query)"
+    );
+
+    let localized = catalog.localize("Unknown operation named \"GetUser\".", Some("es;q=0"));
+    assert_eq!(localized.message, "Unknown operation named \"GetUser\".");
+}
+
+#[test]
+fn test_looks_up_codes_without_a_catalog() {
+    assert_eq!(
+        messages::code("Service products is unavailable"),
+        "SERVICE_UNAVAILABLE"
+    );
+    assert_eq!(
+        messages::code("Something else broke"),
+        "INTERNAL_SERVER_ERROR"
+    );
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{MockService, request};
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const USERS_SCHEMA: &str = r#"
+type Query {
+    search(term: String!): [User]
+}
+
+type User {
+    id: ID!
+    email: String!
+}
+"#;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    search(term: String!): [Product]
+}
+
+type Product {
+    id: ID!
+    name: String!
+}
+"#;
+
+fn service(name: &str, url: &str, schema: &str) -> ServiceConfig {
+    ServiceConfig {
+        name: name.to_string(),
+        url: url.to_string(),
+        schema: schema.to_string(),
+        namespace: Some(format!("{}_", name)),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_routes_namespaced_root_fields_and_strips_the_prefix() {
+    let users =
+        MockService::start(|_| json!({ "data": { "users_search": [{ "email": "a@b.c" }] } })).await;
+    let products =
+        MockService::start(|_| json!({ "data": { "found": [{ "name": "Table" }] } })).await;
+
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(service("users", &users.url, USERS_SCHEMA))
+        .await
+        .unwrap();
+    gateway
+        .register_service(service("products", &products.url, PRODUCTS_SCHEMA))
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(request(
+            r#"{ users_search(term: "a") { email } found: products_search(term: "a") { name } }"#,
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response,
+        json!({ "data": {
+            "users_search": [{ "email": "a@b.c" }],
+            "found": [{ "name": "Table" }]
+        } })
+    );
+    let users_query = users.requests()[0]["query"].as_str().unwrap().to_string();
+    assert!(
+        users_query.contains("users_search: search(term: \"a\")"),
+        "{}",
+        users_query
+    );
+    let products_query = products.requests()[0]["query"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        products_query.contains("found: search(term: \"a\")"),
+        "{}",
+        products_query
+    );
+
+    // Clients only ever see the prefixed fields
+    let response = gateway
+        .process_request(request(r#"{ __type(name: "Query") { fields { name } } }"#))
+        .await
+        .unwrap();
+    let mut fields: Vec<&str> = response["data"]["__type"]["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field["name"].as_str().unwrap())
+        .collect();
+    fields.sort();
+    assert_eq!(fields, vec!["products_search", "users_search"]);
+}
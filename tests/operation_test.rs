@@ -0,0 +1,55 @@
+mod common;
+
+use portkey::{GraphQLRequest, operation::display_name};
+use pretty_assertions::assert_eq;
+
+fn request(query: &str, operation_name: Option<&str>) -> GraphQLRequest {
+    GraphQLRequest {
+        operation_name: operation_name.map(str::to_string),
+        ..common::request(query)
+    }
+}
+
+#[test]
+fn test_names_operations_by_their_own_name() {
+    assert_eq!(
+        display_name(&request("query Feed { products { name } }", None)),
+        "Feed"
+    );
+    assert_eq!(
+        display_name(&request("query A { a } query B { b }", Some("B"))),
+        "B"
+    );
+}
+
+#[test]
+fn test_derives_stable_names_for_anonymous_operations() {
+    let name = display_name(&request(
+        "{ __typename top: products { name } reviews { body } users { id } orders { id } }",
+        None,
+    ));
+    let (prefix, hash) = name.rsplit_once('_').unwrap();
+    assert_eq!(prefix, "query_products_reviews_users");
+    assert_eq!(hash.len(), 8);
+    assert_eq!(
+        display_name(&request(
+            "{ __typename top: products { name } reviews { body } users { id } orders { id } }",
+            None,
+        )),
+        name
+    );
+
+    // Same root fields, different selections
+    let other = display_name(&request("{ products { id } }", None));
+    assert!(other.starts_with("query_products_"), "{}", other);
+    assert_ne!(other, display_name(&request("{ products { name } }", None)));
+
+    let mutation = display_name(&request(
+        "mutation { addProduct(name: \"A\") { id } }",
+        None,
+    ));
+    assert!(mutation.starts_with("mutation_addProduct_"), "{}", mutation);
+
+    let invalid = display_name(&request("{ products", None));
+    assert!(invalid.starts_with("anonymous_"), "{}", invalid);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::MockService;
+use portkey::introspection::IntrospectionSchema;
+use portkey::pagination::{PaginationConfig, PaginationRule};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const SCHEMA: &str = r#"
+type Query {
+    products(first: Int): [Product]
+    search(term: String): [Product]
+}
+
+type Product {
+    name: String!
+    reviews(first: Int): [Review]
+}
+
+type Review {
+    body: String!
+}
+"#;
+
+fn pagination() -> PaginationConfig {
+    PaginationConfig {
+        rules: vec![PaginationRule {
+            field: "*.*".to_string(),
+            argument: "first".to_string(),
+            default: Some(10),
+            max: Some(50),
+        }],
+    }
+}
+
+fn introspection() -> IntrospectionSchema {
+    let document = graphql_parser::parse_schema::<String>(SCHEMA).unwrap();
+    IntrospectionSchema::compose(&[document])
+}
+
+#[test]
+fn test_clamps_and_defaults_pagination_arguments() {
+    let (query, variables) = pagination()
+        .rewrite(
+            "query($n: Int) { products(first: $n) { name reviews(first: 500) { body } } search { name } }",
+            &Some(json!({ "n": 1000 })),
+            &introspection(),
+        )
+        .unwrap();
+
+    let collapsed = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    assert!(collapsed.contains("products(first: $n)"), "{}", collapsed);
+    assert!(collapsed.contains("reviews(first: 50)"), "{}", collapsed);
+    // `search` has no `first` argument to default
+    assert!(collapsed.contains("search {"), "{}", collapsed);
+    assert_eq!(variables, Some(json!({ "n": 50 })));
+
+    // Missing variables take the default
+    let (query, _) = pagination()
+        .rewrite(
+            "query($n: Int) { products(first: $n) { name } }",
+            &None,
+            &introspection(),
+        )
+        .unwrap();
+    assert!(query.contains("products(first: 10)"), "{}", query);
+
+    // Nothing to rewrite within bounds
+    assert!(
+        pagination()
+            .rewrite("{ products(first: 5) { name } }", &None, &introspection())
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_sends_bounded_lists_to_subgraphs() {
+    let products = MockService::start(|_| json!({ "data": { "products": [] } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_pagination(pagination());
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(GraphQLRequest {
+            variables: Some(json!({ "n": 100000 })),
+            ..common::request("query($n: Int) { products(first: $n) { name } }")
+        })
+        .await
+        .unwrap();
+    assert_eq!(response["data"], json!({ "products": [] }));
+    assert_eq!(products.requests()[0]["variables"], json!({ "n": 50 }));
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::MockService;
+use portkey::{
+    FederationGateway, GraphQLRequest,
+    persisted_queries::{InMemoryPersistedQueryStore, PersistedQueryStore},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product {
+    name: String!
+}
+"#;
+
+const QUERY: &str = "{ products { name } }";
+
+async fn gateway(url: &str) -> FederationGateway {
+    common::gateway(&[("products", url, PRODUCTS_SCHEMA)]).await
+}
+
+fn request(query: &str, hash: &str) -> GraphQLRequest {
+    GraphQLRequest {
+        extensions: Some(json!({
+            "persistedQuery": { "version": 1, "sha256Hash": hash }
+        })),
+        ..common::request(query)
+    }
+}
+
+#[tokio::test]
+async fn test_registers_and_serves_persisted_queries() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Table" }] } })).await;
+    let gateway = gateway(&products.url)
+        .await
+        .with_persisted_query_store(Arc::new(InMemoryPersistedQueryStore::new(
+            NonZeroUsize::new(8).unwrap(),
+        )));
+    let hash = hex::encode(Sha256::digest(QUERY.as_bytes()));
+
+    let error = gateway
+        .process_request(request("", &hash))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        gateway.messages().error(&error, None),
+        json!({
+            "message": "PersistedQueryNotFound",
+            "extensions": { "code": "PERSISTED_QUERY_NOT_FOUND" }
+        })
+    );
+    assert!(products.requests().is_empty());
+
+    // The follow-up request carries the query and registers it
+    let expected = json!({ "data": { "products": [{ "name": "Table" }] } });
+    let response = gateway
+        .process_request(request(QUERY, &hash))
+        .await
+        .unwrap();
+    assert_eq!(response, expected);
+
+    let response = gateway.process_request(request("", &hash)).await.unwrap();
+    assert_eq!(response, expected);
+    assert_eq!(
+        products.requests()[1]["query"],
+        products.requests()[0]["query"]
+    );
+
+    let error = gateway
+        .process_request(request("{ products { __typename } }", &hash))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        gateway.messages().error(&error, None)["extensions"]["code"],
+        "PERSISTED_QUERY_HASH_MISMATCH"
+    );
+}
+
+#[tokio::test]
+async fn test_refuses_persisted_queries_without_a_store() {
+    let products = MockService::start(|_| json!({ "data": { "products": [] } })).await;
+    let gateway = gateway(&products.url).await;
+
+    let error = gateway
+        .process_request(request(QUERY, "unknown"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        gateway.messages().error(&error, None)["extensions"]["code"],
+        "PERSISTED_QUERY_NOT_SUPPORTED"
+    );
+}
+
+#[tokio::test]
+async fn test_stores_query_texts_compressed() {
+    let store = InMemoryPersistedQueryStore::new(NonZeroUsize::new(2).unwrap());
+    let query = format!("{{ {} }}", "products { name } ".repeat(100));
+
+    store.put("a", query.clone()).await;
+    assert!(store.stored_bytes() < query.len() / 4);
+    assert_eq!(store.get("a").await, Some(query));
+    assert_eq!(store.get("b").await, None);
+}
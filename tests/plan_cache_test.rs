@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use portkey::{
+    FederatedSchema, PlanNode, QueryPlan, ServiceConfig,
+    plan_cache::{CachingQueryPlanner, normalize_query},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::num::NonZeroUsize;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+const USERS_SCHEMA: &str = r#"
+type Query {
+    user(id: ID!): User
+}
+
+type User {
+    id: ID!
+    name: String!
+    email: String!
+}
+"#;
+
+struct CountingPlanner {
+    calls: Arc<AtomicUsize>,
+    inner: SimpleQueryPlanner,
+}
+
+#[async_trait]
+impl QueryPlanner for CountingPlanner {
+    async fn plan_query(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.inner
+            .plan_query(query, schema, variables, operation_name)
+            .await
+    }
+}
+
+fn caching_planner() -> (CachingQueryPlanner, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let planner = CachingQueryPlanner::new(
+        Box::new(CountingPlanner {
+            calls: Arc::clone(&calls),
+            inner: SimpleQueryPlanner::new(),
+        }),
+        NonZeroUsize::new(8).unwrap(),
+    );
+    (planner, calls)
+}
+
+async fn register(registry: &mut InMemorySchemaRegistry, name: &str) {
+    registry
+        .register_service(ServiceConfig {
+            name: name.to_string(),
+            url: format!("http://{}/graphql", name),
+            schema: USERS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+}
+
+fn fetch_variables(plan: &QueryPlan) -> &Value {
+    match &plan.node {
+        PlanNode::Fetch(fetch) => &fetch.variables,
+        other => panic!("expected a fetch node, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_normalizes_ignored_tokens() {
+    assert_eq!(
+        normalize_query("query User($id: ID!) {\n  # the user\n  user(id: $id) { name, email }\n}"),
+        "query User($id:ID!){user(id:$id){name email}}"
+    );
+    assert_eq!(
+        normalize_query(r#"{ user(id: "a  b,\" c") { name } }"#),
+        r#"{user(id:"a  b,\" c"){name}}"#
+    );
+}
+
+#[tokio::test]
+async fn test_reuses_plans_and_rebinds_variables() {
+    let mut registry = InMemorySchemaRegistry::new();
+    register(&mut registry, "users").await;
+    let schema = registry.get_schema().await.unwrap();
+    let (planner, calls) = caching_planner();
+
+    let first = planner
+        .plan_query(
+            "query($id: ID!) { user(id: $id) { name } }",
+            &schema,
+            Some(json!({ "id": "1" })),
+            None,
+        )
+        .await
+        .unwrap();
+    let second = planner
+        .plan_query(
+            "query($id: ID!) {\n  user(id: $id) {\n    name\n  }\n}",
+            &schema,
+            Some(json!({ "id": "2" })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(planner.len(), 1);
+    assert_eq!(fetch_variables(&first), &json!({ "id": "1" }));
+    assert_eq!(fetch_variables(&second), &json!({ "id": "2" }));
+}
+
+#[tokio::test]
+async fn test_replans_when_conditional_variables_change() {
+    let mut registry = InMemorySchemaRegistry::new();
+    register(&mut registry, "users").await;
+    let schema = registry.get_schema().await.unwrap();
+    let (planner, calls) = caching_planner();
+    let query =
+        "query($withEmail: Boolean!) { user(id: \"1\") { name email @include(if: $withEmail) } }";
+
+    for with_email in [true, false, true] {
+        planner
+            .plan_query(
+                query,
+                &schema,
+                Some(json!({ "withEmail": with_email })),
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_invalidates_plans_on_new_schema() {
+    let mut registry = InMemorySchemaRegistry::new();
+    register(&mut registry, "users").await;
+    let schema = registry.get_schema().await.unwrap();
+    let (planner, calls) = caching_planner();
+    let query = "{ user(id: \"1\") { name } }";
+
+    planner
+        .plan_query(query, &schema, None, None)
+        .await
+        .unwrap();
+    planner
+        .plan_query(query, &schema, None, None)
+        .await
+        .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    register(&mut registry, "accounts").await;
+    let schema = registry.get_schema().await.unwrap();
+    planner
+        .plan_query(query, &schema, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(planner.len(), 1);
+}
+
+#[tokio::test]
+async fn test_keeps_defaults_and_checks_nulls_of_cached_plans() {
+    let mut registry = InMemorySchemaRegistry::new();
+    register(&mut registry, "users").await;
+    let schema = registry.get_schema().await.unwrap();
+    let (planner, calls) = caching_planner();
+    let query = "query($id: ID! = \"1\") { user(id: $id) { name } }";
+
+    for _ in 0..2 {
+        let plan = planner
+            .plan_query(query, &schema, Some(json!({})), None)
+            .await
+            .unwrap();
+        assert_eq!(fetch_variables(&plan), &json!({ "id": "1" }));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    planner
+        .plan_query(query, &schema, Some(json!({ "id": "2" })), None)
+        .await
+        .unwrap();
+    let null = planner
+        .plan_query(query, &schema, Some(json!({ "id": null })), None)
+        .await;
+    assert_eq!(
+        null.err().unwrap(),
+        "Variable \"$id\" of non-null type \"ID!\" must not be null."
+    );
+}
+
+#[tokio::test]
+async fn test_compresses_cached_query_texts() {
+    let mut registry = InMemorySchemaRegistry::new();
+    register(&mut registry, "users").await;
+    let schema = registry.get_schema().await.unwrap();
+    let fields: Vec<String> = (0..200)
+        .map(|i| format!("user{}: user(id: \"{}\") {{ id name email }}", i, i))
+        .collect();
+    let query = format!("{{ {} }}", fields.join(" "));
+
+    let (plain, _) = caching_planner();
+    let (compressed, calls) = caching_planner();
+    let compressed = compressed.with_query_compression(true);
+
+    let expected = plain.plan_query(&query, &schema, None, None).await.unwrap();
+    compressed
+        .plan_query(&query, &schema, None, None)
+        .await
+        .unwrap();
+    let cached = compressed
+        .plan_query(&query, &schema, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(format!("{:?}", cached.node), format!("{:?}", expected.node));
+    assert!(
+        compressed.query_bytes() * 4 < plain.query_bytes(),
+        "{} compressed, {} plain",
+        compressed.query_bytes(),
+        plain.query_bytes()
+    );
+}
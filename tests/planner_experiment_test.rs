@@ -0,0 +1,167 @@
+mod common;
+
+use async_trait::async_trait;
+use portkey::{
+    FederatedSchema, QueryPlan,
+    plan_cache::CachingQueryPlanner,
+    planner_experiment::{ExperimentQueryPlanner, PlannerSplit},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    request_context::RequestContext,
+};
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Plans like SimpleQueryPlanner, counting the operations it's asked to plan
+#[derive(Clone, Default)]
+struct CountingPlanner {
+    planned: Arc<AtomicUsize>,
+    fail: bool,
+}
+
+impl CountingPlanner {
+    fn planned(&self) -> usize {
+        self.planned.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl QueryPlanner for CountingPlanner {
+    async fn plan_query(
+        &self,
+        query: &str,
+        schema: &FederatedSchema,
+        variables: Option<Value>,
+        operation_name: Option<&str>,
+    ) -> Result<QueryPlan, String> {
+        self.planned.fetch_add(1, Ordering::Relaxed);
+        if self.fail {
+            return Err("Entity resolution is not supported yet".to_string());
+        }
+        SimpleQueryPlanner::new()
+            .plan_query(query, schema, variables, operation_name)
+            .await
+    }
+}
+
+async fn build_schema() -> FederatedSchema {
+    common::build_schema(&[(
+        "products",
+        "http://localhost:4001/graphql",
+        "type Query { products: [Product] } type Product { name: String }",
+    )])
+    .await
+}
+
+fn context(tenant: &str, experiments: &[&str]) -> RequestContext {
+    let mut context = RequestContext::default();
+    context.tenant = Some(tenant.to_string());
+    context.experiments = experiments.iter().map(|e| e.to_string()).collect();
+    context
+}
+
+#[tokio::test]
+async fn test_splits_requests_between_planners_by_tenant() {
+    let schema = build_schema().await;
+    let control = CountingPlanner::default();
+    let variant = CountingPlanner::default();
+    let planner = ExperimentQueryPlanner::new("simple", Box::new(control.clone())).with_variant(
+        "entities",
+        Box::new(variant.clone()),
+        PlannerSplit {
+            percentage: 50,
+            experiment: None,
+        },
+    );
+
+    for tenant in 0..200 {
+        let context = context(&format!("client-{}", tenant), &[]);
+        for _ in 0..2 {
+            planner
+                .plan_in_context("{ products { name } }", &schema, None, None, &context)
+                .await
+                .unwrap();
+        }
+    }
+
+    // Every client sticks to one planner
+    assert_eq!(control.planned() + variant.planned(), 400);
+    assert_eq!(control.planned() % 2, 0);
+    assert!((100..300).contains(&control.planned()));
+}
+
+#[tokio::test]
+async fn test_requests_in_the_experiment_use_the_variant() {
+    let schema = build_schema().await;
+    let control = CountingPlanner::default();
+    let variant = CountingPlanner::default();
+    let planner = CachingQueryPlanner::new(
+        Box::new(
+            ExperimentQueryPlanner::new("simple", Box::new(control.clone())).with_variant(
+                "entities",
+                Box::new(variant.clone()),
+                PlannerSplit {
+                    percentage: 0,
+                    experiment: Some("new-planner".to_string()),
+                },
+            ),
+        ),
+        NonZeroUsize::new(10).unwrap(),
+    );
+
+    for experiments in [&[][..], &["new-planner"], &[], &["new-planner"]] {
+        planner
+            .plan_in_context(
+                "{ products { name } }",
+                &schema,
+                None,
+                None,
+                &context("acme", experiments),
+            )
+            .await
+            .unwrap();
+    }
+
+    // Each planner's plan is cached apart
+    assert_eq!(control.planned(), 1);
+    assert_eq!(variant.planned(), 1);
+    assert_eq!(planner.len(), 2);
+}
+
+#[tokio::test]
+async fn test_falls_back_to_the_control_and_reports_each_planner() {
+    let schema = build_schema().await;
+    let control = CountingPlanner::default();
+    let variant = CountingPlanner {
+        fail: true,
+        ..Default::default()
+    };
+    let planner = ExperimentQueryPlanner::new("simple", Box::new(control.clone())).with_variant(
+        "entities",
+        Box::new(variant.clone()),
+        PlannerSplit {
+            percentage: 100,
+            experiment: None,
+        },
+    );
+
+    let plan = planner
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+    assert_eq!(plan.fetches().len(), 1);
+    assert_eq!((control.planned(), variant.planned()), (1, 1));
+
+    let metrics = planner.metrics();
+    for line in [
+        "portkey_planner_plans_total{planner=\"simple\"} 1",
+        "portkey_planner_plans_total{planner=\"entities\"} 0",
+        "portkey_planner_errors_total{planner=\"simple\"} 0",
+        "portkey_planner_errors_total{planner=\"entities\"} 1",
+    ] {
+        assert!(metrics.contains(line), "{} missing from {}", line, metrics);
+    }
+    assert!(metrics.contains("portkey_planner_planning_microseconds_total{planner=\"entities\"}"));
+}
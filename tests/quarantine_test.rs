@@ -0,0 +1,113 @@
+mod common;
+
+use common::MockService;
+use portkey::quarantine::{FieldQuarantine, QuarantineConfig, is_valid};
+use portkey::{
+    FederatedSchema,
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product {
+    name: String!
+    price: Int
+    tags: [String!]
+}
+"#;
+
+async fn build_schema(url: &str) -> FederatedSchema {
+    common::build_schema(&[("products", url, PRODUCTS_SCHEMA)]).await
+}
+
+#[test]
+fn test_checks_values_against_their_types() {
+    let int = json!({ "kind": "SCALAR", "name": "Int", "ofType": null });
+    let strings = json!({ "kind": "LIST", "name": null, "ofType": {
+        "kind": "NON_NULL", "name": null, "ofType": { "kind": "SCALAR", "name": "String", "ofType": null },
+    } });
+    let object = json!({ "kind": "OBJECT", "name": "Product", "ofType": null });
+
+    assert!(is_valid(&json!(3), &int));
+    assert!(is_valid(&Value::Null, &int));
+    assert!(!is_valid(&json!("3"), &int));
+    assert!(!is_valid(&json!(3.5), &int));
+    assert!(!is_valid(&json!(1_i64 << 40), &int));
+    assert!(is_valid(&json!(["a", "b"]), &strings));
+    assert!(!is_valid(&json!(["a", 1]), &strings));
+    assert!(!is_valid(&json!("a"), &strings));
+    assert!(is_valid(&json!({ "name": "Lamp" }), &object));
+    assert!(!is_valid(&json!("Lamp"), &object));
+}
+
+#[tokio::test]
+async fn test_quarantines_fields_with_repeatedly_invalid_values() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "name": "Lamp", "price": "12", "tags": ["home"] },
+            { "name": "Desk", "price": 80, "tags": ["office"] },
+        ] } })
+    })
+    .await;
+    let schema = build_schema(&products.url).await;
+    let quarantine = Arc::new(FieldQuarantine::new(&QuarantineConfig {
+        enabled: true,
+        threshold: 2,
+        ..Default::default()
+    }));
+    let executor = HttpQueryExecutor::new().with_quarantine(Arc::clone(&quarantine));
+    let execute = || async {
+        let plan = SimpleQueryPlanner::new()
+            .plan_query("{ products { name price tags } }", &schema, None, None)
+            .await
+            .unwrap();
+        executor.execute_plan(plan, &schema, None).await.unwrap()
+    };
+
+    // Invalid values are answered with null and an error
+    let response = execute().await;
+    assert_eq!(
+        response["data"]["products"],
+        json!([
+            { "name": "Lamp", "price": null, "tags": ["home"] },
+            { "name": "Desk", "price": 80, "tags": ["office"] },
+        ])
+    );
+    assert_eq!(
+        response["errors"],
+        json!([{
+            "message": "Service products returned an invalid value for Product.price",
+            "path": ["products", 0, "price"],
+            "extensions": { "code": "SUBREQUEST_INVALID_RESPONSE", "serviceName": "products" },
+        }])
+    );
+    assert!(!quarantine.is_quarantined("Product.price"));
+
+    // The second invalid response quarantines the field, valid values
+    // included
+    execute().await;
+    assert!(quarantine.is_quarantined("Product.price"));
+    assert_eq!(quarantine.quarantined()[0]["field"], "Product.price");
+    assert_eq!(quarantine.quarantined()[0]["service"], "products");
+
+    let response = execute().await;
+    assert_eq!(response["data"]["products"][1]["price"], Value::Null);
+    assert_eq!(response["data"]["products"][1]["name"], "Desk");
+    assert_eq!(response["errors"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        response["errors"][1]["extensions"]["code"],
+        "FIELD_QUARANTINED"
+    );
+    assert!(
+        executor
+            .metrics()
+            .contains("portkey_field_quarantines_total 1")
+    );
+}
@@ -0,0 +1,1221 @@
+mod common;
+
+use common::{MockService, build_schema};
+use portkey::{
+    CompressionConfig, ContentEncoding, FederatedSchema, JoinConfig, ServiceCapabilities,
+    ServiceConfig,
+    query_executor::{AuthForwardingConfig, HttpClientConfig, HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews: [Review]
+}
+"#;
+
+async fn build_compressed_schema(url: &str, compression: CompressionConfig) -> FederatedSchema {
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            compression,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    registry.get_schema().await.unwrap()
+}
+
+async fn execute(query: &str, schema: &FederatedSchema) -> Result<serde_json::Value, String> {
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(query, schema, None, None)
+        .await
+        .unwrap();
+    HttpQueryExecutor::new()
+        .execute_plan(plan, schema, None)
+        .await
+}
+
+#[tokio::test]
+async fn test_decompresses_responses_up_to_the_configured_limit() {
+    let products = MockService::start_gzip(
+        |_| json!({ "data": { "products": [{ "id": "1", "name": "x".repeat(64 * 1024) }] } }),
+    )
+    .await;
+
+    let schema = build_compressed_schema(&products.url, CompressionConfig::default()).await;
+    let result = execute("{ products { name } }", &schema).await.unwrap();
+    assert_eq!(
+        result["data"]["products"][0]["name"]
+            .as_str()
+            .unwrap()
+            .len(),
+        64 * 1024
+    );
+
+    let schema = build_compressed_schema(
+        &products.url,
+        CompressionConfig {
+            enabled: true,
+            max_decompressed_bytes: 4 * 1024,
+            ..Default::default()
+        },
+    )
+    .await;
+    let error = execute("{ products { name } }", &schema).await.unwrap_err();
+    assert!(
+        error.contains("exceeds the limit of 4096 bytes"),
+        "{}",
+        error
+    );
+
+    assert!(products.headers().iter().all(|headers| {
+        headers["accept-encoding"]
+            .to_str()
+            .unwrap()
+            .contains("gzip")
+    }));
+}
+
+#[tokio::test]
+async fn test_refuses_responses_declaring_a_larger_length() {
+    let products = MockService::start(
+        |_| json!({ "data": { "products": [{ "id": "1", "name": "x".repeat(8 * 1024) }] } }),
+    )
+    .await;
+
+    let schema = build_compressed_schema(
+        &products.url,
+        CompressionConfig {
+            max_decompressed_bytes: 4 * 1024,
+            ..Default::default()
+        },
+    )
+    .await;
+    let error = execute("{ products { name } }", &schema).await.unwrap_err();
+    assert_eq!(
+        error,
+        "Response from service products exceeds the limit of 4096 bytes"
+    );
+}
+
+#[tokio::test]
+async fn test_requests_identity_encoding_when_compression_is_disabled() {
+    let products = MockService::start_gzip(
+        |_| json!({ "data": { "products": [{ "id": "1", "name": "Table" }] } }),
+    )
+    .await;
+
+    let schema = build_compressed_schema(
+        &products.url,
+        CompressionConfig {
+            enabled: false,
+            ..Default::default()
+        },
+    )
+    .await;
+    let result = execute("{ products { name } }", &schema).await.unwrap();
+
+    assert_eq!(result["data"]["products"][0]["name"], json!("Table"));
+    assert_eq!(products.headers()[0]["accept-encoding"], "identity");
+}
+
+#[tokio::test]
+async fn test_compresses_request_bodies_past_the_threshold() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "id": "1", "name": "Table" }] } }))
+            .await;
+
+    for encoding in [ContentEncoding::Gzip, ContentEncoding::Br] {
+        let schema = build_compressed_schema(
+            &products.url,
+            CompressionConfig {
+                request_encoding: Some(encoding),
+                min_request_bytes: 0,
+                ..Default::default()
+            },
+        )
+        .await;
+        let result = execute("{ products { name } }", &schema).await.unwrap();
+        assert_eq!(result["data"]["products"][0]["name"], json!("Table"));
+    }
+
+    // Bodies under the threshold are sent as they are
+    let schema = build_compressed_schema(
+        &products.url,
+        CompressionConfig {
+            request_encoding: Some(ContentEncoding::Gzip),
+            ..Default::default()
+        },
+    )
+    .await;
+    execute("{ products { name } }", &schema).await.unwrap();
+
+    let encodings: Vec<_> = products
+        .headers()
+        .iter()
+        .map(|headers| headers.get("content-encoding").cloned())
+        .collect();
+    assert_eq!(
+        encodings,
+        vec![
+            Some("gzip".parse().unwrap()),
+            Some("br".parse().unwrap()),
+            None
+        ]
+    );
+    // The service sees the same request whatever the encoding
+    let requests = products.requests();
+    assert!(requests.iter().all(|request| *request == requests[2]));
+    assert!(requests[2]["query"].as_str().unwrap().contains("products"));
+}
+
+#[tokio::test]
+async fn test_merges_entities_into_parent_list() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "id": "1", "name": "Table" },
+            { "id": "2", "name": "Chair" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let entities: Vec<_> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rep| json!({ "reviews": [{ "body": format!("Review of {}", rep["id"].as_str().unwrap()) }] }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([
+            { "name": "Table", "reviews": [{ "body": "Review of 1" }] },
+            { "name": "Chair", "reviews": [{ "body": "Review of 2" }] }
+        ])
+    );
+
+    let requests = reviews.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0]["variables"]["representations"],
+        json!([
+            { "__typename": "Product", "id": "1" },
+            { "__typename": "Product", "id": "2" }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_sends_each_distinct_representation_once() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "id": "1", "name": "Table" },
+            { "id": "2", "name": "Chair" },
+            { "id": "1", "name": "Table" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let entities: Vec<_> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rep| json!({ "reviews": [{ "body": format!("Review of {}", rep["id"].as_str().unwrap()) }] }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { reviews { body } } }", &schema, None, None)
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([
+            { "reviews": [{ "body": "Review of 1" }] },
+            { "reviews": [{ "body": "Review of 2" }] },
+            { "reviews": [{ "body": "Review of 1" }] }
+        ])
+    );
+    assert_eq!(
+        reviews.requests()[0]["variables"]["representations"],
+        json!([
+            { "__typename": "Product", "id": "1" },
+            { "__typename": "Product", "id": "2" }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_resolves_configured_joins_per_parent() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "id": "1", "name": "Table" },
+            { "id": "2", "name": "Chair" },
+            { "id": "1", "name": "Table" },
+            { "id": null, "name": "Draft" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let product_id = body["variables"]["_join_productId"].as_str().unwrap();
+        json!({ "data": { "reviews": [{ "body": format!("Review of {}", product_id) }] } })
+    })
+    .await;
+
+    let (_, join) =
+        JoinConfig::parse("Product.reviews -> reviews.reviewsByProductId(productId: $Product.id)")
+            .unwrap();
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: "type Query { products: [Product] } type Product { id: ID name: String }"
+                .to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    registry
+        .register_service(ServiceConfig {
+            name: "reviews".to_string(),
+            url: reviews.url.clone(),
+            schema: r#"
+                type Query { reviewsByProductId(productId: ID!): [Review] }
+                type Review { body: String }
+            "#
+            .to_string(),
+            joins: vec![join],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([
+            { "name": "Table", "reviews": [{ "body": "Review of 1" }] },
+            { "name": "Chair", "reviews": [{ "body": "Review of 2" }] },
+            { "name": "Table", "reviews": [{ "body": "Review of 1" }] },
+            { "name": "Draft", "reviews": null }
+        ])
+    );
+
+    // One fetch per distinct product, none without an id
+    let mut product_ids: Vec<_> = reviews
+        .requests()
+        .iter()
+        .map(|request| request["variables"]["_join_productId"].clone())
+        .collect();
+    product_ids.sort_by_key(|id| id.to_string());
+    assert_eq!(product_ids, vec![json!("1"), json!("2")]);
+}
+
+#[tokio::test]
+async fn test_builds_nested_representations_for_composite_keys() {
+    let accounts_schema = r#"
+        type Query {
+            members: [Member]
+        }
+
+        type Member @key(fields: "id organization { id }") {
+            id: ID!
+            name: String!
+            organization: Organization!
+        }
+
+        type Organization {
+            id: ID!
+            name: String!
+        }
+    "#;
+    let billing_schema = r#"
+        type Organization {
+            id: ID!
+        }
+
+        extend type Member @key(fields: "id organization { id }") {
+            id: ID! @external
+            organization: Organization! @external
+            plan: String
+        }
+    "#;
+
+    let accounts = MockService::start(|_| {
+        json!({ "data": { "members": [{
+            "__typename": "Member",
+            "id": "1",
+            "name": "Ann",
+            "organization": { "id": "o1", "name": "Acme" }
+        }] } })
+    })
+    .await;
+    let billing =
+        MockService::start(|_| json!({ "data": { "_entities": [{ "plan": "pro" }] } })).await;
+
+    let schema = build_schema(&[
+        ("accounts", &accounts.url, accounts_schema),
+        ("billing", &billing.url, billing_schema),
+    ])
+    .await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ members { name organization { name } plan } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        plan.explain()["nodes"][1]["node"]["entity"]["keyFields"],
+        json!(["id", "organization { id }"])
+    );
+
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["members"],
+        json!([{ "name": "Ann", "organization": { "name": "Acme" }, "plan": "pro" }])
+    );
+    assert_eq!(
+        billing.requests()[0]["variables"]["representations"],
+        json!([{ "__typename": "Member", "id": "1", "organization": { "id": "o1" } }])
+    );
+}
+
+const SHIPPING_SCHEMA: &str = r#"
+    extend type Product @key(fields: "id") {
+        id: ID! @external
+        weight: Int @external
+        shippingEstimate: Int @requires(fields: "weight")
+    }
+"#;
+
+// Answers `shippingEstimate` from the `weight` in each representation
+async fn start_shipping() -> MockService {
+    MockService::start(|body| {
+        let entities: Vec<_> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rep| json!({ "shippingEstimate": rep["weight"].as_i64().unwrap() * 10 }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_sends_required_fields_in_representations() {
+    let products_schema = r#"
+        type Query {
+            products: [Product]
+        }
+
+        type Product @key(fields: "id") {
+            id: ID!
+            name: String!
+            weight: Int
+        }
+    "#;
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "__typename": "Product", "id": "1", "name": "Table", "weight": 5 }
+        ] } })
+    })
+    .await;
+    let shipping = start_shipping().await;
+
+    let schema = build_schema(&[
+        ("products", &products.url, products_schema),
+        ("shipping", &shipping.url, SHIPPING_SCHEMA),
+    ])
+    .await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name shippingEstimate } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    // `weight` is fetched for the representations only
+    assert_eq!(
+        result["data"]["products"],
+        json!([{ "name": "Table", "shippingEstimate": 50 }])
+    );
+    let query = products.requests()[0]["query"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(query.contains("weight"), "{}", query);
+    assert_eq!(
+        shipping.requests()[0]["variables"]["representations"],
+        json!([{ "__typename": "Product", "id": "1", "weight": 5 }])
+    );
+}
+
+#[tokio::test]
+async fn test_fetches_required_fields_from_their_owner_first() {
+    let inventory_schema = r#"
+        extend type Product @key(fields: "id") {
+            id: ID! @external
+            weight: Int
+        }
+    "#;
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Table" }] } })
+    })
+    .await;
+    let inventory =
+        MockService::start(|_| json!({ "data": { "_entities": [{ "weight": 7 }] } })).await;
+    let shipping = start_shipping().await;
+
+    let schema = build_schema(&[
+        ("inventory", &inventory.url, inventory_schema),
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("shipping", &shipping.url, SHIPPING_SCHEMA),
+    ])
+    .await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name shippingEstimate } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([{ "name": "Table", "shippingEstimate": 70 }])
+    );
+    assert_eq!(
+        inventory.requests()[0]["variables"]["representations"],
+        json!([{ "__typename": "Product", "id": "1" }])
+    );
+    assert_eq!(
+        shipping.requests()[0]["variables"]["representations"],
+        json!([{ "__typename": "Product", "id": "1", "weight": 7 }])
+    );
+}
+
+#[tokio::test]
+async fn test_routes_overridden_fields_to_their_new_owner() {
+    // `inStock` is being migrated to inventory, products still declares it
+    let products_schema = r#"
+        type Query {
+            products: [Product]
+        }
+
+        type Product @key(fields: "id") {
+            id: ID!
+            name: String!
+            inStock: Boolean
+        }
+    "#;
+    let inventory_schema = r#"
+        extend type Product @key(fields: "id") {
+            id: ID! @external
+            inStock: Boolean @override(from: "products")
+        }
+    "#;
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Table" }] } })
+    })
+    .await;
+    let inventory =
+        MockService::start(|_| json!({ "data": { "_entities": [{ "inStock": true }] } })).await;
+
+    let schema = build_schema(&[
+        ("inventory", &inventory.url, inventory_schema),
+        ("products", &products.url, products_schema),
+    ])
+    .await;
+    assert_eq!(
+        schema.type_to_service_map["Product.inStock"],
+        vec!["inventory"]
+    );
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name inStock } }", &schema, None, None)
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([{ "name": "Table", "inStock": true }])
+    );
+    let query = products.requests()[0]["query"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(!query.contains("inStock"), "{}", query);
+    assert_eq!(
+        inventory.requests()[0]["variables"]["representations"],
+        json!([{ "__typename": "Product", "id": "1" }])
+    );
+}
+
+#[tokio::test]
+async fn test_concatenates_fanned_out_lists_and_filters_entities_by_typename() {
+    let search_schema = |own_type: &str| {
+        format!(
+            "type Query {{ search: [SearchResult] }} union SearchResult = {0} \
+             type {0} @key(fields: \"id\") {{ id: ID! name: String }}",
+            own_type
+        )
+    };
+
+    let products = MockService::start(|_| {
+        json!({ "data": { "search": [{ "__typename": "Product", "id": "p1", "name": "Table" }] } })
+    })
+    .await;
+    let users = MockService::start(
+        |_| json!({ "data": { "search": [{ "__typename": "User", "id": "u1", "name": "Ada" }] } }),
+    )
+    .await;
+    let reviews = MockService::start(|body| {
+        let count = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .len();
+        json!({ "data": { "_entities": vec![json!({ "reviews": [] }); count] } })
+    })
+    .await;
+
+    let schema = build_schema(&[
+        ("products", &products.url, &search_schema("Product")),
+        ("users", &users.url, &search_schema("User")),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ search { __typename ... on Product { name reviews { body } } ... on User { name } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    let search = result["data"]["search"].as_array().unwrap();
+    assert_eq!(search.len(), 2);
+    for item in search {
+        match item["__typename"].as_str().unwrap() {
+            "Product" => assert_eq!(item["reviews"], json!([])),
+            _ => assert!(item.get("reviews").is_none()),
+        }
+    }
+
+    let requests = reviews.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0]["variables"]["representations"],
+        json!([{ "__typename": "Product", "id": "p1" }])
+    );
+}
+
+#[tokio::test]
+async fn test_answers_typename_and_drops_fields_added_by_the_gateway() {
+    let search_schema = |own_type: &str| {
+        format!(
+            "type Query {{ search: [SearchResult] }} union SearchResult = {0} \
+             type {0} @key(fields: \"id\") {{ id: ID! name: String }}",
+            own_type
+        )
+    };
+
+    let products = MockService::start(|_| {
+        json!({ "data": { "search": [{ "__typename": "Product", "id": "p1", "name": "Table" }] } })
+    })
+    .await;
+    let users = MockService::start(
+        |_| json!({ "data": { "search": [{ "__typename": "User", "id": "u1", "name": "Ada" }] } }),
+    )
+    .await;
+
+    let schema = build_schema(&[
+        ("products", &products.url, &search_schema("Product")),
+        ("users", &users.url, &search_schema("User")),
+    ])
+    .await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ __typename search { ... on Product { kind: __typename name } ... on User { id } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let result = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+
+    assert_eq!(result["data"]["__typename"], json!("Query"));
+    let mut search = result["data"]["search"].as_array().unwrap().clone();
+    search.sort_by_key(|item| item.to_string());
+    assert_eq!(
+        search,
+        vec![
+            json!({ "id": "u1" }),
+            json!({ "kind": "Product", "name": "Table" }),
+        ]
+    );
+
+    // Both services are asked for `__typename` so the union members can be told apart
+    for service in [&products, &users] {
+        assert!(
+            service.requests()[0]["query"]
+                .as_str()
+                .unwrap()
+                .contains("__typename")
+        );
+    }
+}
+
+async fn build_persisted_query_schema(url: &str) -> FederatedSchema {
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            capabilities: ServiceCapabilities {
+                persisted_queries: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    registry.get_schema().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_sends_persisted_query_hashes_to_capable_services() {
+    let known = Arc::new(Mutex::new(HashSet::new()));
+    let products = MockService::start(move |body| {
+        let hash = body["extensions"]["persistedQuery"]["sha256Hash"].clone();
+        let mut known = known.lock().unwrap();
+        if body["query"].is_string() {
+            known.insert(hash.to_string());
+        } else if !known.contains(&hash.to_string()) {
+            return json!({ "errors": [{
+                "message": "PersistedQueryNotFound",
+                "extensions": { "code": "PERSISTED_QUERY_NOT_FOUND" }
+            }] });
+        }
+        json!({ "data": { "products": [{ "id": "1", "name": "Table" }] } })
+    })
+    .await;
+    let schema = build_persisted_query_schema(&products.url).await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+    let executor = HttpQueryExecutor::new();
+
+    for _ in 0..2 {
+        let response = executor
+            .execute_plan(plan.clone(), &schema, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            json!({ "data": { "products": [{ "name": "Table" }] } })
+        );
+    }
+
+    let sent_queries: Vec<bool> = products
+        .requests()
+        .iter()
+        .map(|body| body["query"].is_string())
+        .collect();
+    assert_eq!(sent_queries, vec![false, true, false]);
+}
+
+#[tokio::test]
+async fn test_falls_back_to_full_queries_without_persisted_query_support() {
+    let products = MockService::start(|body| {
+        if body["query"].is_string() {
+            json!({ "data": { "products": [] } })
+        } else {
+            json!({ "errors": [{ "message": "PersistedQueryNotSupported" }] })
+        }
+    })
+    .await;
+    let schema = build_persisted_query_schema(&products.url).await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+    let executor = HttpQueryExecutor::new();
+
+    for _ in 0..2 {
+        let response = executor
+            .execute_plan(plan.clone(), &schema, None)
+            .await
+            .unwrap();
+        assert_eq!(response, json!({ "data": { "products": [] } }));
+    }
+
+    let requests = products.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2]["extensions"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_resolves_deferred_fragments_with_the_response() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [
+            { "__typename": "Product", "id": "1", "name": "Table" },
+            { "__typename": "Product", "id": "2", "name": "Chair" }
+        ] } })
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let entities: Vec<Value> = body["variables"]["representations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repr| json!({ "reviews": [{ "body": format!("Review of {}", repr["id"]) }] }))
+            .collect();
+        json!({ "data": { "_entities": entities } })
+    })
+    .await;
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+
+    let response = execute(
+        "{ products { name ... @defer { reviews { body } } } }",
+        &schema,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        response,
+        json!({ "data": { "products": [
+            { "name": "Table", "reviews": [{ "body": "Review of \"1\"" }] },
+            { "name": "Chair", "reviews": [{ "body": "Review of \"2\"" }] }
+        ] } })
+    );
+}
+
+#[tokio::test]
+async fn test_copies_deduplicated_root_fields_into_every_alias() {
+    let products =
+        MockService::start(|_| json!({ "data": { "first": [{ "name": "Table" }] } })).await;
+    let schema = build_schema(&[("products", &products.url, PRODUCTS_SCHEMA)]).await;
+
+    let response = execute(
+        "{ first: products { name } second: products { name } }",
+        &schema,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        response,
+        json!({ "data": {
+            "first": [{ "name": "Table" }],
+            "second": [{ "name": "Table" }],
+        } })
+    );
+    assert_eq!(products.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_reuses_connections_across_requests() {
+    let products = MockService::start(|_| json!({ "data": { "products": [] } })).await;
+    let schema = build_schema(&[("products", &products.url, PRODUCTS_SCHEMA)]).await;
+    let executor = HttpQueryExecutor::new();
+
+    for _ in 0..3 {
+        let plan = SimpleQueryPlanner::new()
+            .plan_query("{ products { name } }", &schema, None, None)
+            .await
+            .unwrap();
+        executor.execute_plan(plan, &schema, None).await.unwrap();
+    }
+
+    assert_eq!(products.requests().len(), 3);
+    assert_eq!(products.connections(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gives_up_on_services_past_the_request_timeout() {
+    let products = MockService::start(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        json!({ "data": { "products": [] } })
+    })
+    .await;
+    let schema = build_schema(&[("products", &products.url, PRODUCTS_SCHEMA)]).await;
+    let executor = HttpQueryExecutor::new().with_http_client(&HttpClientConfig {
+        request_timeout_ms: Some(50),
+        ..Default::default()
+    });
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+    let result = executor.execute_plan(plan, &schema, None).await;
+    let error = match result {
+        Err(error) => error,
+        Ok(response) => response["errors"][0]["message"].to_string(),
+    };
+    assert!(error.contains("HTTP request failed"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_returns_partial_data_when_a_service_fails() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Lamp" }] } })
+    })
+    .await;
+    // Nothing listens there, connections are refused
+    let reviews_url = "http://127.0.0.1:1/graphql";
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", reviews_url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let plan = || async {
+        SimpleQueryPlanner::new()
+            .plan_query(
+                "{ products { name reviews { body } } }",
+                &schema,
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+    };
+
+    let failed = HttpQueryExecutor::new()
+        .execute_plan(plan().await, &schema, None)
+        .await;
+    assert!(failed.unwrap_err().contains("HTTP request failed"));
+
+    let response = HttpQueryExecutor::new()
+        .with_partial_results(true)
+        .execute_plan(plan().await, &schema, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Lamp", "reviews": null }] })
+    );
+    let error = &response["errors"][0];
+    assert!(
+        error["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("HTTP request failed")
+    );
+    assert_eq!(
+        error["extensions"],
+        json!({ "code": "SUBREQUEST_HTTP_ERROR", "serviceName": "reviews" })
+    );
+}
+
+#[test]
+fn test_scopes_credential_forwarding_to_services() {
+    let everyone = AuthForwardingConfig::default();
+    assert!(everyone.forwards_to("reviews"));
+
+    let scoped = AuthForwardingConfig {
+        allow: Some(vec!["products".to_string(), "partner-*".to_string()]),
+        deny: vec!["partner-ads".to_string()],
+    };
+    assert!(scoped.forwards_to("products"));
+    assert!(scoped.forwards_to("partner-maps"));
+    assert!(!scoped.forwards_to("partner-ads"));
+    assert!(!scoped.forwards_to("reviews"));
+}
+
+#[tokio::test]
+async fn test_withholds_credentials_from_denied_services() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Lamp" }] } })
+    })
+    .await;
+    let reviews = MockService::start(
+        |_| json!({ "data": { "_entities": [{ "__typename": "Product", "reviews": [] }] } }),
+    )
+    .await;
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let executor = HttpQueryExecutor::new().with_auth_forwarding(AuthForwardingConfig {
+        deny: vec!["reviews".to_string()],
+        ..Default::default()
+    });
+    let auth_headers = HashMap::from([
+        ("Authorization".to_string(), "Bearer internal".to_string()),
+        ("x-token".to_string(), "internal".to_string()),
+    ]);
+    executor
+        .execute_plan(plan, &schema, Some(auth_headers))
+        .await
+        .unwrap();
+
+    assert_eq!(products.headers()[0]["authorization"], "Bearer internal");
+    assert_eq!(products.headers()[0]["x-token"], "internal");
+    assert!(!reviews.headers()[0].contains_key("authorization"));
+    assert!(!reviews.headers()[0].contains_key("x-token"));
+}
+
+#[tokio::test]
+async fn test_sends_services_their_static_headers() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Lamp" }] } })
+    })
+    .await;
+    let reviews = MockService::start(
+        |_| json!({ "data": { "_entities": [{ "__typename": "Product", "reviews": [] }] } }),
+    )
+    .await;
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            headers: HashMap::from([
+                ("x-internal-key".to_string(), "products-key".to_string()),
+                ("authorization".to_string(), "Bearer service".to_string()),
+            ]),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    registry
+        .register_service(ServiceConfig {
+            name: "reviews".to_string(),
+            url: reviews.url.clone(),
+            schema: REVIEWS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Sent whether or not the client's credentials are forwarded, replacing
+    // the client's own
+    let executor = HttpQueryExecutor::new().with_auth_forwarding(AuthForwardingConfig {
+        deny: vec!["products".to_string()],
+        ..Default::default()
+    });
+    let auth_headers = HashMap::from([
+        ("Authorization".to_string(), "Bearer client".to_string()),
+        ("x-token".to_string(), "client".to_string()),
+    ]);
+    executor
+        .execute_plan(plan.clone(), &schema, Some(auth_headers.clone()))
+        .await
+        .unwrap();
+    assert_eq!(products.headers()[0]["x-internal-key"], "products-key");
+    assert_eq!(products.headers()[0]["authorization"], "Bearer service");
+    assert!(!products.headers()[0].contains_key("x-token"));
+    assert!(!reviews.headers()[0].contains_key("x-internal-key"));
+    assert_eq!(reviews.headers()[0]["authorization"], "Bearer client");
+
+    HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, Some(auth_headers))
+        .await
+        .unwrap();
+    let forwarded = &products.headers()[1];
+    assert_eq!(forwarded.get_all("authorization").iter().count(), 1);
+    assert_eq!(forwarded["authorization"], "Bearer service");
+    assert_eq!(forwarded["x-token"], "client");
+}
+
+#[tokio::test]
+async fn test_reports_service_errors_at_their_path_in_the_response() {
+    let products = MockService::start(|_| {
+        json!({
+            "data": { "products": [
+                { "__typename": "Product", "id": "1", "name": "Lamp" },
+                { "__typename": "Product", "id": "2", "name": "Desk" },
+                { "__typename": "Product", "id": "2", "name": "Desk" },
+            ] },
+            "errors": [{ "message": "Stale", "extensions": { "code": "STALE" } }],
+        })
+    })
+    .await;
+    let reviews = MockService::start(|_| {
+        json!({
+            "data": { "_entities": [
+                { "__typename": "Product", "reviews": [] },
+                { "__typename": "Product", "reviews": null },
+            ] },
+            "errors": [{ "message": "Reviews are unavailable", "path": ["_entities", 1, "reviews"] }],
+        })
+    })
+    .await;
+    let schema = build_schema(&[
+        ("products", &products.url, PRODUCTS_SCHEMA),
+        ("reviews", &reviews.url, REVIEWS_SCHEMA),
+    ])
+    .await;
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let response = HttpQueryExecutor::new()
+        .execute_plan(plan, &schema, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        response["errors"],
+        json!([
+            {
+                "message": "Stale",
+                "extensions": { "code": "STALE", "serviceName": "products" },
+            },
+            {
+                "message": "Reviews are unavailable",
+                "path": ["products", 1, "reviews"],
+                "extensions": { "code": "DOWNSTREAM_SERVICE_ERROR", "serviceName": "reviews" },
+            },
+        ])
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::{MockService, request};
+use portkey::query_limits::QueryLimitsConfig;
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const SCHEMA: &str = r#"
+type Query {
+    product(id: ID!): Product
+}
+
+type Product {
+    id: ID!
+    name: String!
+}
+"#;
+
+fn limits() -> QueryLimitsConfig {
+    QueryLimitsConfig {
+        max_tokens: Some(200),
+        max_aliases: Some(3),
+        max_root_fields: Some(2),
+        max_directives: Some(2),
+    }
+}
+
+#[test]
+fn test_counts_aliases_through_fragments() {
+    // Two aliases in a fragment spread twice
+    let query = r#"
+        { product(id: 1) { ...Names } other: product(id: 2) { ...Names } }
+        fragment Names on Product { a: name b: name }
+    "#;
+    assert_eq!(
+        limits().check(query, None),
+        Err("Operation has 5 aliases, exceeding the limit of 3".to_string())
+    );
+    assert_eq!(
+        limits().check("{ product(id: 1) { a: name b: name } }", None),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_limits_root_fields_directives_and_tokens() {
+    let query = "query { a: product(id: 1) { id } ...More } fragment More on Query { product(id: 2) { id } c: product(id: 3) { id } }";
+    assert_eq!(
+        limits().check(query, None),
+        Err("Operation has 3 root fields, exceeding the limit of 2".to_string())
+    );
+
+    let query = "query($x: Boolean!) { product(id: 1) @include(if: $x) { id @skip(if: $x) name @skip(if: $x) } }";
+    assert_eq!(
+        limits().check(query, None),
+        Err("Operation has 3 directives, exceeding the limit of 2".to_string())
+    );
+
+    let query = format!("{{ product(id: 1) {{ {} }} }}", "id ".repeat(300));
+    assert_eq!(
+        limits().check(&query, None),
+        Err("Operation has more than 200 tokens, exceeding the limit".to_string())
+    );
+
+    // Strings and comments are single tokens or none at all
+    let query = format!(
+        "# {}\n{{ product(id: \"{}\") {{ id }} }}",
+        "x ".repeat(300),
+        "y ".repeat(300)
+    );
+    assert_eq!(limits().check(&query, None), Ok(()));
+}
+
+#[tokio::test]
+async fn test_rejects_operations_over_the_limits_before_planning() {
+    let products = MockService::start(|_| json!({ "data": { "product": null } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_query_limits(limits());
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(request("{ a: product(id: 1) { id } b: product(id: 2) { id } c: product(id: 3) { id } d: product(id: 4) { id } }"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Operation has 4 aliases, exceeding the limit of 3"
+    );
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"],
+        "OPERATION_TOO_LARGE"
+    );
+    assert!(products.requests().is_empty());
+}
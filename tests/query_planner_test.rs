@@ -0,0 +1,1227 @@
+mod common;
+
+use graphql_parser::query::{Definition, OperationDefinition, Selection};
+use portkey::{
+    JoinConfig, KeyField, PlanNode, ServiceConfig,
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews(first: Int): [Review]
+}
+"#;
+
+async fn build_schema(services: &[(&str, &str)]) -> portkey::FederatedSchema {
+    let urls: Vec<String> = services
+        .iter()
+        .map(|(name, _)| format!("http://{}/graphql", name))
+        .collect();
+    let services: Vec<(&str, &str, &str)> = services
+        .iter()
+        .zip(&urls)
+        .map(|((name, schema), url)| (*name, url.as_str(), *schema))
+        .collect();
+    common::build_schema(&services).await
+}
+
+fn collapse(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn fetch_node(node: &PlanNode) -> &portkey::FetchNode {
+    match node {
+        PlanNode::Fetch(fetch) => fetch,
+        other => panic!("expected a fetch node, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_plans_entity_fetch_for_foreign_fields() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "query($n: Int) { products { name reviews(first: $n) { body } } }",
+            &schema,
+            Some(json!({ "n": 5 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let nodes = match &plan.node {
+        PlanNode::Sequence(nodes) => nodes,
+        other => panic!("expected a sequence, got {:?}", other),
+    };
+    assert_eq!(nodes.len(), 2);
+
+    let products = fetch_node(&nodes[0]);
+    assert_eq!(products.service_name, "products");
+    assert!(products.query.contains("name"));
+    assert!(products.query.contains("id"));
+    assert!(!products.query.contains("reviews"));
+    assert!(!products.query.contains("$n"));
+    assert_eq!(products.variables, json!({}));
+    assert!(products.entity.is_none());
+
+    let flatten = match &nodes[1] {
+        PlanNode::Flatten(flatten) => flatten,
+        other => panic!("expected a flatten node, got {:?}", other),
+    };
+    assert_eq!(flatten.path, vec!["products".to_string()]);
+
+    let reviews = fetch_node(&flatten.node);
+    assert_eq!(reviews.service_name, "reviews");
+    let entity = reviews.entity.as_ref().unwrap();
+    assert_eq!(entity.type_name, "Product");
+    assert_eq!(
+        entity.key_fields,
+        vec![KeyField {
+            name: "id".to_string(),
+            selections: Vec::new(),
+        }]
+    );
+    assert!(
+        reviews
+            .query
+            .contains("query($representations: [_Any!]!, $n: Int)")
+    );
+    assert!(
+        reviews
+            .query
+            .contains("_entities(representations: $representations)")
+    );
+    assert!(reviews.query.contains("... on Product"));
+    assert_eq!(reviews.variables, json!({ "n": 5 }));
+}
+
+#[tokio::test]
+async fn test_exposes_fetches_with_paths_and_parsed_operations() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "query($n: Int) { products { name reviews(first: $n) { body } } }",
+            &schema,
+            Some(json!({ "n": 5 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let fetches = plan.fetches();
+    assert_eq!(fetches.len(), 2);
+    assert_eq!(fetches[0].fetch.service_name, "products");
+    assert!(fetches[0].path.is_empty());
+    assert!(fetches[0].fetch.required_variables().unwrap().is_empty());
+    assert_eq!(fetches[1].fetch.service_name, "reviews");
+    assert_eq!(fetches[1].path, ["products".to_string()]);
+    assert_eq!(fetches[1].fetch.required_variables().unwrap(), vec!["n"]);
+
+    let operation = fetches[1].fetch.operation().unwrap();
+    let selection_set = match &operation.definitions[0] {
+        Definition::Operation(OperationDefinition::Query(query)) => &query.selection_set,
+        other => panic!("expected a query, got {:?}", other),
+    };
+    assert!(matches!(
+        &selection_set.items[0],
+        Selection::Field(field) if field.name == "_entities"
+    ));
+
+    let serialized = serde_json::to_value(&plan).unwrap();
+    assert_eq!(serialized["rootType"], "Query");
+    assert_eq!(
+        serialized["node"]["Sequence"][1]["Flatten"]["node"]["Fetch"]["entity"]["keyFields"],
+        json!([{ "name": "id", "selections": [] }])
+    );
+}
+
+#[tokio::test]
+async fn test_plans_root_fields_of_same_service_in_parallel() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "{ products { id } other: products { name } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    match &plan.node {
+        PlanNode::Parallel(nodes) => {
+            assert_eq!(nodes.len(), 2);
+            for node in nodes {
+                assert_eq!(fetch_node(node).service_name, "products");
+            }
+        }
+        other => panic!("expected a parallel node, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_plans_mutation_fields_in_document_order() {
+    let products_schema = format!(
+        "{}\ntype Mutation {{ addProduct(name: String!): Product }}",
+        PRODUCTS_SCHEMA
+    );
+    let reviews_schema = format!(
+        "{}\ntype Mutation {{ addReview(body: String!): Review }}",
+        REVIEWS_SCHEMA
+    );
+    let schema =
+        build_schema(&[("products", &products_schema), ("reviews", &reviews_schema)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"mutation {
+                first: addReview(body: "a") { body }
+                addProduct(name: "Lamp") { id }
+                second: addReview(body: "b") { body }
+            }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    match &plan.node {
+        PlanNode::Sequence(nodes) => {
+            let services: Vec<_> = nodes
+                .iter()
+                .map(|node| fetch_node(node).service_name.as_str())
+                .collect();
+            assert_eq!(services, vec!["reviews", "products", "reviews"]);
+            assert!(fetch_node(&nodes[0]).query.contains("first: addReview"));
+            assert!(fetch_node(&nodes[2]).query.contains("second: addReview"));
+        }
+        other => panic!("expected a sequence, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_rejects_subscriptions_to_services_without_support() {
+    let products_schema = format!(
+        "{}\ntype Subscription {{ productAdded: Product }}",
+        PRODUCTS_SCHEMA
+    );
+    let schema = build_schema(&[("products", &products_schema)]).await;
+
+    let result = SimpleQueryPlanner::new()
+        .plan_query("subscription { productAdded { id } }", &schema, None, None)
+        .await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        "Service products does not support subscriptions"
+    );
+}
+
+#[tokio::test]
+async fn test_plans_deferred_fragments_separately() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"query($n: Int) {
+                products {
+                    name
+                    ... @defer(label: "reviews") { reviews(first: $n) { body } }
+                }
+            }"#,
+            &schema,
+            Some(json!({ "n": 5 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    // The initial fetch only selects what the deferred entity fetch needs
+    let products = fetch_node(&plan.node);
+    assert_eq!(products.service_name, "products");
+    assert!(products.query.contains("id"));
+    assert!(!products.query.contains("reviews"));
+
+    assert_eq!(plan.deferred.len(), 1);
+    let deferred = &plan.deferred[0];
+    assert_eq!(deferred.label.as_deref(), Some("reviews"));
+    assert_eq!(deferred.path, vec!["products".to_string()]);
+    assert_eq!(deferred.response_shape[0].response_key, "reviews");
+    let flatten = match &deferred.node {
+        PlanNode::Flatten(flatten) => flatten,
+        other => panic!("expected a flatten node, got {:?}", other),
+    };
+    assert_eq!(flatten.path, vec!["products".to_string()]);
+    let reviews = fetch_node(&flatten.node);
+    assert_eq!(reviews.service_name, "reviews");
+    assert_eq!(reviews.variables, json!({ "n": 5 }));
+
+    // The whole selection is still shaped, deferred fields included
+    let shape: Vec<_> = plan.response_shape[0]
+        .selections
+        .iter()
+        .map(|field| field.response_key.as_str())
+        .collect();
+    assert_eq!(shape, vec!["name", "reviews"]);
+}
+
+#[tokio::test]
+async fn test_plans_root_defers_and_streams() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"query($defer: Boolean, $count: Int) {
+                first: products @stream(initialCount: $count) { id }
+                ... @defer { later: products { name } }
+                ... @defer(if: $defer) { inline: products { name } }
+                products { ... on Product @defer { __typename } }
+            }"#,
+            &schema,
+            Some(json!({ "defer": false, "count": 2 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let initial: Vec<_> = match &plan.node {
+        PlanNode::Parallel(nodes) => nodes
+            .iter()
+            .map(|node| fetch_node(node).query.clone())
+            .collect(),
+        other => panic!("expected a parallel node, got {:?}", other),
+    };
+    assert_eq!(initial.len(), 3);
+    assert!(initial[0].contains("first: products"));
+    assert!(initial[1].contains("inline: products"));
+
+    assert_eq!(plan.deferred.len(), 1);
+    assert_eq!(plan.deferred[0].path, Vec::<String>::new());
+    assert!(
+        fetch_node(&plan.deferred[0].node)
+            .query
+            .contains("later: products")
+    );
+
+    assert_eq!(plan.streams.len(), 1);
+    assert_eq!(plan.streams[0].path, vec!["first".to_string()]);
+    assert_eq!(plan.streams[0].initial_count, 2);
+    assert_eq!(plan.streams[0].label, None);
+
+    assert_eq!(
+        plan.explain()["deferred"][0]["node"]["serviceName"],
+        json!("products")
+    );
+    assert_eq!(plan.explain()["streams"][0]["initialCount"], json!(2));
+}
+
+#[tokio::test]
+async fn test_resolves_provided_fields_without_entity_fetch() {
+    let products_schema = r#"
+        type Query {
+            products: [Product]
+        }
+
+        type Product @key(fields: "id") {
+            id: ID!
+            name: String!
+            price: Int
+        }
+    "#;
+    let reviews_schema = r#"
+        type Query {
+            topReviews: [Review]
+        }
+
+        type Review {
+            body: String!
+            product: Product @provides(fields: "name")
+        }
+
+        extend type Product @key(fields: "id") {
+            id: ID! @external
+            name: String! @external
+        }
+    "#;
+    let schema = build_schema(&[("products", products_schema), ("reviews", reviews_schema)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "{ topReviews { body product { name } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let reviews = fetch_node(&plan.node);
+    assert_eq!(reviews.service_name, "reviews");
+    assert!(reviews.query.contains("name"), "{}", reviews.query);
+
+    // Fields that aren't provided still come from their owner
+    let plan = planner
+        .plan_query(
+            "{ topReviews { product { name price } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let nodes = match &plan.node {
+        PlanNode::Sequence(nodes) => nodes,
+        other => panic!("expected a sequence, got {:?}", other),
+    };
+    assert!(fetch_node(&nodes[0]).query.contains("name"));
+    let products = match &nodes[1] {
+        PlanNode::Flatten(flatten) => fetch_node(&flatten.node),
+        other => panic!("expected a flatten node, got {:?}", other),
+    };
+    assert_eq!(products.service_name, "products");
+    assert!(products.query.contains("price"));
+    assert!(!products.query.contains("name"), "{}", products.query);
+}
+
+#[tokio::test]
+async fn test_rejects_foreign_fields_without_key() {
+    let schema = build_schema(&[
+        (
+            "products",
+            "type Query { products: [Product] } type Product { id: ID! }",
+        ),
+        ("reviews", "type Product { reviews: [String] }"),
+    ])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let result = planner
+        .plan_query("{ products { id reviews } }", &schema, None, None)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_fetches_foreign_value_type_fields_through_their_entity() {
+    let schema = build_schema(&[
+        (
+            "products",
+            "type Query { products: [Product] } \
+             type Product @key(fields: \"id\") { id: ID! price: Money } \
+             type Money { amount: Int }",
+        ),
+        (
+            "inventory",
+            "type Product @key(fields: \"id\") { id: ID! price: Money } \
+             type Money { currency: String }",
+        ),
+    ])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "{ products { price { amount currency } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let nodes = match &plan.node {
+        PlanNode::Sequence(nodes) => nodes,
+        other => panic!("expected a sequence, got {:?}", other),
+    };
+    let products = fetch_node(&nodes[0]);
+    assert!(collapse(&products.query).contains("price { amount }"));
+    assert!(!products.query.contains("currency"));
+
+    // The foreign field is fetched through the entity owning `price`
+    let inventory = match &nodes[1] {
+        PlanNode::Flatten(flatten) => fetch_node(&flatten.node),
+        other => panic!("expected a flatten node, got {:?}", other),
+    };
+    assert_eq!(inventory.service_name, "inventory");
+    assert!(collapse(&inventory.query).contains("price { currency }"));
+    assert_eq!(inventory.entity.as_ref().unwrap().type_name, "Product");
+}
+
+#[tokio::test]
+async fn test_fetches_foreign_value_type_fields_through_shared_root_fields() {
+    let schema = build_schema(&[
+        (
+            "products",
+            "type Query { shop: Shop } type Shop { name: String }",
+        ),
+        (
+            "inventory",
+            "type Query { shop: Shop } type Shop { stock: Int }",
+        ),
+    ])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query("{ shop { name stock } }", &schema, None, None)
+        .await
+        .unwrap();
+    let mut fetches: Vec<(&str, String)> = plan
+        .fetches()
+        .iter()
+        .map(|planned| {
+            (
+                planned.fetch.service_name.as_str(),
+                collapse(&planned.fetch.query),
+            )
+        })
+        .collect();
+    fetches.sort();
+    assert_eq!(
+        fetches,
+        vec![
+            ("inventory", "query { shop { stock } }".to_string()),
+            ("products", "query { shop { name } }".to_string()),
+        ]
+    );
+
+    // Mutations are never sent to a second service
+    let schema = build_schema(&[
+        (
+            "products",
+            "type Query { a: Int } type Mutation { order: Order } type Order { id: ID }",
+        ),
+        (
+            "inventory",
+            "type Mutation { order: Order } type Order { stock: Int }",
+        ),
+    ])
+    .await;
+    let result = planner
+        .plan_query("mutation { order { id stock } }", &schema, None, None)
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_merges_repeated_selections_in_subqueries() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"
+            query {
+                products {
+                    name
+                    label: name
+                    name
+                    ... on Product { id }
+                    ...ProductName
+                    ... { id reviews { body } }
+                    reviews { body }
+                }
+            }
+            fragment ProductName on Product { name }
+            "#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let nodes = match &plan.node {
+        PlanNode::Sequence(nodes) => nodes,
+        other => panic!("expected a sequence, got {:?}", other),
+    };
+    assert_eq!(
+        collapse(&fetch_node(&nodes[0]).query),
+        "query { products { name label: name ... on Product { id name } id __typename } }"
+    );
+    let reviews = match &nodes[1] {
+        PlanNode::Flatten(flatten) => fetch_node(&flatten.node),
+        other => panic!("expected a flatten node, got {:?}", other),
+    };
+    assert!(
+        collapse(&reviews.query).contains("... on Product { reviews { body } }"),
+        "{}",
+        reviews.query
+    );
+}
+
+#[tokio::test]
+async fn test_fetches_identical_root_fields_once() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "{ a: products { name } b: products { name } products { id } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let queries: Vec<String> = plan
+        .fetches()
+        .iter()
+        .map(|planned| collapse(&planned.fetch.query))
+        .collect();
+    assert_eq!(
+        queries,
+        vec![
+            "query { a: products { name } }".to_string(),
+            "query { products { id } }".to_string(),
+        ]
+    );
+    let sources: Vec<(&str, Option<&str>)> = plan
+        .response_shape
+        .iter()
+        .map(|field| (field.response_key.as_str(), field.source_key.as_deref()))
+        .collect();
+    assert_eq!(
+        sources,
+        vec![("a", None), ("b", Some("a")), ("products", None)]
+    );
+}
+
+#[tokio::test]
+async fn test_inlines_named_fragments() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"
+            query { ...Root }
+            fragment Root on Query { products { ...ProductFields } }
+            fragment ProductFields on Product { id name }
+            "#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let fetch = fetch_node(&plan.node);
+    assert_eq!(fetch.service_name, "products");
+    assert!(fetch.query.contains("... on Product {"));
+    assert!(fetch.query.contains("name"));
+    assert!(!fetch.query.contains("ProductFields"));
+}
+
+#[tokio::test]
+async fn test_rejects_unknown_and_recursive_fragments() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let unknown = planner
+        .plan_query("{ products { ...Missing } }", &schema, None, None)
+        .await;
+    assert!(unknown.is_err());
+
+    let recursive = planner
+        .plan_query(
+            "{ products { ...A } } fragment A on Product { id ...A }",
+            &schema,
+            None,
+            None,
+        )
+        .await;
+    assert!(recursive.is_err());
+}
+
+const SEARCH_PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    search(term: String!): [SearchResult]
+}
+
+union SearchResult = Product
+
+type Product {
+    id: ID!
+    name: String!
+}
+"#;
+
+const SEARCH_USERS_SCHEMA: &str = r#"
+type Query {
+    search(term: String!): [SearchResult]
+}
+
+union SearchResult = User
+
+type User {
+    id: ID!
+    email: String!
+}
+"#;
+
+#[tokio::test]
+async fn test_fans_out_abstract_root_field_by_fragment_owner() {
+    let schema = build_schema(&[
+        ("products", SEARCH_PRODUCTS_SCHEMA),
+        ("users", SEARCH_USERS_SCHEMA),
+    ])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"{ search(term: "a") { ... on Product { name } ... on User { email } } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let merge = match &plan.node {
+        PlanNode::Merge(merge) => merge,
+        other => panic!("expected a merge node, got {:?}", other),
+    };
+    assert_eq!(merge.response_key, "search");
+    assert_eq!(merge.nodes.len(), 2);
+
+    for node in &merge.nodes {
+        let fetch = fetch_node(node);
+        match fetch.service_name.as_str() {
+            "products" => {
+                assert!(fetch.query.contains("... on Product"));
+                assert!(!fetch.query.contains("... on User"));
+            }
+            "users" => {
+                assert!(fetch.query.contains("... on User"));
+                assert!(!fetch.query.contains("... on Product"));
+            }
+            other => panic!("unexpected service {}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_fans_out_abstract_root_field_by_service_members() {
+    // The users service knows about products, but never returns them from
+    // its search
+    let users_schema = r#"
+        type Query {
+            search(term: String!): [SearchResult]
+        }
+
+        union SearchResult = User
+
+        type User {
+            id: ID!
+            email: String!
+            favorite: Product
+        }
+
+        type Product {
+            id: ID!
+        }
+    "#;
+    let schema = build_schema(&[
+        ("products", SEARCH_PRODUCTS_SCHEMA),
+        ("users", users_schema),
+    ])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"{ search(term: "a") { ... on Product { id } } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(fetch_node(&plan.node).service_name, "products");
+
+    // Selections on the union itself apply to the members of every service
+    let plan = planner
+        .plan_query(
+            r#"{ search(term: "a") { __typename } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let merge = match &plan.node {
+        PlanNode::Merge(merge) => merge,
+        other => panic!("expected a merge node, got {:?}", other),
+    };
+    let mut services: Vec<&str> = merge
+        .nodes
+        .iter()
+        .map(|node| fetch_node(node).service_name.as_str())
+        .collect();
+    services.sort();
+    assert_eq!(services, vec!["products", "users"]);
+
+    // Fragments on types a service doesn't return are left out of its fetch
+    let plan = planner
+        .plan_query(
+            r#"{ search(term: "a") { __typename ... on Product { id } } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let merge = match &plan.node {
+        PlanNode::Merge(merge) => merge,
+        other => panic!("expected a merge node, got {:?}", other),
+    };
+    for node in &merge.nodes {
+        let fetch = fetch_node(node);
+        assert_eq!(
+            fetch.query.contains("... on Product"),
+            fetch.service_name == "products",
+            "{}",
+            fetch.query
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_preserves_aliases() {
+    let schema = build_schema(&[(
+        "users",
+        "type Query { user(id: ID!): User } type User { id: ID! name: String! }",
+    )])
+    .await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            "{ a: user(id: 1) { fullName: name } b: user(id: 2) { name } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let nodes = match &plan.node {
+        PlanNode::Parallel(nodes) => nodes,
+        other => panic!("expected a parallel node, got {:?}", other),
+    };
+    let queries: Vec<&str> = nodes
+        .iter()
+        .map(|node| fetch_node(node).query.as_str())
+        .collect();
+
+    assert!(queries[0].contains("a: user(id: 1)"));
+    assert!(queries[0].contains("fullName: name"));
+    assert!(queries[1].contains("b: user(id: 2)"));
+}
+
+#[tokio::test]
+async fn test_evaluates_skip_and_include() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query(
+            r#"
+            query($withReviews: Boolean!, $hideName: Boolean = true) {
+                products {
+                    id
+                    name @skip(if: $hideName)
+                    reviews @include(if: $withReviews) { body }
+                }
+            }
+            "#,
+            &schema,
+            Some(json!({ "withReviews": false })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let fetch = fetch_node(&plan.node);
+    assert!(!fetch.query.contains("name"));
+    assert!(!fetch.query.contains("@skip"));
+    assert!(!fetch.query.contains("reviews"));
+}
+
+#[tokio::test]
+async fn test_plans_nothing_when_every_root_field_is_skipped() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    let plan = planner
+        .plan_query("{ products @skip(if: true) { id } }", &schema, None, None)
+        .await
+        .unwrap();
+
+    match &plan.node {
+        PlanNode::Parallel(nodes) => assert!(nodes.is_empty()),
+        other => panic!("expected an empty parallel node, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_selects_operation_by_name() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+    let document = "query Ids { products { id } } query Names { products { name } }";
+
+    let plan = planner
+        .plan_query(document, &schema, None, Some("Names"))
+        .await
+        .unwrap();
+    assert!(fetch_node(&plan.node).query.contains("name"));
+
+    let missing = planner.plan_query(document, &schema, None, None).await;
+    assert_eq!(
+        missing.err().unwrap(),
+        "Must provide operation name if query contains multiple operations."
+    );
+
+    let unknown = planner
+        .plan_query(document, &schema, None, Some("Other"))
+        .await;
+    assert_eq!(unknown.err().unwrap(), "Unknown operation named \"Other\".");
+}
+
+#[tokio::test]
+async fn test_rejects_variables_the_operation_does_not_define() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+
+    // Shorthand operations can't declare variables
+    let shorthand = planner
+        .plan_query(
+            "{ products { reviews(first: $first) { body } } }",
+            &schema,
+            Some(json!({ "first": 2 })),
+            None,
+        )
+        .await;
+    assert_eq!(
+        shorthand.err().unwrap(),
+        "Variable \"$first\" is not defined."
+    );
+
+    let plan = planner
+        .plan_query(
+            "query($first: Int) { products { reviews(first: $first) { body } } }",
+            &schema,
+            Some(json!({ "first": 2 })),
+            None,
+        )
+        .await
+        .unwrap();
+    let PlanNode::Sequence(nodes) = &plan.node else {
+        panic!("expected a sequence, got {:?}", plan.node);
+    };
+    let PlanNode::Flatten(flatten) = &nodes[1] else {
+        panic!("expected a flatten, got {:?}", nodes[1]);
+    };
+    let reviews = fetch_node(&flatten.node);
+    assert!(reviews.query.contains("$first: Int"), "{}", reviews.query);
+    assert_eq!(reviews.variables, json!({ "first": 2 }));
+}
+
+#[tokio::test]
+async fn test_substitutes_defaults_and_requires_non_null_variables() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new();
+    let query = "query($first: Int = 5) { products { reviews(first: $first) { body } } }";
+
+    let plan = planner
+        .plan_query(query, &schema, None, None)
+        .await
+        .unwrap();
+    let PlanNode::Sequence(nodes) = &plan.node else {
+        panic!("expected a sequence, got {:?}", plan.node);
+    };
+    let PlanNode::Flatten(flatten) = &nodes[1] else {
+        panic!("expected a flatten, got {:?}", nodes[1]);
+    };
+    assert_eq!(fetch_node(&flatten.node).variables, json!({ "first": 5 }));
+
+    let missing = planner
+        .plan_query(
+            "query($first: Int!) { products { reviews(first: $first) { body } } }",
+            &schema,
+            Some(json!({})),
+            None,
+        )
+        .await;
+    assert_eq!(
+        missing.err().unwrap(),
+        "Variable \"$first\" of required type \"Int!\" was not provided."
+    );
+
+    let null = planner
+        .plan_query(
+            "query($first: Int! = 5) { products { reviews(first: $first) { body } } }",
+            &schema,
+            Some(json!({ "first": null })),
+            None,
+        )
+        .await;
+    assert_eq!(
+        null.err().unwrap(),
+        "Variable \"$first\" of non-null type \"Int!\" must not be null."
+    );
+}
+
+#[tokio::test]
+async fn test_forwards_allowed_directives_to_subgraphs() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new().with_forwarded_directives(vec!["live".to_string()]);
+
+    let plan = planner
+        .plan_query(
+            r#"query($throttle: Int) {
+                products @live(throttle: $throttle) {
+                    name @vendor(tag: "x")
+                    reviews @live { body }
+                }
+            }"#,
+            &schema,
+            Some(json!({ "throttle": 100 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let PlanNode::Sequence(nodes) = &plan.node else {
+        panic!("expected a sequence, got {:?}", plan.node);
+    };
+    let products = fetch_node(&nodes[0]);
+    assert!(
+        products
+            .query
+            .starts_with("query($throttle: Int) {\n  products @live(throttle: $throttle) {\n"),
+        "{}",
+        products.query
+    );
+    assert!(!products.query.contains("@vendor"), "{}", products.query);
+    assert_eq!(products.variables, json!({ "throttle": 100 }));
+
+    let PlanNode::Flatten(flatten) = &nodes[1] else {
+        panic!("expected a flatten, got {:?}", nodes[1]);
+    };
+    let reviews = fetch_node(&flatten.node);
+    assert!(
+        reviews.query.contains("reviews @live {"),
+        "{}",
+        reviews.query
+    );
+
+    // Without an allowlist, client directives never reach a subgraph
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products @live { name } }", &schema, None, None)
+        .await
+        .unwrap();
+    assert!(!fetch_node(&plan.node).query.contains('@'));
+}
+
+#[tokio::test]
+async fn test_minifies_subqueries() {
+    let schema = build_schema(&[("products", PRODUCTS_SCHEMA), ("reviews", REVIEWS_SCHEMA)]).await;
+    let planner = SimpleQueryPlanner::new().with_minified_queries(true);
+
+    let plan = planner
+        .plan_query(
+            r#"query($first: Int) {
+                products {
+                    name
+                    reviews(first: $first) { body }
+                }
+            }"#,
+            &schema,
+            Some(json!({ "first": 2 })),
+            None,
+        )
+        .await
+        .unwrap();
+
+    let PlanNode::Sequence(nodes) = &plan.node else {
+        panic!("expected a sequence, got {:?}", plan.node);
+    };
+    let products = fetch_node(&nodes[0]);
+    assert_eq!(products.query, "query { products { name __typename id } }");
+
+    let PlanNode::Flatten(flatten) = &nodes[1] else {
+        panic!("expected a flatten, got {:?}", nodes[1]);
+    };
+    let reviews = fetch_node(&flatten.node);
+    assert_eq!(
+        reviews.query,
+        "query($representations: [_Any!]!, $first: Int) { _entities(representations: $representations) { ... on Product { reviews(first: $first) { body } } } }"
+    );
+    graphql_parser::query::parse_query::<String>(&reviews.query).unwrap();
+}
+
+#[tokio::test]
+async fn test_plans_configured_joins() {
+    let (_, join) =
+        JoinConfig::parse("Product.reviews -> reviews.reviewsByProductId(productId: $Product.id)")
+            .unwrap();
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: "http://products/graphql".to_string(),
+            schema: "type Query { products: [Product] } type Product { id: ID! name: String }"
+                .to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    registry
+        .register_service(ServiceConfig {
+            name: "reviews".to_string(),
+            url: "http://reviews/graphql".to_string(),
+            schema: r#"
+                type Query { reviewsByProductId(productId: ID!, first: Int): [Review] }
+                type Review { body: String }
+            "#
+            .to_string(),
+            joins: vec![join],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            "{ products { name latest: reviews { body } } }",
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let PlanNode::Sequence(nodes) = &plan.node else {
+        panic!("expected a sequence, got {:?}", plan.node);
+    };
+    assert_eq!(
+        fetch_node(&nodes[0]).query,
+        "query {\n  products {\n    name\n    id\n  }\n}\n"
+    );
+
+    let PlanNode::Flatten(flatten) = &nodes[1] else {
+        panic!("expected a flatten, got {:?}", nodes[1]);
+    };
+    assert_eq!(flatten.path, vec!["products"]);
+    let reviews = fetch_node(&flatten.node);
+    assert_eq!(reviews.service_name, "reviews");
+    assert_eq!(
+        reviews.query,
+        "query($_join_productId: ID!) {\n  latest: reviewsByProductId(productId: $_join_productId) {\n    body\n  }\n}\n"
+    );
+    let join = reviews.join.as_ref().unwrap();
+    assert_eq!(join.type_name, "Product");
+    assert_eq!(join.arguments["_join_productId"], "id");
+    assert!(reviews.required_variables().unwrap().is_empty());
+}
+
+const ACCOUNTS_SCHEMA: &str = r#"
+type Query {
+    me: User
+}
+
+type User {
+    id: ID!
+    name(format: String): String!
+}
+"#;
+
+#[tokio::test]
+async fn test_serializes_plans_deterministically() {
+    let schema =
+        build_schema(&[("products", PRODUCTS_SCHEMA), ("accounts", ACCOUNTS_SCHEMA)]).await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(
+            r#"{ products { name } me { name(format: "a  b") } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let reordered = SimpleQueryPlanner::new()
+        .with_minified_queries(true)
+        .plan_query(
+            r#"{ me { name(format: "a  b") } products { name } }"#,
+            &schema,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let serialized = serde_json::to_value(&plan).unwrap();
+    assert_eq!(
+        serialized["node"]["Parallel"],
+        json!([
+            {
+                "Fetch": {
+                    "serviceName": "accounts",
+                    "query": "query { me { name(format: \"a  b\") } }",
+                    "variables": {},
+                    "entity": null,
+                    "join": null,
+                }
+            },
+            {
+                "Fetch": {
+                    "serviceName": "products",
+                    "query": "query { products { name } }",
+                    "variables": {},
+                    "entity": null,
+                    "join": null,
+                }
+            },
+        ])
+    );
+    assert_eq!(
+        serialized["node"],
+        serde_json::to_value(&reordered).unwrap()["node"]
+    );
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::MockService;
+use portkey::complexity::ComplexityConfig;
+use portkey::quota::{QuotaConfig, QuotaTracker};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+
+const SCHEMA: &str = r#"
+type Query {
+    me: User
+}
+
+type User {
+    id: ID!
+    name: String!
+}
+"#;
+
+fn quota(limit: u64) -> QuotaTracker {
+    QuotaTracker::new(&QuotaConfig {
+        enabled: true,
+        limit,
+        window_seconds: 60,
+    })
+}
+
+fn request(api_key: Option<&str>) -> GraphQLRequest {
+    GraphQLRequest {
+        auth_headers: api_key
+            .map(|api_key| HashMap::from([("x-api-key".to_string(), api_key.to_string())])),
+        ..common::request("{ me { id name } }")
+    }
+}
+
+#[test]
+fn test_charges_clients_separately() {
+    let quota = quota(10);
+
+    assert_eq!(quota.charge("web", 4, None), Ok(6));
+    assert_eq!(quota.charge("web", 6, None), Ok(0));
+    assert_eq!(quota.remaining("mobile", None), 10);
+    assert_eq!(quota.charge("mobile", 3, None), Ok(7));
+
+    // Refused operations aren't charged
+    assert_eq!(
+        quota.charge("web", 1, None),
+        Err("Quota of 10 per 60 seconds exceeded".to_string())
+    );
+    assert_eq!(quota.remaining("web", None), 0);
+
+    // A client's own limit replaces the configured one
+    assert_eq!(quota.charge("partner", 15, Some(20)), Ok(5));
+    assert_eq!(
+        quota.charge("partner", 6, Some(20)),
+        Err("Quota of 20 per 60 seconds exceeded".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_reports_cost_quota_and_timing() {
+    let users =
+        MockService::start(|_| json!({ "data": { "me": { "id": "1", "name": "Ada" } } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_complexity(ComplexityConfig {
+        enabled: true,
+        ..Default::default()
+    })
+    .with_quota(quota(7))
+    .with_subgraph_timing(true);
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // me (1) + id (1) + name (1)
+    let response = gateway.process_request(request(Some("key"))).await.unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "me": { "id": "1", "name": "Ada" } })
+    );
+    assert_eq!(
+        response["extensions"]["cost"],
+        json!({ "estimated": 3, "max": null })
+    );
+    assert_eq!(response["extensions"]["remainingQuota"], 4);
+    assert_eq!(response["extensions"]["timing"]["users"]["requests"], 1);
+    assert!(response["extensions"]["timing"]["users"]["durationMs"].is_u64());
+
+    let response = gateway.process_request(request(Some("key"))).await.unwrap();
+    assert_eq!(response["extensions"]["remainingQuota"], 1);
+
+    let response = gateway.process_request(request(Some("key"))).await.unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "Quota of 7 per 60 seconds exceeded",
+                "extensions": { "code": "QUOTA_EXCEEDED" },
+            }],
+            "extensions": { "remainingQuota": 1 },
+        })
+    );
+    assert_eq!(users.requests().len(), 2);
+
+    // Anonymous requests aren't metered
+    let response = gateway.process_request(request(None)).await.unwrap();
+    assert!(response["extensions"].get("remainingQuota").is_none());
+}
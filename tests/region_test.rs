@@ -0,0 +1,186 @@
+mod common;
+
+use common::MockService;
+use hyper::StatusCode;
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+// Nothing listens there, connections are refused
+const UNREACHABLE: &str = "http://127.0.0.1:1/graphql";
+
+fn products(url: &str, regions: &[(&str, &str)]) -> ServiceConfig {
+    ServiceConfig {
+        name: "products".to_string(),
+        url: url.to_string(),
+        schema: PRODUCTS_SCHEMA.to_string(),
+        regions: regions
+            .iter()
+            .map(|(region, url)| (region.to_string(), url.to_string()))
+            .collect::<BTreeMap<_, _>>(),
+        ..Default::default()
+    }
+}
+
+fn request(region: Option<&str>) -> GraphQLRequest {
+    query_request("{ products { name } }", region)
+}
+
+fn query_request(query: &str, region: Option<&str>) -> GraphQLRequest {
+    GraphQLRequest {
+        region: region.map(str::to_string),
+        ..common::request(query)
+    }
+}
+
+async fn build_gateway(service: ServiceConfig) -> FederationGateway {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway.register_service(service).await.unwrap();
+    gateway
+}
+
+#[test]
+fn test_orders_endpoints_by_preferred_region() {
+    let mut service = products(
+        "http://default/graphql",
+        &[
+            ("us", "http://us/graphql"),
+            ("eu", "http://eu/graphql"),
+            ("ap", "http://default/graphql"),
+        ],
+    );
+    assert_eq!(
+        service.endpoints(),
+        vec![
+            "http://default/graphql",
+            "http://eu/graphql",
+            "http://us/graphql"
+        ]
+    );
+
+    service.preferred_region = Some("us".to_string());
+    assert_eq!(
+        service.endpoints(),
+        vec![
+            "http://us/graphql",
+            "http://default/graphql",
+            "http://eu/graphql"
+        ]
+    );
+
+    // Unknown regions keep the default order
+    service.preferred_region = Some("sa".to_string());
+    assert_eq!(service.endpoints()[0], "http://default/graphql");
+}
+
+#[tokio::test]
+async fn test_routes_to_the_hinted_region() {
+    let eu = MockService::start(|_| json!({ "data": { "products": [{ "name": "EU" }] } })).await;
+    let us = MockService::start(|_| json!({ "data": { "products": [{ "name": "US" }] } })).await;
+    let gateway = build_gateway(products(
+        UNREACHABLE,
+        &[("eu", eu.url.as_str()), ("us", us.url.as_str())],
+    ))
+    .await;
+
+    let response = gateway.process_request(request(Some("us"))).await.unwrap();
+    assert_eq!(response["data"]["products"][0]["name"], "US");
+    assert_eq!(us.requests().len(), 1);
+    assert!(eu.requests().is_empty());
+
+    // The local region applies to requests without a hint
+    let gateway = gateway.with_default_region("eu".to_string());
+    let response = gateway.process_request(request(None)).await.unwrap();
+    assert_eq!(response["data"]["products"][0]["name"], "EU");
+    assert_eq!(us.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn test_fails_over_to_other_regions() {
+    let eu = MockService::start(|_| json!({ "data": { "products": [{ "name": "EU" }] } })).await;
+    let gateway = build_gateway(products(
+        UNREACHABLE,
+        &[("eu", eu.url.as_str()), ("us", UNREACHABLE)],
+    ))
+    .await;
+
+    // Neither the hinted region nor the default URL can be reached
+    let response = gateway.process_request(request(Some("us"))).await.unwrap();
+    assert_eq!(response["data"]["products"][0]["name"], "EU");
+    assert_eq!(eu.requests().len(), 1);
+
+    let gateway = build_gateway(products(UNREACHABLE, &[])).await;
+    let error = gateway
+        .process_request(request(Some("us")))
+        .await
+        .unwrap_err();
+    assert!(error.starts_with("HTTP request failed"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_does_not_fail_over_mutations_after_server_errors() {
+    const SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Mutation {
+    addProduct(name: String!): Product
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+    let eu = MockService::start_with_status(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        |_| json!({ "errors": [{ "message": "boom" }] }),
+    )
+    .await;
+    let us = MockService::start(
+        |_| json!({ "data": { "products": [{ "name": "US" }], "addProduct": { "name": "US" } } }),
+    )
+    .await;
+    let service = ServiceConfig {
+        schema: SCHEMA.to_string(),
+        ..products(eu.url.as_str(), &[("us", us.url.as_str())])
+    };
+    let gateway = build_gateway(service).await;
+
+    // The write may have been applied before the error
+    let error = gateway
+        .process_request(query_request(
+            r#"mutation { addProduct(name: "Chair") { name } }"#,
+            None,
+        ))
+        .await
+        .unwrap_err();
+    assert!(error.contains("500"), "{}", error);
+    assert_eq!(eu.requests().len(), 1);
+    assert!(us.requests().is_empty());
+
+    // Queries still move on to the next region
+    let response = gateway.process_request(request(None)).await.unwrap();
+    assert_eq!(response["data"]["products"][0]["name"], "US");
+    assert_eq!(us.requests().len(), 1);
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::MockService;
+use http::{HeaderMap, HeaderValue};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+    request_journal::{JournalConfig, ReplayRequest, RequestJournal},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    product(id: ID!): Product
+}
+
+type Product {
+    name: String!
+}
+"#;
+
+fn config(staging: &[(&str, &str)]) -> JournalConfig {
+    JournalConfig {
+        capacity: 2,
+        window_seconds: 60,
+        file: None,
+        headers: vec!["x-tenant".to_string()],
+        staging: staging
+            .iter()
+            .map(|(name, url)| (name.to_string(), url.to_string()))
+            .collect::<HashMap<_, _>>(),
+    }
+}
+
+fn request(id: &str) -> GraphQLRequest {
+    GraphQLRequest {
+        variables: Some(json!({ "id": id })),
+        operation_name: Some("Product".to_string()),
+        ..common::request("query Product($id: ID!) { product(id: $id) { name } }")
+    }
+}
+
+fn headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-tenant", HeaderValue::from_static("acme"));
+    headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+    headers
+}
+
+// A gateway whose journal holds one request
+async fn journaling_gateway(production_url: &str, staging: &[(&str, &str)]) -> FederationGateway {
+    let journal = RequestJournal::new(config(staging));
+    journal.record(&request("1"), journal.allowed_headers(&headers()));
+
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_request_journal(journal);
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: production_url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    gateway
+}
+
+#[test]
+fn test_journals_sanitized_requests_in_a_ring_buffer() {
+    let path = std::env::temp_dir().join(format!("portkey-journal-{}.jsonl", std::process::id()));
+    let journal = RequestJournal::new(JournalConfig {
+        file: Some(path.clone()),
+        ..config(&[])
+    });
+
+    for id in ["1", "2", "3"] {
+        journal.record(&request(id), journal.allowed_headers(&headers()));
+    }
+
+    let entries = journal.entries();
+    assert_eq!(
+        entries.iter().map(|entry| entry.id).collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert_eq!(entries[0].variables_shape, json!({ "id": "string" }));
+    assert_eq!(entries[0].headers["x-tenant"], "acme");
+    assert!(!entries[0].headers.contains_key("authorization"));
+    assert!(journal.get(1).is_none());
+
+    // The file keeps everything
+    let lines = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let first: Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+    assert_eq!(lines.lines().count(), 3);
+    assert_eq!(first["id"], 1);
+    assert!(!lines.contains("Bearer"));
+}
+
+#[tokio::test]
+async fn test_replays_journaled_requests_against_staging() {
+    let production =
+        MockService::start(|_| json!({ "data": { "product": { "name": "Live" } } })).await;
+    let staging =
+        MockService::start(|_| json!({ "data": { "product": { "name": "Staged" } } })).await;
+
+    let gateway = journaling_gateway(&production.url, &[("products", staging.url.as_str())]).await;
+    let result = gateway
+        .replay(ReplayRequest {
+            id: 1,
+            variables: Some(json!({ "id": "42" })),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(result["data"]["product"]["name"], "Staged");
+    assert!(production.requests().is_empty());
+    assert_eq!(staging.requests()[0]["variables"]["id"], "42");
+    assert_eq!(staging.headers()[0]["x-tenant"], "acme");
+
+    let error = gateway
+        .replay(ReplayRequest {
+            id: 7,
+            variables: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error, "No journaled request with id 7");
+
+    // Without a staging URL the request would reach production
+    let gateway = journaling_gateway(&production.url, &[]).await;
+    let error = gateway
+        .replay(ReplayRequest {
+            id: 1,
+            variables: Some(json!({ "id": "42" })),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error,
+        "Service products has no staging URL to replay against"
+    );
+    assert!(production.requests().is_empty());
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use async_trait::async_trait;
+use portkey::{
+    GraphQLRequest,
+    request_mirror::{MirrorRecord, MirrorSink, RequestMirror},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+struct CapturingSink {
+    records: Arc<Mutex<Vec<MirrorRecord>>>,
+    received: Arc<Notify>,
+}
+
+#[async_trait]
+impl MirrorSink for CapturingSink {
+    async fn send(&self, records: Vec<MirrorRecord>) -> Result<(), String> {
+        self.records.lock().unwrap().extend(records);
+        self.received.notify_one();
+        Ok(())
+    }
+}
+
+fn request() -> GraphQLRequest {
+    GraphQLRequest {
+        variables: Some(json!({ "id": "1", "filter": { "tags": ["a", "b"], "limit": 3 } })),
+        operation_name: Some("User".to_string()),
+        client_name: Some("web".to_string()),
+        ..common::request("query User($id: ID!) { user(id: $id) { name } }")
+    }
+}
+
+#[test]
+fn test_records_sanitized_metadata() {
+    let record = MirrorRecord::from_request(&request());
+
+    assert_eq!(record.operation_hash.len(), 64);
+    assert_eq!(record.operation_name.as_deref(), Some("User"));
+    assert_eq!(record.client_name.as_deref(), Some("web"));
+    assert_eq!(
+        record.variables_shape,
+        json!({ "id": "string", "filter": { "tags": ["string"], "limit": "number" } })
+    );
+}
+
+#[tokio::test]
+async fn test_ships_records_to_sink() {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::new(Notify::new());
+    let sink = CapturingSink {
+        records: Arc::clone(&records),
+        received: Arc::clone(&received),
+    };
+
+    let mirror = RequestMirror::spawn(Box::new(sink), 8, 8);
+    mirror.record(MirrorRecord::from_request(&request()));
+    received.notified().await;
+
+    assert_eq!(records.lock().unwrap().len(), 1);
+    assert_eq!(mirror.dropped(), 0);
+}
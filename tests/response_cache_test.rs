@@ -0,0 +1,211 @@
+mod common;
+
+use common::MockService;
+use portkey::response_cache::CachingQueryExecutor;
+use portkey::{
+    CacheInvalidation, FederatedSchema,
+    plan_cache::cache_key,
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    request_context::RequestContext,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+const PRODUCTS: &str = r#"
+type Query {
+    products: [Product] @cacheControl(maxAge: 60)
+    featured: Product
+    inventory: Int
+}
+
+type Mutation {
+    restock: Boolean
+}
+
+type Product @cacheControl(maxAge: 30) {
+    name: String
+}
+"#;
+
+const REVIEWS: &str = r#"
+type Query {
+    reviews: [Review]
+}
+
+type Product @cacheControl(maxAge: 10) {
+    rating: Int
+}
+
+type Review {
+    body: String
+}
+"#;
+
+async fn build_schema(url: &str) -> FederatedSchema {
+    common::build_schema(&[("products", url, PRODUCTS), ("reviews", url, REVIEWS)]).await
+}
+
+async fn execute(
+    executor: &CachingQueryExecutor,
+    schema: &FederatedSchema,
+    query: &str,
+    token: &str,
+) -> Value {
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(query, schema, None, None)
+        .await
+        .unwrap();
+    let headers = HashMap::from([("authorization".to_string(), token.to_string())]);
+    executor
+        .execute_plan(plan, schema, Some(headers))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_cache_control_hints_take_the_shortest_max_age() {
+    let schema = build_schema("http://localhost:4001/graphql").await;
+    assert_eq!(schema.cache_control["Query.products"], 60);
+    assert_eq!(schema.cache_control["Product"], 10);
+    assert!(!schema.cache_control.contains_key("Query.inventory"));
+}
+
+#[tokio::test]
+async fn test_caches_responses_per_session() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "name": "Lamp" }], "inventory": 3, "restock": true } })
+    })
+    .await;
+    let schema = build_schema(&products.url).await;
+    let executor = CachingQueryExecutor::new(
+        Box::new(HttpQueryExecutor::new()),
+        NonZeroUsize::new(10).unwrap(),
+    );
+
+    for token in ["a", "a", "b", "a"] {
+        let response = execute(&executor, &schema, "{ products { name } }", token).await;
+        assert_eq!(
+            response["data"],
+            json!({ "products": [{ "name": "Lamp" }] })
+        );
+    }
+    // One fetch per set of credentials
+    assert_eq!(products.requests().len(), 2);
+
+    // Fields without a TTL and mutations are always fetched
+    for query in [
+        "{ inventory }",
+        "{ inventory }",
+        "mutation { restock }",
+        "mutation { restock }",
+    ] {
+        execute(&executor, &schema, query, "a").await;
+    }
+    assert_eq!(products.requests().len(), 6);
+    assert_eq!(executor.len(), 2);
+
+    let metrics = executor.metrics();
+    assert!(metrics.contains("portkey_response_cache_hits_total 2"));
+    assert!(metrics.contains("portkey_response_cache_misses_total 2"));
+    assert!(metrics.contains("portkey_response_cache_entries 2"));
+}
+
+#[tokio::test]
+async fn test_configured_ttls_override_the_hints() {
+    let products = MockService::start(|_| json!({ "data": { "inventory": 3 } })).await;
+    let schema = build_schema(&products.url).await;
+    let executor = CachingQueryExecutor::new(
+        Box::new(HttpQueryExecutor::new()),
+        NonZeroUsize::new(10).unwrap(),
+    )
+    .with_ttl_overrides(HashMap::from([("Query.inventory".to_string(), 1)]));
+
+    for _ in 0..2 {
+        assert_eq!(
+            execute(&executor, &schema, "{ inventory }", "a").await["data"],
+            json!({ "inventory": 3 })
+        );
+    }
+    assert_eq!(products.requests().len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    execute(&executor, &schema, "{ inventory }", "a").await;
+    assert_eq!(products.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_entity_invalidations_drop_responses_holding_the_type() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "name": "Lamp" }], "reviews": [{ "body": "Bright" }] } })
+    })
+    .await;
+    let schema = build_schema(&products.url).await;
+    let executor = CachingQueryExecutor::new(
+        Box::new(HttpQueryExecutor::new()),
+        NonZeroUsize::new(10).unwrap(),
+    )
+    .with_default_ttl(Duration::from_secs(60));
+
+    execute(&executor, &schema, "{ products { name } }", "a").await;
+    execute(&executor, &schema, "{ reviews { body } }", "a").await;
+    assert_eq!(executor.len(), 2);
+
+    executor.invalidate_entities(&CacheInvalidation {
+        types: vec!["Product".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(executor.len(), 1);
+
+    execute(&executor, &schema, "{ reviews { body } }", "a").await;
+    execute(&executor, &schema, "{ products { name } }", "a").await;
+    assert_eq!(products.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn test_query_hash_invalidations_drop_the_responses_of_the_operation() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "name": "Lamp" }], "reviews": [{ "body": "Bright" }] } })
+    })
+    .await;
+    let schema = build_schema(&products.url).await;
+    let executor = CachingQueryExecutor::new(
+        Box::new(HttpQueryExecutor::new()),
+        NonZeroUsize::new(10).unwrap(),
+    )
+    .with_default_ttl(Duration::from_secs(60));
+    let execute = |query: &'static str| {
+        let executor = &executor;
+        let schema = &schema;
+        async move {
+            let plan = SimpleQueryPlanner::new()
+                .plan_query(query, schema, None, None)
+                .await
+                .unwrap();
+            let mut context = RequestContext::default();
+            context.query_hash = Some(cache_key(query, None, &None));
+            executor
+                .execute_in_context(plan, schema, None, &context)
+                .await
+                .unwrap();
+        }
+    };
+
+    execute("{ products { name } }").await;
+    execute("{ reviews { body } }").await;
+    assert_eq!(executor.len(), 2);
+
+    let query_hash = cache_key("{ products { name } }", None, &None);
+    executor.invalidate_entities(&CacheInvalidation {
+        query_hash_prefixes: vec![query_hash[..8].to_string()],
+        ..Default::default()
+    });
+    assert_eq!(executor.len(), 1);
+
+    execute("{ reviews { body } }").await;
+    execute("{ products { name } }").await;
+    assert_eq!(products.requests().len(), 3);
+}
@@ -0,0 +1,410 @@
+use portkey::{
+    FederatedSchema, FederationGateway, GraphQLRequest, HttpQueryExecutor, JoinConfig,
+    ServiceConfig, SimpleQueryPlanner,
+    query_planner::QueryPlanner,
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry, ServiceSelectionConfig},
+};
+use pretty_assertions::assert_eq;
+use std::collections::HashMap;
+
+fn subgraph(index: usize) -> ServiceConfig {
+    ServiceConfig {
+        name: format!("service_{:02}", index),
+        url: format!("http://service-{}/graphql", index),
+        schema: format!(
+            r#"
+            type Query {{
+                product{index}: Product
+            }}
+
+            extend type Product @key(fields: "id") {{
+                id: ID! @external
+                field{index}: String
+            }}
+
+            type Widget{index} implements Node {{
+                id: ID!
+            }}
+            "#
+        ),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_composes_many_subgraphs() {
+    let mut registry = InMemorySchemaRegistry::new();
+    for index in 0..24 {
+        registry.register_service(subgraph(index)).await.unwrap();
+    }
+
+    let schema = registry.get_schema().await.unwrap();
+
+    assert_eq!(schema.composition.subgraphs, 24);
+    assert!(schema.composition.indexing <= schema.composition.total);
+    // Services contributing to a shared type are listed in name order
+    let product_services = &schema.type_to_service_map["Product"];
+    assert_eq!(product_services.len(), 24);
+    assert_eq!(product_services[0], "service_00");
+    assert_eq!(product_services[23], "service_23");
+    assert_eq!(
+        schema.type_to_service_map["Product.field7"],
+        vec!["service_07"]
+    );
+    // Every field refers to the one copy of its service's name
+    assert_eq!(
+        schema.type_to_service_map["Product.field7"][0].as_ptr(),
+        schema.type_to_service_map["Query.product7"][0].as_ptr()
+    );
+    assert_eq!(
+        schema.type_to_service_map["Product"][7].as_ptr(),
+        schema.type_to_service_map["Widget7"][0].as_ptr()
+    );
+    assert_eq!(schema.field_types["Query.product3"], "Product");
+    assert_eq!(schema.entity_keys["Product"], "id");
+    assert_eq!(schema.possible_types["Node"].len(), 24);
+    assert!(schema.introspection.get_type("Widget12").is_some());
+}
+
+#[tokio::test]
+async fn test_reports_the_subgraph_that_fails_to_parse() {
+    let mut registry = InMemorySchemaRegistry::new();
+    registry.register_service(subgraph(0)).await.unwrap();
+    registry
+        .register_service(ServiceConfig {
+            name: "broken".to_string(),
+            url: "http://broken/graphql".to_string(),
+            schema: "type Query {".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let error = registry.get_schema().await.err().unwrap();
+
+    assert!(
+        error.starts_with("Failed to parse schema for service broken"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_keeps_serving_the_last_good_schema() {
+    let mut registry = InMemorySchemaRegistry::new();
+    registry.register_service(subgraph(0)).await.unwrap();
+    let good = registry.get_schema().await.unwrap();
+    assert_eq!(registry.composition_error().await, None);
+
+    registry
+        .register_service(ServiceConfig {
+            name: "broken".to_string(),
+            url: "http://broken/graphql".to_string(),
+            schema: "type Query {".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let standby = registry.get_schema().await.unwrap();
+    assert_eq!(standby.generation, good.generation);
+    assert!(!standby.services.contains_key("broken"));
+    let error = registry.composition_error().await.unwrap();
+    assert!(
+        error.starts_with("Failed to parse schema for service broken"),
+        "{}",
+        error
+    );
+
+    // Fixing the registration composes again and clears the error
+    registry
+        .register_service(ServiceConfig {
+            name: "broken".to_string(),
+            url: "http://broken/graphql".to_string(),
+            schema: "type Query { fixed: String }".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let schema = registry.get_schema().await.unwrap();
+    assert!(schema.generation > good.generation);
+    assert!(schema.services.contains_key("broken"));
+    assert_eq!(registry.composition_error().await, None);
+}
+
+#[tokio::test]
+async fn test_reports_schema_health() {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway.register_service(subgraph(0)).await.unwrap();
+    assert_eq!(gateway.schema_health().await["status"], "ok");
+
+    gateway
+        .register_service(ServiceConfig {
+            name: "broken".to_string(),
+            url: "http://broken/graphql".to_string(),
+            schema: "type Query {".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let health = gateway.schema_health().await;
+    assert_eq!(health["status"], "degraded");
+    assert_eq!(health["generation"], 1);
+    assert!(
+        health["compositionError"]
+            .as_str()
+            .unwrap()
+            .contains("service broken")
+    );
+}
+
+#[tokio::test]
+async fn test_refuses_requests_until_lazy_schema_is_ready() {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_lazy_schema(true);
+    let request = || GraphQLRequest {
+        query: "{ __typename }".to_string(),
+        ..Default::default()
+    };
+
+    assert!(!gateway.is_schema_ready());
+    assert_eq!(gateway.schema_health().await["status"], "loading");
+    let error = gateway.process_request(request()).await.unwrap_err();
+    assert_eq!(
+        gateway.messages().localize(&error, None).code,
+        "SCHEMA_NOT_READY"
+    );
+
+    // Services registered in the meantime are served once it's ready
+    gateway.register_service(subgraph(0)).await.unwrap();
+    assert!(gateway.process_request(request()).await.is_err());
+    gateway.mark_schema_ready();
+    assert_eq!(gateway.schema_health().await["status"], "ok");
+    let response = gateway.process_request(request()).await.unwrap();
+    assert_eq!(response["data"]["__typename"], "Query");
+}
+
+fn shared_root_field(name: &str, priority: i32) -> ServiceConfig {
+    ServiceConfig {
+        name: name.to_string(),
+        url: format!("http://{}/graphql", name),
+        schema: "type Query { top: [String] }".to_string(),
+        priority,
+        ..Default::default()
+    }
+}
+
+async fn compose(selection: ServiceSelectionConfig, priorities: [i32; 3]) -> FederatedSchema {
+    let mut registry = InMemorySchemaRegistry::new().with_service_selection(selection);
+    for (name, priority) in ["a", "b", "c"].into_iter().zip(priorities) {
+        registry
+            .register_service(shared_root_field(name, priority))
+            .await
+            .unwrap();
+    }
+    registry.get_schema().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_orders_shared_root_fields_by_owner_and_priority() {
+    let schema = compose(ServiceSelectionConfig::default(), [0, 5, 0]).await;
+    assert_eq!(schema.type_to_service_map["Query.top"], vec!["b", "a", "c"]);
+
+    let owners = HashMap::from([("Query.top".to_string(), "c".to_string())]);
+    let schema = compose(
+        ServiceSelectionConfig {
+            owners,
+            reject_ambiguous: true,
+            ..Default::default()
+        },
+        [0, 5, 0],
+    )
+    .await;
+    assert_eq!(schema.type_to_service_map["Query.top"], vec!["c", "b", "a"]);
+    assert!(schema.ambiguous_root_fields.is_empty());
+}
+
+async fn compose_with_owner_directives(
+    selection: ServiceSelectionConfig,
+    directives: [&str; 3],
+) -> Result<FederatedSchema, String> {
+    let mut registry = InMemorySchemaRegistry::new().with_service_selection(selection);
+    for (name, directive) in ["a", "b", "c"].into_iter().zip(directives) {
+        registry
+            .register_service(ServiceConfig {
+                schema: format!("type Query {{ top: [String] {} }}", directive),
+                ..shared_root_field(name, 0)
+            })
+            .await
+            .unwrap();
+    }
+    registry.get_schema().await
+}
+
+#[tokio::test]
+async fn test_routes_root_fields_to_their_declared_owner() {
+    let owned_by_b = r#"@owner(service: "b")"#;
+    let schema =
+        compose_with_owner_directives(ServiceSelectionConfig::default(), ["", owned_by_b, ""])
+            .await
+            .unwrap();
+    assert_eq!(schema.type_to_service_map["Query.top"], vec!["b", "a", "c"]);
+
+    // The overlay wins over the subgraphs, the configured owners over both
+    let overlay = ServiceSelectionConfig {
+        overlay: Some(r#"extend type Query { top: [String] @owner(service: "c") }"#.to_string()),
+        reject_ambiguous: true,
+        ..Default::default()
+    };
+    let schema = compose_with_owner_directives(overlay.clone(), ["", owned_by_b, ""])
+        .await
+        .unwrap();
+    assert_eq!(schema.type_to_service_map["Query.top"], vec!["c", "a", "b"]);
+    assert!(schema.ambiguous_root_fields.is_empty());
+
+    let configured = ServiceSelectionConfig {
+        owners: HashMap::from([("Query.top".to_string(), "a".to_string())]),
+        ..overlay
+    };
+    let schema = compose_with_owner_directives(configured, ["", owned_by_b, ""])
+        .await
+        .unwrap();
+    assert_eq!(schema.type_to_service_map["Query.top"], vec!["a", "b", "c"]);
+}
+
+#[tokio::test]
+async fn test_rejects_conflicting_owner_directives() {
+    let error = compose_with_owner_directives(
+        ServiceSelectionConfig::default(),
+        [r#"@owner(service: "a")"#, r#"@owner(service: "b")"#, ""],
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(
+        error,
+        "Field Query.top has conflicting @owner directives: a and b"
+    );
+}
+
+#[tokio::test]
+async fn test_rejects_ambiguous_root_fields_when_configured() {
+    let selection = ServiceSelectionConfig {
+        reject_ambiguous: true,
+        ..Default::default()
+    };
+    let planner = SimpleQueryPlanner::new();
+
+    let schema = compose(selection.clone(), [1, 0, 1]).await;
+    let error = planner
+        .plan_query("{ top }", &schema, None, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error,
+        "Field Query.top is resolved by services a, c, b with equal priority, configure an owner"
+    );
+
+    // A single service with the highest priority isn't ambiguous
+    let schema = compose(selection, [1, 2, 1]).await;
+    let plan = planner
+        .plan_query("{ top }", &schema, None, None)
+        .await
+        .unwrap();
+    assert_eq!(plan.fetches()[0].fetch.service_name, "b");
+}
+
+fn joined_services(resolver: &str) -> [ServiceConfig; 2] {
+    let (_, join) = JoinConfig::parse(&format!("Product.reviews -> reviews.{}", resolver)).unwrap();
+    [
+        ServiceConfig {
+            name: "products".to_string(),
+            url: "http://products/graphql".to_string(),
+            schema: "type Query { products: [Product] } type Product { id: ID! }".to_string(),
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "reviews".to_string(),
+            url: "http://reviews/graphql".to_string(),
+            schema: r#"
+                type Query {
+                    "Reviews of a product"
+                    reviewsByProductId(productId: ID!): [Review!]!
+                }
+                type Review { body: String }
+            "#
+            .to_string(),
+            joins: vec![join],
+            ..Default::default()
+        },
+    ]
+}
+
+#[tokio::test]
+async fn test_composes_configured_joins() {
+    let mut registry = InMemorySchemaRegistry::new();
+    for service in joined_services("reviewsByProductId(productId: $Product.id)") {
+        registry.register_service(service).await.unwrap();
+    }
+    let schema = registry.get_schema().await.unwrap();
+
+    assert_eq!(schema.field_types["Product.reviews"], "Review");
+    let join = &schema.joins["Product.reviews"];
+    assert_eq!(join.service_name, "reviews");
+    assert_eq!(join.resolver, "reviewsByProductId");
+    assert_eq!(join.arguments[0].name, "productId");
+    assert_eq!(join.arguments[0].field, "id");
+    assert_eq!(join.arguments[0].value_type.to_string(), "ID!");
+
+    // Clients see the joined field, without the resolver's arguments
+    let product = schema.introspection.get_type("Product").unwrap();
+    let reviews = product["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|field| field["name"] == "reviews")
+        .unwrap();
+    assert_eq!(reviews["args"], serde_json::json!([]));
+    assert_eq!(reviews["description"], "Reviews of a product");
+}
+
+#[tokio::test]
+async fn test_rejects_invalid_joins() {
+    for (resolver, expected) in [
+        (
+            "reviewsByProductId(productId: \"1\")",
+            "Argument productId of join Product.reviews must take a field of Product, e.g. $Product.id",
+        ),
+        (
+            "reviewsByProduct(productId: $Product.id)",
+            "Service reviews has no Query.reviewsByProduct to resolve join Product.reviews",
+        ),
+        (
+            "reviewsByProductId(product: $Product.id)",
+            "Query.reviewsByProductId has no argument product",
+        ),
+    ] {
+        let mut registry = InMemorySchemaRegistry::new();
+        for service in joined_services(resolver) {
+            registry.register_service(service).await.unwrap();
+        }
+        assert_eq!(registry.get_schema().await.err().unwrap(), expected);
+    }
+
+    assert_eq!(
+        JoinConfig::parse("Product.reviews reviews.reviewsByProductId")
+            .err()
+            .unwrap(),
+        "Invalid join \"Product.reviews reviews.reviewsByProductId\", expected Type.field -> service.field(argument: $Type.field)"
+    );
+}
@@ -0,0 +1,86 @@
+use portkey::{
+    ServiceConfig,
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+    schema_trim::SchemaTrimConfig,
+};
+use pretty_assertions::assert_eq;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+    debugInfo: String
+    debugCache(key: String): String
+}
+
+type Product {
+    id: ID!
+    name: String!
+    internal: InternalStats
+}
+
+type InternalStats {
+    hits: Int
+}
+"#;
+
+fn trim() -> SchemaTrimConfig {
+    SchemaTrimConfig {
+        hide: vec!["Query.debug*".to_string(), "Internal*".to_string()],
+    }
+}
+
+#[test]
+fn test_matches_type_and_field_patterns() {
+    let trim = trim();
+
+    assert!(trim.hides_field("Query", "debugInfo"));
+    assert!(trim.hides_field("Query", "debug"));
+    assert!(!trim.hides_field("Query", "products"));
+    assert!(!trim.hides_field("Mutation", "debugInfo"));
+    assert!(trim.hides_type("InternalStats"));
+    assert!(!trim.hides_type("Product"));
+}
+
+#[tokio::test]
+async fn test_hides_types_and_fields_from_the_composed_schema() {
+    let mut registry = InMemorySchemaRegistry::new().with_schema_trim(trim());
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: "http://products/graphql".to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+
+    let fields = |type_name: &str| -> Vec<String> {
+        schema.introspection.get_type(type_name).unwrap()["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(fields("Query"), vec!["products"]);
+    // Fields returning a hidden type are hidden along with it
+    assert_eq!(fields("Product"), vec!["id", "name"]);
+    assert!(schema.introspection.get_type("InternalStats").is_none());
+
+    // Hidden fields aren't routed to the subgraph
+    let planner = SimpleQueryPlanner::new();
+    assert!(
+        planner
+            .plan_query("{ debugInfo }", &schema, None, None)
+            .await
+            .is_err()
+    );
+    assert!(
+        planner
+            .plan_query("{ products { name } }", &schema, None, None)
+            .await
+            .is_ok()
+    );
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{MockService, request};
+use portkey::service_health::{ServiceHealth, ServiceHealthConfig};
+use portkey::{
+    FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig, SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::sync::Arc;
+
+#[test]
+fn test_services_are_unavailable_after_failing_in_a_row() {
+    let health = ServiceHealth::new(&ServiceHealthConfig {
+        enabled: true,
+        failure_threshold: 2,
+        recovery_seconds: 60,
+    });
+
+    health.record("reviews", false);
+    health.record("reviews", true);
+    health.record("reviews", false);
+    assert!(health.is_available("reviews"));
+
+    health.record("reviews", false);
+    assert!(!health.is_available("reviews"));
+    assert!(health.is_available("products"));
+    assert_eq!(health.unavailable(), vec!["reviews"]);
+
+    // A fetch going through again makes it available
+    health.record("reviews", true);
+    assert!(health.is_available("reviews"));
+    assert!(health.unavailable().is_empty());
+}
+
+#[tokio::test]
+async fn test_fails_fields_of_unavailable_services_without_fetching() {
+    let products =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Lamp" }] } })).await;
+    let health = Arc::new(ServiceHealth::new(&ServiceHealthConfig {
+        enabled: true,
+        failure_threshold: 2,
+        recovery_seconds: 60,
+    }));
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(
+            HttpQueryExecutor::new()
+                .with_partial_results(true)
+                .with_service_health(Arc::clone(&health)),
+        ),
+    )
+    .with_service_health(Arc::clone(&health));
+    for (name, url, schema) in [
+        (
+            "products",
+            products.url.as_str(),
+            "type Query { products: [Product] } type Product { name: String }",
+        ),
+        (
+            "reviews",
+            "http://127.0.0.1:9/graphql",
+            "type Query { reviews: [Review] topReview: Review } type Review { body: String }",
+        ),
+    ] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: url.to_string(),
+                schema: schema.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    let query = "{ products { name } reviews { body } }";
+    for _ in 0..2 {
+        let response = gateway.process_request(request(query)).await.unwrap();
+        assert_eq!(
+            response["errors"][0]["extensions"]["code"],
+            "SUBREQUEST_HTTP_ERROR"
+        );
+    }
+
+    let response = gateway.process_request(request(query)).await.unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Lamp" }], "reviews": null })
+    );
+    assert_eq!(
+        response["errors"],
+        json!([{
+            "message": "Service reviews is unavailable",
+            "extensions": { "code": "SERVICE_UNAVAILABLE", "serviceName": "reviews" },
+        }])
+    );
+
+    let response = gateway
+        .process_request(request("{ __schema { queryType { name } } }"))
+        .await
+        .unwrap();
+    assert_eq!(response["data"]["__schema"]["queryType"]["name"], "Query");
+    assert_eq!(
+        response["extensions"]["unavailableFields"],
+        json!([
+            { "field": "Query.reviews", "service": "reviews" },
+            { "field": "Query.topReview", "service": "reviews" },
+        ])
+    );
+    assert_eq!(products.requests().len(), 3);
+}
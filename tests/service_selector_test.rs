@@ -0,0 +1,122 @@
+mod common;
+
+use common::MockService;
+use portkey::plan_cache::CachingQueryPlanner;
+use portkey::request_context::RequestContext;
+use portkey::service_selector::ServiceSelector;
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+const SEARCH_SCHEMA: &str = r#"
+type Query {
+    search(term: String): [String]
+}
+"#;
+
+// Requests in the "beta-search" experiment go to `search-beta`
+struct ExperimentSelector;
+
+impl ServiceSelector for ExperimentSelector {
+    fn select(
+        &self,
+        field: &str,
+        candidates: &[String],
+        context: &RequestContext,
+    ) -> Option<String> {
+        assert_eq!(field, "Query.search");
+        assert_eq!(candidates, ["search", "search-beta"]);
+        context
+            .experiments
+            .iter()
+            .any(|experiment| experiment == "beta-search")
+            .then(|| "search-beta".to_string())
+    }
+}
+
+fn request(extensions: Option<Value>) -> GraphQLRequest {
+    GraphQLRequest {
+        extensions,
+        ..common::request("{ search(term: \"lamp\") }")
+    }
+}
+
+#[tokio::test]
+async fn test_routes_root_fields_by_request_context() {
+    let stable = MockService::start(|_| json!({ "data": { "search": ["stable"] } })).await;
+    let beta = MockService::start(|_| json!({ "data": { "search": ["beta"] } })).await;
+
+    let planner = SimpleQueryPlanner::new().with_service_selector(Arc::new(ExperimentSelector));
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(CachingQueryPlanner::new(
+            Box::new(planner),
+            NonZeroUsize::new(16).unwrap(),
+        )),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for (name, url) in [("search", &stable.url), ("search-beta", &beta.url)] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: url.clone(),
+                schema: SEARCH_SCHEMA.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    let in_beta = Some(json!({ "experiments": ["beta-search"] }));
+    // Plans routed apart are cached apart, each request is served by the
+    // service it was routed to whichever was planned first
+    for (extensions, expected) in [
+        (None, "stable"),
+        (in_beta.clone(), "beta"),
+        (None, "stable"),
+        (in_beta, "beta"),
+    ] {
+        let response = gateway.process_request(request(extensions)).await.unwrap();
+        assert_eq!(response["data"], json!({ "search": [expected] }));
+    }
+    assert_eq!(stable.requests().len(), 2);
+    assert_eq!(beta.requests().len(), 2);
+}
+
+// Selections outside the candidates are ignored
+struct UnknownSelector;
+
+impl ServiceSelector for UnknownSelector {
+    fn select(&self, _: &str, _: &[String], _: &RequestContext) -> Option<String> {
+        Some("elsewhere".to_string())
+    }
+}
+
+#[tokio::test]
+async fn test_ignores_services_that_are_not_candidates() {
+    let stable = MockService::start(|_| json!({ "data": { "search": ["stable"] } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new().with_service_selector(Arc::new(UnknownSelector))),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for name in ["search", "search-beta"] {
+        gateway
+            .register_service(ServiceConfig {
+                name: name.to_string(),
+                url: stable.url.clone(),
+                schema: SEARCH_SCHEMA.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    let response = gateway.process_request(request(None)).await.unwrap();
+    assert_eq!(response["data"], json!({ "search": ["stable"] }));
+}
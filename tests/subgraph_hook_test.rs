@@ -0,0 +1,103 @@
+mod common;
+
+use common::MockService;
+use portkey::{
+    ServiceConfig,
+    query_executor::{HttpQueryExecutor, QueryExecutor},
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+    subgraph_hook::{SubgraphHook, SubgraphRequest},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products(locale: String): [Product]
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+// Sends the products service to a canary deployment in a given locale
+struct Canary {
+    url: String,
+}
+
+impl SubgraphHook for Canary {
+    fn on_subgraph_request(&self, request: &mut SubgraphRequest) {
+        if request.service_name != "products" {
+            return;
+        }
+        request.url = self.url.clone();
+        request.query = request
+            .query
+            .replace("products", "products(locale: \"de\")");
+        request.variables["experiment"] = json!("canary");
+        request
+            .headers
+            .insert("x-experiment".to_string(), "canary".to_string());
+    }
+
+    fn on_subgraph_response(&self, service_name: &str, response: &mut Value) {
+        response["data"]["products"][0]["name"] = json!(format!("{} (canary)", service_name));
+    }
+}
+
+#[tokio::test]
+async fn test_hooks_rewrite_subgraph_requests_and_responses() {
+    let stable =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Table" }] } })).await;
+    let canary =
+        MockService::start(|_| json!({ "data": { "products": [{ "name": "Tisch" }] } })).await;
+
+    let mut registry = InMemorySchemaRegistry::new();
+    registry
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: stable.url.clone(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let schema = registry.get_schema().await.unwrap();
+    let plan = SimpleQueryPlanner::new()
+        .plan_query("{ products { name } }", &schema, None, None)
+        .await
+        .unwrap();
+
+    let executor = HttpQueryExecutor::new().with_subgraph_hook(Arc::new(Canary {
+        url: canary.url.clone(),
+    }));
+    let auth_headers = HashMap::from([("authorization".to_string(), "Bearer t".to_string())]);
+    let result = executor
+        .execute_plan(plan, &schema, Some(auth_headers))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        result["data"]["products"],
+        json!([{ "name": "products (canary)" }])
+    );
+    assert!(stable.requests().is_empty());
+
+    let request = &canary.requests()[0];
+    assert!(
+        request["query"]
+            .as_str()
+            .unwrap()
+            .contains("products(locale: \"de\")"),
+        "{}",
+        request["query"]
+    );
+    assert_eq!(request["variables"]["experiment"], "canary");
+    let headers = &canary.headers()[0];
+    assert_eq!(headers["x-experiment"], "canary");
+    assert_eq!(headers["authorization"], "Bearer t");
+}
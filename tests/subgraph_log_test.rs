@@ -0,0 +1,110 @@
+use portkey::subgraph_log::{
+    SubgraphLogConfig, SubgraphLogEntry, SubgraphLogSettings, SubgraphLogUpdate, SubgraphLogger,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+fn logger(service: &str, sample_rate: f64, max_body_bytes: usize) -> SubgraphLogger {
+    let config: SubgraphLogConfig = serde_yaml::from_str(&format!(
+        "services:\n  {}:\n    sample_rate: {}\n    max_body_bytes: {}\n",
+        service, sample_rate, max_body_bytes
+    ))
+    .unwrap();
+    SubgraphLogger::new(&config)
+}
+
+#[test]
+fn test_logs_only_configured_services() {
+    let logger = logger("products", 1.0, 0);
+
+    assert_eq!(
+        logger
+            .entry("products", "{ products { name } }", &json!({ "first": 2 }))
+            .unwrap(),
+        SubgraphLogEntry {
+            service: "products".to_string(),
+            query: "{ products { name } }".to_string(),
+            variables: r#"{"first":2}"#.to_string(),
+        }
+    );
+    assert!(
+        logger
+            .entry("reviews", "{ reviews { body } }", &json!({}))
+            .is_none()
+    );
+}
+
+#[test]
+fn test_samples_requests_evenly() {
+    let logger = logger("products", 0.25, 0);
+
+    let logged: Vec<bool> = (0..8)
+        .map(|_| {
+            logger
+                .entry("products", "{ products { name } }", &json!({}))
+                .is_some()
+        })
+        .collect();
+    assert_eq!(
+        logged,
+        vec![false, false, false, true, false, false, false, true]
+    );
+}
+
+#[test]
+fn test_truncates_long_bodies() {
+    let logger = logger("products", 1.0, 10);
+
+    // Cut at a character boundary, before the `é` that doesn't fit
+    let entry = logger
+        .entry(
+            "products",
+            "{ products { name } }",
+            &json!({ "name": "ééééé" }),
+        )
+        .unwrap();
+    assert_eq!(
+        entry,
+        SubgraphLogEntry {
+            service: "products".to_string(),
+            query: "{ products... (21 bytes)".to_string(),
+            variables: r#"{"name":"... (21 bytes)"#.to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_changes_settings_at_runtime() {
+    let logger = SubgraphLogger::default();
+    assert!(
+        logger
+            .entry("products", "{ products { name } }", &json!({}))
+            .is_none()
+    );
+
+    let update: SubgraphLogUpdate =
+        serde_json::from_value(json!({ "service": "products", "max_body_bytes": 100 })).unwrap();
+    logger.set(&update.service, update.settings);
+    assert_eq!(
+        logger.settings()["products"],
+        SubgraphLogSettings {
+            sample_rate: 1.0,
+            max_body_bytes: 100,
+        }
+    );
+    assert!(
+        logger
+            .entry("products", "{ products { name } }", &json!({}))
+            .is_some()
+    );
+
+    // A sample rate of 0 turns logging off again
+    let update: SubgraphLogUpdate =
+        serde_json::from_value(json!({ "service": "products", "sample_rate": 0.0 })).unwrap();
+    logger.set(&update.service, update.settings);
+    assert!(
+        logger
+            .entry("products", "{ products { name } }", &json!({}))
+            .is_none()
+    );
+}
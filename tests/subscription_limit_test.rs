@@ -0,0 +1,70 @@
+use futures::{StreamExt, stream};
+use portkey::{
+    messages::MessageCatalog,
+    subscription_limit::{SubscriptionLimitConfig, SubscriptionLimiter},
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::sync::Arc;
+
+fn limiter(max_per_connection: usize, max_per_api_key: usize) -> Arc<SubscriptionLimiter> {
+    Arc::new(SubscriptionLimiter::new(&SubscriptionLimitConfig {
+        max_per_connection,
+        max_per_api_key,
+    }))
+}
+
+#[test]
+fn test_caps_subscriptions_per_connection() {
+    let limiter = limiter(2, 0);
+
+    let first = limiter.acquire(1, None).unwrap();
+    let _second = limiter.acquire(1, None).unwrap();
+    let error = limiter.acquire(1, None).unwrap_err();
+    assert_eq!(error, "Subscription limit of 2 per connection exceeded");
+    assert_eq!(
+        MessageCatalog::new().error(&error, None)["extensions"]["code"],
+        "SUBSCRIPTION_LIMIT_EXCEEDED"
+    );
+
+    // Other connections have their own allowance
+    let _other = limiter.acquire(2, None).unwrap();
+
+    // Ending a subscription frees its slot
+    drop(first);
+    assert_eq!(limiter.active_for_connection(1), 1);
+    limiter.acquire(1, None).unwrap();
+}
+
+#[test]
+fn test_caps_subscriptions_per_api_key_across_connections() {
+    let limiter = limiter(0, 2);
+
+    let _first = limiter.acquire(1, Some("key")).unwrap();
+    let second = limiter.acquire(2, Some("key")).unwrap();
+    let error = limiter.acquire(3, Some("key")).unwrap_err();
+    assert_eq!(error, "Subscription limit of 2 per API key exceeded");
+
+    // A rejected subscription holds nothing
+    assert_eq!(limiter.active_for_connection(3), 0);
+    let _anonymous = limiter.acquire(3, None).unwrap();
+    let _other_key = limiter.acquire(3, Some("other")).unwrap();
+
+    drop(second);
+    assert_eq!(limiter.active_for_api_key("key"), 1);
+    assert_eq!(limiter.active_for_connection(2), 0);
+}
+
+#[tokio::test]
+async fn test_releases_the_permit_with_the_event_stream() {
+    let limiter = limiter(1, 0);
+    let events = limiter
+        .acquire(1, None)
+        .unwrap()
+        .hold(stream::iter(vec![json!({ "data": { "n": 1 } })]).boxed());
+    assert!(limiter.acquire(1, None).is_err());
+
+    let received: Vec<_> = events.collect().await;
+    assert_eq!(received, vec![json!({ "data": { "n": 1 } })]);
+    assert_eq!(limiter.active_for_connection(1), 0);
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::{MockService, request};
+use futures::StreamExt;
+use portkey::{
+    FederatedSchema, FederationGateway, PlanNode, ServiceCapabilities, ServiceConfig,
+    query_executor::HttpQueryExecutor,
+    query_planner::{QueryPlanner, SimpleQueryPlanner},
+    schema_registry::{InMemorySchemaRegistry, SchemaRegistry},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Subscription {
+    productAdded: Product
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+const REVIEWS_SCHEMA: &str = r#"
+type Review {
+    body: String!
+}
+
+extend type Product @key(fields: "id") {
+    id: ID! @external
+    reviews: [Review]
+}
+"#;
+
+const SUBSCRIPTION: &str = "subscription { productAdded { name reviews { body } } }";
+
+fn services(products_url: &str, reviews_url: &str) -> Vec<ServiceConfig> {
+    vec![
+        ServiceConfig {
+            name: "products".to_string(),
+            url: products_url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            capabilities: ServiceCapabilities {
+                subscriptions: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ServiceConfig {
+            name: "reviews".to_string(),
+            url: reviews_url.to_string(),
+            schema: REVIEWS_SCHEMA.to_string(),
+            ..Default::default()
+        },
+    ]
+}
+
+async fn build_schema(services: Vec<ServiceConfig>) -> FederatedSchema {
+    let mut registry = InMemorySchemaRegistry::new();
+    for service in services {
+        registry.register_service(service).await.unwrap();
+    }
+    registry.get_schema().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_plans_subscription_on_the_owning_service() {
+    let schema = build_schema(services("http://products", "http://reviews")).await;
+
+    let plan = SimpleQueryPlanner::new()
+        .plan_query(SUBSCRIPTION, &schema, None, None)
+        .await
+        .unwrap();
+
+    let subscription = match &plan.node {
+        PlanNode::Subscription(subscription) => subscription,
+        other => panic!("expected a subscription, got {:?}", other),
+    };
+    assert_eq!(subscription.primary.service_name, "products");
+    assert!(subscription.primary.query.starts_with("subscription {"));
+    match subscription.rest.as_deref() {
+        Some(PlanNode::Flatten(flatten)) => {
+            assert_eq!(flatten.path, vec!["productAdded"]);
+        }
+        other => panic!("expected a flatten, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_streams_events_with_fields_from_other_services() {
+    let products = MockService::start(|_| {
+        json!([
+            { "data": { "productAdded": { "id": "1", "name": "Table" } } },
+            { "data": { "productAdded": { "id": "2", "name": "Chair" } } },
+        ])
+    })
+    .await;
+    let reviews = MockService::start(|body| {
+        let id = &body["variables"]["representations"][0]["id"];
+        json!({ "data": { "_entities": [{ "reviews": [{ "body": format!("Review of {}", id) }] }] } })
+    })
+    .await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for service in services(&products.url, &reviews.url) {
+        gateway.register_service(service).await.unwrap();
+    }
+
+    let events: Vec<Value> = gateway
+        .subscribe(request(SUBSCRIPTION))
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    assert_eq!(
+        events,
+        vec![
+            json!({ "data": { "productAdded": {
+                "name": "Table", "reviews": [{ "body": "Review of \"1\"" }]
+            } } }),
+            json!({ "data": { "productAdded": {
+                "name": "Chair", "reviews": [{ "body": "Review of \"2\"" }]
+            } } }),
+        ]
+    );
+    assert_eq!(products.headers()[0]["accept"], "text/event-stream");
+    assert_eq!(reviews.requests().len(), 2);
+
+    // Without an event stream the subscription can't be served
+    let response = gateway.process_request(request(SUBSCRIPTION)).await;
+    assert_eq!(
+        response.unwrap_err(),
+        "Subscriptions must be requested with Accept: text/event-stream"
+    );
+}
+
+#[tokio::test]
+async fn test_rejects_subscriptions_selecting_several_fields() {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    for service in services("http://products", "http://reviews") {
+        gateway.register_service(service).await.unwrap();
+    }
+
+    let events: Vec<Value> = gateway
+        .subscribe(request(
+            "subscription OnProduct { productAdded { id } again: productAdded { name } }",
+        ))
+        .await
+        .unwrap()
+        .collect()
+        .await;
+
+    assert_eq!(
+        events,
+        vec![json!({ "errors": [{
+            "message": "Subscription \"OnProduct\" must select only one top level field.",
+            "extensions": { "code": "GRAPHQL_VALIDATION_FAILED" },
+            "locations": [{ "line": 1, "column": 46 }]
+        }] })]
+    );
+}
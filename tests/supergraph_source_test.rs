@@ -0,0 +1,408 @@
+mod common;
+
+use bytes::Bytes;
+use common::{MockService, request};
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use portkey::s3::S3Config;
+use portkey::supergraph_source::{SupergraphSource, SupergraphSourceConfig, split_supergraph};
+use portkey::{FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, SimpleQueryPlanner};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+const SUPERGRAPH: &str = r#"
+schema
+  @link(url: "https://specs.apollo.dev/link/v1.0")
+  @link(url: "https://specs.apollo.dev/join/v0.3", for: EXECUTION)
+{
+  query: Query
+}
+
+directive @join__field(graph: join__Graph, requires: join__FieldSet, provides: join__FieldSet, type: String, external: Boolean, override: String, usedOverridden: Boolean) repeatable on FIELD_DEFINITION | INPUT_FIELD_DEFINITION
+directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+directive @join__type(graph: join__Graph!, key: join__FieldSet, extension: Boolean! = false, resolvable: Boolean! = true, isInterfaceObject: Boolean! = false) repeatable on OBJECT | INTERFACE | UNION | ENUM | INPUT_OBJECT | SCALAR
+directive @link(url: String, as: String, for: link__Purpose, import: [link__Import]) repeatable on SCHEMA
+
+scalar join__FieldSet
+scalar link__Import
+
+enum join__Graph {
+  PRODUCTS @join__graph(name: "products", url: "PRODUCTS_URL")
+  REVIEWS @join__graph(name: "reviews", url: "REVIEWS_URL")
+}
+
+enum link__Purpose {
+  SECURITY
+  EXECUTION
+}
+
+type Product
+  @join__type(graph: PRODUCTS, key: "id")
+  @join__type(graph: REVIEWS, key: "id")
+{
+  id: ID!
+  name: String! @join__field(graph: PRODUCTS)
+  reviews: [Review] @join__field(graph: REVIEWS)
+}
+
+type Query
+  @join__type(graph: PRODUCTS)
+  @join__type(graph: REVIEWS)
+{
+  products: [Product] @join__field(graph: PRODUCTS)
+}
+
+type Review
+  @join__type(graph: REVIEWS)
+{
+  body: String!
+}
+"#;
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Product {
+    name: String!
+}
+"#;
+
+const LEGACY_SCHEMA: &str = r#"
+type Query {
+    legacyProducts: [String]
+}
+"#;
+
+type Requests = Arc<Mutex<Vec<(String, HeaderMap)>>>;
+
+// Serves `files` by path while `up`, answering 503 otherwise, and records the
+// path and headers of every request
+async fn serve_files(
+    files: HashMap<&'static str, String>,
+    up: Arc<AtomicBool>,
+) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let files = Arc::new(files);
+    let requests: Requests = Arc::default();
+    let recorded = Arc::clone(&requests);
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (files, up, recorded) =
+                (Arc::clone(&files), Arc::clone(&up), Arc::clone(&recorded));
+            let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((req.uri().path().to_string(), req.headers().clone()));
+                let response = match files.get(req.uri().path()) {
+                    Some(file) if up.load(Ordering::SeqCst) => {
+                        Response::new(Full::new(Bytes::from(file.clone())))
+                    }
+                    _ => Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body(Full::new(Bytes::new()))
+                        .unwrap(),
+                };
+                async move { Ok::<_, Infallible>(response) }
+            });
+            tokio::spawn(
+                hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service),
+            );
+        }
+    });
+    (url, requests)
+}
+
+#[tokio::test]
+async fn test_serves_subgraphs_split_from_a_supergraph_schema() {
+    let products = MockService::start(|_| {
+        json!({ "data": { "products": [{ "__typename": "Product", "id": "1", "name": "Table" }] } })
+    })
+    .await;
+    let reviews = MockService::start(
+        |_| json!({ "data": { "_entities": [{ "reviews": [{ "body": "Sturdy" }] }] } }),
+    )
+    .await;
+    let supergraph = SUPERGRAPH
+        .replace("PRODUCTS_URL", &products.url)
+        .replace("REVIEWS_URL", &reviews.url);
+
+    let services = split_supergraph(&supergraph).unwrap();
+    let names: Vec<_> = services
+        .iter()
+        .map(|service| service.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["products", "reviews"]);
+    assert_eq!(services[0].url, products.url);
+    let collapsed = services[1]
+        .schema
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    assert!(
+        collapsed.contains(r#"type Product @key(fields: "id") { id: ID! reviews: [Review] }"#),
+        "{}",
+        collapsed
+    );
+    assert!(!collapsed.contains("join__"), "{}", collapsed);
+
+    // Loaded from a supergraph schema file, subgraphs are queried as usual
+    let path =
+        std::env::temp_dir().join(format!("portkey-supergraph-{}.graphql", std::process::id()));
+    std::fs::write(&path, &supergraph).unwrap();
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_supergraph_sources(&SupergraphSourceConfig {
+        sources: vec![SupergraphSource::File { path: path.clone() }],
+        ..Default::default()
+    });
+    gateway.load_schemas().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let response = gateway
+        .process_request(request("{ products { name reviews { body } } }"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response["data"],
+        json!({ "products": [{ "name": "Table", "reviews": [{ "body": "Sturdy" }] }] })
+    );
+}
+
+#[tokio::test]
+async fn test_falls_back_to_later_sources_and_back() {
+    let up = Arc::new(AtomicBool::new(false));
+    let products = MockService::start(|_| json!({ "data": { "products": [] } })).await;
+    let (base, _) = serve_files(
+        HashMap::from([
+            (
+                "/supergraph/supergraph.yaml",
+                format!(
+                    "subgraphs:\n  products:\n    routing_url: {}\n    schema:\n      file: ./products.graphql\n",
+                    products.url
+                ),
+            ),
+            ("/supergraph/products.graphql", PRODUCTS_SCHEMA.to_string()),
+        ]),
+        Arc::clone(&up),
+    )
+    .await;
+    let url = format!("{}/supergraph/supergraph.yaml", base);
+
+    let dir: PathBuf = std::env::temp_dir().join(format!("portkey-sources-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("supergraph.yaml"),
+        "subgraphs:\n  legacy:\n    routing_url: http://legacy\n    schema:\n      file: ./legacy.graphql\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("legacy.graphql"), LEGACY_SCHEMA).unwrap();
+
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_supergraph_sources(&SupergraphSourceConfig {
+        sources: vec![
+            SupergraphSource::Url {
+                url: url.clone(),
+                headers: HashMap::new(),
+            },
+            SupergraphSource::File {
+                path: dir.join("supergraph.yaml"),
+            },
+        ],
+        ..Default::default()
+    });
+
+    // The URL is down, the file is served meanwhile
+    gateway.load_schemas().await.unwrap();
+    assert_eq!(
+        gateway.schema_health().await["source"],
+        format!("file {}", dir.join("supergraph.yaml").display())
+    );
+    assert_eq!(gateway.retry_preferred_sources().await.ok().flatten(), None);
+
+    // Once the URL is back, its subgraphs replace the file's
+    up.store(true, Ordering::SeqCst);
+    assert_eq!(
+        gateway.retry_preferred_sources().await.unwrap(),
+        Some(format!("url {}", url))
+    );
+    assert_eq!(
+        gateway.schema_health().await["source"],
+        format!("url {}", url)
+    );
+    let response = gateway
+        .process_request(request("{ products { name } }"))
+        .await
+        .unwrap();
+    assert_eq!(response["data"], json!({ "products": [] }));
+    let response = gateway
+        .process_request(request("{ legacyProducts }"))
+        .await
+        .unwrap();
+    assert!(response["data"].is_null(), "{}", response);
+
+    // Nothing comes before the first source
+    assert_eq!(gateway.retry_preferred_sources().await.unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_reports_every_failing_source() {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_supergraph_sources(&SupergraphSourceConfig {
+        sources: vec![
+            SupergraphSource::File {
+                path: PathBuf::from("./missing/supergraph.yaml"),
+            },
+            SupergraphSource::Url {
+                url: "not a url".to_string(),
+                headers: HashMap::new(),
+            },
+        ],
+        ..Default::default()
+    });
+
+    let error = gateway.load_schemas().await.unwrap_err();
+    assert!(
+        error.starts_with("file ./missing/supergraph.yaml: Failed to read config file"),
+        "{}",
+        error
+    );
+    assert!(error.contains("; url not a url: Invalid URL"), "{}", error);
+    assert_eq!(gateway.schema_health().await["source"], json!(null));
+}
+
+#[tokio::test]
+async fn test_loads_supergraphs_from_s3() {
+    let products = MockService::start(|_| json!({ "data": { "products": [] } })).await;
+    let (endpoint, requests) = serve_files(
+        HashMap::from([
+            (
+                "/configs/gateway/supergraph.yaml",
+                format!(
+                    "subgraphs:\n  products:\n    routing_url: {}\n    schema:\n      file: products.graphql\n",
+                    products.url
+                ),
+            ),
+            ("/configs/gateway/products.graphql", PRODUCTS_SCHEMA.to_string()),
+        ]),
+        Arc::new(AtomicBool::new(true)),
+    )
+    .await;
+
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_supergraph_sources(&SupergraphSourceConfig {
+        sources: vec![SupergraphSource::Url {
+            url: "s3://configs/gateway/supergraph.yaml".to_string(),
+            headers: HashMap::new(),
+        }],
+        s3: S3Config {
+            region: Some("eu-west-1".to_string()),
+            access_key_id: Some("AKIDEXAMPLE".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            session_token: Some("token".to_string()),
+            endpoint: Some(endpoint),
+        },
+        ..Default::default()
+    });
+    gateway.load_schemas().await.unwrap();
+    let response = gateway
+        .process_request(request("{ products { name } }"))
+        .await
+        .unwrap();
+    assert_eq!(response["data"], json!({ "products": [] }));
+
+    // Both objects are read path-style under the endpoint, signed
+    let requests = requests.lock().unwrap();
+    let paths: Vec<&str> = requests.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "/configs/gateway/supergraph.yaml",
+            "/configs/gateway/products.graphql"
+        ]
+    );
+    for (_, headers) in requests.iter() {
+        let date = headers["x-amz-date"].to_str().unwrap();
+        let authorization = headers["authorization"].to_str().unwrap();
+        let signed = format!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/{}/eu-west-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature=",
+            &date[..8]
+        );
+        assert!(authorization.starts_with(&signed), "{}", authorization);
+        assert_eq!(authorization.len(), signed.len() + 64);
+        assert_eq!(headers["x-amz-security-token"], "token");
+    }
+}
+
+#[tokio::test]
+async fn test_sends_source_headers_to_its_own_origin_only() {
+    let up = Arc::new(AtomicBool::new(true));
+    let (schemas, schema_requests) = serve_files(
+        HashMap::from([("/products.graphql", PRODUCTS_SCHEMA.to_string())]),
+        Arc::clone(&up),
+    )
+    .await;
+    let (base, config_requests) = serve_files(
+        HashMap::from([(
+            "/supergraph.yaml",
+            format!(
+                "subgraphs:\n  products:\n    routing_url: http://products\n    schema:\n      file: {}/products.graphql\n",
+                schemas
+            ),
+        )]),
+        up,
+    )
+    .await;
+
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_supergraph_sources(&SupergraphSourceConfig {
+        sources: vec![SupergraphSource::Url {
+            url: format!("{}/supergraph.yaml", base),
+            headers: HashMap::from([("Authorization".to_string(), "Bearer config".to_string())]),
+        }],
+        ..Default::default()
+    });
+    gateway.load_schemas().await.unwrap();
+
+    assert_eq!(
+        config_requests.lock().unwrap()[0].1["authorization"],
+        "Bearer config"
+    );
+    assert!(
+        !schema_requests.lock().unwrap()[0]
+            .1
+            .contains_key("authorization")
+    );
+}
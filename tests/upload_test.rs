@@ -0,0 +1,187 @@
+mod common;
+
+use bytes::Bytes;
+use common::MockService;
+use futures::stream;
+use portkey::upload::{self, UploadConfig, Uploads};
+use portkey::{
+    FederationGateway, GraphQLRequest, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::convert::Infallible;
+use std::path::PathBuf;
+
+const SCHEMA: &str = r#"
+scalar Upload
+
+type Query {
+    files: [File]
+}
+
+type Mutation {
+    upload(file: Upload!, attachments: [Upload!]): File
+}
+
+type File {
+    name: String!
+}
+"#;
+
+const BOUNDARY: &str = "portkey-boundary";
+
+// A multipart request body following the GraphQL multipart request spec,
+// with `files` as (field name, file name, content)
+fn multipart(operations: &str, map: &str, files: &[(&str, &str, &str)]) -> String {
+    let mut body = String::new();
+    for (name, value) in [("operations", operations), ("map", map)] {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            BOUNDARY, name, value
+        ));
+    }
+    for (name, file_name, content) in files {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n",
+            BOUNDARY, name, file_name, content
+        ));
+    }
+    body.push_str(&format!("--{}--\r\n", BOUNDARY));
+    body
+}
+
+// Parses `body` as it would arrive, a few bytes at a time
+async fn parse(body: String, config: &UploadConfig) -> Result<(GraphQLRequest, Uploads), String> {
+    let chunks: Vec<Result<Bytes, Infallible>> = body
+        .into_bytes()
+        .chunks(7)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+    upload::parse(stream::iter(chunks), BOUNDARY.to_string(), config).await
+}
+
+fn spool_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("portkey-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_streams_uploaded_files_to_the_service() {
+    let files = MockService::start(|_| json!({ "data": { "upload": { "name": "a.txt" } } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "files".to_string(),
+            url: files.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    // Files past 8 bytes are spooled to disk
+    let dir = spool_dir("upload-spool");
+    let config = UploadConfig {
+        max_in_memory_bytes: 8,
+        spool_dir: Some(dir.clone()),
+        ..Default::default()
+    };
+    let body = multipart(
+        r#"{ "query": "mutation($file: Upload!, $attachments: [Upload!]) { upload(file: $file, attachments: $attachments) { name } }", "variables": { "file": null, "attachments": [null] } }"#,
+        r#"{ "0": ["variables.file"], "1": ["variables.attachments.0"] }"#,
+        &[
+            ("0", "a.txt", "small"),
+            ("1", "b.txt", "larger than the threshold"),
+        ],
+    );
+    let (request, uploads) = parse(body, &config).await.unwrap();
+    let spooled = uploads.spooled();
+    assert_eq!(spooled.len(), 1);
+    assert!(spooled[0].starts_with(&dir));
+    assert!(spooled[0].exists());
+
+    let response = gateway.process_upload(request, uploads).await.unwrap();
+    assert_eq!(response["data"], json!({ "upload": { "name": "a.txt" } }));
+
+    let sent = &files.requests()[0];
+    assert_eq!(
+        sent["operations"]["variables"],
+        json!({ "file": null, "attachments": [null] })
+    );
+    assert_eq!(
+        sent["map"],
+        json!({ "0": ["variables.file"], "1": ["variables.attachments.0"] })
+    );
+    assert_eq!(
+        sent["files"],
+        json!({
+            "0": { "fileName": "a.txt", "contentType": "text/plain", "content": "small" },
+            "1": {
+                "fileName": "b.txt",
+                "contentType": "text/plain",
+                "content": "larger than the threshold",
+            },
+        })
+    );
+    assert!(
+        files.headers()[0]["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("multipart/form-data")
+    );
+
+    // Spooled files are removed once the request is done
+    assert!(!spooled[0].exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_rejects_malformed_uploads() {
+    let operations = r#"{ "query": "mutation($file: Upload!) { upload(file: $file) { name } }", "variables": { "file": null } }"#;
+    let config = UploadConfig::default();
+
+    let files_first = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"0\"; filename=\"a.txt\"\r\n\r\na\r\n--{b}--\r\n",
+        b = BOUNDARY
+    );
+    assert_eq!(
+        parse(files_first, &config).await.unwrap_err(),
+        "Invalid multipart request: `operations` and `map` must precede the files"
+    );
+
+    let unsent = multipart(operations, r#"{ "0": ["variables.file"] }"#, &[]);
+    assert_eq!(
+        parse(unsent, &config).await.unwrap_err(),
+        "Invalid multipart request: file 0 is mapped but wasn't sent"
+    );
+
+    let not_a_variable = multipart(
+        operations,
+        r#"{ "0": ["variables.other"] }"#,
+        &[("0", "a.txt", "a")],
+    );
+    assert_eq!(
+        parse(not_a_variable, &config).await.unwrap_err(),
+        "Invalid multipart request: variables.other in `map` isn't a null variable"
+    );
+
+    let too_large = multipart(
+        operations,
+        r#"{ "0": ["variables.file"] }"#,
+        &[("0", "a.txt", "more than four bytes")],
+    );
+    let limited = UploadConfig {
+        max_file_bytes: Some(4),
+        ..Default::default()
+    };
+    assert_eq!(
+        parse(too_large, &limited).await.unwrap_err(),
+        "File 0 is larger than the limit of 4 bytes"
+    );
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use common::{MockService, request};
+use graphql_parser::parse_schema;
+use graphql_parser::query::{Definition, Document, OperationDefinition, Selection};
+use portkey::introspection::IntrospectionSchema;
+use portkey::validation::{ValidationError, ValidationRule, validate};
+use portkey::{
+    FederatedSchema, FederationGateway, HttpQueryExecutor, InMemorySchemaRegistry, ServiceConfig,
+    SimpleQueryPlanner,
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::sync::Arc;
+
+const SCHEMA: &str = r#"
+type Query {
+    user(id: ID!): User
+    users(role: Role, filter: UserFilter, first: Int = 10): [User!]!
+}
+
+enum Role {
+    ADMIN
+    MEMBER
+}
+
+input UserFilter {
+    name: String
+    minAge: Int!
+}
+
+type User {
+    id: ID!
+    name: String!
+    friends: [User]
+}
+"#;
+
+fn schema() -> IntrospectionSchema {
+    IntrospectionSchema::compose(&[parse_schema::<String>(SCHEMA).unwrap()])
+}
+
+fn messages(query: &str) -> Vec<String> {
+    validate(query, None, &schema())
+        .into_iter()
+        .map(|error| error.message)
+        .collect()
+}
+
+#[test]
+fn test_accepts_valid_operations() {
+    let query = r#"
+        query($id: ID!, $role: Role) {
+            user(id: $id) { __typename ...Friends }
+            users(role: $role, filter: { minAge: 18, name: "a" }) { id }
+            __schema { types { name } }
+        }
+        fragment Friends on User { friends { name } }
+    "#;
+
+    assert_eq!(messages(query), Vec::<String>::new());
+    assert_eq!(messages(r#"{ user(id: 1) { id } }"#), Vec::<String>::new());
+}
+
+#[test]
+fn test_reports_unknown_fields_and_arguments() {
+    assert_eq!(
+        validate("{ user(id: \"1\") { email } }", None, &schema()),
+        vec![ValidationError {
+            message: "Cannot query field \"email\" on type \"User\".".to_string(),
+            line: 1,
+            column: 19,
+        }]
+    );
+    assert_eq!(
+        messages("{ user(id: \"1\", verbose: true) { name { first } } users }"),
+        vec![
+            "Unknown argument \"verbose\" on field \"Query.user\".",
+            "Field \"name\" must not have a selection since type \"String!\" has no subfields.",
+            "Field \"users\" of type \"[User!]!\" must have a selection of subfields.",
+        ]
+    );
+}
+
+#[test]
+fn test_reports_invalid_argument_values() {
+    assert_eq!(
+        messages(r#"{ user { id } users(role: OWNER, first: "ten", filter: { age: 3 }) { id } }"#),
+        vec![
+            "Field \"Query.user\" argument \"id\" of type \"ID!\" is required, but it was not provided.",
+            "Expected value of type \"Role\", found OWNER.",
+            "Expected value of type \"Int\", found \"ten\".",
+            "Field \"age\" is not defined by type \"UserFilter\".",
+            "Field \"UserFilter.minAge\" of required type \"Int!\" was not provided.",
+        ]
+    );
+}
+
+#[test]
+fn test_reports_undefined_and_mismatched_variables() {
+    assert_eq!(
+        messages(
+            "query($id: ID, $role: Rank) { user(id: $id) { id } users(role: $missing) { id } }"
+        ),
+        vec![
+            "Unknown type \"Rank\".",
+            "Variable \"$id\" of type \"ID\" used in position expecting type \"ID!\".",
+            "Variable \"$missing\" is not defined.",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_gateway_rejects_invalid_queries_before_planning() {
+    let users = MockService::start(|_| json!({ "data": null })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    let response = gateway
+        .process_request(request("{ user(id: \"1\") { email } }"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "Cannot query field \"email\" on type \"User\".",
+                "extensions": { "code": "GRAPHQL_VALIDATION_FAILED" },
+                "locations": [{ "line": 1, "column": 19 }]
+            }]
+        })
+    );
+    assert!(users.requests().is_empty());
+}
+
+fn is_list(type_ref: &Value) -> bool {
+    type_ref["kind"] == "LIST" || (type_ref["kind"] == "NON_NULL" && is_list(&type_ref["ofType"]))
+}
+
+// Lists must be paginated
+struct RequireFirst;
+
+impl ValidationRule for RequireFirst {
+    fn validate(
+        &self,
+        document: &Document<'_, String>,
+        _operation_name: Option<&str>,
+        schema: &FederatedSchema,
+    ) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for definition in &document.definitions {
+            let Definition::Operation(OperationDefinition::Query(query)) = definition else {
+                continue;
+            };
+            for selection in &query.selection_set.items {
+                let Selection::Field(field) = selection else {
+                    continue;
+                };
+                let is_list = schema
+                    .introspection
+                    .get_type("Query")
+                    .and_then(|query_type| query_type["fields"].as_array())
+                    .and_then(|fields| fields.iter().find(|f| f["name"] == field.name.as_str()))
+                    .is_some_and(|f| is_list(&f["type"]));
+                if is_list && !field.arguments.iter().any(|(name, _)| name == "first") {
+                    errors.push(ValidationError::new(
+                        field.position,
+                        format!("List field \"{}\" requires a `first` argument.", field.name),
+                    ));
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[tokio::test]
+async fn test_gateway_runs_custom_validation_rules() {
+    let users = MockService::start(|_| json!({ "data": { "users": [] } })).await;
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    )
+    .with_validation_rule(Arc::new(RequireFirst));
+    gateway
+        .register_service(ServiceConfig {
+            name: "users".to_string(),
+            url: users.url.clone(),
+            schema: SCHEMA.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let response = gateway
+        .process_request(request("query { users { id } }"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response,
+        json!({
+            "errors": [{
+                "message": "List field \"users\" requires a `first` argument.",
+                "extensions": { "code": "GRAPHQL_VALIDATION_FAILED" },
+                "locations": [{ "line": 1, "column": 9 }]
+            }]
+        })
+    );
+    assert!(users.requests().is_empty());
+
+    let response = gateway
+        .process_request(request("query { users(first: 5) { id } }"))
+        .await
+        .unwrap();
+    assert_eq!(response, json!({ "data": { "users": [] } }));
+}
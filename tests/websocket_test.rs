@@ -0,0 +1,354 @@
+use futures::{SinkExt, StreamExt};
+use portkey::{
+    FederationGateway, ServiceCapabilities, ServiceConfig,
+    query_executor::HttpQueryExecutor,
+    query_planner::SimpleQueryPlanner,
+    schema_registry::InMemorySchemaRegistry,
+    subscription_limit::{SubscriptionLimitConfig, SubscriptionLimiter},
+    websocket::{self, ConnectionContext, GRAPHQL_TRANSPORT_WS, WebSocketConfig},
+};
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::{
+    Message,
+    handshake::server::{Request, Response},
+    http::HeaderValue,
+    protocol::Role,
+};
+
+const PRODUCTS_SCHEMA: &str = r#"
+type Query {
+    products: [Product]
+}
+
+type Subscription {
+    productAdded: Product
+}
+
+type Product @key(fields: "id") {
+    id: ID!
+    name: String!
+}
+"#;
+
+// A service taking subscriptions over graphql-transport-ws, answering every
+// subscription with `events` and recording the messages and headers it got
+struct WebSocketService {
+    url: String,
+    messages: Arc<Mutex<Vec<Value>>>,
+    authorization: Arc<Mutex<Vec<String>>>,
+}
+
+impl WebSocketService {
+    async fn start(events: Vec<Value>, complete: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/graphql", listener.local_addr().unwrap());
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let authorization = Arc::new(Mutex::new(Vec::new()));
+
+        let (recorded, headers) = (Arc::clone(&messages), Arc::clone(&authorization));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (recorded, headers, events) =
+                    (Arc::clone(&recorded), Arc::clone(&headers), events.clone());
+                tokio::spawn(async move {
+                    // The error response's type is tungstenite's to choose
+                    #[allow(clippy::result_large_err)]
+                    let callback = |request: &Request, mut response: Response| {
+                        if let Some(value) = request.headers().get("Authorization") {
+                            headers
+                                .lock()
+                                .unwrap()
+                                .push(value.to_str().unwrap().to_string());
+                        }
+                        response.headers_mut().insert(
+                            "Sec-WebSocket-Protocol",
+                            HeaderValue::from_static(GRAPHQL_TRANSPORT_WS),
+                        );
+                        Ok(response)
+                    };
+                    let mut socket = tokio_tungstenite::accept_hdr_async(stream, callback)
+                        .await
+                        .unwrap();
+
+                    while let Some(Ok(Message::Text(text))) = socket.next().await {
+                        let message: Value = serde_json::from_str(text.as_str()).unwrap();
+                        recorded.lock().unwrap().push(message.clone());
+                        match message["type"].as_str() {
+                            Some("connection_init") => {
+                                send(&mut socket, json!({ "type": "connection_ack" })).await;
+                            }
+                            Some("subscribe") => {
+                                let id = &message["id"];
+                                for event in &events {
+                                    send(
+                                        &mut socket,
+                                        json!({ "id": id, "type": "next", "payload": event }),
+                                    )
+                                    .await;
+                                }
+                                if complete {
+                                    send(&mut socket, json!({ "id": id, "type": "complete" }))
+                                        .await;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                });
+            }
+        });
+
+        WebSocketService {
+            url,
+            messages,
+            authorization,
+        }
+    }
+
+    fn messages(&self) -> Vec<Value> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+async fn send<S>(socket: &mut WebSocketStream<S>, message: Value)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    socket
+        .send(Message::text(message.to_string()))
+        .await
+        .unwrap();
+}
+
+async fn receive(socket: &mut WebSocketStream<DuplexStream>) -> Value {
+    match socket.next().await {
+        Some(Ok(Message::Text(text))) => serde_json::from_str(text.as_str()).unwrap(),
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
+
+async fn gateway(products_url: &str) -> Arc<FederationGateway> {
+    let gateway = FederationGateway::new(
+        Box::new(InMemorySchemaRegistry::new()),
+        Box::new(SimpleQueryPlanner::new()),
+        Box::new(HttpQueryExecutor::new()),
+    );
+    gateway
+        .register_service(ServiceConfig {
+            name: "products".to_string(),
+            url: products_url.to_string(),
+            schema: PRODUCTS_SCHEMA.to_string(),
+            capabilities: ServiceCapabilities {
+                websocket_subscriptions: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    Arc::new(gateway)
+}
+
+// A client connected to the gateway as if over `/graphql`, with `Bearer
+// upgrade` on the upgrade request
+fn connect(gateway: Arc<FederationGateway>) -> impl Future<Output = WebSocketStream<DuplexStream>> {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let connection = ConnectionContext {
+        auth_headers: Some(HashMap::from([(
+            "Authorization".to_string(),
+            "Bearer upgrade".to_string(),
+        )])),
+        ..Default::default()
+    };
+    let subscriptions = Arc::new(SubscriptionLimiter::new(&SubscriptionLimitConfig::default()));
+    tokio::spawn(async move {
+        websocket::serve(
+            server,
+            gateway,
+            subscriptions,
+            connection,
+            &WebSocketConfig::default(),
+        )
+        .await
+    });
+    WebSocketStream::from_raw_socket(client, Role::Client, None)
+}
+
+#[tokio::test]
+async fn test_proxies_subscriptions_over_websockets() {
+    let products = WebSocketService::start(
+        vec![
+            json!({ "data": { "productAdded": { "name": "Table" } } }),
+            json!({ "data": { "productAdded": { "name": "Chair" } } }),
+        ],
+        true,
+    )
+    .await;
+    let mut socket = connect(gateway(&products.url).await).await;
+
+    // Credentials of `connection_init` replace the upgrade request's
+    send(
+        &mut socket,
+        json!({ "type": "connection_init", "payload": { "Authorization": "Bearer init" } }),
+    )
+    .await;
+    assert_eq!(
+        receive(&mut socket).await,
+        json!({ "type": "connection_ack" })
+    );
+
+    send(&mut socket, json!({ "type": "ping" })).await;
+    assert_eq!(receive(&mut socket).await, json!({ "type": "pong" }));
+
+    send(
+        &mut socket,
+        json!({
+            "id": "a",
+            "type": "subscribe",
+            "payload": { "query": "subscription { productAdded { name } }" },
+        }),
+    )
+    .await;
+    assert_eq!(
+        receive(&mut socket).await,
+        json!({ "id": "a", "type": "next", "payload": {
+            "data": { "productAdded": { "name": "Table" } }
+        } })
+    );
+    assert_eq!(
+        receive(&mut socket).await,
+        json!({ "id": "a", "type": "next", "payload": {
+            "data": { "productAdded": { "name": "Chair" } }
+        } })
+    );
+    assert_eq!(
+        receive(&mut socket).await,
+        json!({ "id": "a", "type": "complete" })
+    );
+
+    let messages = products.messages();
+    assert_eq!(
+        messages[0],
+        json!({ "type": "connection_init", "payload": { "Authorization": "Bearer init" } })
+    );
+    assert_eq!(messages[1]["type"], "subscribe");
+    assert_eq!(
+        *products.authorization.lock().unwrap(),
+        vec!["Bearer init".to_string()]
+    );
+
+    // Operations failing validation end with an `error` message
+    send(
+        &mut socket,
+        json!({
+            "id": "b",
+            "type": "subscribe",
+            "payload": { "query": "subscription { productRemoved { name } }" },
+        }),
+    )
+    .await;
+    let error = receive(&mut socket).await;
+    assert_eq!(error["id"], "b");
+    assert_eq!(error["type"], "error");
+    assert_eq!(
+        error["payload"][0]["extensions"]["code"],
+        "GRAPHQL_VALIDATION_FAILED"
+    );
+}
+
+#[tokio::test]
+async fn test_completing_a_subscription_completes_it_at_the_service() {
+    let products = WebSocketService::start(
+        vec![json!({ "data": { "productAdded": { "name": "Table" } } })],
+        false,
+    )
+    .await;
+    let mut socket = connect(gateway(&products.url).await).await;
+
+    send(&mut socket, json!({ "type": "connection_init" })).await;
+    receive(&mut socket).await;
+    let subscribe = json!({
+        "id": "a",
+        "type": "subscribe",
+        "payload": { "query": "subscription { productAdded { name } }" },
+    });
+    send(&mut socket, subscribe.clone()).await;
+    assert_eq!(receive(&mut socket).await["type"], "next");
+    // The upgrade request's credentials apply without any in `connection_init`
+    assert_eq!(
+        *products.authorization.lock().unwrap(),
+        vec!["Bearer upgrade".to_string()]
+    );
+
+    send(&mut socket, json!({ "id": "a", "type": "complete" })).await;
+    let mut completed = false;
+    for _ in 0..50 {
+        completed = products
+            .messages()
+            .iter()
+            .any(|message| message["type"] == "complete");
+        if completed {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(completed, "{:?}", products.messages());
+
+    // The id is free again, a duplicate one closes the connection
+    send(&mut socket, subscribe.clone()).await;
+    assert_eq!(receive(&mut socket).await["type"], "next");
+    send(&mut socket, subscribe).await;
+    match socket.next().await {
+        Some(Ok(Message::Close(Some(frame)))) => {
+            assert_eq!(u16::from(frame.code), 4409);
+            assert_eq!(frame.reason.as_str(), "Subscriber for a already exists");
+        }
+        other => panic!("expected the connection to close, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_closes_connections_subscribing_before_init() {
+    let mut socket = connect(gateway("http://products").await).await;
+
+    send(
+        &mut socket,
+        json!({
+            "id": "a",
+            "type": "subscribe",
+            "payload": { "query": "subscription { productAdded { name } }" },
+        }),
+    )
+    .await;
+    match socket.next().await {
+        Some(Ok(Message::Close(Some(frame)))) => assert_eq!(u16::from(frame.code), 4401),
+        other => panic!("expected the connection to close, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_answers_only_graphql_transport_ws_upgrades() {
+    let mut headers = http::HeaderMap::new();
+    headers.insert("Upgrade", "websocket".parse().unwrap());
+    headers.insert(
+        "Sec-WebSocket-Key",
+        "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap(),
+    );
+    assert!(websocket::is_upgrade(&headers));
+    assert!(websocket::accept_key(&headers).is_err());
+
+    headers.insert(
+        "Sec-WebSocket-Protocol",
+        "graphql-ws, graphql-transport-ws".parse().unwrap(),
+    );
+    assert_eq!(
+        websocket::accept_key(&headers).unwrap(),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}